@@ -4,21 +4,26 @@ extern crate tower_web;
 
 use bytes::Bytes;
 use futures::Future;
+use interledger_ccp::{RouteManagerAdmin, RoutePolicy, RoutingRelation};
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::Address;
 use interledger_router::RouterStore;
-use interledger_service::{Account as AccountTrait, IncomingService, Username};
+use interledger_service::{Account as AccountTrait, IncomingService, StoreError, Username};
 use interledger_service_util::{BalanceStore, ExchangeRateStore};
 use interledger_settlement::{SettlementAccount, SettlementStore};
 use serde::Serialize;
-use std::str;
+use std::{pin::Pin, str::FromStr, sync::Arc};
 use tower_web::{net::ConnectionStream, Extract, Response, ServiceBuilder};
 
 mod routes;
 use self::routes::*;
 
-pub(crate) const BEARER_TOKEN_START: usize = 7;
+/// Returns true if the Authorization header is `Bearer <token>` with the given token.
+/// Headers that are too short or use another scheme are rejected rather than sliced.
+pub(crate) fn is_bearer_token(authorization: &str, token: &str) -> bool {
+    authorization.strip_prefix("Bearer ") == Some(token)
+}
 
 pub trait NodeStore: Clone + Send + Sync + 'static {
     type Account: AccountTrait;
@@ -26,41 +31,112 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
     fn insert_account(
         &self,
         account: AccountDetails,
-    ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send>;
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Self::Account, StoreError>> + Send>>;
 
     fn delete_account(
         &self,
         id: <Self::Account as AccountTrait>::AccountId,
-    ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send>;
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Self::Account, StoreError>> + Send>>;
 
     fn update_account(
         &self,
         id: <Self::Account as AccountTrait>::AccountId,
         account: AccountDetails,
-    ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send>;
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Self::Account, StoreError>> + Send>>;
 
     // TODO limit the number of results and page through them
-    fn get_all_accounts(&self) -> Box<dyn Future<Item = Vec<Self::Account>, Error = ()> + Send>;
+    fn get_all_accounts(
+        &self,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Vec<Self::Account>, StoreError>> + Send>>;
 
-    fn set_rates<R>(&self, rates: R) -> Box<dyn Future<Item = (), Error = ()> + Send>
+    fn set_rates<R>(
+        &self,
+        rates: R,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>>
     where
         R: IntoIterator<Item = (String, f64)>;
 
-    fn set_static_routes<R>(&self, routes: R) -> Box<dyn Future<Item = (), Error = ()> + Send>
+    /// Replace all of the static routes. Each prefix maps to its ranked next hops.
+    fn set_static_routes<R>(
+        &self,
+        routes: R,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>>
     where
-        R: IntoIterator<Item = (String, <Self::Account as AccountTrait>::AccountId)>;
+        R: IntoIterator<
+            Item = (
+                String,
+                Vec<StaticNextHop<<Self::Account as AccountTrait>::AccountId>>,
+            ),
+        >;
 
+    /// Set the static route for a single prefix to one account
     fn set_static_route(
         &self,
         prefix: String,
         account_id: <Self::Account as AccountTrait>::AccountId,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
+        self.set_static_next_hops(prefix, vec![StaticNextHop::new(account_id)])
+    }
+
+    /// Set the static route for a single prefix to several accounts, ranked from most to
+    /// least preferred. Which one the Router uses depends on its `RoutingPolicy`.
+    fn set_static_next_hops(
+        &self,
+        prefix: String,
+        next_hops: Vec<StaticNextHop<<Self::Account as AccountTrait>::AccountId>>,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>>;
+
+    /// Save the node's own ILP address, which is used to derive the addresses of child accounts.
+    /// If the node had a different address before, the child accounts whose addresses were
+    /// derived from it are moved under the new one.
+    fn set_ilp_address(
+        &self,
+        ilp_address: Address,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>>;
+}
+
+/// One of the accounts that a static route forwards packets to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaticNextHop<T> {
+    pub account_id: T,
+    /// This hop's share of the packets under the weighted routing policy.
+    /// If it is not set, the account's `route_weight` is used.
+    pub weight: Option<u32>,
+}
+
+impl<T> StaticNextHop<T> {
+    pub fn new(account_id: T) -> Self {
+        StaticNextHop {
+            account_id,
+            weight: None,
+        }
+    }
+
+    pub fn with_weight(account_id: T, weight: u32) -> Self {
+        StaticNextHop {
+            account_id,
+            weight: Some(weight),
+        }
+    }
+}
+
+/// The address of a child account under the node's address: `<node address>.<username>`
+pub fn child_address(node_address: &Address, username: &Username) -> Result<Address, StoreError> {
+    node_address
+        .with_suffix(username.as_bytes())
+        .map_err(|err| {
+            StoreError::Invalid(format!(
+                "Unable to derive an ILP address for {} from the node's address: {:?}",
+                username, err
+            ))
+        })
 }
 
 /// The Account type for the RedisStore.
 #[derive(Debug, Extract, Response, Clone)]
 pub struct AccountDetails {
-    pub ilp_address: Address,
+    /// Can be omitted for Child accounts, which then get `<node address>.<username>`
+    pub ilp_address: Option<Address>,
     pub username: Username,
     pub asset_code: String,
     pub asset_scale: u8,
@@ -83,17 +159,61 @@ pub struct AccountDetails {
     pub amount_per_minute_limit: Option<u64>,
     pub packets_per_minute_limit: Option<u32>,
     pub settlement_engine_url: Option<String>,
+    /// This account's share of the packets for the prefixes it shares with other
+    /// accounts, when the node uses the weighted routing policy. Defaults to 1.
+    pub route_weight: Option<u32>,
+    /// A secret shared with the peer that is used to sign and verify the CCP routes
+    /// exchanged with it. It is stored encrypted, like the outgoing tokens.
+    pub route_auth_key: Option<String>,
+    /// Rules for the routes the account sends us
+    pub route_import_policy: Option<RoutePolicy>,
+    /// Rules for the routes we send the account
+    pub route_export_policy: Option<RoutePolicy>,
+    /// How expensive it is to forward packets through the account. Of the routes from accounts
+    /// with the same routing relation, the ones from the cheapest account are preferred.
+    /// Defaults to 0
+    pub route_cost: Option<u32>,
 }
 
-pub struct NodeApi<S, I> {
+impl AccountDetails {
+    /// Fill in the address of a Child account that was submitted without one, using the
+    /// node's address. Every other account must be given an address.
+    pub fn derive_ilp_address(&mut self, node_address: Option<&Address>) -> Result<(), StoreError> {
+        if self.ilp_address.is_some() {
+            return Ok(());
+        }
+        let is_child = self
+            .routing_relation
+            .as_ref()
+            .and_then(|relation| RoutingRelation::from_str(relation).ok())
+            == Some(RoutingRelation::Child);
+        match node_address {
+            Some(node_address) if is_child => {
+                self.ilp_address = Some(child_address(node_address, &self.username)?);
+                Ok(())
+            }
+            Some(_) => Err(StoreError::Invalid(
+                "An ILP address is required for accounts that are not children of the node"
+                    .to_string(),
+            )),
+            None => Err(StoreError::Invalid(
+                "An ILP address is required because the node's address is not known yet"
+                    .to_string(),
+            )),
+        }
+    }
+}
+
+pub struct NodeApi<S, I, A: AccountTrait> {
     store: S,
     admin_api_token: String,
     default_spsp_account: Option<Username>,
     incoming_handler: I,
     server_secret: Bytes,
+    route_manager: Option<Arc<dyn RouteManagerAdmin<A>>>,
 }
 
-impl<S, I, A> NodeApi<S, I>
+impl<S, I, A> NodeApi<S, I, A>
 where
     S: NodeStore<Account = A>
         + HttpStore<Account = A>
@@ -123,6 +243,7 @@ where
             default_spsp_account: None,
             incoming_handler,
             server_secret,
+            route_manager: None,
         }
     }
 
@@ -131,6 +252,16 @@ where
         self
     }
 
+    /// Expose the state of the CCP route manager, let admins ask peers to resend their routes,
+    /// and tell the route manager when an account's settings change
+    pub fn route_manager<R>(&mut self, route_manager: R) -> &mut Self
+    where
+        R: RouteManagerAdmin<A> + 'static,
+    {
+        self.route_manager = Some(Arc::new(route_manager));
+        self
+    }
+
     pub fn serve<T>(&self, incoming: T) -> impl Future<Item = (), Error = ()>
     where
         T: ConnectionStream,
@@ -155,11 +286,16 @@ where
             .resource(AccountsApi::new(
                 self.admin_api_token.clone(),
                 self.store.clone(),
+                self.route_manager.clone(),
             ))
             .resource(SettingsApi::new(
                 self.admin_api_token.clone(),
                 self.store.clone(),
             ))
+            .resource(CcpApi::new(
+                self.admin_api_token.clone(),
+                self.route_manager.clone(),
+            ))
             .serve(incoming)
     }
 }
@@ -1,17 +1,20 @@
-use crate::{AccountDetails, NodeStore, BEARER_TOKEN_START};
+use super::store_error_status;
+use crate::{is_bearer_token, AccountDetails, NodeStore};
 use futures::{
     future::{err, ok, result, Either},
     Future,
 };
+use futures03::TryFutureExt;
 use hyper::Response;
+use interledger_ccp::RouteManagerAdmin;
 use interledger_http::{HttpAccount, HttpStore};
-use interledger_service::{Account, AuthToken, Username};
+use interledger_service::{Account, AuthToken, StoreError, Username};
 use interledger_service_util::BalanceStore;
 use log::{debug, error, trace};
 use reqwest::r#async::Client;
 use serde::Serialize;
 use serde_json::{json, Value};
-use std::str::FromStr;
+use std::{str::FromStr, sync::Arc};
 use tokio_retry::{strategy::FixedInterval, Retry};
 use tower_web::{impl_web, Response};
 use url::Url;
@@ -33,9 +36,10 @@ struct BalanceResponse {
 }
 
 #[derive(Clone)]
-pub struct AccountsApi<T> {
+pub struct AccountsApi<T, A: Account> {
     store: T,
     admin_api_token: String,
+    route_manager: Option<Arc<dyn RouteManagerAdmin<A>>>,
 }
 
 const MAX_RETRIES: usize = 10;
@@ -43,29 +47,49 @@ const MAX_RETRIES: usize = 10;
 // Convenience function to clean up error handling and reduce unwrap quantity
 trait ErrorStatus {
     fn error(code: u16) -> Self;
+
+    fn store_error(err: StoreError) -> Self;
+
+    /// Like `store_error`, but responds with 401 if no account matched the credentials
+    fn auth_error(err: StoreError) -> Self;
 }
 
 impl ErrorStatus for Response<()> {
     fn error(code: u16) -> Self {
         Response::builder().status(code).body(()).unwrap()
     }
+
+    fn store_error(err: StoreError) -> Self {
+        error!("Store error: {}", err);
+        Response::error(store_error_status(&err))
+    }
+
+    fn auth_error(err: StoreError) -> Self {
+        if let StoreError::NotFound(_) = err {
+            debug!("No account found with the given auth: {}", err);
+            Response::error(401)
+        } else {
+            Response::store_error(err)
+        }
+    }
 }
 
 impl_web! {
-    impl<T, A> AccountsApi<T>
+    impl<T, A> AccountsApi<T, A>
     where T: NodeStore<Account = A> + HttpStore<Account = A> + BalanceStore<Account = A>,
     A: Account + HttpAccount + Serialize + 'static,
 
     {
-        pub fn new(admin_api_token: String, store: T) -> Self {
+        pub fn new(admin_api_token: String, store: T, route_manager: Option<Arc<dyn RouteManagerAdmin<A>>>) -> Self {
             AccountsApi {
                 store,
                 admin_api_token,
+                route_manager,
             }
         }
 
         fn is_admin(&self, authorization: &str) -> bool {
-            authorization[BEARER_TOKEN_START..] == self.admin_api_token
+            is_bearer_token(authorization, &self.admin_api_token)
         }
 
         fn validate_admin(&self, authorization: String) -> impl Future<Item = T, Error = Response<()>> {
@@ -85,8 +109,8 @@ impl_web! {
             // a btp_uri
             let se_url = body.settlement_engine_url.clone();
             self.validate_admin(authorization)
-                .and_then(move |store| store.insert_account(body)
-                .map_err(|_| Response::error(500))
+                .and_then(move |store| store.insert_account(body).compat()
+                .map_err(Response::store_error)
                 .and_then(|account| {
                     // if the account had a SE associated with it, then register
                     // the account in the SE.
@@ -138,8 +162,8 @@ impl_web! {
         fn http_get_accounts(&self, authorization: String) -> impl Future<Item = Value, Error = Response<()>> {
             let store = self.store.clone();
             if self.is_admin(&authorization) {
-                Either::A(store.get_all_accounts()
-                    .map_err(|_| Response::error(500))
+                Either::A(store.get_all_accounts().compat()
+                    .map_err(Response::store_error)
                     .and_then(|accounts| Ok(json!(accounts))))
             } else {
                 // Only allow the user to see their own account
@@ -149,7 +173,7 @@ impl_web! {
                         Response::error(401)
                     })
                     .and_then(move |auth| {
-                        store.get_account_from_http_auth(&auth.username(), &auth.password()).map_err(|_| Response::error(401))
+                        store.get_account_from_http_auth(&auth.username(), &auth.password()).compat().map_err(Response::auth_error)
                         .and_then(|account| Ok(json!(vec![account])))
                     })
                 )
@@ -178,17 +202,17 @@ impl_web! {
                 Response::builder().status(500).body(()).unwrap()
             })
             .and_then(move |username| {
-            store.get_account_id_from_username(&username)
-            .map_err(move |_| {
+            store.get_account_id_from_username(&username).compat()
+            .map_err(move |err| {
                 error!("Error getting account id from username: {}", username_clone);
-                Response::builder().status(404).body(()).unwrap()
+                Response::store_error(err)
             })
             .and_then(move |id| {
                 if is_admin  {
-                    Either::A(store.get_accounts(vec![id])
-                    .map_err(move |_| {
+                    Either::A(store.get_accounts(vec![id]).compat()
+                    .map_err(move |err| {
                         debug!("Account not found: {:?}", id);
-                        Response::error(404)
+                        Response::store_error(err)
                     })
                     .and_then(|mut accounts| Ok(json!(accounts.pop().unwrap()))))
                 } else {
@@ -198,10 +222,10 @@ impl_web! {
                             Response::error(401)
                         })
                         .and_then(move |auth| {
-                            store.get_account_from_http_auth(&auth.username(), &auth.password())
-                            .map_err(move |_| {
+                            store.get_account_from_http_auth(&auth.username(), &auth.password()).compat()
+                            .map_err(move |err| {
                                 debug!("No account found with auth: {}", authorization);
-                                Response::error(401)
+                                Response::auth_error(err)
                             })
                             .and_then(move |account| {
                                 if account.id() == id {
@@ -228,17 +252,17 @@ impl_web! {
                 Response::builder().status(500).body(()).unwrap()
             })
             .and_then(move |username| {
-                store_clone.get_account_id_from_username(&username)
-                .map_err(move |_| {
+                store_clone.get_account_id_from_username(&username).compat()
+                .map_err(move |err| {
                     error!("Error getting account id from username: {}", username);
-                    Response::builder().status(500).body(()).unwrap()
+                    Response::store_error(err)
                 })
                 .and_then(move |id| {
                     self_clone.validate_admin(authorization)
                     .and_then(move |store| Ok((store, id)))
                     .and_then(move |(store, id)|
-                        store.delete_account(id)
-                            .map_err(move |_| Response::error(500))
+                        store.delete_account(id).compat()
+                            .map_err(Response::store_error)
                             .and_then(move |account| {
                                 // TODO: deregister from SE if url is present
                                 Ok(json!(account))
@@ -258,21 +282,27 @@ impl_web! {
                 Response::builder().status(500).body(()).unwrap()
             })
             .and_then(move |username| {
-            self_clone.store.get_account_id_from_username(&username)
-            .map_err(move |_| {
+            self_clone.store.get_account_id_from_username(&username).compat()
+            .map_err(move |err| {
                 error!("Error getting account id from username: {}", username);
-                Response::builder().status(500).body(()).unwrap()
+                Response::store_error(err)
             })
             .and_then(move |id| {
                 let id = id.to_owned();
+                let route_manager = self_clone.route_manager.clone();
                 self_clone.validate_admin(authorization)
                 .and_then(move |store|
-                    store.update_account(id, body)
-                        .map_err(move |_| Response::error(500))
-                        .and_then(move |account| {
-                            Ok(json!(account))
-                        })
+                    store.update_account(id, body).compat()
+                        .map_err(Response::store_error)
                 )
+                .and_then(move |account| {
+                    // The route manager picks its routes with the account's old settings until it is told about the new ones
+                    let notify_route_manager = match route_manager {
+                        Some(route_manager) => Either::A(route_manager.account_updated(account.clone()).compat().map_err(Response::store_error)),
+                        None => Either::B(ok(())),
+                    };
+                    notify_route_manager.and_then(move |_| Ok(json!(account)))
+                })
             })
             })
 
@@ -293,17 +323,17 @@ impl_web! {
                 Response::builder().status(500).body(()).unwrap()
             })
             .and_then(move |username| {
-            store_clone.get_account_id_from_username(&username)
-            .map_err(move |_| {
+            store_clone.get_account_id_from_username(&username).compat()
+            .map_err(move |err| {
                 error!("Error getting account id from username: {}", username_clone);
-                Response::builder().status(500).body(()).unwrap()
+                Response::store_error(err)
             })
             .and_then(move |id| {
                 if is_admin  {
-                    Either::A(store.get_accounts(vec![id])
-                        .map_err(move |_| {
+                    Either::A(store.get_accounts(vec![id]).compat()
+                        .map_err(move |err| {
                             debug!("Account not found: {}", id);
-                            Response::error(404)
+                            Response::store_error(err)
                         })
                         .and_then(|mut accounts| Ok(accounts.pop().unwrap())))
                 } else {
@@ -313,10 +343,10 @@ impl_web! {
                             Response::error(401)
                         })
                         .and_then(move |auth| {
-                            store.get_account_from_http_auth(&auth.username(), &auth.password())
-                            .map_err(move |_| {
+                            store.get_account_from_http_auth(&auth.username(), &auth.password()).compat()
+                            .map_err(move |err| {
                                 error!("No account found with auth: {}", authorization);
-                                Response::error(401)
+                                Response::auth_error(err)
                             })
                             .and_then(move |account| {
                                 if account.id() == id {
@@ -329,8 +359,8 @@ impl_web! {
                     )
                 }
             })
-            .and_then(move |account| store_clone.get_balance(account)
-            .map_err(|_| Response::error(500)))
+            .and_then(move |account| store_clone.get_balance(account).compat()
+            .map_err(Response::store_error))
             .and_then(|balance| Ok(BalanceResponse {
                 balance: balance.to_string(),
             }))
@@ -0,0 +1,282 @@
+use super::store_error_status;
+use crate::is_bearer_token;
+use futures::{
+    future::{err, ok},
+    Future,
+};
+use futures03::TryFutureExt;
+use hyper::Response;
+use interledger_ccp::RouteManagerAdmin;
+use interledger_service::Account;
+use log::{debug, error};
+use serde_json::{json, Value};
+use std::{str::FromStr, sync::Arc};
+
+#[derive(Response)]
+#[web(status = "200")]
+struct Success;
+
+pub struct CcpApi<A: Account> {
+    route_manager: Option<Arc<dyn RouteManagerAdmin<A>>>,
+    admin_api_token: String,
+}
+
+impl_web! {
+    impl<A> CcpApi<A>
+    where A: Account + 'static,
+
+    {
+        pub fn new(admin_api_token: String, route_manager: Option<Arc<dyn RouteManagerAdmin<A>>>) -> Self {
+            CcpApi {
+                route_manager,
+                admin_api_token,
+            }
+        }
+
+        // Responds with 404 if the node was not set up with a route manager
+        fn validate_admin(&self, authorization: String) -> impl Future<Item = Arc<dyn RouteManagerAdmin<A>>, Error = Response<()>> {
+            if !is_bearer_token(&authorization, &self.admin_api_token) {
+                error!("Admin API endpoint called with non-admin API key");
+                err(Response::builder().status(401).body(()).unwrap())
+            } else if let Some(route_manager) = &self.route_manager {
+                ok(route_manager.clone())
+            } else {
+                err(Response::builder().status(404).body(()).unwrap())
+            }
+        }
+
+        #[get("/routes/ccp/peers")]
+        #[content_type("application/json")]
+        fn get_incoming_tables(&self, authorization: String) -> impl Future<Item = Value, Error = Response<()>> {
+            self.validate_admin(authorization)
+                .and_then(|route_manager| Ok(json!(route_manager.incoming_tables())))
+        }
+
+        #[get("/routes/ccp/prefixes")]
+        #[content_type("application/json")]
+        fn get_prefixes(&self, authorization: String) -> impl Future<Item = Value, Error = Response<()>> {
+            self.validate_admin(authorization)
+                .and_then(|route_manager| {
+                    route_manager.prefixes().compat()
+                        .and_then(|prefixes| Ok(json!(prefixes)))
+                        .map_err(|err| {
+                            error!("Error getting the best routes: {}", err);
+                            Response::builder().status(store_error_status(&err)).body(()).unwrap()
+                        })
+                })
+        }
+
+        #[post("/routes/ccp/peers/:account_id/resync")]
+        #[content_type("application/json")]
+        fn post_resync(&self, account_id: String, authorization: String) -> impl Future<Item = Success, Error = Response<()>> {
+            self.validate_admin(authorization)
+                .and_then(move |route_manager| {
+                    if let Ok(account_id) = A::AccountId::from_str(account_id.as_str()) {
+                        Ok((route_manager, account_id))
+                    } else {
+                        Err(Response::builder().status(400).body(()).unwrap())
+                    }
+                })
+                .and_then(|(route_manager, account_id)| {
+                    debug!("Re-syncing routes with account: {}", account_id);
+                    route_manager.resync(account_id).compat()
+                        .and_then(|_| Ok(Success))
+                        .map_err(|err| {
+                            error!("Error re-syncing routes: {}", err);
+                            Response::builder().status(store_error_status(&err)).body(()).unwrap()
+                        })
+                })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures03::future::ready;
+    use interledger_ccp::{BestRouteReason, IncomingTableStatus, PrefixStatus};
+    use interledger_service::{StoreError, Username};
+    use std::{future::Future as StdFuture, pin::Pin, sync::Mutex};
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(Username);
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            1
+        }
+
+        fn username(&self) -> &Username {
+            &self.0
+        }
+    }
+
+    #[derive(Default)]
+    struct TestRouteManager {
+        resynced: Mutex<Vec<u64>>,
+    }
+
+    impl RouteManagerAdmin<TestAccount> for TestRouteManager {
+        fn incoming_tables(&self) -> Vec<IncomingTableStatus<u64>> {
+            vec![IncomingTableStatus {
+                account_id: 1,
+                routing_table_id: "00".repeat(16),
+                epoch: 3,
+                routes: Vec::new(),
+            }]
+        }
+
+        fn prefixes(
+            &self,
+        ) -> Pin<Box<dyn StdFuture<Output = Result<Vec<PrefixStatus<u64>>, StoreError>> + Send>>
+        {
+            Box::pin(ready(Ok(vec![PrefixStatus {
+                prefix: "example.a".to_string(),
+                account_id: 1,
+                path: vec!["example.b".to_string()],
+                reason: BestRouteReason::OnlyRoute,
+                candidates: Vec::new(),
+            }])))
+        }
+
+        fn account_updated(
+            &self,
+            _account: TestAccount,
+        ) -> Pin<Box<dyn StdFuture<Output = Result<(), StoreError>> + Send>> {
+            Box::pin(ready(Ok(())))
+        }
+
+        fn resync(
+            &self,
+            account_id: u64,
+        ) -> Pin<Box<dyn StdFuture<Output = Result<(), StoreError>> + Send>> {
+            if account_id == 1 {
+                self.resynced.lock().unwrap().push(account_id);
+                Box::pin(ready(Ok(())))
+            } else {
+                Box::pin(ready(Err(StoreError::NotFound(format!(
+                    "No account {}",
+                    account_id
+                )))))
+            }
+        }
+    }
+
+    fn test_api() -> (CcpApi<TestAccount>, Arc<TestRouteManager>) {
+        let route_manager = Arc::new(TestRouteManager::default());
+        let api = CcpApi::new("admin".to_string(), Some(route_manager.clone() as _));
+        (api, route_manager)
+    }
+
+    fn status<T>(result: Result<T, Response<()>>) -> u16 {
+        match result {
+            Ok(_) => 200,
+            Err(response) => response.status().as_u16(),
+        }
+    }
+
+    #[test]
+    fn rejects_missing_and_malformed_authorization() {
+        let (api, _) = test_api();
+        for authorization in &[
+            "",
+            "Bearer",
+            "Bearer ",
+            "admin",
+            "Basic admin",
+            "Bearer wrong",
+        ] {
+            assert_eq!(
+                status(api.get_prefixes(authorization.to_string()).wait()),
+                401,
+                "Authorization: {:?}",
+                authorization
+            );
+            assert_eq!(
+                status(api.get_incoming_tables(authorization.to_string()).wait()),
+                401
+            );
+            assert_eq!(
+                status(
+                    api.post_resync("1".to_string(), authorization.to_string())
+                        .wait()
+                ),
+                401
+            );
+        }
+    }
+
+    #[test]
+    fn responds_not_found_without_a_route_manager() {
+        let api: CcpApi<TestAccount> = CcpApi::new("admin".to_string(), None);
+        assert_eq!(
+            status(api.get_prefixes("Bearer admin".to_string()).wait()),
+            404
+        );
+    }
+
+    #[test]
+    fn returns_incoming_tables() {
+        let (api, _) = test_api();
+        let tables = api
+            .get_incoming_tables("Bearer admin".to_string())
+            .wait()
+            .unwrap();
+        assert_eq!(
+            tables,
+            json!([{
+                "account_id": 1,
+                "routing_table_id": "00".repeat(16),
+                "epoch": 3,
+                "routes": [],
+            }])
+        );
+    }
+
+    #[test]
+    fn returns_prefixes() {
+        let (api, _) = test_api();
+        let prefixes = api.get_prefixes("Bearer admin".to_string()).wait().unwrap();
+        assert_eq!(
+            prefixes,
+            json!([{
+                "prefix": "example.a",
+                "account_id": 1,
+                "path": ["example.b"],
+                "reason": "only_route",
+                "candidates": [],
+            }])
+        );
+    }
+
+    #[test]
+    fn resyncs_peers() {
+        let (api, route_manager) = test_api();
+        assert_eq!(
+            status(
+                api.post_resync("1".to_string(), "Bearer admin".to_string())
+                    .wait()
+            ),
+            200
+        );
+        assert_eq!(*route_manager.resynced.lock().unwrap(), vec![1]);
+
+        // Invalid IDs are bad requests and unknown accounts are not found
+        assert_eq!(
+            status(
+                api.post_resync("one".to_string(), "Bearer admin".to_string())
+                    .wait()
+            ),
+            400
+        );
+        assert_eq!(
+            status(
+                api.post_resync("2".to_string(), "Bearer admin".to_string())
+                    .wait()
+            ),
+            404
+        );
+    }
+}
@@ -1,9 +1,13 @@
+pub(crate) use interledger_service::store_error_status;
+
 mod accounts;
+mod ccp;
 mod ilp;
 mod settings;
 mod spsp;
 
 pub use accounts::AccountsApi;
+pub use ccp::CcpApi;
 pub use ilp::IlpApi;
 pub use settings::SettingsApi;
 pub use spsp::SpspApi;
@@ -1,13 +1,16 @@
-use crate::{NodeStore, BEARER_TOKEN_START};
+use super::store_error_status;
+use crate::{is_bearer_token, NodeStore, StaticNextHop};
 use futures::{
     future::{err, ok},
     Future,
 };
+use futures03::TryFutureExt;
 use hyper::Response;
 use interledger_router::RouterStore;
 use interledger_service::Account;
 use interledger_service_util::ExchangeRateStore;
 use log::{debug, error};
+use serde::Deserialize;
 use std::{
     collections::HashMap,
     iter::FromIterator,
@@ -31,6 +34,50 @@ struct Rates(HashMap<String, f64>);
 #[web(status = "200")]
 struct Routes(HashMap<String, String>);
 
+/// A static route is either the ID of one account or a ranked list of next hops
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum StaticRoute {
+    Account(String),
+    NextHops(Vec<NextHopDetails>),
+}
+
+#[derive(Deserialize, Debug)]
+struct NextHopDetails {
+    account_id: String,
+    weight: Option<u32>,
+}
+
+impl StaticRoute {
+    fn parse<T: FromStr>(self) -> Result<Vec<StaticNextHop<T>>, Response<()>> {
+        let bad_request = || Response::builder().status(400).body(()).unwrap();
+        let next_hops = match self {
+            StaticRoute::Account(account_id) => vec![NextHopDetails {
+                account_id,
+                weight: None,
+            }],
+            StaticRoute::NextHops(next_hops) => next_hops,
+        };
+        if next_hops.is_empty() {
+            return Err(bad_request());
+        }
+        next_hops
+            .into_iter()
+            .map(|hop| {
+                T::from_str(hop.account_id.as_str())
+                    .map(|account_id| StaticNextHop {
+                        account_id,
+                        weight: hop.weight,
+                    })
+                    .map_err(|_| bad_request())
+            })
+            .collect()
+    }
+}
+
+#[derive(Extract, Debug)]
+struct StaticRoutes(HashMap<String, StaticRoute>);
+
 pub struct SettingsApi<T> {
     store: T,
     admin_api_token: String,
@@ -50,7 +97,7 @@ impl_web! {
         }
 
         fn validate_admin(&self, authorization: String) -> impl Future<Item = T, Error = Response<()>> {
-            if authorization[BEARER_TOKEN_START..] == self.admin_api_token {
+            if is_bearer_token(&authorization, &self.admin_api_token) {
                 ok(self.store.clone())
             } else {
                 error!("Admin API endpoint called with non-admin API key");
@@ -72,11 +119,11 @@ impl_web! {
             debug!("Setting exchange rates: {:?}", body);
             self.validate_admin(authorization)
                 .and_then(move |store| {
-                    store.set_rates(body.0)
+                    store.set_rates(body.0).compat()
                         .and_then(|_| Ok(Success))
                         .map_err(|err| {
-                            error!("Error setting rates: {:?}", err);
-                            Response::builder().status(500).body(()).unwrap()
+                            error!("Error setting rates: {}", err);
+                            Response::builder().status(store_error_status(&err)).body(()).unwrap()
                         })
                 })
         }
@@ -85,7 +132,7 @@ impl_web! {
         #[content_type("application/json")]
         fn get_routes(&self) -> impl Future<Item = Routes, Error = Response<()>> {
             ok(Routes(HashMap::from_iter(self.store.routing_table()
-                .into_iter()
+                .iter()
                 .filter_map(|(address, account)| {
                     if let Ok(address) = str::from_utf8(address.as_ref()) {
                         Some((address.to_string(), account.to_string()))
@@ -97,25 +144,21 @@ impl_web! {
 
         #[put("/routes/static")]
         #[content_type("application/json")]
-        fn post_static_routes(&self, body: Routes, authorization: String) -> impl Future<Item = Success, Error = Response<()>> {
+        fn post_static_routes(&self, body: StaticRoutes, authorization: String) -> impl Future<Item = Success, Error = Response<()>> {
             self.validate_admin(authorization)
                 .and_then(move |store| {
-                    let mut routes: HashMap<String, A::AccountId> = HashMap::with_capacity(body.0.len());
-                    for (prefix, account_id) in body.0 {
-                        if let Ok(account_id) = A::AccountId::from_str(account_id.as_str()) {
-                            routes.insert(prefix, account_id);
-                        } else {
-                            return Err(Response::builder().status(400).body(()).unwrap());
-                        }
+                    let mut routes: HashMap<String, Vec<StaticNextHop<A::AccountId>>> = HashMap::with_capacity(body.0.len());
+                    for (prefix, route) in body.0 {
+                        routes.insert(prefix, route.parse()?);
                     }
                     Ok((store, routes))
                 })
                 .and_then(|(store, routes)| {
-                    store.set_static_routes(routes)
+                    store.set_static_routes(routes).compat()
                     .and_then(|_| Ok(Success))
                         .map_err(|err| {
-                            error!("Error setting static routes: {:?}", err);
-                            Response::builder().status(500).body(()).unwrap()
+                            error!("Error setting static routes: {}", err);
+                            Response::builder().status(store_error_status(&err)).body(()).unwrap()
                         })
             })
         }
@@ -125,18 +168,17 @@ impl_web! {
         fn post_static_route(&self, prefix: String, body: String, authorization: String) -> impl Future<Item = Success, Error = Response<()>> {
             self.validate_admin(authorization)
                 .and_then(move |store| {
-                    if let Ok(account_id) = A::AccountId::from_str(body.as_str()) {
-                        Ok((store, account_id))
-                    } else {
-                        Err(Response::builder().status(400).body(()).unwrap())
-                    }
+                    // The body is either a plain account ID or a JSON route
+                    let route = serde_json::from_str(&body)
+                        .unwrap_or(StaticRoute::Account(body));
+                    Ok((store, route.parse()?))
                 })
-                .and_then(move |(store, account_id)| {
-                    store.set_static_route(prefix, account_id)
+                .and_then(move |(store, next_hops)| {
+                    store.set_static_next_hops(prefix, next_hops).compat()
                     .and_then(|_| Ok(Success))
                         .map_err(|err| {
-                            error!("Error setting static route: {:?}", err);
-                            Response::builder().status(500).body(()).unwrap()
+                            error!("Error setting static route: {}", err);
+                            Response::builder().status(store_error_status(&err)).body(()).unwrap()
                         })
                 })
         }
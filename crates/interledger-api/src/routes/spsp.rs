@@ -1,13 +1,15 @@
+use super::store_error_status;
 use bytes::Bytes;
 use futures::{
     future::{err, result, Either},
     Future,
 };
+use futures03::TryFutureExt;
 use hyper::{Body, Response};
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_ildcp::IldcpAccount;
-use interledger_service::{AccountStore, AuthToken, IncomingService, Username};
-use interledger_spsp::{pay, SpspResponder};
+use interledger_service::{AccountStore, AuthToken, IncomingService, StoreError, Username};
+use interledger_spsp::{pay, pay_destination_amount, quote, SpspResponder};
 use log::{debug, error};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
@@ -16,15 +18,34 @@ use tower_web::{impl_web, Extract, Response};
 #[derive(Extract, Debug)]
 struct SpspPayRequest {
     receiver: String,
-    source_amount: u64,
+    source_amount: Option<u64>,
+    destination_amount: Option<u64>,
+    max_source_amount: Option<u64>,
+    min_exchange_rate: Option<f64>,
 }
 
 #[derive(Response, Debug)]
 #[web(status = "200")]
 struct SpspPayResponse {
+    source_amount: u64,
     delivered_amount: u64,
 }
 
+#[derive(Extract, Debug)]
+struct SpspQuoteRequest {
+    receiver: String,
+    source_amount: u64,
+}
+
+#[derive(Response, Debug)]
+#[web(status = "200")]
+struct SpspQuoteResponse {
+    source_amount: u64,
+    estimated_destination_amount: u64,
+    exchange_rate: f64,
+    max_packet_amount: Option<u64>,
+}
+
 #[derive(Response, Debug)]
 #[web(status = "200")]
 struct SpspQueryResponse {
@@ -61,12 +82,23 @@ impl_web! {
 
         #[post("/pay")]
         #[content_type("application/json")]
-        // TODO add a version that lets you specify the destination amount instead
         fn post_pay(&self, body: SpspPayRequest, authorization: String) -> impl Future<Item = SpspPayResponse, Error = Response<String>> {
             let service = self.incoming_handler.clone();
             let store = self.store.clone();
 
-            result(AuthToken::from_str(&authorization))
+            // Either send a fixed source amount or deliver a fixed destination amount
+            let valid_amounts = match (body.source_amount, body.destination_amount) {
+                (Some(_), None) => true,
+                (None, Some(_)) => body.max_source_amount.is_some() && body.min_exchange_rate.is_some(),
+                _ => false,
+            };
+            if !valid_amounts {
+                let error_msg = "Either source_amount, or destination_amount with max_source_amount and min_exchange_rate, must be given".to_string();
+                error!("{}", error_msg);
+                return Either::A(err(Response::builder().status(400).body(error_msg).unwrap()));
+            }
+
+            Either::B(result(AuthToken::from_str(&authorization))
             .map_err(|err| {
                 let error_msg = format!("Could not convert auth token {:?}", err);
                 error!("{}", error_msg);
@@ -76,15 +108,39 @@ impl_web! {
                 let username = auth.username();
                 let token = auth.password();
                 debug!("Got request to pay: {:?}", body);
-                store.get_account_from_http_auth(&username, &token)
-                .map_err(|_| Response::builder().status(401).body("Unauthorized".to_string()).unwrap())
+                store.get_account_from_http_auth(&username, &token).compat()
+                .map_err(|err| {
+                    let status = match err {
+                        StoreError::NotFound(_) => 401,
+                        ref err => store_error_status(err),
+                    };
+                    Response::builder().status(status).body(err.to_string()).unwrap()
+                })
                 .and_then(move |account| {
-                    pay(service, account, &body.receiver, body.source_amount)
-                        .and_then(|delivered_amount| {
-                            debug!("Sent SPSP payment and delivered: {} of the receiver's units", delivered_amount);
-                            Ok(SpspPayResponse {
+                    let payment = if let Some(source_amount) = body.source_amount {
+                        Either::A(pay(service, account, &body.receiver, source_amount)
+                            .map(move |delivered_amount| SpspPayResponse {
+                                source_amount,
                                 delivered_amount,
-                                })
+                            }))
+                    } else {
+                        Either::B(pay_destination_amount(
+                            service,
+                            account,
+                            &body.receiver,
+                            body.destination_amount.unwrap_or_default(),
+                            body.max_source_amount.unwrap_or_default(),
+                            body.min_exchange_rate.unwrap_or_default(),
+                        )
+                        .map(|delivery| SpspPayResponse {
+                            source_amount: delivery.sent_amount,
+                            delivered_amount: delivery.delivered_amount,
+                        }))
+                    };
+                    payment
+                        .and_then(|response| {
+                            debug!("Sent SPSP payment of {} and delivered: {} of the receiver's units", response.source_amount, response.delivered_amount);
+                            Ok(response)
                             })
                             .map_err(|err| {
                                 error!("Error sending SPSP payment: {:?}", err);
@@ -92,6 +148,51 @@ impl_web! {
                                 Response::builder().status(500).body(format!("Error sending SPSP payment: {:?}", err)).unwrap()
                             })
                     })
+            }))
+        }
+
+        #[post("/quote")]
+        #[content_type("application/json")]
+        fn post_quote(&self, body: SpspQuoteRequest, authorization: String) -> impl Future<Item = SpspQuoteResponse, Error = Response<String>> {
+            let service = self.incoming_handler.clone();
+            let store = self.store.clone();
+
+            result(AuthToken::from_str(&authorization))
+            .map_err(|err| {
+                let error_msg = format!("Could not convert auth token {:?}", err);
+                error!("{}", error_msg);
+                Response::builder().status(500).body(error_msg).unwrap()
+            })
+            .and_then(move |auth| {
+                let username = auth.username();
+                let token = auth.password();
+                debug!("Got request for quote: {:?}", body);
+                store.get_account_from_http_auth(username, token).compat()
+                .map_err(|err| {
+                    let status = match err {
+                        StoreError::NotFound(_) => 401,
+                        ref err => store_error_status(err),
+                    };
+                    Response::builder().status(status).body(err.to_string()).unwrap()
+                })
+                .and_then(move |account| {
+                    let source_amount = body.source_amount;
+                    quote(service, account, &body.receiver, source_amount)
+                        .and_then(move |quote| {
+                            let estimated_destination_amount = (source_amount as f64 * quote.exchange_rate) as u64;
+                            debug!("Got SPSP quote for sending {}: {:?}", source_amount, quote);
+                            Ok(SpspQuoteResponse {
+                                source_amount,
+                                estimated_destination_amount,
+                                exchange_rate: quote.exchange_rate,
+                                max_packet_amount: quote.max_packet_amount,
+                            })
+                        })
+                        .map_err(|err| {
+                            error!("Error getting SPSP quote: {:?}", err);
+                            Response::builder().status(500).body(format!("Error getting SPSP quote: {:?}", err)).unwrap()
+                        })
+                })
             })
         }
 
@@ -105,15 +206,15 @@ impl_web! {
                 Response::builder().status(500).body(()).unwrap()
             })
             .and_then(move |username| {
-            store.get_account_id_from_username(&username)
-            .map_err(move |_| {
-                error!("Error getting account id from username: {}", username);
-                Response::builder().status(500).body(()).unwrap()
+            store.get_account_id_from_username(&username).compat()
+            .map_err(move |err| {
+                error!("Error getting account id from username: {}: {}", username, err);
+                Response::builder().status(store_error_status(&err)).body(()).unwrap()
             })
-            .and_then(move |id| store.get_accounts(vec![id])
-                .map_err(move |_| {
-                    error!("Account not found: {}", id);
-                    Response::builder().status(404).body(()).unwrap()
+            .and_then(move |id| store.get_accounts(vec![id]).compat()
+                .map_err(move |err| {
+                    error!("Error loading account {}: {}", id, err);
+                    Response::builder().status(store_error_status(&err)).body(()).unwrap()
                 }))
                 .and_then(move |accounts| {
                     // TODO return the response without instantiating an SpspResponder (use a simple fn)
@@ -136,7 +237,5 @@ impl_web! {
                 Either::B(err(Response::builder().status(404).body(()).unwrap()))
             }
         }
-
-        // TODO add quoting via SPSP/STREAM
     }
 }
@@ -6,8 +6,8 @@
 //! Because this protocol uses WebSockets, only one party needs to have a publicly-accessible HTTPS
 //! endpoint but both sides can send and receive ILP packets.
 
-use futures::Future;
-use interledger_service::{Account, Username};
+use interledger_service::{Account, StoreError, Username};
+use std::{future::Future, pin::Pin};
 use url::Url;
 
 mod client;
@@ -36,12 +36,12 @@ pub trait BtpStore {
         &self,
         username: &Username,
         token: &str,
-    ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<Self::Account, StoreError>> + Send>>;
 
     /// Load accounts that have a btp_uri configured
     fn get_btp_outgoing_accounts(
         &self,
-    ) -> Box<dyn Future<Item = Vec<Self::Account>, Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Self::Account>, StoreError>> + Send>>;
 }
 
 pub struct BtpOpenSignupAccount<'a> {
@@ -64,13 +64,17 @@ pub trait BtpOpenSignupStore {
     fn create_btp_account<'a>(
         &self,
         account: BtpOpenSignupAccount<'a>,
-    ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<Self::Account, StoreError>> + Send>>;
 }
 
 #[cfg(test)]
 mod client_server {
     use super::*;
-    use futures::future::{err, ok, result};
+    use futures::Future as _;
+    use futures03::{
+        future::{err, ok, ready},
+        FutureExt, TryFutureExt,
+    };
     use interledger_packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
     use interledger_service::*;
     use std::str::FromStr;
@@ -131,7 +135,7 @@ mod client_server {
         fn get_accounts(
             &self,
             account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
-        ) -> Box<dyn Future<Item = Vec<Self::Account>, Error = ()> + Send> {
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<Self::Account>, StoreError>> + Send>> {
             let accounts: Vec<TestAccount> = self
                 .accounts
                 .iter()
@@ -144,9 +148,9 @@ mod client_server {
                 })
                 .collect();
             if accounts.len() == account_ids.len() {
-                Box::new(ok(accounts))
+                Box::pin(ok(accounts))
             } else {
-                Box::new(err(()))
+                Box::pin(err(StoreError::NotFound("Accounts not found".to_string())))
             }
         }
 
@@ -154,8 +158,8 @@ mod client_server {
         fn get_account_id_from_username(
             &self,
             _username: &Username,
-        ) -> Box<dyn Future<Item = u64, Error = ()> + Send> {
-            Box::new(ok(1))
+        ) -> Pin<Box<dyn Future<Output = Result<u64, StoreError>> + Send>> {
+            Box::pin(ok(1))
         }
     }
 
@@ -166,9 +170,9 @@ mod client_server {
             &self,
             username: &Username,
             token: &str,
-        ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send> {
+        ) -> Pin<Box<dyn Future<Output = Result<Self::Account, StoreError>> + Send>> {
             let saved_token = format!("{}:{}", username, token);
-            Box::new(result(
+            Box::pin(ready(
                 self.accounts
                     .iter()
                     .find(|account| {
@@ -179,14 +183,16 @@ mod client_server {
                         }
                     })
                     .cloned()
-                    .ok_or(()),
+                    .ok_or_else(|| {
+                        StoreError::NotFound("No account found for BTP auth".to_string())
+                    }),
             ))
         }
 
         fn get_btp_outgoing_accounts(
             &self,
-        ) -> Box<dyn Future<Item = Vec<TestAccount>, Error = ()> + Send> {
-            Box::new(ok(self
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<TestAccount>, StoreError>> + Send>> {
+            Box::pin(ok(self
                 .accounts
                 .iter()
                 .filter(|account| account.btp_uri.is_some())
@@ -214,7 +220,7 @@ mod client_server {
             "127.0.0.1:12345".parse().unwrap(),
             server_store,
             outgoing_service_fn(move |_| {
-                Err(RejectBuilder {
+                err(RejectBuilder {
                     code: ErrorCode::F02_UNREACHABLE,
                     message: b"No other outgoing handler",
                     triggered_by: Some(&server_address_clone),
@@ -225,7 +231,7 @@ mod client_server {
         )
         .and_then(|btp_server| {
             btp_server.handle_incoming(incoming_service_fn(|_| {
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: b"test data",
                 }
@@ -249,7 +255,7 @@ mod client_server {
             accounts,
             true,
             outgoing_service_fn(move |_| {
-                Err(RejectBuilder {
+                err(RejectBuilder {
                     code: ErrorCode::F02_UNREACHABLE,
                     message: &[],
                     data: &[],
@@ -260,7 +266,7 @@ mod client_server {
         )
         .and_then(move |btp_service| {
             let mut btp_service = btp_service.handle_incoming(incoming_service_fn(move |_| {
-                Err(RejectBuilder {
+                err(RejectBuilder {
                     code: ErrorCode::F02_UNREACHABLE,
                     message: &[],
                     data: &[],
@@ -282,7 +288,10 @@ mod client_server {
                         data: b"test data",
                     }
                     .build(),
+                    extensions: Extensions::new(),
                 })
+                .boxed()
+                .compat()
                 .map_err(|reject| println!("Packet was rejected: {:?}", reject))
                 .and_then(move |_| {
                     btp_service_clone.close();
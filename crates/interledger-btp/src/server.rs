@@ -6,6 +6,7 @@ use futures::{
     future::{err, result, Either},
     Future, Sink, Stream,
 };
+use futures03::TryFutureExt;
 use interledger_ildcp::IldcpResponse;
 use interledger_packet::Address;
 use interledger_service::*;
@@ -176,6 +177,7 @@ where
         result(AuthToken::from_str(&auth.token).map_err(|_| ())).and_then(move |auth_token| {
             store
                 .get_account_from_btp_auth(&auth_token.username(), &auth_token.password())
+                .compat()
                 .map_err(move |_| {
                     warn!("Got BTP connection that does not correspond to an account")
                 })
@@ -218,7 +220,16 @@ where
         result(AuthToken::from_str(&auth.token).map_err(|_| ())).and_then(move |auth_token| {
             store
                 .get_account_from_btp_auth(&auth_token.username(), &auth_token.password())
-                .or_else(move |_| {
+                .compat()
+                .or_else(move |error| {
+                    // Only sign up new accounts if the lookup worked and there was no account
+                    match error {
+                        StoreError::NotFound(_) => {}
+                        error => {
+                            error!("Error getting account from BTP auth: {}", error);
+                            return Either::A(err(()));
+                        }
+                    }
                     let local_part = if let Some(username) = auth.username {
                         username
                     } else {
@@ -244,6 +255,8 @@ where
                                     .expect("Asset code provided is not valid utf8"),
                                 asset_scale: ildcp_info.asset_scale(),
                             })
+                            .compat()
+                            .map_err(|err| error!("Error creating BTP account: {}", err))
                             .and_then(|account| {
                                 debug!("Created new account: {:?}", account);
                                 Ok(account)
@@ -1,11 +1,11 @@
 use super::packet::*;
 use bytes::BytesMut;
 use futures::{
-    future::err,
     sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
     sync::oneshot,
     Future, Sink, Stream,
 };
+use futures03::{compat::Future01CompatExt, future::err, FutureExt, TryFutureExt};
 use interledger_packet::{Address, ErrorCode, Fulfill, Packet, Prepare, Reject, RejectBuilder};
 use interledger_service::*;
 use log::{debug, error, trace, warn};
@@ -222,6 +222,7 @@ where
                 let request = IncomingRequest {
                     from: account,
                     prepare,
+                    extensions: Extensions::new(),
                 };
                 trace!(
                     "Handling incoming request {} from account {}",
@@ -230,6 +231,8 @@ where
                 );
                 incoming_handler_clone
                     .handle_request(request)
+                    .boxed()
+                    .compat()
                     .then(move |result| {
                         let packet = match result {
                             Ok(fulfill) => Packet::Fulfill(fulfill),
@@ -301,7 +304,7 @@ where
                 Ok(_) => {
                     let (sender, receiver) = oneshot::channel();
                     (*self.pending_outgoing.lock()).insert(request_id, sender);
-                    Box::new(
+                    Box::pin(
                         receiver
                             .then(move |result| {
                                 // Drop the trigger here since we've gotten the response
@@ -326,7 +329,8 @@ where
                             .and_then(|result| match result {
                                 Ok(fulfill) => Ok(fulfill),
                                 Err(reject) => Err(reject),
-                            }),
+                            })
+                            .compat(),
                     )
                 }
                 Err(send_error) => {
@@ -341,7 +345,7 @@ where
                         data: &[],
                     }
                     .build();
-                    Box::new(err(reject))
+                    Box::pin(err(reject))
                 }
             }
         } else {
@@ -349,7 +353,7 @@ where
                 "No open connection for account: {}, forwarding request to the next service",
                 request.to.id()
             );
-            Box::new(self.next.send_request(request))
+            Box::pin(self.next.send_request(request))
         }
     }
 }
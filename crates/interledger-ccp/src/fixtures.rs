@@ -26,6 +26,7 @@ lazy_static! {
         speaker: Address::from_str("example.alice").unwrap(),
         new_routes: Vec::new(),
         withdrawn_routes: Vec::new(),
+        auth: None,
     };
 
     pub static ref UPDATE_REQUEST_COMPLEX_SERIALIZED: Vec<u8> = hex::decode("0c8201520000000000000000323031353036313630303031303030303066687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f292511706565722e726f7574652e757064617465820104bffbf6ad0ddc4d3ba1e5b4f0537365bd000000340000002e00000032000075300d6578616d706c652e616c69636501020f6578616d706c652e7072656669783101010f6578616d706c652e707265666978317a6c7d85867c46a2fabfad1afa7a4a5e229ce574fcce63f5edeedfc03f8468ea01000f6578616d706c652e707265666978320102126578616d706c652e636f6e6e6563746f72310f6578616d706c652e707265666978322b08e53fbcc17c5f1bd54ae0d9ad7ba39a5f9a7b126ca9b5c0945609a35324cc01025000000b68656c6c6f20776f726c64e0000104a0a0a0a001020f6578616d706c652e707265666978330f6578616d706c652e70726566697834").unwrap();
@@ -64,7 +65,8 @@ lazy_static! {
         withdrawn_routes: vec![
             Bytes::from("example.prefix3"),
             Bytes::from("example.prefix4"),
-        ]
+        ],
+        auth: None,
     };
 }
 /* kcov-ignore-end */
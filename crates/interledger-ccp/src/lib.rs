@@ -10,21 +10,25 @@
 //! we know about.
 
 use bytes::Bytes;
-use futures::Future;
 use interledger_ildcp::IldcpAccount;
-use interledger_service::Account;
+use interledger_service::{Account, StoreError};
 use std::collections::HashMap;
-use std::{str::FromStr, string::ToString};
+use std::{future::Future, pin::Pin, str::FromStr, string::ToString};
 
 #[cfg(test)]
 mod fixtures;
 mod packet;
+mod policy;
 mod routing_table;
 mod server;
+mod snapshot;
+mod status;
 #[cfg(test)]
 mod test_helpers;
 
+pub use policy::RoutePolicy;
 pub use server::{CcpRouteManager, CcpRouteManagerBuilder};
+pub use status::{BestRouteReason, IncomingTableStatus, PrefixStatus, RouteStatus};
 
 use serde::{Deserialize, Serialize};
 
@@ -73,6 +77,36 @@ pub trait CcpRoutingAccount: Account + IldcpAccount {
     fn should_receive_routes(&self) -> bool {
         false
     }
+
+    /// A secret shared with this account that is used to authenticate the routes we exchange.
+    ///
+    /// If this is set, the `auth` field of every route we send to the account is an HMAC of the
+    /// route and the update's epochs keyed with this secret, and every Route Update Request we
+    /// send it ends with an HMAC of the whole update. Route Update Requests from the account are
+    /// rejected unless the update and all of its routes have a valid one, including updates that
+    /// only withdraw routes. Routes from accounts without a key are trusted as-is.
+    fn route_auth_key(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Rules applied to the routes this account sends us, after our own loop and prefix checks.
+    fn route_import_policy(&self) -> Option<&RoutePolicy> {
+        None
+    }
+
+    /// Rules applied to the routes we send this account.
+    fn route_export_policy(&self) -> Option<&RoutePolicy> {
+        None
+    }
+
+    /// How expensive it is to send packets through this account, for example because of its
+    /// exchange rate spread or settlement costs.
+    ///
+    /// When we have routes for a prefix from accounts with the same routing relation, we prefer
+    /// the one from the account with the lowest cost, before comparing their path lengths.
+    fn route_cost(&self) -> u32 {
+        0
+    }
 }
 
 // key = Bytes, key should be Address -- TODO
@@ -85,18 +119,64 @@ pub trait RouteManagerStore: Clone {
     // TODO should we have a way to only get the details for specific routes?
     fn get_local_and_configured_routes(
         &self,
-    ) -> Box<dyn Future<Item = LocalAndConfiguredRoutes<Self::Account>, Error = ()> + Send>;
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<LocalAndConfiguredRoutes<Self::Account>, StoreError>> + Send,
+        >,
+    >;
 
     fn get_accounts_to_send_routes_to(
         &self,
-    ) -> Box<dyn Future<Item = Vec<Self::Account>, Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Self::Account>, StoreError>> + Send>>;
 
     fn get_accounts_to_receive_routes_from(
         &self,
-    ) -> Box<dyn Future<Item = Vec<Self::Account>, Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Self::Account>, StoreError>> + Send>>;
 
     fn set_routes(
         &mut self,
         routes: impl IntoIterator<Item = (Bytes, Self::Account)>,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>>;
+
+    /// Load the forwarding table the node with the given ID last saved with
+    /// `set_forwarding_table`, if there is one
+    fn get_forwarding_table(
+        &self,
+        node_id: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<Bytes>, StoreError>> + Send>>;
+
+    /// Save the route manager's forwarding table, along with its ID and the log of updates for
+    /// each epoch, so a restarted node can pick up where it left off.
+    /// The data is opaque to the store and replaces whatever the node with the same ID saved
+    /// before. Nodes that share a store have different IDs, so they never load each other's table.
+    fn set_forwarding_table(
+        &mut self,
+        node_id: &str,
+        table: Bytes,
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>>;
+}
+
+/// Operations for inspecting and managing the route manager, used by the node's admin API
+pub trait RouteManagerAdmin<A: Account>: Send + Sync {
+    /// The routing tables we have received from each peer
+    fn incoming_tables(&self) -> Vec<IncomingTableStatus<A::AccountId>>;
+
+    /// The route we use for every prefix we know about, why we picked it, and the other
+    /// routes we could have used instead
+    fn prefixes(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<PrefixStatus<A::AccountId>>, StoreError>> + Send>>;
+
+    /// Use the new settings of an account that was updated, such as its route cost,
+    /// and pick the best routes again for the prefixes it sent us
+    fn account_updated(
+        &self,
+        account: A,
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>>;
+
+    /// Drop the routes we have from the given peer and ask it to send us its whole table again
+    fn resync(
+        &self,
+        account_id: A::AccountId,
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>>;
 }
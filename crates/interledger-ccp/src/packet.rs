@@ -7,6 +7,7 @@ use interledger_packet::{
 };
 use lazy_static::lazy_static;
 use log::error;
+use ring::{digest, hmac};
 use std::{
     convert::TryFrom,
     fmt::{self, Debug},
@@ -25,6 +26,9 @@ const FLAG_OPTIONAL: u8 = 0x80;
 const FLAG_TRANSITIVE: u8 = 0x40;
 const FLAG_PARTIAL: u8 = 0x20;
 const FLAG_UTF8: u8 = 0x10;
+/// The ID of the property that tells the receiving node to use a route without
+/// advertising it to its own peers
+pub const NO_TRANSIT_PROP_ID: u16 = 0x7f00;
 
 lazy_static! {
     pub static ref CCP_RESPONSE: Fulfill = FulfillBuilder {
@@ -190,6 +194,19 @@ impl TryFrom<&mut &[u8]> for RouteProp {
 }
 
 impl RouteProp {
+    /// The optional, non-transitive property that marks a route as one the receiver
+    /// should not pass on
+    pub(crate) fn no_transit() -> Self {
+        RouteProp {
+            is_optional: true,
+            is_transitive: false,
+            is_partial: false,
+            id: NO_TRANSIT_PROP_ID,
+            is_utf8: false,
+            value: Bytes::new(),
+        }
+    }
+
     pub fn write_to<B>(&self, buf: &mut B)
     where
         B: BufMut,
@@ -280,6 +297,11 @@ impl TryFrom<&mut &[u8]> for Route {
 }
 
 impl Route {
+    /// Returns true if the sender marked the route as one we should not advertise to our peers
+    pub(crate) fn is_no_transit(&self) -> bool {
+        self.props.iter().any(|prop| prop.id == NO_TRANSIT_PROP_ID)
+    }
+
     pub fn write_to<B>(&self, buf: &mut B)
     where
         B: BufMut,
@@ -307,6 +329,10 @@ pub struct RouteUpdateRequest {
     pub(crate) speaker: Address,
     pub(crate) new_routes: Vec<Route>,
     pub(crate) withdrawn_routes: Vec<Bytes>,
+    /// HMAC of the rest of the update, keyed with the route auth key shared with the peer.
+    /// This is not part of the RFC's format, so it is only appended to updates for peers that
+    /// we share a key with.
+    pub(crate) auth: Option<[u8; 32]>,
 }
 
 impl Debug for RouteUpdateRequest {
@@ -327,6 +353,7 @@ impl Debug for RouteUpdateRequest {
                     .collect();
                 routes
             })
+            .field("auth", &self.auth.map(hex::encode))
             .finish()
     }
 }
@@ -377,6 +404,14 @@ impl RouteUpdateRequest {
         for _i in 0..withdrawn_routes_len {
             withdrawn_routes.push(Bytes::from(data.read_var_octet_string()?));
         }
+        // Only peers that share a route auth key with us send anything after the withdrawn routes
+        let auth = if data.is_empty() {
+            None
+        } else {
+            let mut auth: [u8; 32] = [0; 32];
+            data.read_exact(&mut auth)?;
+            Some(auth)
+        };
 
         Ok(RouteUpdateRequest {
             routing_table_id,
@@ -387,11 +422,74 @@ impl RouteUpdateRequest {
             speaker,
             new_routes,
             withdrawn_routes,
+            auth,
         })
     }
 
-    pub fn to_prepare(&self) -> Prepare {
-        let mut data = Vec::new();
+    /// Sign the update with the secret we share with the peer it is sent to.
+    ///
+    /// The auth of each new route is set to an HMAC of the route and the routing table and epochs
+    /// of this update, as described in the RFC. The update's own auth is an HMAC of the rest of
+    /// the update, so the epochs, hold down time, speaker and withdrawn routes cannot be modified
+    /// either, even if the update has no new routes.
+    pub(crate) fn sign(&mut self, key: &[u8]) {
+        let key = hmac::SigningKey::new(&digest::SHA256, key);
+        let auths: Vec<hmac::Signature> = self
+            .new_routes
+            .iter()
+            .map(|route| hmac::sign(&key, &self.route_auth_message(route)))
+            .collect();
+        for (route, auth) in self.new_routes.iter_mut().zip(auths) {
+            route.auth.copy_from_slice(auth.as_ref());
+        }
+        let mut message = Vec::new();
+        self.write_to(&mut message);
+        let mut auth: [u8; 32] = [0; 32];
+        auth.copy_from_slice(hmac::sign(&key, &message).as_ref());
+        self.auth = Some(auth);
+    }
+
+    /// Check the auth of the update and of each of its new routes against the secret we share
+    /// with the peer that sent it. Updates without an auth are never valid, even if they do not
+    /// have any new routes.
+    pub(crate) fn verify(&self, key: &[u8]) -> bool {
+        let key = hmac::SigningKey::new(&digest::SHA256, key);
+        let auth = match self.auth {
+            Some(ref auth) => auth,
+            None => return false,
+        };
+        let mut message = Vec::new();
+        self.write_to(&mut message);
+        hmac::verify_with_own_key(&key, &message, auth).is_ok()
+            && self.new_routes.iter().all(|route| {
+                hmac::verify_with_own_key(&key, &self.route_auth_message(route), &route.auth)
+                    .is_ok()
+            })
+    }
+
+    /// The route without its auth, along with the routing table and epochs it was sent for
+    fn route_auth_message(&self, route: &Route) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.put(&self.routing_table_id[..]);
+        message.put_u32_be(self.from_epoch_index);
+        message.put_u32_be(self.to_epoch_index);
+        message.put_var_octet_string(&route.prefix[..]);
+        message.put_var_uint(route.path.len() as u64);
+        for address in route.path.iter() {
+            message.put_var_octet_string(&address[..]);
+        }
+        message.put_var_uint(route.props.len() as u64);
+        for prop in route.props.iter() {
+            prop.write_to(&mut message);
+        }
+        message
+    }
+
+    /// Write the update without its own auth
+    fn write_to<B>(&self, data: &mut B)
+    where
+        B: BufMut,
+    {
         data.put(&self.routing_table_id[..]);
         data.put_u32_be(self.current_epoch_index);
         data.put_u32_be(self.from_epoch_index);
@@ -400,12 +498,20 @@ impl RouteUpdateRequest {
         data.put_var_octet_string(&self.speaker[..]);
         data.put_var_uint(self.new_routes.len() as u64);
         for route in self.new_routes.iter() {
-            route.write_to(&mut data);
+            route.write_to(data);
         }
         data.put_var_uint(self.withdrawn_routes.len() as u64);
         for route in self.withdrawn_routes.iter() {
             data.put_var_octet_string(&route[..]);
         }
+    }
+
+    pub fn to_prepare(&self) -> Prepare {
+        let mut data = Vec::new();
+        self.write_to(&mut data);
+        if let Some(ref auth) = self.auth {
+            data.put(&auth[..]);
+        }
 
         PrepareBuilder {
             destination: CCP_UPDATE_DESTINATION.clone(),
@@ -512,6 +618,46 @@ mod route_update_request {
         assert_eq!(prepare.data(), test_prepare.data());
     }
 
+    #[test]
+    fn signs_route_auth() {
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.sign(b"shared secret");
+        let prepare = request.to_prepare();
+        // The update's auth is appended after the withdrawn routes
+        assert_eq!(
+            prepare.data().len(),
+            UPDATE_REQUEST_COMPLEX.to_prepare().data().len() + 32
+        );
+        let parsed = RouteUpdateRequest::try_from_without_expiry(&prepare).unwrap();
+        assert_eq!(parsed, request);
+        assert!(parsed.verify(b"shared secret"));
+        assert!(!parsed.verify(b"other secret"));
+
+        let mut replayed = parsed.clone();
+        replayed.from_epoch_index += 1;
+        replayed.to_epoch_index += 1;
+        assert!(!replayed.verify(b"shared secret"));
+    }
+
+    #[test]
+    fn signs_updates_without_new_routes() {
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.new_routes = Vec::new();
+        assert!(!request.verify(b"shared secret"));
+        request.sign(b"shared secret");
+        assert!(request.verify(b"shared secret"));
+
+        let mut withdrawal = request.clone();
+        withdrawal
+            .withdrawn_routes
+            .push(Bytes::from("example.other"));
+        assert!(!withdrawal.verify(b"shared secret"));
+
+        let mut heartbeat = request.clone();
+        heartbeat.hold_down_time += 1;
+        assert!(!heartbeat.verify(b"shared secret"));
+    }
+
     #[test]
     fn errors_with_wrong_destination() {
         let prepare = Prepare::try_from(BytesMut::from(hex::decode("0c7e0000000000000000323031353036313630303031303030303066687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f292511706565722e726f7574652e7570646174643221e55f8eabcd4e979ab9bf0ff00a224c000000340000003400000034000075300d6578616d706c652e616c69636501000100").unwrap())).unwrap();
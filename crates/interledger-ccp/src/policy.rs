@@ -0,0 +1,199 @@
+use crate::packet::{Route, RouteProp};
+use bytes::Bytes;
+use log::trace;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str;
+
+/// Rules for the routes we exchange with a single account.
+///
+/// An account's import policy is applied to the routes it sends us before we consider them,
+/// and its export policy is applied to the routes we send it. Prefixes are matched with
+/// `starts_with`, so "example.a" also covers "example.a.b".
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoutePolicy {
+    /// If this is not empty, only routes for prefixes that start with one of these are allowed
+    #[serde(default)]
+    pub allow_prefixes: Vec<String>,
+    /// Routes for prefixes that start with one of these are dropped, even if they are allowed
+    #[serde(default)]
+    pub deny_prefixes: Vec<String>,
+    /// Routes whose paths are longer than this are dropped
+    #[serde(default)]
+    pub max_path_length: Option<usize>,
+    /// Path entries to replace, keyed by the address they replace
+    #[serde(default)]
+    pub rewrite_path: HashMap<String, String>,
+    /// Addresses to add to the start of every route's path (for example, our own address
+    /// repeated a few times to make the routes less attractive)
+    #[serde(default)]
+    pub prepend_path: Vec<String>,
+    /// On import, routes from the account are used to forward packets but not advertised
+    /// to any of our other peers. On export, the routes' properties are marked as
+    /// non-transitive and the no-transit property is added so the account does not pass
+    /// them on
+    #[serde(default)]
+    pub non_transitive: bool,
+}
+
+impl RoutePolicy {
+    /// Returns true if the prefix passes the allow and deny lists
+    pub fn allows_prefix(&self, prefix: &[u8]) -> bool {
+        let matches = |prefixes: &Vec<String>| {
+            prefixes
+                .iter()
+                .any(|allowed| prefix.starts_with(allowed.as_bytes()))
+        };
+        (self.allow_prefixes.is_empty() || matches(&self.allow_prefixes))
+            && !matches(&self.deny_prefixes)
+    }
+
+    /// Apply the policy to a route, returning None if the route should be dropped
+    pub(crate) fn apply(&self, mut route: Route) -> Option<Route> {
+        if !self.allows_prefix(&route.prefix) {
+            trace!("Route policy does not allow route: {:?}", route);
+            return None;
+        }
+
+        for address in route.path.iter_mut() {
+            if let Some(replacement) = self
+                .rewrite_path
+                .get(str::from_utf8(address).unwrap_or_default())
+            {
+                *address = Bytes::from(replacement.as_str());
+            }
+        }
+        if !self.prepend_path.is_empty() {
+            let prepend = self
+                .prepend_path
+                .iter()
+                .map(|address| Bytes::from(address.as_str()));
+            route.path.splice(0..0, prepend);
+        }
+
+        if let Some(max_path_length) = self.max_path_length {
+            if route.path.len() > max_path_length {
+                trace!(
+                    "Dropping route with a path longer than {}: {:?}",
+                    max_path_length,
+                    route
+                );
+                return None;
+            }
+        }
+
+        if self.non_transitive {
+            for prop in route.props.iter_mut() {
+                prop.is_transitive = false;
+            }
+            if !route.is_no_transit() {
+                route.props.push(RouteProp::no_transit());
+            }
+        }
+        Some(route)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::RouteProp;
+
+    fn route(prefix: &str, path: &[&str]) -> Route {
+        Route {
+            prefix: Bytes::from(prefix),
+            path: path.iter().map(|address| Bytes::from(*address)).collect(),
+            auth: [0; 32],
+            props: vec![RouteProp {
+                is_optional: false,
+                is_transitive: true,
+                is_partial: false,
+                id: 1,
+                is_utf8: true,
+                value: Bytes::from("value"),
+            }],
+        }
+    }
+
+    #[test]
+    fn default_policy_allows_everything() {
+        let original = route("example.a", &["example.b"]);
+        assert_eq!(
+            RoutePolicy::default().apply(original.clone()),
+            Some(original)
+        );
+    }
+
+    #[test]
+    fn filters_prefixes() {
+        let policy = RoutePolicy {
+            allow_prefixes: vec!["example.a".to_string()],
+            deny_prefixes: vec!["example.a.secret".to_string()],
+            ..RoutePolicy::default()
+        };
+        assert!(policy.allows_prefix(b"example.a.b"));
+        assert!(!policy.allows_prefix(b"example.b"));
+        assert!(!policy.allows_prefix(b"example.a.secret.c"));
+        assert!(policy.apply(route("example.b", &[])).is_none());
+    }
+
+    #[test]
+    fn rewrites_and_prepends_path() {
+        let policy = RoutePolicy {
+            rewrite_path: vec![("example.old".to_string(), "example.new".to_string())]
+                .into_iter()
+                .collect(),
+            prepend_path: vec!["example.me".to_string(), "example.me".to_string()],
+            ..RoutePolicy::default()
+        };
+        let route = policy
+            .apply(route("example.a", &["example.old", "example.b"]))
+            .unwrap();
+        assert_eq!(
+            route.path,
+            vec![
+                Bytes::from("example.me"),
+                Bytes::from("example.me"),
+                Bytes::from("example.new"),
+                Bytes::from("example.b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_long_paths_after_prepending() {
+        let policy = RoutePolicy {
+            max_path_length: Some(2),
+            prepend_path: vec!["example.me".to_string()],
+            ..RoutePolicy::default()
+        };
+        assert!(policy.apply(route("example.a", &["example.b"])).is_some());
+        assert!(policy
+            .apply(route("example.a", &["example.b", "example.c"]))
+            .is_none());
+    }
+
+    #[test]
+    fn marks_props_non_transitive() {
+        let policy = RoutePolicy {
+            non_transitive: true,
+            ..RoutePolicy::default()
+        };
+        let route = policy.apply(route("example.a", &[])).unwrap();
+        assert!(!route.props[0].is_transitive);
+        assert!(route.is_no_transit());
+    }
+
+    #[test]
+    fn adds_the_no_transit_prop_once() {
+        let policy = RoutePolicy {
+            non_transitive: true,
+            ..RoutePolicy::default()
+        };
+        let mut original = route("example.a", &[]);
+        original.props.clear();
+        let route = policy.apply(original).unwrap();
+        assert_eq!(route.props, vec![RouteProp::no_transit()]);
+        assert_eq!(policy.apply(route.clone()), Some(route));
+    }
+}
@@ -6,6 +6,7 @@ use log::{debug, trace};
 use ring::rand::{SecureRandom, SystemRandom};
 use std::collections::HashMap;
 use std::iter::FromIterator;
+use std::time::{Duration, Instant};
 
 lazy_static! {
     static ref RANDOM: SystemRandom = SystemRandom::new();
@@ -31,6 +32,10 @@ impl<T> PrefixMap<T> {
         self.map.remove(prefix).is_some()
     }
 
+    pub fn get(&self, prefix: &[u8]) -> Option<&T> {
+        self.map.get(prefix)
+    }
+
     pub fn resolve(&self, prefix: &[u8]) -> Option<&T> {
         // TODO use parallel iterator
         self.map
@@ -42,14 +47,18 @@ impl<T> PrefixMap<T> {
 }
 
 /// The routing table is identified by an ID (a UUID in array form) and an "epoch".
-/// When an Interledger node reloads, it will generate a new UUID for its routing table.
+/// When an Interledger node starts without a saved forwarding table, it will generate a new UUID for its routing table.
 /// Each update applied increments the epoch number, so it acts as a version tracker.
 /// This helps peers make sure they are in sync with one another and request updates if not.
+///
+/// The tables for the routes we receive from peers also expire if the peer does not send
+/// another update within the hold down time of the last one.
 #[derive(Debug)]
 pub struct RoutingTable<A> {
     id: [u8; 16],
     epoch: u32,
     prefix_map: PrefixMap<(A, Route)>,
+    expires_at: Option<Instant>,
 }
 
 impl<A> RoutingTable<A>
@@ -61,6 +70,7 @@ where
             id,
             epoch: 0,
             prefix_map: PrefixMap::new(),
+            expires_at: None,
         }
     }
 
@@ -70,7 +80,6 @@ where
         self.epoch = 0;
     }
 
-    #[cfg(test)]
     pub fn set_epoch(&mut self, epoch: u32) {
         self.epoch = epoch;
     }
@@ -83,6 +92,36 @@ where
         self.epoch
     }
 
+    /// Returns true if the peer has not refreshed this table within the hold down time
+    pub fn is_expired(&self, now: Instant) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= now,
+            None => false,
+        }
+    }
+
+    /// Remove all of the routes, returning their prefixes
+    pub fn clear_routes(&mut self) -> Vec<Bytes> {
+        self.prefix_map
+            .map
+            .drain()
+            .map(|(prefix, _)| prefix)
+            .collect()
+    }
+
+    /// Replace the account stored with every route, for example after its settings changed,
+    /// returning the prefixes of the routes
+    pub fn set_account(&mut self, account: A) -> Vec<Bytes> {
+        self.prefix_map
+            .map
+            .iter_mut()
+            .map(|(prefix, (route_account, _))| {
+                *route_account = account.clone();
+                prefix.clone()
+            })
+            .collect()
+    }
+
     pub fn increment_epoch(&mut self) -> u32 {
         let epoch = self.epoch;
         self.epoch += 1;
@@ -111,6 +150,19 @@ where
         self.prefix_map.resolve(prefix)
     }
 
+    /// Get the route for exactly the given prefix, ignoring shorter prefixes that contain it
+    pub fn get_exact_route(&self, prefix: &[u8]) -> Option<&(A, Route)> {
+        self.prefix_map.get(prefix)
+    }
+
+    /// Iterate over all of the routes in the table, along with the accounts they were learned from
+    pub fn routes(&self) -> impl Iterator<Item = (&A, &Route)> {
+        self.prefix_map
+            .map
+            .values()
+            .map(|(account, route)| (account, route))
+    }
+
     pub fn get_simplified_table(&self) -> HashMap<Bytes, A> {
         HashMap::from_iter(
             self.prefix_map
@@ -120,6 +172,14 @@ where
         )
     }
 
+    /// Returns true if this table already includes all of the epochs the update covers.
+    /// Heartbeats, which don't cover any epochs, are never considered applied.
+    pub fn has_applied(&self, request: &RouteUpdateRequest) -> bool {
+        self.id == request.routing_table_id
+            && request.from_epoch_index < request.to_epoch_index
+            && request.to_epoch_index <= self.epoch
+    }
+
     /// Handle a CCP Route Update Request from the peer this table represents
     pub fn handle_update_request(
         &mut self,
@@ -145,6 +205,10 @@ where
             ));
         }
 
+        // Any valid update, including heartbeats and duplicates, shows the peer is still there
+        self.expires_at =
+            Some(Instant::now() + Duration::from_millis(u64::from(request.hold_down_time)));
+
         if request.to_epoch_index <= self.epoch {
             trace!(
                 "Ignoring duplicate routing update for epoch: {}",
@@ -307,6 +371,33 @@ mod table {
         assert_eq!(updated_routes.len(), 0);
     }
 
+    #[test]
+    fn expires_after_hold_down_time() {
+        let mut table = RoutingTable::new(UPDATE_REQUEST_COMPLEX.routing_table_id);
+        assert!(!table.is_expired(Instant::now()));
+
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.from_epoch_index = 0;
+        request.to_epoch_index = 1;
+        request.hold_down_time = 30000;
+        table
+            .handle_update_request(ROUTING_ACCOUNT.clone(), request)
+            .unwrap();
+        assert!(!table.is_expired(Instant::now()));
+        assert!(table.is_expired(Instant::now() + Duration::from_secs(31)));
+
+        let mut prefixes = table.clear_routes();
+        prefixes.sort();
+        assert_eq!(
+            prefixes,
+            vec![
+                Bytes::from("example.prefix1"),
+                Bytes::from("example.prefix2")
+            ]
+        );
+        assert!(table.get_simplified_table().is_empty());
+    }
+
     #[test]
     fn converts_to_a_simplified_table() {
         let mut table = RoutingTable::new([0; 16]);
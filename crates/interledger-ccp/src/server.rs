@@ -6,28 +6,33 @@ use crate::{
         CCP_RESPONSE, CCP_UPDATE_DESTINATION,
     },
     routing_table::RoutingTable,
-    CcpRoutingAccount, RouteManagerStore,
+    snapshot::ForwardingTableSnapshot,
+    status::{BestRouteReason, IncomingTableStatus, PrefixStatus, RouteStatus},
+    CcpRoutingAccount, RouteManagerAdmin, RouteManagerStore,
 };
 use bytes::Bytes;
 use futures::{
-    future::{err, join_all, ok, Either},
+    future::{err, join_all, loop_fn, ok, Either, Loop},
     Future, Stream,
 };
+use futures03::{compat::Future01CompatExt, Future as Future03, TryFutureExt};
 #[cfg(test)]
 use interledger_packet::PrepareBuilder;
-use interledger_packet::{Address, ErrorCode, Fulfill, Reject, RejectBuilder};
+use interledger_packet::{Address, ErrorClass, ErrorCode, Fulfill, Reject, RejectBuilder};
 use interledger_service::{
-    Account, BoxedIlpFuture, IncomingRequest, IncomingService, OutgoingRequest, OutgoingService,
+    compat::BoxedIlpFuture01, Account, BoxedIlpFuture, Extensions, IncomingRequest,
+    IncomingService, OutgoingRequest, OutgoingService, StoreError,
 };
 #[cfg(test)]
 use lazy_static::lazy_static;
 use log::{debug, error, trace, warn};
 use parking_lot::{Mutex, RwLock};
 use ring::digest::{digest, SHA256};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::{
     cmp::min,
     convert::TryFrom,
+    pin::Pin,
     str,
     sync::Arc,
     time::{Duration, Instant},
@@ -38,6 +43,10 @@ use tokio_timer::Interval;
 const DEFAULT_ROUTE_EXPIRY_TIME: u32 = 45000;
 const DEFAULT_BROADCAST_INTERVAL: u64 = 30000;
 const DUMMY_ROUTING_TABLE_ID: [u8; 16] = [0; 16];
+/// How long to wait before sending to a peer again after its first failed Route Update Request
+/// (in milliseconds). The delay doubles with every further failure
+const INITIAL_RETRY_DELAY: u64 = 1000;
+const MAX_RETRY_DELAY: u64 = 300_000;
 
 fn hash(preimage: &[u8; 32]) -> [u8; 32] {
     let mut out = [0; 32];
@@ -45,7 +54,68 @@ fn hash(preimage: &[u8; 32]) -> [u8; 32] {
     out
 }
 
-type NewAndWithdrawnRoutes = (Vec<Route>, Vec<Bytes>);
+/// The prefixes of a restored forwarding table, and when to check that we still have routes for them
+type RestoredPrefixes = (Instant, Vec<Bytes>);
+
+/// The state of the route updates we send to one peer.
+#[derive(Clone, Debug, PartialEq)]
+struct PeerState {
+    /// Whether the peer wants route updates from us. Peers start out in Sync mode so that
+    /// they get our routes even if their Route Control Request did not reach us
+    mode: Mode,
+    /// The last epoch of our forwarding table the peer has acknowledged
+    last_acked_epoch: u32,
+    /// How many Route Update Requests in a row the peer has not accepted
+    failures: u32,
+    /// Set while the peer is failing. We do not send it updates before this time
+    retry_at: Option<Instant>,
+}
+
+impl Default for PeerState {
+    fn default() -> Self {
+        PeerState {
+            mode: Mode::Sync,
+            last_acked_epoch: 0,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+}
+
+impl PeerState {
+    fn is_backing_off(&self, now: Instant) -> bool {
+        match self.retry_at {
+            Some(retry_at) => retry_at > now,
+            None => false,
+        }
+    }
+
+    fn record_ack(&mut self, epoch: u32) {
+        self.last_acked_epoch = epoch;
+        self.failures = 0;
+        self.retry_at = None;
+    }
+
+    fn record_failure(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+        // Double the delay with each failure, without overflowing once it reaches the max
+        let delay = INITIAL_RETRY_DELAY.saturating_mul(2u64.saturating_pow(self.failures - 1));
+        self.retry_at = Some(Instant::now() + Duration::from_millis(min(delay, MAX_RETRY_DELAY)));
+    }
+}
+
+/// Writes the snapshots of the forwarding table to the store one at a time, so that a slow write
+/// of an older snapshot can never overwrite a newer one.
+#[derive(Debug, Default)]
+struct SnapshotWriter {
+    /// The epoch and bytes of the latest snapshot that has not been written yet
+    pending: Option<(u32, Bytes)>,
+    /// Whether a write is in progress. The write picks up the pending snapshot when it is done
+    writing: bool,
+    /// The last epoch that was written to the store. Peers are not sent any later epochs, so
+    /// that the table we restore after a restart is never behind what they have seen
+    saved_epoch: u32,
+}
 
 pub struct CcpRouteManagerBuilder<I, O, S> {
     /// The next request handler that will be used both to pass on requests that are not CCP messages.
@@ -59,6 +129,7 @@ pub struct CcpRouteManagerBuilder<I, O, S> {
     store: S,
     ilp_address: Address,
     global_prefix: Bytes,
+    node_id: Option<String>,
     spawn_tasks: bool,
     broadcast_interval: u64,
 }
@@ -74,6 +145,7 @@ where
         CcpRouteManagerBuilder {
             ilp_address,
             global_prefix: Bytes::from_static(b"g."),
+            node_id: None,
             next_incoming,
             outgoing,
             store,
@@ -93,6 +165,13 @@ where
         self
     }
 
+    /// Set the ID the forwarding table is saved under in the store, so that nodes sharing
+    /// a store each restore their own table. Defaults to the node's ILP address
+    pub fn node_id(&mut self, node_id: String) -> &mut Self {
+        self.node_id = Some(node_id);
+        self
+    }
+
     pub fn disable_spawn(&mut self) -> &mut Self {
         self.spawn_tasks = false;
         self
@@ -105,16 +184,24 @@ where
     }
 
     pub fn to_service(&self) -> CcpRouteManager<I, O, S, A> {
+        let node_id = self.node_id.clone().unwrap_or_else(|| {
+            str::from_utf8(self.ilp_address.as_ref())
+                .unwrap_or_default()
+                .to_string()
+        });
         let service = CcpRouteManager {
             ilp_address: self.ilp_address.clone(),
             global_prefix: self.global_prefix.clone(),
+            node_id,
             next_incoming: self.next_incoming.clone(),
             outgoing: self.outgoing.clone(),
             store: self.store.clone(),
             spawn_tasks: self.spawn_tasks,
             forwarding_table: Arc::new(RwLock::new(RoutingTable::default())),
-            forwarding_table_updates: Arc::new(RwLock::new(Vec::new())),
-            last_epoch_updates_sent_for: Arc::new(Mutex::new(0)),
+            forwarding_table_changes: Arc::new(RwLock::new(HashMap::new())),
+            restored_prefixes: Arc::new(RwLock::new(None)),
+            snapshot_writer: Arc::new(Mutex::new(SnapshotWriter::default())),
+            peers: Arc::new(RwLock::new(HashMap::new())),
             local_table: Arc::new(RwLock::new(RoutingTable::default())),
             incoming_tables: Arc::new(RwLock::new(HashMap::new())),
         };
@@ -137,6 +224,8 @@ where
 pub struct CcpRouteManager<I, O, S, A: Account> {
     ilp_address: Address,
     global_prefix: Bytes,
+    /// The ID the forwarding table is saved under in the store
+    node_id: String,
     /// The next request handler that will be used both to pass on requests that are not CCP messages.
     next_incoming: I,
     /// The outgoing request handler that will be used to send outgoing CCP messages.
@@ -145,10 +234,20 @@ pub struct CcpRouteManager<I, O, S, A: Account> {
     outgoing: O,
     /// This represents the routing table we will forward to our peers.
     /// It is the same as the local_table with our own address added to the path of each route.
-    forwarding_table: Arc<RwLock<RoutingTable<A>>>,
-    last_epoch_updates_sent_for: Arc<Mutex<u32>>,
-    /// These updates are stored such that index 0 is the transition from epoch 0 to epoch 1
-    forwarding_table_updates: Arc<RwLock<Vec<NewAndWithdrawnRoutes>>>,
+    /// It only keeps the IDs of the accounts the routes came from so that it can be saved to
+    /// the store and restored when the node restarts.
+    forwarding_table: Arc<RwLock<RoutingTable<A::AccountId>>>,
+    /// The mode and progress of each peer we send route updates to
+    peers: Arc<RwLock<HashMap<A::AccountId, PeerState>>>,
+    /// The epoch in which the forwarding route for each prefix was last added, changed or withdrawn.
+    /// Withdrawn prefixes are kept until every peer has acknowledged the epoch they were withdrawn in,
+    /// but otherwise only the current state is stored rather than a log of every update.
+    forwarding_table_changes: Arc<RwLock<HashMap<Bytes, u32>>>,
+    /// The prefixes in the forwarding table we restored from the store, and when to withdraw
+    /// the ones that no peer has sent us a route for since then
+    restored_prefixes: Arc<RwLock<Option<RestoredPrefixes>>>,
+    /// Saves the forwarding table to the store and tracks which epoch was saved last
+    snapshot_writer: Arc<Mutex<SnapshotWriter>>,
     /// This is the routing table we have compile from configuration and
     /// broadcasts we have received from our peers. It is saved to the Store so that
     /// the Router services forwards packets according to what it says.
@@ -175,27 +274,154 @@ where
     S: RouteManagerStore<Account = A> + Clone + Send + Sync + 'static,
     A: CcpRoutingAccount + Send + Sync + 'static,
 {
-    /// Returns a future that will trigger this service to update its routes and broadcast
-    /// updates to peers on the given interval.
+    /// Returns a future that will restore the forwarding table saved in the store and then
+    /// trigger this service to update its routes and broadcast updates to peers on the given interval.
     pub fn start_broadcast_interval(&self, interval: u64) -> impl Future<Item = (), Error = ()> {
         let clone = self.clone();
-        self.request_all_routes().and_then(move |_| {
-            Interval::new(Instant::now(), Duration::from_millis(interval))
-                .map_err(|err| error!("Interval error, no longer sending route updates: {:?}", err))
-                .for_each(move |_| {
-                    clone.broadcast_routes().then(|_| {
-                        // Returning an error would end the broadcast loop
-                        // so we want to return Ok even if there was an error
-                        Ok(())
+        let clone2 = self.clone();
+        self.load_forwarding_table()
+            .and_then(move |_| clone2.request_all_routes())
+            .and_then(move |_| {
+                Interval::new(Instant::now(), Duration::from_millis(interval))
+                    .map_err(|err| {
+                        error!("Interval error, no longer sending route updates: {:?}", err)
                     })
-                })
-        })
+                    .for_each(move |_| {
+                        clone.broadcast_routes().then(|_| {
+                            // Returning an error would end the broadcast loop
+                            // so we want to return Ok even if there was an error
+                            Ok(())
+                        })
+                    })
+            })
     }
 
     pub fn broadcast_routes(&self) -> impl Future<Item = (), Error = ()> {
         let clone = self.clone();
-        self.update_best_routes(None)
-            .and_then(move |_| clone.send_route_updates())
+        let clone2 = self.clone();
+        self.expire_routes()
+            .and_then(move |_| clone.update_best_routes(None))
+            // Retry saving the table if the last attempt failed, because until it is saved
+            // peers do not get the latest epochs
+            .and_then(move |_| clone2.save_forwarding_table().map(move |_| clone2))
+            .and_then(|clone| clone.send_route_updates())
+    }
+
+    /// Restore the forwarding table we saved to the store before the node restarted, so that we
+    /// keep advertising the same routing table ID and peers only need the updates since the
+    /// epoch they last saw. This is safe because peers are only sent epochs that were already saved,
+    /// so none of them can have seen a later epoch of this table than the one we restore.
+    /// If the table has already changed since we started, the saved one is ignored.
+    fn load_forwarding_table(&self) -> impl Future<Item = (), Error = ()> {
+        let forwarding_table = self.forwarding_table.clone();
+        let forwarding_table_changes = self.forwarding_table_changes.clone();
+        let restored_prefixes = self.restored_prefixes.clone();
+        let snapshot_writer = self.snapshot_writer.clone();
+        self.store
+            .get_forwarding_table(&self.node_id)
+            .compat()
+            .then(move |result| {
+                let snapshot = match result {
+                    Ok(Some(bytes)) => {
+                        match ForwardingTableSnapshot::<A::AccountId>::from_bytes(&bytes[..]) {
+                            Ok(snapshot) => snapshot,
+                            Err(err) => {
+                                warn!(
+                                    "Saved forwarding table is invalid, starting a new one: {:?}",
+                                    err
+                                );
+                                return Ok(());
+                            }
+                        }
+                    }
+                    Ok(None) => return Ok(()),
+                    Err(err) => {
+                        warn!(
+                            "Error loading the saved forwarding table, starting a new one: {}",
+                            err
+                        );
+                        return Ok(());
+                    }
+                };
+
+                let mut forwarding_table = forwarding_table.write();
+                let mut forwarding_table_changes = forwarding_table_changes.write();
+                if forwarding_table.epoch() > 0 {
+                    debug!("Forwarding table was already updated, ignoring the saved one");
+                    return Ok(());
+                }
+
+                let mut table = RoutingTable::new(snapshot.id);
+                table.set_epoch(snapshot.epoch);
+                let mut prefixes = Vec::with_capacity(snapshot.routes.len());
+                for (account_id, route) in snapshot.routes {
+                    prefixes.push(route.prefix.clone());
+                    table.add_route(account_id, route);
+                }
+                debug!(
+                    "Restored forwarding table {} at epoch {} with {} routes",
+                    hex::encode(&snapshot.id[..]),
+                    table.epoch(),
+                    prefixes.len()
+                );
+                snapshot_writer.lock().saved_epoch = table.epoch();
+                *forwarding_table = table;
+                *forwarding_table_changes = snapshot.changes.into_iter().collect();
+                // Give our peers until they would have expired our routes to send us theirs again
+                *restored_prefixes.write() = Some((
+                    Instant::now() + Duration::from_millis(u64::from(DEFAULT_ROUTE_EXPIRY_TIME)),
+                    prefixes,
+                ));
+                Ok(())
+            })
+    }
+
+    /// Remove the routes from peers that have not sent us a Route Update Request within the
+    /// hold down time of their last one, and recalculate the best routes for those prefixes.
+    /// Prefixes we no longer have any route for are withdrawn in our next update to our peers.
+    ///
+    /// Routes we restored into the forwarding table are also checked once the peers have had
+    /// enough time to send them to us again, so the ones nobody sent are withdrawn.
+    fn expire_routes(&self) -> impl Future<Item = (), Error = ()> {
+        let now = Instant::now();
+        let restored_prefixes = {
+            let mut restored_prefixes = self.restored_prefixes.write();
+            match *restored_prefixes {
+                Some((check_at, _)) if check_at <= now => {
+                    restored_prefixes.take().map(|(_, prefixes)| prefixes)
+                }
+                _ => None,
+            }
+        };
+        let expired_prefixes: Vec<Bytes> = {
+            let mut incoming_tables = self.incoming_tables.write();
+            let expired_accounts: Vec<A::AccountId> = incoming_tables
+                .iter()
+                .filter(|(_, table)| table.is_expired(now))
+                .map(|(account_id, _)| *account_id)
+                .collect();
+            expired_accounts
+                .into_iter()
+                .flat_map(|account_id| {
+                    warn!(
+                        "Routes from account {} expired because it has not sent a route update within the hold down time",
+                        account_id
+                    );
+                    // Drop the whole table so that the next update from the peer needs to start from scratch
+                    incoming_tables
+                        .remove(&account_id)
+                        .map(|mut table| table.clear_routes())
+                        .unwrap_or_default()
+                })
+                .chain(restored_prefixes.unwrap_or_default())
+                .collect()
+        };
+
+        if expired_prefixes.is_empty() {
+            Either::A(ok(()))
+        } else {
+            Either::B(self.update_best_routes(Some(expired_prefixes)))
+        }
     }
 
     /// Request routes from all the peers we are willing to receive routes from.
@@ -204,6 +430,7 @@ where
         let clone = self.clone();
         self.store
             .get_accounts_to_receive_routes_from()
+            .compat()
             .then(|result| {
                 let accounts = result.unwrap_or_else(|_| Vec::new());
                 join_all(accounts.into_iter().map(move |account| {
@@ -214,7 +441,8 @@ where
     }
 
     /// Handle a CCP Route Control Request. If this is from an account that we broadcast routes to,
-    /// we'll send an outgoing Route Update Request to them.
+    /// we'll switch it to the mode it asked for. In Sync mode we send it an outgoing Route Update
+    /// Request right away, while in Idle mode we stop sending it updates until it asks again.
     fn handle_route_control_request(
         &self,
         request: IncomingRequest<A>,
@@ -246,11 +474,11 @@ where
             control
         );
 
-        // TODO stop sending updates if they are in Idle mode
         if control.mode == Mode::Sync {
             let (from_epoch_index, to_epoch_index) = {
                 let forwarding_table = self.forwarding_table.read();
-                let to_epoch_index = forwarding_table.epoch();
+                // Only send the epochs we would restore if the node restarted
+                let to_epoch_index = self.snapshot_writer.lock().saved_epoch;
                 let from_epoch_index =
                     if control.last_known_routing_table_id != forwarding_table.id() {
                         0
@@ -259,6 +487,13 @@ where
                     };
                 (from_epoch_index, to_epoch_index)
             };
+            {
+                // The peer tells us what it already has, so start again from there
+                let mut peers = self.peers.write();
+                let peer = peers.entry(request.from.id()).or_default();
+                peer.mode = Mode::Sync;
+                peer.record_ack(from_epoch_index);
+            }
 
             let ilp_address = self.ilp_address.clone();
             if !self.spawn_tasks {
@@ -282,13 +517,25 @@ where
                     to_epoch_index,
                 ));
             }
+        } else {
+            debug!(
+                "Account {} switched to Idle mode, no longer sending it route updates",
+                request.from.id()
+            );
+            self.peers
+                .write()
+                .entry(request.from.id())
+                .or_default()
+                .mode = Mode::Idle;
         }
 
         Either::A(ok(CCP_RESPONSE.clone()))
     }
 
-    /// Remove invalid routes before processing the Route Update Request
-    fn filter_routes(&self, mut update: RouteUpdateRequest) -> RouteUpdateRequest {
+    /// Remove invalid routes before processing the Route Update Request and apply the
+    /// import policy of the account that sent it
+    fn filter_routes(&self, account: &A, mut update: RouteUpdateRequest) -> RouteUpdateRequest {
+        let policy = account.route_import_policy();
         update.new_routes = update
             .new_routes
             .into_iter()
@@ -312,6 +559,10 @@ where
                     true
                 }
             })
+            .filter_map(|route| match policy {
+                Some(policy) => policy.apply(route),
+                None => Some(route),
+            })
             .collect();
         update
     }
@@ -320,7 +571,7 @@ where
     /// If updates are applied to the Incoming Routing Table for this peer, we will
     /// then check whether those routes are better than the current best ones we have in the
     /// Local Routing Table.
-    fn handle_route_update_request(&self, request: IncomingRequest<A>) -> BoxedIlpFuture {
+    fn handle_route_update_request(&self, request: IncomingRequest<A>) -> BoxedIlpFuture01 {
         // Ignore the request if we don't accept routes from them
         if !request.from.should_receive_routes() {
             return Box::new(err(RejectBuilder {
@@ -349,8 +600,24 @@ where
             update
         );
 
+        if let Some(key) = request.from.route_auth_key() {
+            if !update.verify(key) {
+                warn!(
+                    "Rejecting route update request from account {} because it does not have a valid auth",
+                    request.from.id(),
+                );
+                return Box::new(err(RejectBuilder {
+                    code: ErrorCode::F00_BAD_REQUEST,
+                    message: b"Invalid auth for route update request",
+                    triggered_by: Some(&self.ilp_address),
+                    data: &[],
+                }
+                .build()));
+            }
+        }
+
         // Filter out routes that don't make sense or that we won't accept
-        let update = self.filter_routes(update);
+        let update = self.filter_routes(&request.from, update);
 
         let mut incoming_tables = self.incoming_tables.write();
         if !&incoming_tables.contains_key(&request.from.id()) {
@@ -363,11 +630,23 @@ where
         // Update the routing table we maintain for the account we got this from.
         // Figure out whether we need to update our routes for any of the prefixes
         // that were included in this route update.
-        match (*incoming_tables)
+        let table = (*incoming_tables)
             .get_mut(&request.from.id())
-            .expect("Should have inserted a routing table for this account")
-            .handle_update_request(request.from.clone(), update)
-        {
+            .expect("Should have inserted a routing table for this account");
+        // The peer sends the same epochs again if our response to its last update got lost,
+        // so we acknowledge them again. The update is not applied, because it may also be a
+        // replay of routes the peer has since withdrawn
+        if table.has_applied(&update) {
+            debug!(
+                "Ignoring route update for epochs {} to {} of routing table {} from account {} because it was already applied",
+                update.from_epoch_index,
+                update.to_epoch_index,
+                hex::encode(&update.routing_table_id[..]),
+                request.from.id(),
+            );
+            return Box::new(ok(CCP_RESPONSE.clone()));
+        }
+        match table.handle_update_request(request.from.clone(), update) {
             Ok(prefixes_updated) => {
                 if prefixes_updated.is_empty() {
                     trace!("Route update request did not contain any prefixes we need to update our routes for");
@@ -444,28 +723,27 @@ where
         };
         debug!("Sending Route Control Request to account: {}, last known table id: {}, last known epoch: {}", account_id, hex::encode(&last_known_routing_table_id[..]), last_known_epoch);
         let prepare = control.to_prepare();
-        self.clone()
-            .outgoing
-            .send_request(OutgoingRequest {
-                // TODO If we start charging or paying for CCP broadcasts we'll need to
-                // have a separate account that we send from, but for now it's fine to
-                // set the peer's account as the from account as well as the to account
-                from: account.clone(),
-                to: account,
-                original_amount: prepare.amount(),
-                prepare,
-            })
-            .then(move |result| {
-                if let Err(err) = result {
-                    warn!(
-                        "Error sending Route Control Request to account {}: {:?}",
-                        account_id, err
-                    )
-                } else {
-                    trace!("Sent Route Control Request to account: {}", account_id);
-                }
-                Ok(())
-            })
+        let send_request = self.clone().outgoing.send_request(OutgoingRequest {
+            // TODO If we start charging or paying for CCP broadcasts we'll need to
+            // have a separate account that we send from, but for now it's fine to
+            // set the peer's account as the from account as well as the to account
+            from: account.clone(),
+            to: account,
+            original_amount: prepare.amount(),
+            prepare,
+            extensions: Extensions::new(),
+        });
+        Box::pin(send_request).compat().then(move |result| {
+            if let Err(err) = result {
+                warn!(
+                    "Error sending Route Control Request to account {}: {:?}",
+                    account_id, err
+                )
+            } else {
+                trace!("Sent Route Control Request to account: {}", account_id);
+            }
+            Ok(())
+        })
     }
 
     /// Check whether the Local Routing Table currently has the best routes for the
@@ -479,14 +757,18 @@ where
     ) -> impl Future<Item = (), Error = ()> + 'static {
         let local_table = self.local_table.clone();
         let forwarding_table = self.forwarding_table.clone();
-        let forwarding_table_updates = self.forwarding_table_updates.clone();
+        let forwarding_table_changes = self.forwarding_table_changes.clone();
         let incoming_tables = self.incoming_tables.clone();
         let ilp_address = self.ilp_address.clone();
         let global_prefix = self.global_prefix.clone();
         let mut store = self.store.clone();
+        let clone = self.clone();
 
-        self.store.get_local_and_configured_routes().and_then(
-            move |(ref local_routes, ref configured_routes)| {
+        self.store
+            .get_local_and_configured_routes()
+            .compat()
+            .map_err(|err| error!("Error getting the local and configured routes: {}", err))
+            .and_then(move |(ref local_routes, ref configured_routes)| {
                 let (better_routes, withdrawn_routes) = {
                     // Note we only use a read lock here and later get a write lock if we need to update the table
                     let local_table = local_table.read();
@@ -506,17 +788,17 @@ where
                     let mut withdrawn_routes: Vec<Bytes> = Vec::new();
                     for prefix in prefixes_to_check {
                         // See which prefixes there is now a better route for
-                        if let Some((best_next_account, best_route)) = get_best_route_for_prefix(
+                        if let Some((best_next_account, best_route, _reason)) = get_best_route_for_prefix(
                             local_routes,
                             configured_routes,
                             &incoming_tables,
                             prefix.as_ref(),
                         ) {
-                            if let Some((ref next_account, ref route)) = local_table.get_route(&prefix) {
+                            if let Some((ref next_account, _)) = local_table.get_route(&prefix) {
                                 if next_account.id() == best_next_account.id() {
                                     continue
                                 } else {
-                                    better_routes.push((prefix.clone(), next_account.clone(), route.clone()));
+                                    better_routes.push((prefix.clone(), best_next_account, best_route));
                                 }
                             } else {
                                 better_routes.push((prefix.clone(), best_next_account, best_route));
@@ -533,9 +815,10 @@ where
                 if !better_routes.is_empty() || !withdrawn_routes.is_empty() {
                     let mut local_table = local_table.write();
                     let mut forwarding_table = forwarding_table.write();
-                    let mut forwarding_table_updates = forwarding_table_updates.write();
+                    let mut forwarding_table_changes = forwarding_table_changes.write();
 
-                    let mut new_routes: Vec<Route> = Vec::with_capacity(better_routes.len());
+                    let mut new_routes: Vec<Bytes> = Vec::with_capacity(better_routes.len());
+                    let mut forwarding_withdrawn_routes: Vec<Bytes> = Vec::new();
 
                     for (prefix, account, mut route) in better_routes {
                         debug!(
@@ -554,16 +837,26 @@ where
                             // prefix will make sure we get packets sent to them
                             && !(route.prefix.starts_with(ilp_address.as_ref()) && route.path.is_empty())
                             // Don't include routes we're also withdrawing
-                            && !withdrawn_routes.contains(&prefix) {
+                            && !withdrawn_routes.contains(&prefix)
+                            // Don't pass on routes the account's import policy or the
+                            // sender marks as non-transitive
+                            && is_transitive(&account)
+                            && !route.is_no_transit() {
 
                                 let old_route = forwarding_table.get_route(&prefix);
-                                if old_route.is_none() || old_route.unwrap().0.id() != account.id() {
+                                if old_route.is_none() || old_route.unwrap().0 != account.id() {
                                     route.path.insert(0, ilp_address.to_bytes());
                                     // Each hop hashes the auth before forwarding
                                     route.auth = hash(&route.auth);
-                                    forwarding_table.set_route(prefix.clone(), account.clone(), route.clone());
-                                    new_routes.push(route);
+                                    // Non-transitive properties are only meant for us
+                                    route.props.retain(|prop| prop.is_transitive);
+                                    forwarding_table.set_route(prefix.clone(), account.id(), route);
+                                    new_routes.push(prefix.clone());
                                 }
+                        } else if forwarding_table.delete_route(&prefix) && !withdrawn_routes.contains(&prefix) {
+                            // We advertised a different route for this prefix before, so peers
+                            // need to know that we no longer have one for them
+                            forwarding_withdrawn_routes.push(prefix.clone());
                         }
                     }
 
@@ -573,70 +866,159 @@ where
                         forwarding_table.delete_route(prefix);
                     }
 
-                    let epoch = forwarding_table.increment_epoch();
-                    forwarding_table_updates.push((new_routes, withdrawn_routes));
-                    debug_assert_eq!(epoch as usize + 1, forwarding_table_updates.len());
+                    // Changes are recorded with the epoch they lead to, so an update from epoch
+                    // N includes the prefixes that changed in any epoch after N
+                    let epoch = forwarding_table.increment_epoch() + 1;
+                    for prefix in new_routes.into_iter().chain(forwarding_withdrawn_routes).chain(withdrawn_routes) {
+                        forwarding_table_changes.insert(prefix, epoch);
+                    }
 
-                    Either::A(store.set_routes(local_table.get_simplified_table()))
+                    let set_routes = store
+                        .set_routes(local_table.get_simplified_table())
+                        .compat()
+                        .map_err(|err| error!("Error updating the best routes: {}", err));
+                    // The snapshot is taken once the locks are released
+                    Either::A(set_routes.and_then(move |_| clone.save_forwarding_table()))
                 } else {
                     // The routing table hasn't changed
                     Either::B(ok(()))
                 }
-            },
-        )
+            })
     }
 
-    /// Send RouteUpdateRequests to all peers that we send routing messages to
-    fn send_route_updates(&self) -> impl Future<Item = (), Error = ()> {
-        let mut outgoing = self.outgoing.clone();
-        let to_epoch_index = self.forwarding_table.read().epoch();
-
-        let from_epoch_index: u32 = {
-            let mut lock = self.last_epoch_updates_sent_for.lock();
-            let epoch = *lock;
-            *lock = to_epoch_index;
-            epoch
-        };
+    /// Save the forwarding table to the store, unless its current epoch was already saved.
+    ///
+    /// Only one write is made at a time. If one is in progress, the snapshot is left for it to
+    /// write once it is done and this returns right away. Errors are logged and the save is
+    /// retried on the next broadcast.
+    fn save_forwarding_table(&self) -> impl Future<Item = (), Error = ()> {
+        {
+            // Lock the tables before the writer, in the same order as load_forwarding_table
+            let forwarding_table = self.forwarding_table.read();
+            let forwarding_table_changes = self.forwarding_table_changes.read();
+            let mut writer = self.snapshot_writer.lock();
+            let epoch = forwarding_table.epoch();
+            let newer_than_pending = match writer.pending {
+                Some((pending_epoch, _)) => epoch > pending_epoch,
+                None => true,
+            };
+            if epoch > writer.saved_epoch && newer_than_pending {
+                let snapshot = ForwardingTableSnapshot {
+                    id: forwarding_table.id(),
+                    epoch,
+                    routes: forwarding_table
+                        .routes()
+                        .map(|(account_id, route)| (*account_id, route.clone()))
+                        .collect(),
+                    changes: forwarding_table_changes
+                        .iter()
+                        .map(|(prefix, epoch)| (prefix.clone(), *epoch))
+                        .collect(),
+                };
+                writer.pending = Some((epoch, snapshot.to_bytes()));
+            }
+            if writer.writing || writer.pending.is_none() {
+                return Either::A(ok(()));
+            }
+            writer.writing = true;
+        }
 
-        let route_update_request = self.create_route_update(from_epoch_index, to_epoch_index);
-        debug!(
-            "Sending route udpates for epochs {} - {}: {:?}",
-            from_epoch_index, to_epoch_index, route_update_request,
-        );
+        let snapshot_writer = self.snapshot_writer.clone();
+        let store = self.store.clone();
+        let node_id = self.node_id.clone();
+        Either::B(loop_fn((), move |_| {
+            let pending = {
+                let mut writer = snapshot_writer.lock();
+                let pending = writer.pending.take();
+                writer.writing = pending.is_some();
+                pending
+            };
+            let (epoch, bytes) = match pending {
+                Some(pending) => pending,
+                None => return Either::A(ok(Loop::Break(()))),
+            };
+            let snapshot_writer = snapshot_writer.clone();
+            Either::B(
+                store
+                    .clone()
+                    .set_forwarding_table(&node_id, bytes)
+                    .compat()
+                    .then(move |result| {
+                        let mut writer = snapshot_writer.lock();
+                        match result {
+                            Ok(_) => {
+                                trace!("Saved forwarding table at epoch {}", epoch);
+                                writer.saved_epoch = epoch;
+                            }
+                            Err(err) => error!(
+                                "Error saving the forwarding table at epoch {}: {}",
+                                epoch, err
+                            ),
+                        }
+                        Ok(Loop::Continue(()))
+                    }),
+            )
+        }))
+    }
 
-        let prepare = route_update_request.to_prepare();
+    /// Send RouteUpdateRequests to all peers that we send routing messages to.
+    /// Each peer in Sync mode gets the changes since the last epoch it acknowledged up to the last
+    /// epoch saved to the store, while peers in Idle mode and ones we are backing off from after
+    /// failed updates are skipped.
+    /// The state of peers we no longer send routes to (for example, deleted accounts) is dropped.
+    fn send_route_updates(&self) -> impl Future<Item = (), Error = ()> {
+        let to_epoch_index = self.snapshot_writer.lock().saved_epoch;
+        let clone = self.clone();
         self.store
             .get_accounts_to_send_routes_to()
+            .compat()
+            .map_err(|err| error!("Error getting accounts to send routes to: {}", err))
             .and_then(move |mut accounts| {
                 accounts.sort_unstable_by_key(|a| a.id().to_string());
                 accounts.dedup_by_key(|a| a.id());
 
-                let broadcasting = !accounts.is_empty();
-                if broadcasting {
+                let (accounts, acked_epoch) = {
+                    let mut peers = clone.peers.write();
+                    peers.retain(|id, _| accounts.iter().any(|account| account.id() == *id));
+                    // The last epoch every peer has acknowledged, including the idle ones
+                    let acked_epoch = accounts
+                        .iter()
+                        .map(|account| peers.get(&account.id()).map(|peer| peer.last_acked_epoch).unwrap_or(0))
+                        .min()
+                        .unwrap_or(to_epoch_index);
+                    let now = Instant::now();
+                    let accounts: Vec<(A, u32)> = accounts
+                        .into_iter()
+                        .filter_map(|account| match peers.get(&account.id()) {
+                            Some(peer) if peer.mode == Mode::Idle => {
+                                trace!("Not sending route updates to account {} because it is in Idle mode", account.id());
+                                None
+                            }
+                            Some(peer) if peer.is_backing_off(now) => {
+                                trace!("Not sending route updates to account {} because the last {} failed", account.id(), peer.failures);
+                                None
+                            }
+                            Some(peer) => Some((account, peer.last_acked_epoch)),
+                            None => Some((account, 0)),
+                        })
+                        .collect();
+                    (accounts, acked_epoch)
+                };
+                clone.prune_withdrawn_routes(acked_epoch);
+
+                if !accounts.is_empty() {
                     debug!("Sending route updates to accounts: {}", {
                         let account_list: Vec<String> = accounts
                             .iter()
-                            .map(|a| format!("{} ({})", a.id(), a.client_address()))
+                            .map(|(a, epoch)| {
+                                format!("{} ({}, from epoch {})", a.id(), a.client_address(), epoch)
+                            })
                             .collect();
                         account_list.join(", ")
                     });
                     Either::A(
-                        join_all(accounts.into_iter().map(move |account| {
-                            let account_id = account.id();
-                            outgoing
-                                .send_request(OutgoingRequest {
-                                    from: account.clone(),
-                                    to: account,
-                                    original_amount: prepare.amount(),
-                                    prepare: prepare.clone(),
-                                })
-                                .map_err(move |err| {
-                                    warn!(
-                                        "Error sending route update to account {}: {:?}",
-                                        account_id, err
-                                    )
-                                })
-                                .then(|_| Ok(()))
+                        join_all(accounts.into_iter().map(move |(account, from_epoch_index)| {
+                            clone.send_route_update(account, from_epoch_index, to_epoch_index)
                         }))
                         .and_then(|_| {
                             trace!("Finished sending route updates");
@@ -650,54 +1032,68 @@ where
             })
     }
 
-    /// Create a RouteUpdateRequest representing the given range of Forwarding Routing Table epochs.
+    /// Forget the prefixes that were withdrawn in or before the given epoch, once every peer
+    /// has acknowledged it. Prefixes we still have a route for stay in the forwarding table.
+    fn prune_withdrawn_routes(&self, acked_epoch: u32) {
+        let forwarding_table = self.forwarding_table.read();
+        self.forwarding_table_changes
+            .write()
+            .retain(|prefix, epoch| {
+                *epoch > acked_epoch || forwarding_table.get_exact_route(prefix).is_some()
+            });
+    }
+
+    /// Create a RouteUpdateRequest for the given account representing the given range of
+    /// Forwarding Routing Table epochs, with the account's export policy applied.
     /// If the epoch range is not specified, it will create an update for the last epoch only.
     fn create_route_update(
         &self,
+        account: &A,
         from_epoch_index: u32,
         to_epoch_index: u32,
     ) -> RouteUpdateRequest {
-        let (start, end) = (from_epoch_index as usize, to_epoch_index as usize);
-        let (routing_table_id, current_epoch_index) = {
+        let (routing_table_id, current_epoch_index, mut new_routes, mut withdrawn_routes) = {
             let table = self.forwarding_table.read();
-            (table.id(), table.epoch())
-        };
-        let forwarding_table_updates = self.forwarding_table_updates.read();
-        let epochs_to_take = end.saturating_sub(start);
-
-        // Merge the new routes and withdrawn routes from all of the given epochs
-        let mut new_routes: Vec<Route> = Vec::with_capacity(epochs_to_take);
-        let mut withdrawn_routes: Vec<Bytes> = Vec::new();
-        // Iterate through each of the given epochs
-        for (new, withdrawn) in forwarding_table_updates
-            .iter()
-            .skip(start)
-            .take(epochs_to_take)
-        {
-            for new_route in new {
-                new_routes.push(new_route.clone());
-                // If the route was previously withdrawn, ignore that now since it was added back
-                if withdrawn_routes.contains(&new_route.prefix) {
-                    withdrawn_routes = withdrawn_routes
-                        .into_iter()
-                        .filter(|prefix| prefix != &new_route.prefix)
-                        .collect();
+            let forwarding_table_changes = self.forwarding_table_changes.read();
+
+            // Take every prefix that changed in the given epochs, in the order they changed
+            let mut changed: Vec<(u32, &Bytes)> = forwarding_table_changes
+                .iter()
+                .filter(|(_, epoch)| from_epoch_index < **epoch && **epoch <= to_epoch_index)
+                .map(|(prefix, epoch)| (*epoch, prefix))
+                .collect();
+            changed.sort_unstable();
+
+            // The table only has the latest route for each prefix, so a prefix that was
+            // withdrawn and then added back is sent as a new route and vice versa
+            let mut new_routes: Vec<Route> = Vec::with_capacity(changed.len());
+            let mut withdrawn_routes: Vec<Bytes> = Vec::new();
+            for (_epoch, prefix) in changed {
+                if let Some((_, route)) = table.get_exact_route(prefix) {
+                    new_routes.push(route.clone());
+                } else {
+                    withdrawn_routes.push(prefix.clone());
                 }
             }
+            (table.id(), table.epoch(), new_routes, withdrawn_routes)
+        };
 
-            for withdrawn_route in withdrawn {
-                withdrawn_routes.push(withdrawn_route.clone());
-                // If the route was previously added, ignore that since it was withdrawn later
-                if new_routes
-                    .iter()
-                    .any(|route| route.prefix == withdrawn_route)
-                {
-                    new_routes = new_routes
-                        .into_iter()
-                        .filter(|route| route.prefix != withdrawn_route)
-                        .collect();
-                }
-            }
+        if let Some(policy) = account.route_export_policy() {
+            // Don't reveal the prefixes the account is not allowed to see either
+            withdrawn_routes.retain(|prefix| policy.allows_prefix(prefix));
+            new_routes = new_routes
+                .into_iter()
+                .filter_map(|route| {
+                    let prefix = route.prefix.clone();
+                    let route = policy.apply(route);
+                    // The route may now be dropped for another reason, such as its path
+                    // getting too long, so the account has to remove the one we sent before
+                    if route.is_none() && policy.allows_prefix(&prefix) {
+                        withdrawn_routes.push(prefix);
+                    }
+                    route
+                })
+                .collect();
         }
 
         RouteUpdateRequest {
@@ -705,47 +1101,68 @@ where
             from_epoch_index,
             to_epoch_index,
             current_epoch_index,
-            new_routes: new_routes.clone(),
-            withdrawn_routes: withdrawn_routes.clone(),
+            new_routes,
+            withdrawn_routes,
             speaker: self.ilp_address.clone(),
             hold_down_time: DEFAULT_ROUTE_EXPIRY_TIME,
+            auth: None,
         }
     }
 
-    /// Send a Route Update Request to a specific account for the given epoch range.
-    /// This is used when the peer has fallen behind and has requested a specific range of updates.
+    /// Send a Route Update Request to a specific account for the given epoch range and
+    /// record whether the peer acknowledged it.
     fn send_route_update(
         &self,
         account: A,
         from_epoch_index: u32,
         to_epoch_index: u32,
     ) -> impl Future<Item = (), Error = ()> {
-        let prepare = self
-            .create_route_update(from_epoch_index, to_epoch_index)
-            .to_prepare();
+        let mut update = self.create_route_update(&account, from_epoch_index, to_epoch_index);
+        if let Some(key) = account.route_auth_key() {
+            update.sign(key);
+        }
+        let prepare = update.to_prepare();
         let account_id = account.id();
         debug!(
             "Sending individual route update to account: {} for epochs from: {} to: {}",
             account_id, from_epoch_index, to_epoch_index
         );
-        self.outgoing
-            .clone()
-            .send_request(OutgoingRequest {
-                from: account.clone(),
-                to: account,
-                original_amount: prepare.amount(),
-                prepare,
-            })
-            .and_then(|_| Ok(()))
-            .then(move |result| {
-                if let Err(err) = result {
-                    error!(
-                        "Error sending route update to account {}: {:?}",
-                        account_id, err
+        let send_request = self.outgoing.clone().send_request(OutgoingRequest {
+            from: account.clone(),
+            to: account,
+            original_amount: prepare.amount(),
+            prepare,
+            extensions: Extensions::new(),
+        });
+        let peers = self.peers.clone();
+        Box::pin(send_request).compat().then(move |result| {
+            let mut peers = peers.write();
+            let peer = peers.entry(account_id).or_default();
+            match result {
+                Ok(_) => peer.record_ack(to_epoch_index),
+                // The peer is there but did not accept the update. If it is missing epochs it
+                // asks for them with a Route Control Request, so backing off would only delay that
+                Err(ref err) if err.code().class() == ErrorClass::Final => warn!(
+                    "Account {} rejected route update for epochs from: {} to: {}: {:?}",
+                    account_id, from_epoch_index, to_epoch_index, err
+                ),
+                Err(err) => {
+                    peer.record_failure();
+                    warn!(
+                        "Error sending route update to account {} ({} failures in a row): {:?}",
+                        account_id, peer.failures, err
                     )
                 }
-                Ok(())
-            })
+            }
+            Ok(())
+        })
+    }
+}
+
+fn is_transitive<A: CcpRoutingAccount>(account: &A) -> bool {
+    match account.route_import_policy() {
+        Some(policy) => !policy.non_transitive,
+        None => true,
     }
 }
 
@@ -754,7 +1171,7 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
     configured_routes: &HashMap<Bytes, A>,
     incoming_tables: &HashMap<A::AccountId, RoutingTable<A>>,
     prefix: &[u8],
-) -> Option<(A, Route)> {
+) -> Option<(A, Route, BestRouteReason)> {
     // Check if we have a configured route for that specific prefix
     // or any shorter prefix ("example.a.b.c" will match "example.a.b" and "example.a")
     // Note that this logic is duplicated from the Address type. We are not using
@@ -772,6 +1189,7 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
                     path: Vec::new(),
                     props: Vec::new(),
                 },
+                BestRouteReason::Configured,
             ));
         }
     }
@@ -785,6 +1203,7 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
                 path: Vec::new(),
                 props: Vec::new(),
             },
+            BestRouteReason::Local,
         ));
     }
 
@@ -802,6 +1221,13 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
                     return (account, route);
                 }
 
+                // Prioritize the cheapest account
+                if best_account.route_cost() < account.route_cost() {
+                    return (best_account, best_route);
+                } else if best_account.route_cost() > account.route_cost() {
+                    return (account, route);
+                }
+
                 // Prioritize shortest path
                 if best_route.path.len() < route.path.len() {
                     return (best_account, best_route);
@@ -817,7 +1243,26 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
                 }
             },
         );
-        Some((best_account.clone(), best_route.clone()))
+
+        // Find the weakest tie breaker the best route needed to beat every other candidate
+        let reason = incoming_tables
+            .values()
+            .filter_map(|incoming_table| incoming_table.get_route(prefix))
+            .filter(|(account, _)| account.id() != best_account.id())
+            .map(|(account, route)| {
+                if account.routing_relation() != best_account.routing_relation() {
+                    BestRouteReason::RoutingRelation
+                } else if account.route_cost() != best_account.route_cost() {
+                    BestRouteReason::RouteCost
+                } else if route.path.len() != best_route.path.len() {
+                    BestRouteReason::ShortestPath
+                } else {
+                    BestRouteReason::AccountId
+                }
+            })
+            .max()
+            .unwrap_or(BestRouteReason::OnlyRoute);
+        Some((best_account.clone(), best_route.clone(), reason))
     } else {
         None
     }
@@ -837,15 +1282,157 @@ where
     fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
         let destination = request.prepare.destination();
         if destination == *CCP_CONTROL_DESTINATION {
-            Box::new(self.handle_route_control_request(request))
+            Box::pin(self.handle_route_control_request(request).compat())
         } else if destination == *CCP_UPDATE_DESTINATION {
-            Box::new(self.handle_route_update_request(request))
+            Box::pin(self.handle_route_update_request(request).compat())
         } else {
-            Box::new(self.next_incoming.handle_request(request))
+            Box::pin(self.next_incoming.handle_request(request))
         }
     }
 }
 
+impl<I, O, S, A> RouteManagerAdmin<A> for CcpRouteManager<I, O, S, A>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    O: OutgoingService<A> + Clone + Send + Sync + 'static,
+    S: RouteManagerStore<Account = A> + Clone + Send + Sync + 'static,
+    A: CcpRoutingAccount + Send + Sync + 'static,
+{
+    fn incoming_tables(&self) -> Vec<IncomingTableStatus<A::AccountId>> {
+        let mut tables: Vec<IncomingTableStatus<A::AccountId>> = self
+            .incoming_tables
+            .read()
+            .iter()
+            .map(|(account_id, table)| {
+                let mut routes: Vec<RouteStatus<A::AccountId>> = table
+                    .routes()
+                    .map(|(_, route)| RouteStatus::new(*account_id, route))
+                    .collect();
+                routes.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+                IncomingTableStatus {
+                    account_id: *account_id,
+                    routing_table_id: hex::encode(&table.id()[..]),
+                    epoch: table.epoch(),
+                    routes,
+                }
+            })
+            .collect();
+        tables.sort_by_key(|table| table.account_id.to_string());
+        tables
+    }
+
+    fn prefixes(
+        &self,
+    ) -> Pin<Box<dyn Future03<Output = Result<Vec<PrefixStatus<A::AccountId>>, StoreError>> + Send>>
+    {
+        let incoming_tables = self.incoming_tables.clone();
+        Box::pin(self.store.get_local_and_configured_routes().map_ok(
+            move |(local_routes, configured_routes)| {
+                let incoming_tables = incoming_tables.read();
+                let prefixes: BTreeSet<Bytes> =
+                    configured_routes
+                        .keys()
+                        .chain(local_routes.keys())
+                        .cloned()
+                        .chain(incoming_tables.values().flat_map(|table| {
+                            table.routes().map(|(_, route)| route.prefix.clone())
+                        }))
+                        .collect();
+
+                prefixes
+                    .into_iter()
+                    .filter_map(|prefix| {
+                        let (account, route, reason) = get_best_route_for_prefix(
+                            &local_routes,
+                            &configured_routes,
+                            &incoming_tables,
+                            &prefix,
+                        )?;
+                        let mut candidates: Vec<RouteStatus<A::AccountId>> = incoming_tables
+                            .iter()
+                            .filter_map(|(account_id, table)| {
+                                table
+                                    .get_route(&prefix)
+                                    .map(|(_, route)| RouteStatus::new(*account_id, route))
+                            })
+                            .collect();
+                        candidates.sort_by_key(|candidate| candidate.account_id.to_string());
+                        let best = RouteStatus::new(account.id(), &route);
+                        Some(PrefixStatus {
+                            prefix: str::from_utf8(&prefix).unwrap_or("<not utf8>").to_string(),
+                            account_id: best.account_id,
+                            path: best.path,
+                            reason,
+                            candidates,
+                        })
+                    })
+                    .collect()
+            },
+        ))
+    }
+
+    fn account_updated(
+        &self,
+        account: A,
+    ) -> Pin<Box<dyn Future03<Output = Result<(), StoreError>> + Send>> {
+        // The incoming tables keep a copy of the account that sent the routes, which
+        // is where get_best_route_for_prefix reads the route cost and relation from
+        let prefixes = self
+            .incoming_tables
+            .write()
+            .get_mut(&account.id())
+            .map(|table| table.set_account(account))
+            .unwrap_or_default();
+        let clone = self.clone();
+        Box::pin(async move {
+            if !prefixes.is_empty() {
+                // Errors are logged by update_best_routes
+                let _ = clone.update_best_routes(Some(prefixes)).compat().await;
+            }
+            Ok(())
+        })
+    }
+
+    fn resync(
+        &self,
+        account_id: A::AccountId,
+    ) -> Pin<Box<dyn Future03<Output = Result<(), StoreError>> + Send>> {
+        let clone = self.clone();
+        let accounts = self.store.get_accounts_to_receive_routes_from();
+        Box::pin(async move {
+            let account = accounts
+                .await?
+                .into_iter()
+                .find(|account| account.id() == account_id)
+                .ok_or_else(|| {
+                    StoreError::NotFound(format!(
+                        "No account with ID {} that we receive routes from",
+                        account_id
+                    ))
+                })?;
+
+            debug!("Re-syncing routes with account: {}", account_id);
+            // Drop the whole table, like when it expires, so the peer's next update is applied
+            // from scratch instead of being ignored as a duplicate of epochs we have already seen
+            let prefixes = clone
+                .incoming_tables
+                .write()
+                .remove(&account_id)
+                .map(|mut table| table.clear_routes())
+                .unwrap_or_default();
+            if !prefixes.is_empty() {
+                // Errors are logged by update_best_routes and should not stop us from asking for new routes
+                let _ = clone.update_best_routes(Some(prefixes)).compat().await;
+            }
+            let _ = clone
+                .send_route_control_request(account, DUMMY_ROUTING_TABLE_ID, 0)
+                .compat()
+                .await;
+            Ok(())
+        })
+    }
+}
+
 #[cfg(test)]
 mod ranking_routes {
     use super::*;
@@ -967,6 +1554,104 @@ mod ranking_routes {
         assert_eq!(best_route.unwrap().0.id(), 7);
     }
 
+    fn incoming_with_costs(
+        accounts: Vec<(TestAccount, usize)>,
+    ) -> HashMap<u64, RoutingTable<TestAccount>> {
+        HashMap::from_iter(accounts.into_iter().map(|(account, path_length)| {
+            let mut table = RoutingTable::default();
+            table.add_route(
+                account.clone(),
+                Route {
+                    prefix: Bytes::from("example.f"),
+                    path: vec![Bytes::from("example.one"); path_length],
+                    auth: [0; 32],
+                    props: Vec::new(),
+                },
+            );
+            (account.id(), table)
+        }))
+    }
+
+    #[test]
+    fn prioritizes_cheaper_accounts() {
+        let incoming = incoming_with_costs(vec![
+            (
+                TestAccount {
+                    route_cost: 10,
+                    ..TestAccount::new(9, "example.expensive")
+                },
+                1,
+            ),
+            (
+                TestAccount {
+                    route_cost: 2,
+                    ..TestAccount::new(10, "example.cheap")
+                },
+                3,
+            ),
+        ]);
+        let (account, _route, reason) =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &incoming, b"example.f").unwrap();
+        assert_eq!(account.id(), 10);
+        assert_eq!(reason, BestRouteReason::RouteCost);
+    }
+
+    #[test]
+    fn prioritizes_relation_over_cost() {
+        let incoming = incoming_with_costs(vec![
+            (
+                TestAccount {
+                    route_cost: 10,
+                    relation: RoutingRelation::Child,
+                    ..TestAccount::new(9, "example.child")
+                },
+                1,
+            ),
+            (TestAccount::new(10, "example.peer"), 1),
+        ]);
+        let (account, _route, reason) =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &incoming, b"example.f").unwrap();
+        assert_eq!(account.id(), 9);
+        assert_eq!(reason, BestRouteReason::RoutingRelation);
+    }
+
+    #[test]
+    fn prioritizes_shorter_paths_between_equal_costs() {
+        let incoming = incoming_with_costs(vec![
+            (
+                TestAccount {
+                    route_cost: 5,
+                    ..TestAccount::new(9, "example.long")
+                },
+                2,
+            ),
+            (
+                TestAccount {
+                    route_cost: 5,
+                    ..TestAccount::new(10, "example.short")
+                },
+                1,
+            ),
+        ]);
+        let (account, _route, reason) =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &incoming, b"example.f").unwrap();
+        assert_eq!(account.id(), 10);
+        assert_eq!(reason, BestRouteReason::ShortestPath);
+    }
+
+    #[test]
+    fn explains_why_routes_were_chosen() {
+        let reason = |prefix: &[u8]| {
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, prefix)
+                .unwrap()
+                .2
+        };
+        assert_eq!(reason(b"example.a.sub-prefix"), BestRouteReason::Configured);
+        assert_eq!(reason(b"example.c"), BestRouteReason::Local);
+        assert_eq!(reason(b"example.d"), BestRouteReason::RoutingRelation);
+        assert_eq!(reason(b"example.e"), BestRouteReason::ShortestPath);
+    }
+
     #[test]
     fn returns_none_for_no_route() {
         let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.z");
@@ -979,28 +1664,30 @@ mod handle_route_control_request {
     use super::*;
     use crate::fixtures::*;
     use crate::test_helpers::*;
+    use futures03::executor::block_on;
     use std::time::{Duration, SystemTime};
 
     #[test]
     fn handles_valid_request() {
-        test_service_with_routes()
-            .0
-            .handle_request(IncomingRequest {
-                prepare: CONTROL_REQUEST.to_prepare(),
-                from: ROUTING_ACCOUNT.clone(),
-            })
-            .wait()
-            .unwrap();
+        block_on(
+            test_service_with_routes()
+                .0
+                .handle_request(IncomingRequest {
+                    prepare: CONTROL_REQUEST.to_prepare(),
+                    from: ROUTING_ACCOUNT.clone(),
+                    extensions: Extensions::new(),
+                }),
+        )
+        .unwrap();
     }
 
     #[test]
     fn rejects_from_non_sending_account() {
-        let result = test_service()
-            .handle_request(IncomingRequest {
-                prepare: CONTROL_REQUEST.to_prepare(),
-                from: NON_ROUTING_ACCOUNT.clone(),
-            })
-            .wait();
+        let result = block_on(test_service().handle_request(IncomingRequest {
+            prepare: CONTROL_REQUEST.to_prepare(),
+            from: NON_ROUTING_ACCOUNT.clone(),
+            extensions: Extensions::new(),
+        }));
         assert!(result.is_err());
         assert_eq!(
             str::from_utf8(result.unwrap_err().message()).unwrap(),
@@ -1010,8 +1697,8 @@ mod handle_route_control_request {
 
     #[test]
     fn rejects_invalid_packet() {
-        let result = test_service()
-            .handle_request(IncomingRequest {
+        let result = block_on(
+            test_service().handle_request(IncomingRequest {
                 prepare: PrepareBuilder {
                     destination: CCP_CONTROL_DESTINATION.clone(),
                     amount: 0,
@@ -1021,8 +1708,9 @@ mod handle_route_control_request {
                 }
                 .build(),
                 from: ROUTING_ACCOUNT.clone(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
         assert!(result.is_err());
         assert_eq!(
             str::from_utf8(result.unwrap_err().message()).unwrap(),
@@ -1035,8 +1723,8 @@ mod handle_route_control_request {
         let (mut service, outgoing_requests) = test_service_with_routes();
         (*service.forwarding_table.write()).set_id([0; 16]);
         service.update_best_routes(None).wait().unwrap();
-        service
-            .handle_request(IncomingRequest {
+        block_on(
+            service.handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: RouteControlRequest {
                     last_known_routing_table_id: [0; 16],
@@ -1045,9 +1733,10 @@ mod handle_route_control_request {
                     features: Vec::new(),
                 }
                 .to_prepare(),
-            })
-            .wait()
-            .unwrap();
+                extensions: Extensions::new(),
+            }),
+        )
+        .unwrap();
         let request: &OutgoingRequest<TestAccount> = &outgoing_requests.lock()[0];
         assert_eq!(request.to.id(), ROUTING_ACCOUNT.id());
         let update = RouteUpdateRequest::try_from(&request.prepare).unwrap();
@@ -1059,22 +1748,66 @@ mod handle_route_control_request {
     }
 
     #[test]
-    fn sends_whole_table_if_id_is_different() {
+    fn switches_peer_between_idle_and_sync() {
         let (mut service, outgoing_requests) = test_service_with_routes();
         service.update_best_routes(None).wait().unwrap();
-        service
-            .handle_request(IncomingRequest {
+        let routing_table_id = service.forwarding_table.read().id();
+
+        block_on(
+            service.handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: RouteControlRequest {
-                    last_known_routing_table_id: [0; 16],
+                    last_known_routing_table_id: routing_table_id,
+                    mode: Mode::Idle,
+                    last_known_epoch: 0,
+                    features: Vec::new(),
+                }
+                .to_prepare(),
+                extensions: Extensions::new(),
+            }),
+        )
+        .unwrap();
+        assert!(outgoing_requests.lock().is_empty());
+        assert_eq!(service.peers.read()[&ROUTING_ACCOUNT.id()].mode, Mode::Idle);
+
+        block_on(
+            service.handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: RouteControlRequest {
+                    last_known_routing_table_id: routing_table_id,
+                    mode: Mode::Sync,
+                    last_known_epoch: 0,
+                    features: Vec::new(),
+                }
+                .to_prepare(),
+                extensions: Extensions::new(),
+            }),
+        )
+        .unwrap();
+        assert_eq!(outgoing_requests.lock().len(), 1);
+        let peer = service.peers.read()[&ROUTING_ACCOUNT.id()].clone();
+        assert_eq!(peer.mode, Mode::Sync);
+        assert_eq!(peer.last_acked_epoch, 1);
+    }
+
+    #[test]
+    fn sends_whole_table_if_id_is_different() {
+        let (mut service, outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        block_on(
+            service.handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: RouteControlRequest {
+                    last_known_routing_table_id: [0; 16],
                     mode: Mode::Sync,
                     last_known_epoch: 32,
                     features: Vec::new(),
                 }
                 .to_prepare(),
-            })
-            .wait()
-            .unwrap();
+                extensions: Extensions::new(),
+            }),
+        )
+        .unwrap();
         let routing_table_id = service.forwarding_table.read().id();
         let request: &OutgoingRequest<TestAccount> = &outgoing_requests.lock()[0];
         assert_eq!(request.to.id(), ROUTING_ACCOUNT.id());
@@ -1092,6 +1825,8 @@ mod handle_route_update_request {
     use super::*;
     use crate::fixtures::*;
     use crate::test_helpers::*;
+    use crate::RoutePolicy;
+    use futures03::executor::block_on;
     use std::{
         iter::FromIterator,
         time::{Duration, SystemTime},
@@ -1104,23 +1839,21 @@ mod handle_route_update_request {
         update.to_epoch_index = 1;
         update.from_epoch_index = 0;
 
-        service
-            .handle_request(IncomingRequest {
-                prepare: update.to_prepare(),
-                from: ROUTING_ACCOUNT.clone(),
-            })
-            .wait()
-            .unwrap();
+        block_on(service.handle_request(IncomingRequest {
+            prepare: update.to_prepare(),
+            from: ROUTING_ACCOUNT.clone(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
     }
 
     #[test]
     fn rejects_from_non_receiving_account() {
-        let result = test_service()
-            .handle_request(IncomingRequest {
-                prepare: UPDATE_REQUEST_SIMPLE.to_prepare(),
-                from: NON_ROUTING_ACCOUNT.clone(),
-            })
-            .wait();
+        let result = block_on(test_service().handle_request(IncomingRequest {
+            prepare: UPDATE_REQUEST_SIMPLE.to_prepare(),
+            from: NON_ROUTING_ACCOUNT.clone(),
+            extensions: Extensions::new(),
+        }));
         assert!(result.is_err());
         assert_eq!(
             str::from_utf8(result.unwrap_err().message()).unwrap(),
@@ -1130,8 +1863,8 @@ mod handle_route_update_request {
 
     #[test]
     fn rejects_invalid_packet() {
-        let result = test_service()
-            .handle_request(IncomingRequest {
+        let result = block_on(
+            test_service().handle_request(IncomingRequest {
                 prepare: PrepareBuilder {
                     destination: CCP_UPDATE_DESTINATION.clone(),
                     amount: 0,
@@ -1141,8 +1874,9 @@ mod handle_route_update_request {
                 }
                 .build(),
                 from: ROUTING_ACCOUNT.clone(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
         assert!(result.is_err());
         assert_eq!(
             str::from_utf8(result.unwrap_err().message()).unwrap(),
@@ -1157,13 +1891,12 @@ mod handle_route_update_request {
         update.to_epoch_index = 1;
         update.from_epoch_index = 0;
 
-        service
-            .handle_request(IncomingRequest {
-                prepare: update.to_prepare(),
-                from: ROUTING_ACCOUNT.clone(),
-            })
-            .wait()
-            .unwrap();
+        block_on(service.handle_request(IncomingRequest {
+            prepare: update.to_prepare(),
+            from: ROUTING_ACCOUNT.clone(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
         assert_eq!(service.incoming_tables.read().len(), 1);
     }
 
@@ -1183,7 +1916,7 @@ mod handle_route_update_request {
             auth: [0; 32],
             props: Vec::new(),
         });
-        let request = service.filter_routes(request);
+        let request = service.filter_routes(&ROUTING_ACCOUNT, request);
         assert_eq!(request.new_routes.len(), 1);
         assert_eq!(request.new_routes[0].prefix, Bytes::from("example.valid"));
     }
@@ -1204,7 +1937,7 @@ mod handle_route_update_request {
             auth: [0; 32],
             props: Vec::new(),
         });
-        let request = service.filter_routes(request);
+        let request = service.filter_routes(&ROUTING_ACCOUNT, request);
         assert_eq!(request.new_routes.len(), 1);
         assert_eq!(request.new_routes[0].prefix, Bytes::from("example.valid"));
     }
@@ -1229,11 +1962,276 @@ mod handle_route_update_request {
             auth: [0; 32],
             props: Vec::new(),
         });
-        let request = service.filter_routes(request);
+        let request = service.filter_routes(&ROUTING_ACCOUNT, request);
         assert_eq!(request.new_routes.len(), 1);
         assert_eq!(request.new_routes[0].prefix, Bytes::from("example.valid"));
     }
 
+    fn authenticated_peer() -> TestAccount {
+        TestAccount {
+            route_auth_key: Some(Bytes::from("shared secret")),
+            ..ROUTING_ACCOUNT.clone()
+        }
+    }
+
+    #[test]
+    fn accepts_routes_with_valid_auth() {
+        let mut service = test_service();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        request.sign(b"shared secret");
+        block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix1")
+            .is_some());
+    }
+
+    #[test]
+    fn rejects_forged_routes() {
+        let mut service = test_service();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        request.sign(b"shared secret");
+        // Shorten the path after the update was signed to make it look better than it is
+        request.new_routes[1].path.pop();
+        let result = block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }));
+        let reject = result.unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F00_BAD_REQUEST);
+        assert_eq!(
+            str::from_utf8(reject.message()).unwrap(),
+            "Invalid auth for route update request"
+        );
+        // None of the routes in the update are applied
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix1")
+            .is_none());
+    }
+
+    #[test]
+    fn rejects_unsigned_routes_from_authenticated_peer() {
+        let mut service = test_service();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        let result = block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }));
+        assert_eq!(result.unwrap_err().code(), ErrorCode::F00_BAD_REQUEST);
+
+        request.sign(b"some other secret");
+        let result = block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }));
+        assert_eq!(result.unwrap_err().code(), ErrorCode::F00_BAD_REQUEST);
+    }
+
+    fn signed_update() -> RouteUpdateRequest {
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        request.withdrawn_routes = Vec::new();
+        request.sign(b"shared secret");
+        request
+    }
+
+    #[test]
+    fn rejects_forged_withdrawals() {
+        let mut service = test_service();
+        let request = signed_update();
+        block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
+
+        // Someone tries to remove a route with an update for the peer's next epochs
+        let mut withdrawal = UPDATE_REQUEST_COMPLEX.clone();
+        withdrawal.from_epoch_index = 1;
+        withdrawal.to_epoch_index = 2;
+        withdrawal.new_routes = Vec::new();
+        withdrawal.withdrawn_routes = vec![Bytes::from("example.prefix1")];
+        let result = block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: withdrawal.to_prepare(),
+            extensions: Extensions::new(),
+        }));
+        assert_eq!(
+            str::from_utf8(result.unwrap_err().message()).unwrap(),
+            "Invalid auth for route update request"
+        );
+
+        // Or signs it with the wrong key
+        withdrawal.sign(b"some other secret");
+        let result = block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: withdrawal.to_prepare(),
+            extensions: Extensions::new(),
+        }));
+        assert_eq!(
+            str::from_utf8(result.unwrap_err().message()).unwrap(),
+            "Invalid auth for route update request"
+        );
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix1")
+            .is_some());
+    }
+
+    #[test]
+    fn rejects_forged_heartbeats() {
+        let mut service = test_service();
+        let request = signed_update();
+        block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
+
+        // An update without any routes that only moves the epochs and hold down time
+        let mut heartbeat = UPDATE_REQUEST_COMPLEX.clone();
+        heartbeat.from_epoch_index = 1;
+        heartbeat.to_epoch_index = 2;
+        heartbeat.hold_down_time = 0;
+        heartbeat.new_routes = Vec::new();
+        heartbeat.withdrawn_routes = Vec::new();
+        let result = block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: heartbeat.to_prepare(),
+            extensions: Extensions::new(),
+        }));
+        assert_eq!(result.unwrap_err().code(), ErrorCode::F00_BAD_REQUEST);
+        assert_eq!(
+            (*service.incoming_tables.read())[&ROUTING_ACCOUNT.id()].epoch(),
+            1
+        );
+
+        heartbeat.sign(b"shared secret");
+        block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: heartbeat.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
+        assert_eq!(
+            (*service.incoming_tables.read())[&ROUTING_ACCOUNT.id()].epoch(),
+            2
+        );
+    }
+
+    #[test]
+    fn rejects_replayed_routes() {
+        let mut service = test_service();
+        let request = signed_update();
+        block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
+
+        // The peer withdraws one of the routes
+        let mut withdrawal = UPDATE_REQUEST_COMPLEX.clone();
+        withdrawal.from_epoch_index = 1;
+        withdrawal.to_epoch_index = 2;
+        withdrawal.new_routes = Vec::new();
+        withdrawal.withdrawn_routes = vec![Bytes::from("example.prefix1")];
+        withdrawal.sign(b"shared secret");
+        block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: withdrawal.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix1")
+            .is_none());
+
+        // Someone tries to add it back by replaying the peer's first update.
+        // It is acknowledged like any duplicate but not applied again
+        block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix1")
+            .is_none());
+
+        // Or by moving the signed routes to a later update
+        let mut replay = request.clone();
+        replay.from_epoch_index = 2;
+        replay.to_epoch_index = 3;
+        let result = block_on(service.handle_request(IncomingRequest {
+            from: authenticated_peer(),
+            prepare: replay.to_prepare(),
+            extensions: Extensions::new(),
+        }));
+        assert_eq!(result.unwrap_err().code(), ErrorCode::F00_BAD_REQUEST);
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix1")
+            .is_none());
+    }
+
+    #[test]
+    fn applies_import_policy() {
+        let service = test_service();
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        request.new_routes.push(Route {
+            prefix: Bytes::from("example.allowed"),
+            path: vec![Bytes::from("example.old")],
+            auth: [0; 32],
+            props: Vec::new(),
+        });
+        request.new_routes.push(Route {
+            prefix: Bytes::from("example.other"),
+            path: Vec::new(),
+            auth: [0; 32],
+            props: Vec::new(),
+        });
+        request.new_routes.push(Route {
+            prefix: Bytes::from("example.allowed.far"),
+            path: vec![
+                Bytes::from("example.a"),
+                Bytes::from("example.b"),
+                Bytes::from("example.c"),
+            ],
+            auth: [0; 32],
+            props: Vec::new(),
+        });
+        let account = TestAccount {
+            import_policy: Some(RoutePolicy {
+                allow_prefixes: vec!["example.allowed".to_string()],
+                max_path_length: Some(2),
+                rewrite_path: vec![("example.old".to_string(), "example.new".to_string())]
+                    .into_iter()
+                    .collect(),
+                ..RoutePolicy::default()
+            }),
+            ..ROUTING_ACCOUNT.clone()
+        };
+        let request = service.filter_routes(&account, request);
+        assert_eq!(request.new_routes.len(), 1);
+        assert_eq!(request.new_routes[0].prefix, Bytes::from("example.allowed"));
+        assert_eq!(request.new_routes[0].path, vec![Bytes::from("example.new")]);
+    }
+
     #[test]
     fn filters_own_prefix_routes() {
         let service = test_service();
@@ -1250,7 +2248,7 @@ mod handle_route_update_request {
             auth: [0; 32],
             props: Vec::new(),
         });
-        let request = service.filter_routes(request);
+        let request = service.filter_routes(&ROUTING_ACCOUNT, request);
         assert_eq!(request.new_routes.len(), 1);
         assert_eq!(request.new_routes[0].prefix, Bytes::from("example.valid"));
     }
@@ -1261,13 +2259,12 @@ mod handle_route_update_request {
         let mut request = UPDATE_REQUEST_COMPLEX.clone();
         request.to_epoch_index = 1;
         request.from_epoch_index = 0;
-        service
-            .handle_request(IncomingRequest {
-                from: ROUTING_ACCOUNT.clone(),
-                prepare: request.to_prepare(),
-            })
-            .wait()
-            .unwrap();
+        block_on(service.handle_request(IncomingRequest {
+            from: ROUTING_ACCOUNT.clone(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
         assert_eq!(
             (*service.local_table.read())
                 .get_route(b"example.prefix1")
@@ -1292,13 +2289,12 @@ mod handle_route_update_request {
         let mut request = UPDATE_REQUEST_COMPLEX.clone();
         request.to_epoch_index = 1;
         request.from_epoch_index = 0;
-        service
-            .handle_request(IncomingRequest {
-                from: ROUTING_ACCOUNT.clone(),
-                prepare: request.to_prepare(),
-            })
-            .wait()
-            .unwrap();
+        block_on(service.handle_request(IncomingRequest {
+            from: ROUTING_ACCOUNT.clone(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
         assert_eq!(
             service
                 .store
@@ -1339,13 +2335,12 @@ mod handle_route_update_request {
         let mut request = UPDATE_REQUEST_COMPLEX.clone();
         request.to_epoch_index = 1;
         request.from_epoch_index = 0;
-        service
-            .handle_request(IncomingRequest {
-                from: ROUTING_ACCOUNT.clone(),
-                prepare: request.to_prepare(),
-            })
-            .wait()
-            .unwrap();
+        block_on(service.handle_request(IncomingRequest {
+            from: ROUTING_ACCOUNT.clone(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
         assert_eq!(
             (*service.local_table.read())
                 .get_route(b"example.prefix1")
@@ -1370,15 +2365,14 @@ mod handle_route_update_request {
         let mut request = UPDATE_REQUEST_COMPLEX.clone();
         request.to_epoch_index = 1;
         request.from_epoch_index = 0;
-        service
-            .handle_request(IncomingRequest {
-                from: ROUTING_ACCOUNT.clone(),
-                prepare: request.to_prepare(),
-            })
-            .wait()
-            .unwrap();
-        service
-            .handle_request(IncomingRequest {
+        block_on(service.handle_request(IncomingRequest {
+            from: ROUTING_ACCOUNT.clone(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
+        block_on(
+            service.handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: RouteUpdateRequest {
                     routing_table_id: UPDATE_REQUEST_COMPLEX.routing_table_id,
@@ -1389,11 +2383,13 @@ mod handle_route_update_request {
                     speaker: UPDATE_REQUEST_COMPLEX.speaker.clone(),
                     new_routes: Vec::new(),
                     withdrawn_routes: vec![Bytes::from("example.prefix2")],
+                    auth: None,
                 }
                 .to_prepare(),
-            })
-            .wait()
-            .unwrap();
+                extensions: Extensions::new(),
+            }),
+        )
+        .unwrap();
 
         assert_eq!(
             (*service.local_table.read())
@@ -1415,26 +2411,24 @@ mod handle_route_update_request {
         let mut request1 = UPDATE_REQUEST_COMPLEX.clone();
         request1.to_epoch_index = 3;
         request1.from_epoch_index = 0;
-        service
-            .handle_request(IncomingRequest {
-                from: ROUTING_ACCOUNT.clone(),
-                prepare: request1.to_prepare(),
-            })
-            .wait()
-            .unwrap();
+        block_on(service.handle_request(IncomingRequest {
+            from: ROUTING_ACCOUNT.clone(),
+            prepare: request1.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
 
         // Second has a gap in epochs
         let mut request2 = UPDATE_REQUEST_COMPLEX.clone();
         request2.to_epoch_index = 8;
         request2.from_epoch_index = 7;
         request2.routing_table_id = [9; 16];
-        let err = service
-            .handle_request(IncomingRequest {
-                from: ROUTING_ACCOUNT.clone(),
-                prepare: request2.to_prepare(),
-            })
-            .wait()
-            .unwrap_err();
+        let err = block_on(service.handle_request(IncomingRequest {
+            from: ROUTING_ACCOUNT.clone(),
+            prepare: request2.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap_err();
         assert_eq!(err.code(), ErrorCode::F00_BAD_REQUEST);
 
         let request = &outgoing_requests.lock()[0];
@@ -1454,25 +2448,23 @@ mod handle_route_update_request {
         let mut request = UPDATE_REQUEST_COMPLEX.clone();
         request.to_epoch_index = 1;
         request.from_epoch_index = 0;
-        service
-            .handle_request(IncomingRequest {
-                from: ROUTING_ACCOUNT.clone(),
-                prepare: request.to_prepare(),
-            })
-            .wait()
-            .unwrap();
+        block_on(service.handle_request(IncomingRequest {
+            from: ROUTING_ACCOUNT.clone(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap();
 
         // Second has a gap in epochs
         let mut request = UPDATE_REQUEST_COMPLEX.clone();
         request.to_epoch_index = 8;
         request.from_epoch_index = 7;
-        let err = service
-            .handle_request(IncomingRequest {
-                from: ROUTING_ACCOUNT.clone(),
-                prepare: request.to_prepare(),
-            })
-            .wait()
-            .unwrap_err();
+        let err = block_on(service.handle_request(IncomingRequest {
+            from: ROUTING_ACCOUNT.clone(),
+            prepare: request.to_prepare(),
+            extensions: Extensions::new(),
+        }))
+        .unwrap_err();
         assert_eq!(err.code(), ErrorCode::F00_BAD_REQUEST);
 
         let request = &outgoing_requests.lock()[0];
@@ -1485,11 +2477,12 @@ mod handle_route_update_request {
 mod create_route_update {
     use super::*;
     use crate::test_helpers::*;
+    use crate::RoutePolicy;
 
     #[test]
     fn heartbeat_message_for_empty_table() {
         let service = test_service();
-        let update = service.create_route_update(0, 0);
+        let update = service.create_route_update(&ROUTING_ACCOUNT, 0, 0);
         assert_eq!(update.from_epoch_index, 0);
         assert_eq!(update.to_epoch_index, 0);
         assert_eq!(update.current_epoch_index, 0);
@@ -1498,48 +2491,126 @@ mod create_route_update {
     }
 
     #[test]
-    fn includes_the_given_range_of_epochs() {
+    fn applies_export_policy() {
         let service = test_service();
-        (*service.forwarding_table.write()).set_epoch(4);
-        *service.forwarding_table_updates.write() = vec![
-            (
-                vec![Route {
+        (*service.forwarding_table.write()).set_epoch(1);
+        {
+            let mut table = service.forwarding_table.write();
+            table.add_route(
+                1,
+                Route {
                     prefix: Bytes::from("example.a"),
-                    path: vec![Bytes::from("example.x")],
+                    path: vec![Bytes::from("example.connector")],
                     auth: [1; 32],
                     props: Vec::new(),
-                }],
-                Vec::new(),
-            ),
-            (
-                vec![Route {
-                    prefix: Bytes::from("example.b"),
-                    path: vec![Bytes::from("example.x")],
+                },
+            );
+            table.add_route(
+                1,
+                Route {
+                    prefix: Bytes::from("example.private"),
+                    path: vec![Bytes::from("example.connector")],
                     auth: [2; 32],
                     props: Vec::new(),
-                }],
-                Vec::new(),
-            ),
-            (
-                vec![Route {
-                    prefix: Bytes::from("example.c"),
-                    path: vec![Bytes::from("example.x"), Bytes::from("example.y")],
-                    auth: [3; 32],
-                    props: Vec::new(),
-                }],
-                vec![Bytes::from("example.m")],
-            ),
-            (
-                vec![Route {
-                    prefix: Bytes::from("example.d"),
-                    path: vec![Bytes::from("example.x"), Bytes::from("example.y")],
-                    auth: [4; 32],
-                    props: Vec::new(),
-                }],
-                vec![Bytes::from("example.n")],
-            ),
-        ];
-        let update = service.create_route_update(1, 3);
+                },
+            );
+        }
+        *service.forwarding_table_changes.write() = vec![
+            (Bytes::from("example.a"), 1),
+            (Bytes::from("example.private"), 1),
+            (Bytes::from("example.private.old"), 1),
+        ]
+        .into_iter()
+        .collect();
+        let account = TestAccount {
+            export_policy: Some(RoutePolicy {
+                deny_prefixes: vec!["example.private".to_string()],
+                prepend_path: vec!["example.connector".to_string()],
+                ..RoutePolicy::default()
+            }),
+            ..ROUTING_ACCOUNT.clone()
+        };
+
+        let update = service.create_route_update(&account, 0, 1);
+        assert_eq!(update.new_routes.len(), 1);
+        assert_eq!(update.new_routes[0].prefix, Bytes::from("example.a"));
+        assert_eq!(update.new_routes[0].path.len(), 2);
+        assert!(update.withdrawn_routes.is_empty());
+
+        // Other accounts still get everything
+        let update = service.create_route_update(&ROUTING_ACCOUNT, 0, 1);
+        assert_eq!(update.new_routes.len(), 2);
+        assert_eq!(update.withdrawn_routes.len(), 1);
+    }
+
+    #[test]
+    fn withdraws_routes_the_export_policy_drops() {
+        let service = test_service();
+        (*service.forwarding_table.write()).set_epoch(2);
+        service.forwarding_table.write().add_route(
+            1,
+            Route {
+                prefix: Bytes::from("example.a"),
+                path: vec![
+                    Bytes::from("example.connector"),
+                    Bytes::from("example.b"),
+                    Bytes::from("example.c"),
+                ],
+                auth: [1; 32],
+                props: Vec::new(),
+            },
+        );
+        // The route to example.a got longer in epoch 2
+        *service.forwarding_table_changes.write() =
+            vec![(Bytes::from("example.a"), 2)].into_iter().collect();
+        let account = TestAccount {
+            export_policy: Some(RoutePolicy {
+                max_path_length: Some(2),
+                ..RoutePolicy::default()
+            }),
+            ..ROUTING_ACCOUNT.clone()
+        };
+
+        let update = service.create_route_update(&account, 1, 2);
+        assert!(update.new_routes.is_empty());
+        assert_eq!(update.withdrawn_routes, vec![Bytes::from("example.a")]);
+    }
+
+    #[test]
+    fn includes_the_given_range_of_epochs() {
+        let service = test_service();
+        (*service.forwarding_table.write()).set_epoch(4);
+        {
+            let mut table = service.forwarding_table.write();
+            for (prefix, auth) in &[
+                ("example.a", 1),
+                ("example.b", 2),
+                ("example.c", 3),
+                ("example.d", 4),
+            ] {
+                table.add_route(
+                    1,
+                    Route {
+                        prefix: Bytes::from(*prefix),
+                        path: vec![Bytes::from("example.x")],
+                        auth: [*auth; 32],
+                        props: Vec::new(),
+                    },
+                );
+            }
+        }
+        // example.m and example.n were withdrawn, so only the epoch they changed in is kept
+        *service.forwarding_table_changes.write() = vec![
+            (Bytes::from("example.a"), 1),
+            (Bytes::from("example.b"), 2),
+            (Bytes::from("example.c"), 3),
+            (Bytes::from("example.m"), 3),
+            (Bytes::from("example.d"), 4),
+            (Bytes::from("example.n"), 4),
+        ]
+        .into_iter()
+        .collect();
+        let update = service.create_route_update(&ROUTING_ACCOUNT, 1, 3);
         assert_eq!(update.from_epoch_index, 1);
         assert_eq!(update.to_epoch_index, 3);
         assert_eq!(update.current_epoch_index, 4);
@@ -1560,7 +2631,11 @@ mod create_route_update {
 #[cfg(test)]
 mod send_route_updates {
     use super::*;
+    use crate::packet::RouteProp;
     use crate::test_helpers::*;
+    use crate::RoutePolicy;
+    use interledger_service::outgoing_service_fn;
+    use parking_lot::Mutex;
     use std::str::FromStr;
 
     #[test]
@@ -1577,29 +2652,337 @@ mod send_route_updates {
     }
 
     #[test]
-    fn broadcasts_configured_and_local_routes() {
-        let (service, outgoing_requests) = test_service_with_routes();
-
-        // This is normally spawned as a task when the service is created
+    fn signs_routes_for_authenticated_peers() {
+        let (mut service, outgoing_requests) = test_service_with_routes();
+        let key = Bytes::from("shared secret");
+        for account in service.store.local.values_mut() {
+            if account.id() == 1 {
+                account.route_auth_key = Some(key.clone());
+            }
+        }
         service.update_best_routes(None).wait().unwrap();
 
         service.send_route_updates().wait().unwrap();
-        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
-        assert_eq!(update.new_routes.len(), 2);
-        let prefixes: Vec<&str> = update
-            .new_routes
+        let requests = outgoing_requests.lock();
+        let signed = requests
             .iter()
-            .map(|route| str::from_utf8(route.prefix.as_ref()).unwrap())
-            .collect();
-        assert!(prefixes.contains(&"example.local.1"));
-        assert!(prefixes.contains(&"example.configured.1"));
+            .find(|request| request.to.id() == 1)
+            .unwrap();
+        let signed = RouteUpdateRequest::try_from(&signed.prepare).unwrap();
+        assert_eq!(signed.new_routes.len(), 2);
+        assert!(signed.verify(&key));
+
+        // Other peers get the same routes with the auth of the hash chain instead
+        let unsigned = requests
+            .iter()
+            .find(|request| request.to.id() == 2)
+            .unwrap();
+        let unsigned = RouteUpdateRequest::try_from(&unsigned.prepare).unwrap();
+        assert!(!unsigned.verify(&key));
+        assert_eq!(unsigned.new_routes.len(), 2);
+        for (signed, unsigned) in signed.new_routes.iter().zip(unsigned.new_routes.iter()) {
+            assert_eq!(signed.prefix, unsigned.prefix);
+            assert_eq!(signed.path, unsigned.path);
+            assert_ne!(signed.auth, unsigned.auth);
+        }
     }
 
     #[test]
-    fn broadcasts_received_routes() {
+    fn skips_idle_peers() {
         let (service, outgoing_requests) = test_service_with_routes();
+        service.peers.write().insert(
+            1,
+            PeerState {
+                mode: Mode::Idle,
+                ..PeerState::default()
+            },
+        );
+        service.send_route_updates().wait().unwrap();
+        let accounts: Vec<u64> = outgoing_requests
+            .lock()
+            .iter()
+            .map(|request| request.to.id())
+            .collect();
+        assert_eq!(accounts, vec![2]);
+    }
 
-        // This is normally spawned as a task when the service is created
+    #[test]
+    fn only_sends_changes_since_last_acknowledged_epoch() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+
+        service.send_route_updates().wait().unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        assert_eq!(update.from_epoch_index, 0);
+        assert_eq!(update.new_routes.len(), 2);
+        assert_eq!(service.peers.read()[&1].last_acked_epoch, 1);
+
+        // Nothing changed, so the next broadcast is just a heartbeat
+        outgoing_requests.lock().clear();
+        service.send_route_updates().wait().unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        assert_eq!(update.from_epoch_index, 1);
+        assert_eq!(update.to_epoch_index, 1);
+        assert!(update.new_routes.is_empty());
+    }
+
+    #[test]
+    fn forgets_withdrawn_routes_once_all_peers_acknowledged_them() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        {
+            let mut table = service.forwarding_table.write();
+            table.set_epoch(3);
+            service.snapshot_writer.lock().saved_epoch = 3;
+            table.add_route(
+                1,
+                Route {
+                    prefix: Bytes::from("example.kept"),
+                    path: vec![Bytes::from("example.x")],
+                    auth: [1; 32],
+                    props: Vec::new(),
+                },
+            );
+        }
+        *service.forwarding_table_changes.write() = vec![
+            (Bytes::from("example.kept"), 1),
+            (Bytes::from("example.withdrawn"), 2),
+        ]
+        .into_iter()
+        .collect();
+        for (id, epoch) in &[(1, 3), (2, 1)] {
+            service.peers.write().insert(
+                *id,
+                PeerState {
+                    last_acked_epoch: *epoch,
+                    ..PeerState::default()
+                },
+            );
+        }
+
+        // Account 2 has not heard about the withdrawal yet
+        service.send_route_updates().wait().unwrap();
+        assert_eq!(service.forwarding_table_changes.read().len(), 2);
+        assert_eq!(service.peers.read()[&2].last_acked_epoch, 3);
+
+        service.send_route_updates().wait().unwrap();
+        let changes = service.forwarding_table_changes.read();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes.get(&Bytes::from("example.kept")), Some(&1));
+    }
+
+    #[test]
+    fn backs_off_from_peers_that_stop_responding() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let attempts_clone = attempts.clone();
+        let service = test_service_with_routes_and_outgoing(outgoing_service_fn(
+            move |request: OutgoingRequest<TestAccount>| {
+                attempts_clone.lock().push(request.to.id());
+                if request.to.id() == 1 {
+                    futures03::future::err(
+                        RejectBuilder {
+                            code: ErrorCode::T01_PEER_UNREACHABLE,
+                            message: &[],
+                            triggered_by: None,
+                            data: &[],
+                        }
+                        .build(),
+                    )
+                } else {
+                    futures03::future::ok(CCP_RESPONSE.clone())
+                }
+            },
+        ));
+        service.update_best_routes(None).wait().unwrap();
+
+        service.send_route_updates().wait().unwrap();
+        let peer = service.peers.read()[&1].clone();
+        assert_eq!(peer.failures, 1);
+        assert_eq!(peer.last_acked_epoch, 0);
+
+        // The failing peer is skipped until its retry time
+        attempts.lock().clear();
+        service.send_route_updates().wait().unwrap();
+        assert_eq!(*attempts.lock(), vec![2]);
+
+        // Once it is time to retry, it gets everything it missed
+        service.peers.write().get_mut(&1).unwrap().retry_at = Some(Instant::now());
+        attempts.lock().clear();
+        service.send_route_updates().wait().unwrap();
+        assert!(attempts.lock().contains(&1));
+        let peer = service.peers.read()[&1].clone();
+        assert_eq!(peer.failures, 2);
+        assert!(peer.retry_at.unwrap() >= Instant::now() + Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn does_not_back_off_from_peers_that_reject_updates() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let attempts_clone = attempts.clone();
+        let service = test_service_with_routes_and_outgoing(outgoing_service_fn(
+            move |request: OutgoingRequest<TestAccount>| {
+                attempts_clone.lock().push(request.to.id());
+                futures03::future::err(
+                    RejectBuilder {
+                        code: ErrorCode::F00_BAD_REQUEST,
+                        message: b"Route update was already applied",
+                        triggered_by: None,
+                        data: &[],
+                    }
+                    .build(),
+                )
+            },
+        ));
+        service.update_best_routes(None).wait().unwrap();
+
+        service.send_route_updates().wait().unwrap();
+        let peer = service.peers.read()[&1].clone();
+        assert_eq!(peer.failures, 0);
+        assert!(peer.retry_at.is_none());
+
+        attempts.lock().clear();
+        service.send_route_updates().wait().unwrap();
+        assert!(attempts.lock().contains(&1));
+    }
+
+    #[test]
+    fn caps_the_retry_delay_for_peers_that_keep_failing() {
+        let mut peer = PeerState::default();
+        for _ in 0..100 {
+            peer.record_failure();
+        }
+        assert_eq!(peer.failures, 100);
+        let max_retry_at = Instant::now() + Duration::from_millis(MAX_RETRY_DELAY);
+        assert!(peer.retry_at.unwrap() <= max_retry_at);
+        assert!(peer.retry_at.unwrap() + Duration::from_secs(1) >= max_retry_at);
+
+        peer.failures = u32::MAX;
+        peer.record_failure();
+        assert_eq!(peer.failures, u32::MAX);
+        assert!(peer.retry_at.unwrap() <= Instant::now() + Duration::from_millis(MAX_RETRY_DELAY));
+    }
+
+    #[test]
+    fn forgets_peers_that_are_no_longer_sent_routes() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.peers.write().insert(1000, PeerState::default());
+        service.update_best_routes(None).wait().unwrap();
+        service.send_route_updates().wait().unwrap();
+        let peers = service.peers.read();
+        assert!(!peers.contains_key(&1000));
+        assert!(!peers.is_empty());
+    }
+
+    #[test]
+    fn does_not_pass_on_non_transitive_routes() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+
+        let peer = TestAccount {
+            import_policy: Some(RoutePolicy {
+                non_transitive: true,
+                ..RoutePolicy::default()
+            }),
+            ..TestAccount::new(10, "example.peer")
+        };
+        service
+            .handle_route_update_request(IncomingRequest {
+                from: peer,
+                prepare: RouteUpdateRequest {
+                    routing_table_id: [0; 16],
+                    current_epoch_index: 1,
+                    from_epoch_index: 0,
+                    to_epoch_index: 1,
+                    hold_down_time: 30000,
+                    speaker: Address::from_str("example.remote").unwrap(),
+                    new_routes: vec![Route {
+                        prefix: Bytes::from("example.remote"),
+                        path: vec![Bytes::from("example.peer")],
+                        auth: [0; 32],
+                        props: Vec::new(),
+                    }],
+                    withdrawn_routes: Vec::new(),
+                    auth: None,
+                }
+                .to_prepare(),
+                extensions: Extensions::new(),
+            })
+            .wait()
+            .unwrap();
+        // The route is still used to forward packets
+        assert_eq!(service.store.routes.lock()[&b"example.remote"[..]].id(), 10);
+
+        service.send_route_updates().wait().unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        assert_eq!(update.new_routes.len(), 2);
+        assert!(update
+            .new_routes
+            .iter()
+            .all(|route| route.prefix != "example.remote"));
+    }
+
+    #[test]
+    fn does_not_pass_on_routes_the_sender_marks_no_transit() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+
+        service
+            .handle_route_update_request(IncomingRequest {
+                from: TestAccount::new(10, "example.peer"),
+                prepare: RouteUpdateRequest {
+                    routing_table_id: [0; 16],
+                    current_epoch_index: 1,
+                    from_epoch_index: 0,
+                    to_epoch_index: 1,
+                    hold_down_time: 30000,
+                    speaker: Address::from_str("example.remote").unwrap(),
+                    new_routes: vec![Route {
+                        prefix: Bytes::from("example.remote"),
+                        path: vec![Bytes::from("example.peer")],
+                        auth: [0; 32],
+                        props: vec![RouteProp::no_transit()],
+                    }],
+                    withdrawn_routes: Vec::new(),
+                    auth: None,
+                }
+                .to_prepare(),
+                extensions: Extensions::new(),
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(service.store.routes.lock()[&b"example.remote"[..]].id(), 10);
+
+        service.send_route_updates().wait().unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        assert!(update
+            .new_routes
+            .iter()
+            .all(|route| route.prefix != "example.remote"));
+    }
+
+    #[test]
+    fn broadcasts_configured_and_local_routes() {
+        let (service, outgoing_requests) = test_service_with_routes();
+
+        // This is normally spawned as a task when the service is created
+        service.update_best_routes(None).wait().unwrap();
+
+        service.send_route_updates().wait().unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        assert_eq!(update.new_routes.len(), 2);
+        let prefixes: Vec<&str> = update
+            .new_routes
+            .iter()
+            .map(|route| str::from_utf8(route.prefix.as_ref()).unwrap())
+            .collect();
+        assert!(prefixes.contains(&"example.local.1"));
+        assert!(prefixes.contains(&"example.configured.1"));
+    }
+
+    #[test]
+    fn broadcasts_received_routes() {
+        let (service, outgoing_requests) = test_service_with_routes();
+
+        // This is normally spawned as a task when the service is created
         service.update_best_routes(None).wait().unwrap();
 
         service
@@ -1619,8 +3002,10 @@ mod send_route_updates {
                         props: Vec::new(),
                     }],
                     withdrawn_routes: Vec::new(),
+                    auth: None,
                 }
                 .to_prepare(),
+                extensions: Extensions::new(),
             })
             .wait()
             .unwrap();
@@ -1662,8 +3047,10 @@ mod send_route_updates {
                         props: Vec::new(),
                     }],
                     withdrawn_routes: Vec::new(),
+                    auth: None,
                 }
                 .to_prepare(),
+                extensions: Extensions::new(),
             })
             .wait()
             .unwrap();
@@ -1679,8 +3066,10 @@ mod send_route_updates {
                     speaker: Address::from_str("example.remote").unwrap(),
                     new_routes: Vec::new(),
                     withdrawn_routes: vec![Bytes::from("example.remote")],
+                    auth: None,
                 }
                 .to_prepare(),
+                extensions: Extensions::new(),
             })
             .wait()
             .unwrap();
@@ -1703,3 +3092,679 @@ mod send_route_updates {
         );
     }
 }
+
+#[cfg(test)]
+mod expire_routes {
+    use super::*;
+    use crate::{test_helpers::*, RoutingRelation};
+    use interledger_packet::Prepare;
+    use std::str::FromStr;
+
+    fn route_update(hold_down_time: u32) -> Prepare {
+        RouteUpdateRequest {
+            routing_table_id: [0; 16],
+            current_epoch_index: 1,
+            from_epoch_index: 0,
+            to_epoch_index: 1,
+            hold_down_time,
+            speaker: Address::from_str("example.remote").unwrap(),
+            new_routes: vec![Route {
+                prefix: Bytes::from("example.remote"),
+                path: vec![Bytes::from("example.peer")],
+                auth: [0; 32],
+                props: Vec::new(),
+            }],
+            withdrawn_routes: Vec::new(),
+            auth: None,
+        }
+        .to_prepare()
+    }
+
+    #[test]
+    fn removes_and_withdraws_expired_routes() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        service
+            .handle_route_update_request(IncomingRequest {
+                from: TestAccount::new(10, "example.peer"),
+                prepare: route_update(0),
+                extensions: Extensions::new(),
+            })
+            .wait()
+            .unwrap();
+        assert!(service
+            .local_table
+            .read()
+            .get_route(b"example.remote")
+            .is_some());
+
+        service.expire_routes().wait().unwrap();
+        assert!(service
+            .local_table
+            .read()
+            .get_route(b"example.remote")
+            .is_none());
+        assert!(!service.incoming_tables.read().contains_key(&10));
+        assert!(!service
+            .store
+            .routes
+            .lock()
+            .contains_key(&b"example.remote"[..]));
+
+        service.send_route_updates().wait().unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        assert_eq!(update.withdrawn_routes, vec![Bytes::from("example.remote")]);
+        assert!(update
+            .new_routes
+            .iter()
+            .all(|route| route.prefix != "example.remote"));
+    }
+
+    #[test]
+    fn keeps_routes_within_hold_down_time() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service
+            .handle_route_update_request(IncomingRequest {
+                from: TestAccount::new(10, "example.peer"),
+                prepare: route_update(30000),
+                extensions: Extensions::new(),
+            })
+            .wait()
+            .unwrap();
+        service.expire_routes().wait().unwrap();
+        assert!(service
+            .local_table
+            .read()
+            .get_route(b"example.remote")
+            .is_some());
+    }
+
+    #[test]
+    fn switches_to_route_from_another_peer() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        let child = TestAccount {
+            relation: RoutingRelation::Child,
+            ..TestAccount::new(10, "example.child")
+        };
+        service
+            .handle_route_update_request(IncomingRequest {
+                from: child,
+                prepare: route_update(0),
+                extensions: Extensions::new(),
+            })
+            .wait()
+            .unwrap();
+        service
+            .handle_route_update_request(IncomingRequest {
+                from: TestAccount::new(11, "example.peer"),
+                prepare: route_update(30000),
+                extensions: Extensions::new(),
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(
+            service
+                .local_table
+                .read()
+                .get_route(b"example.remote")
+                .unwrap()
+                .0
+                .id(),
+            10
+        );
+
+        service.expire_routes().wait().unwrap();
+        assert_eq!(
+            service
+                .local_table
+                .read()
+                .get_route(b"example.remote")
+                .unwrap()
+                .0
+                .id(),
+            11
+        );
+        assert_eq!(service.store.routes.lock()[&b"example.remote"[..]].id(), 11);
+    }
+}
+
+#[cfg(test)]
+mod admin {
+    use super::*;
+    use crate::test_helpers::*;
+    use futures03::executor::block_on;
+    use interledger_packet::Prepare;
+    use std::str::FromStr;
+
+    fn route_update(path: &[&str]) -> Prepare {
+        RouteUpdateRequest {
+            routing_table_id: [1; 16],
+            current_epoch_index: 1,
+            from_epoch_index: 0,
+            to_epoch_index: 1,
+            hold_down_time: 30000,
+            speaker: Address::from_str("example.remote").unwrap(),
+            new_routes: vec![Route {
+                prefix: Bytes::from("example.remote"),
+                path: path.iter().map(|address| Bytes::from(*address)).collect(),
+                auth: [2; 32],
+                props: Vec::new(),
+            }],
+            withdrawn_routes: Vec::new(),
+            auth: None,
+        }
+        .to_prepare()
+    }
+
+    fn receive_routes<I, O, S>(
+        service: &CcpRouteManager<I, O, S, TestAccount>,
+        from: TestAccount,
+        path: &[&str],
+    ) where
+        I: IncomingService<TestAccount> + Clone + Send + Sync + 'static,
+        O: OutgoingService<TestAccount> + Clone + Send + Sync + 'static,
+        S: RouteManagerStore<Account = TestAccount> + Clone + Send + Sync + 'static,
+    {
+        service
+            .handle_route_update_request(IncomingRequest {
+                from,
+                prepare: route_update(path),
+                extensions: Extensions::new(),
+            })
+            .wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn lists_incoming_tables() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        receive_routes(
+            &service,
+            TestAccount::new(10, "example.peer"),
+            &["example.peer"],
+        );
+
+        let tables = service.incoming_tables();
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].account_id, 10);
+        assert_eq!(tables[0].routing_table_id, hex::encode(&[1; 16]));
+        assert_eq!(tables[0].epoch, 1);
+        assert_eq!(
+            tables[0].routes,
+            vec![RouteStatus {
+                prefix: "example.remote".to_string(),
+                account_id: 10,
+                path: vec!["example.peer".to_string()],
+                auth: hex::encode(&[2; 32]),
+            }]
+        );
+    }
+
+    #[test]
+    fn lists_candidates_and_reason_for_each_prefix() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        receive_routes(
+            &service,
+            TestAccount::new(10, "example.peer-a"),
+            &["example.peer-a"],
+        );
+        receive_routes(
+            &service,
+            TestAccount::new(11, "example.peer-b"),
+            &["example.peer-b", "example.other"],
+        );
+
+        let prefixes = block_on(service.prefixes()).unwrap();
+        let names: Vec<&str> = prefixes
+            .iter()
+            .map(|status| status.prefix.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "example.configured.1",
+                "example.connector.other-local",
+                "example.local.1",
+                "example.remote"
+            ]
+        );
+        assert_eq!(prefixes[0].reason, BestRouteReason::Configured);
+        assert_eq!(prefixes[2].reason, BestRouteReason::Local);
+
+        let remote = &prefixes[3];
+        assert_eq!(remote.account_id, 10);
+        assert_eq!(remote.path, vec!["example.peer-a".to_string()]);
+        assert_eq!(remote.reason, BestRouteReason::ShortestPath);
+        let candidates: Vec<u64> = remote
+            .candidates
+            .iter()
+            .map(|candidate| candidate.account_id)
+            .collect();
+        assert_eq!(candidates, vec![10, 11]);
+    }
+
+    #[test]
+    fn resync_drops_routes_and_sends_control_request() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        receive_routes(
+            &service,
+            TestAccount::new(10, "example.peer"),
+            &["example.peer"],
+        );
+        assert!(service
+            .local_table
+            .read()
+            .get_route(b"example.remote")
+            .is_some());
+
+        block_on(service.resync(10)).unwrap();
+        assert!(service.incoming_tables().is_empty());
+        assert!(service
+            .local_table
+            .read()
+            .get_route(b"example.remote")
+            .is_none());
+        let requests = outgoing_requests.lock();
+        let request = requests.last().unwrap();
+        assert_eq!(request.to.id(), 10);
+        let control = RouteControlRequest::try_from(&request.prepare).unwrap();
+        assert_eq!(control.mode, Mode::Sync);
+        assert_eq!(control.last_known_routing_table_id, DUMMY_ROUTING_TABLE_ID);
+        assert_eq!(control.last_known_epoch, 0);
+
+        // The peer's next update is applied even though we saw the same epochs before
+        drop(requests);
+        receive_routes(
+            &service,
+            TestAccount::new(10, "example.peer"),
+            &["example.peer"],
+        );
+        assert_eq!(service.incoming_tables()[0].routes.len(), 1);
+    }
+
+    #[test]
+    fn picks_routes_again_when_an_account_is_updated() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        receive_routes(
+            &service,
+            TestAccount::new(10, "example.peer-a"),
+            &["example.peer-a"],
+        );
+        receive_routes(
+            &service,
+            TestAccount::new(11, "example.peer-b"),
+            &["example.peer-b", "example.other"],
+        );
+        let next_hop = || {
+            service
+                .local_table
+                .read()
+                .get_route(b"example.remote")
+                .unwrap()
+                .0
+                .id()
+        };
+        assert_eq!(next_hop(), 10);
+
+        // Peer A gets more expensive after its routes are in place
+        block_on(service.account_updated(TestAccount {
+            route_cost: 10,
+            ..TestAccount::new(10, "example.peer-a")
+        }))
+        .unwrap();
+        assert_eq!(next_hop(), 11);
+        let prefixes = block_on(service.prefixes()).unwrap();
+        let remote = prefixes
+            .iter()
+            .find(|status| status.prefix == "example.remote")
+            .unwrap();
+        assert_eq!(remote.account_id, 11);
+        assert_eq!(remote.reason, BestRouteReason::RouteCost);
+    }
+
+    #[test]
+    fn ignores_updates_to_accounts_without_routes() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        block_on(service.account_updated(TestAccount::new(10, "example.peer"))).unwrap();
+        assert!(service.incoming_tables().is_empty());
+    }
+
+    #[test]
+    fn resync_rejects_accounts_we_do_not_receive_routes_from() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        match block_on(service.resync(3)) {
+            Err(StoreError::NotFound(_)) => {}
+            result => panic!("Expected NotFound error, got: {:?}", result),
+        }
+        assert!(outgoing_requests.lock().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod persistence {
+    use super::*;
+    use crate::test_helpers::*;
+    use futures03::future::{err, ok};
+    use interledger_service::{incoming_service_fn, outgoing_service_fn};
+    use parking_lot::Mutex;
+    use std::str::FromStr;
+
+    /// Start another route manager with the same store, as if the node restarted
+    fn restart(
+        store: TestStore,
+        node_id: Option<&str>,
+    ) -> (
+        CcpRouteManager<
+            impl IncomingService<TestAccount, Future = BoxedIlpFuture> + Clone,
+            impl OutgoingService<TestAccount, Future = BoxedIlpFuture> + Clone,
+            TestStore,
+            TestAccount,
+        >,
+        OutgoingRequests,
+    ) {
+        let outgoing_requests: OutgoingRequests = Arc::new(Mutex::new(Vec::new()));
+        let outgoing_requests_clone = outgoing_requests.clone();
+        let mut builder = CcpRouteManagerBuilder::new(
+            EXAMPLE_CONNECTOR.clone(),
+            store,
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                outgoing_requests_clone.lock().push(request);
+                ok(CCP_RESPONSE.clone())
+            }),
+            incoming_service_fn(|_request| {
+                err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other incoming handler!",
+                    data: &[],
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                }
+                .build())
+            }),
+        );
+        builder
+            .disable_spawn()
+            .ilp_address(EXAMPLE_CONNECTOR.clone());
+        if let Some(node_id) = node_id {
+            builder.node_id(node_id.to_string());
+        }
+        (builder.to_service(), outgoing_requests)
+    }
+
+    fn receive_remote_route<I, O, S>(service: &CcpRouteManager<I, O, S, TestAccount>)
+    where
+        I: IncomingService<TestAccount> + Clone + Send + Sync + 'static,
+        O: OutgoingService<TestAccount> + Clone + Send + Sync + 'static,
+        S: RouteManagerStore<Account = TestAccount> + Clone + Send + Sync + 'static,
+    {
+        service
+            .handle_route_update_request(IncomingRequest {
+                from: TestAccount::new(10, "example.peer"),
+                prepare: RouteUpdateRequest {
+                    routing_table_id: [0; 16],
+                    current_epoch_index: 1,
+                    from_epoch_index: 0,
+                    to_epoch_index: 1,
+                    hold_down_time: 30000,
+                    speaker: Address::from_str("example.remote").unwrap(),
+                    new_routes: vec![Route {
+                        prefix: Bytes::from("example.remote"),
+                        path: vec![Bytes::from("example.peer")],
+                        auth: [0; 32],
+                        props: Vec::new(),
+                    }],
+                    withdrawn_routes: Vec::new(),
+                    auth: None,
+                }
+                .to_prepare(),
+                extensions: Extensions::new(),
+            })
+            .wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn saves_forwarding_table_to_store() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        receive_remote_route(&service);
+
+        let saved = service.store.forwarding_tables.lock()["example.connector"].clone();
+        let snapshot = ForwardingTableSnapshot::<u64>::from_bytes(&saved[..]).unwrap();
+        assert_eq!(snapshot.id, service.forwarding_table.read().id());
+        assert_eq!(snapshot.epoch, 2);
+        let mut changes = snapshot.changes.clone();
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                (Bytes::from("example.configured.1"), 1),
+                (Bytes::from("example.local.1"), 1),
+                (Bytes::from("example.remote"), 2),
+            ]
+        );
+        let mut prefixes: Vec<&[u8]> = snapshot
+            .routes
+            .iter()
+            .map(|(_, route)| &route.prefix[..])
+            .collect();
+        prefixes.sort();
+        assert_eq!(
+            prefixes,
+            vec![
+                &b"example.configured.1"[..],
+                &b"example.local.1"[..],
+                &b"example.remote"[..]
+            ]
+        );
+        assert_eq!(
+            snapshot
+                .routes
+                .iter()
+                .find(|(_, route)| route.prefix == "example.remote")
+                .unwrap()
+                .0,
+            10
+        );
+    }
+
+    #[test]
+    fn restores_table_id_and_sends_incremental_updates() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        receive_remote_route(&service);
+        let (table_id, epoch) = {
+            let table = service.forwarding_table.read();
+            (table.id(), table.epoch())
+        };
+
+        let (restarted, outgoing_requests) = restart(service.store.clone(), None);
+        restarted.load_forwarding_table().wait().unwrap();
+        assert_eq!(restarted.forwarding_table.read().id(), table_id);
+        assert_eq!(restarted.forwarding_table.read().epoch(), epoch);
+        assert_eq!(
+            restarted
+                .forwarding_table_changes
+                .read()
+                .get(&Bytes::from("example.remote")),
+            Some(&2)
+        );
+
+        // Picking the local routes again does not change what we advertise
+        restarted.update_best_routes(None).wait().unwrap();
+        receive_remote_route(&restarted);
+
+        // A peer that saw our table before the restart only gets what changed since then
+        restarted
+            .handle_route_control_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: RouteControlRequest {
+                    last_known_routing_table_id: table_id,
+                    mode: Mode::Sync,
+                    last_known_epoch: epoch,
+                    features: Vec::new(),
+                }
+                .to_prepare(),
+                extensions: Extensions::new(),
+            })
+            .wait()
+            .unwrap();
+        let update =
+            RouteUpdateRequest::try_from(&outgoing_requests.lock().last().unwrap().prepare)
+                .unwrap();
+        assert_eq!(update.routing_table_id, table_id);
+        assert_eq!(update.from_epoch_index, epoch);
+        assert!(update.new_routes.is_empty());
+        assert!(update.withdrawn_routes.is_empty());
+    }
+
+    #[test]
+    fn withdraws_restored_routes_no_peer_sends_again() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        receive_remote_route(&service);
+
+        let (restarted, _outgoing_requests) = restart(service.store.clone(), None);
+        restarted.load_forwarding_table().wait().unwrap();
+        restarted.update_best_routes(None).wait().unwrap();
+
+        // The remote route is kept until the peers have had time to send it again
+        restarted.expire_routes().wait().unwrap();
+        assert!(restarted
+            .forwarding_table
+            .read()
+            .get_route(b"example.remote")
+            .is_some());
+
+        if let Some((ref mut check_at, _)) = *restarted.restored_prefixes.write() {
+            *check_at = Instant::now();
+        }
+        restarted.expire_routes().wait().unwrap();
+        assert!(restarted
+            .forwarding_table
+            .read()
+            .get_route(b"example.remote")
+            .is_none());
+        assert!(restarted
+            .forwarding_table
+            .read()
+            .get_route(b"example.local.1")
+            .is_some());
+        let epoch = restarted.forwarding_table.read().epoch();
+        assert_eq!(
+            restarted
+                .forwarding_table_changes
+                .read()
+                .get(&Bytes::from("example.remote")),
+            Some(&epoch)
+        );
+        let update = restarted.create_route_update(&ROUTING_ACCOUNT, epoch - 1, epoch);
+        assert!(update.new_routes.is_empty());
+        assert_eq!(update.withdrawn_routes, vec![Bytes::from("example.remote")]);
+        assert!(restarted.restored_prefixes.read().is_none());
+    }
+
+    #[test]
+    fn ignores_saved_table_once_updated() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        let table_id = service.forwarding_table.read().id();
+
+        let (restarted, _outgoing_requests) = restart(service.store.clone(), None);
+        receive_remote_route(&restarted);
+        let new_table_id = restarted.forwarding_table.read().id();
+        restarted.load_forwarding_table().wait().unwrap();
+        assert_ne!(restarted.forwarding_table.read().id(), table_id);
+        assert_eq!(restarted.forwarding_table.read().id(), new_table_id);
+        assert!(restarted.restored_prefixes.read().is_none());
+    }
+
+    #[test]
+    fn keeps_a_forwarding_table_per_node() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        receive_remote_route(&service);
+        let table_id = service.forwarding_table.read().id();
+
+        // Another node sharing the store starts its own table instead of taking this one
+        let (other, _outgoing_requests) = restart(service.store.clone(), Some("other-node"));
+        other.load_forwarding_table().wait().unwrap();
+        assert_ne!(other.forwarding_table.read().id(), table_id);
+        other.update_best_routes(None).wait().unwrap();
+        let other_table_id = other.forwarding_table.read().id();
+
+        let tables = service.store.forwarding_tables.lock().clone();
+        assert_eq!(tables.len(), 2);
+        let snapshot =
+            ForwardingTableSnapshot::<u64>::from_bytes(&tables["example.connector"][..]).unwrap();
+        assert_eq!(snapshot.id, table_id);
+        assert_eq!(snapshot.epoch, 2);
+        let snapshot =
+            ForwardingTableSnapshot::<u64>::from_bytes(&tables["other-node"][..]).unwrap();
+        assert_eq!(snapshot.id, other_table_id);
+        assert_eq!(snapshot.epoch, 1);
+
+        // Each node restores its own table after a restart
+        let (restarted, _outgoing_requests) = restart(service.store.clone(), None);
+        restarted.load_forwarding_table().wait().unwrap();
+        assert_eq!(restarted.forwarding_table.read().id(), table_id);
+        let (restarted, _outgoing_requests) = restart(service.store.clone(), Some("other-node"));
+        restarted.load_forwarding_table().wait().unwrap();
+        assert_eq!(restarted.forwarding_table.read().id(), other_table_id);
+    }
+
+    #[test]
+    fn only_sends_peers_saved_epochs() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        *service.store.fail_forwarding_table_saves.lock() = true;
+        service.update_best_routes(None).wait().unwrap();
+        assert_eq!(service.forwarding_table.read().epoch(), 1);
+
+        // Epoch 1 is not saved, so a restart would not restore it and peers must not see it yet
+        service.send_route_updates().wait().unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        assert_eq!(update.to_epoch_index, 0);
+        assert!(update.new_routes.is_empty());
+        assert!(service.store.forwarding_tables.lock().is_empty());
+
+        // The next broadcast saves the table again and sends the routes
+        *service.store.fail_forwarding_table_saves.lock() = false;
+        outgoing_requests.lock().clear();
+        service.broadcast_routes().wait().unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        assert_eq!(update.to_epoch_index, 1);
+        assert_eq!(update.new_routes.len(), 2);
+
+        let (restarted, _outgoing_requests) = restart(service.store.clone(), None);
+        restarted.load_forwarding_table().wait().unwrap();
+        assert_eq!(restarted.forwarding_table.read().epoch(), 1);
+        assert_eq!(restarted.snapshot_writer.lock().saved_epoch, 1);
+    }
+
+    #[test]
+    fn leaves_snapshot_for_the_write_in_progress() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.snapshot_writer.lock().writing = true;
+        service.update_best_routes(None).wait().unwrap();
+        // The write in progress saves the new snapshot once it is done
+        assert!(service.store.forwarding_tables.lock().is_empty());
+        assert_eq!(
+            service
+                .snapshot_writer
+                .lock()
+                .pending
+                .as_ref()
+                .map(|(epoch, _)| *epoch),
+            Some(1)
+        );
+
+        service.snapshot_writer.lock().writing = false;
+        service.save_forwarding_table().wait().unwrap();
+        let saved = service.store.forwarding_tables.lock()["example.connector"].clone();
+        let snapshot = ForwardingTableSnapshot::<u64>::from_bytes(&saved[..]).unwrap();
+        assert_eq!(snapshot.epoch, 1);
+        let writer = service.snapshot_writer.lock();
+        assert_eq!(writer.saved_epoch, 1);
+        assert!(writer.pending.is_none());
+        assert!(!writer.writing);
+    }
+}
@@ -0,0 +1,185 @@
+//! The binary format the route manager uses to save its forwarding routing table to the store.
+//!
+//! The snapshot holds the table ID and epoch, every route in the table with the ID of the account
+//! it was learned from, and the epoch in which the route for each prefix last changed (including
+//! prefixes that were withdrawn). With these a restarted node keeps advertising the same table ID
+//! and can send peers incremental updates instead of making them re-sync the whole table.
+
+use crate::packet::Route;
+use byteorder::{BigEndian, ReadBytesExt};
+use bytes::{BufMut, Bytes};
+use interledger_packet::{
+    oer::{BufOerExt, MutBufOerExt},
+    ParseError,
+};
+use std::{convert::TryFrom, io::Read, str::FromStr, string::ToString};
+
+const VERSION: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ForwardingTableSnapshot<T> {
+    pub id: [u8; 16],
+    pub epoch: u32,
+    pub routes: Vec<(T, Route)>,
+    /// The epoch in which each prefix was last added, changed or withdrawn
+    pub changes: Vec<(Bytes, u32)>,
+}
+
+impl<T> ForwardingTableSnapshot<T>
+where
+    T: ToString + FromStr,
+{
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = Vec::new();
+        buf.put_u8(VERSION);
+        buf.put(&self.id[..]);
+        buf.put_u32_be(self.epoch);
+        buf.put_var_uint(self.routes.len() as u64);
+        for (account_id, route) in self.routes.iter() {
+            buf.put_var_octet_string(account_id.to_string().as_bytes());
+            route.write_to(&mut buf);
+        }
+        buf.put_var_uint(self.changes.len() as u64);
+        for (prefix, epoch) in self.changes.iter() {
+            buf.put_var_octet_string(&prefix[..]);
+            buf.put_u32_be(*epoch);
+        }
+        Bytes::from(buf)
+    }
+
+    pub fn from_bytes(mut data: &[u8]) -> Result<Self, ParseError> {
+        let mut version = [0; 1];
+        data.read_exact(&mut version)?;
+        if version[0] != VERSION {
+            return Err(ParseError::InvalidPacket(format!(
+                "Unknown forwarding table version: {}",
+                version[0]
+            )));
+        }
+
+        let mut id: [u8; 16] = [0; 16];
+        data.read_exact(&mut id)?;
+        let epoch = data.read_u32::<BigEndian>()?;
+
+        let routes_len = read_len(&mut data)?;
+        let mut routes = Vec::with_capacity(routes_len);
+        for _i in 0..routes_len {
+            let account_id = std::str::from_utf8(data.read_var_octet_string()?)
+                .ok()
+                .and_then(|account_id| T::from_str(account_id).ok())
+                .ok_or_else(|| ParseError::InvalidPacket("Invalid account ID".to_string()))?;
+            routes.push((account_id, Route::try_from(&mut data)?));
+        }
+
+        let changes_len = read_len(&mut data)?;
+        let mut changes = Vec::with_capacity(changes_len);
+        for _i in 0..changes_len {
+            let prefix = Bytes::from(data.read_var_octet_string()?);
+            changes.push((prefix, data.read_u32::<BigEndian>()?));
+        }
+
+        Ok(ForwardingTableSnapshot {
+            id,
+            epoch,
+            routes,
+            changes,
+        })
+    }
+}
+
+/// Read the number of items in a list. Every item takes at least one byte, so a length
+/// longer than the rest of the data is invalid and must not be used to allocate the list.
+fn read_len(data: &mut &[u8]) -> Result<usize, ParseError> {
+    let len = data.read_var_uint()?;
+    if len > data.len() as u64 {
+        return Err(ParseError::InvalidPacket(format!(
+            "List length {} is longer than the remaining {} bytes",
+            len,
+            data.len()
+        )));
+    }
+    Ok(len as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::RouteProp;
+
+    fn route(prefix: &str) -> Route {
+        Route {
+            prefix: Bytes::from(prefix),
+            path: vec![
+                Bytes::from("example.connector"),
+                Bytes::from("example.peer"),
+            ],
+            auth: [7; 32],
+            props: vec![RouteProp {
+                is_optional: false,
+                is_transitive: true,
+                is_partial: false,
+                id: 1,
+                is_utf8: true,
+                value: Bytes::from("value"),
+            }],
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let snapshot: ForwardingTableSnapshot<u64> = ForwardingTableSnapshot {
+            id: [3; 16],
+            epoch: 2,
+            routes: vec![(1, route("example.a")), (2, route("example.b"))],
+            changes: vec![
+                (Bytes::from("example.a"), 1),
+                (Bytes::from("example.b"), 2),
+                (Bytes::from("example.c"), 2),
+            ],
+        };
+        let parsed = ForwardingTableSnapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+        assert_eq!(parsed, snapshot);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut bytes = ForwardingTableSnapshot::<u64> {
+            id: [0; 16],
+            epoch: 0,
+            routes: Vec::new(),
+            changes: Vec::new(),
+        }
+        .to_bytes()
+        .to_vec();
+        bytes[0] = 1;
+        assert!(ForwardingTableSnapshot::<u64>::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let bytes = ForwardingTableSnapshot::<u64> {
+            id: [0; 16],
+            epoch: 1,
+            routes: vec![(1, route("example.a"))],
+            changes: Vec::new(),
+        }
+        .to_bytes();
+        assert!(ForwardingTableSnapshot::<u64>::from_bytes(&bytes[..bytes.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn rejects_lengths_longer_than_the_data() {
+        let mut bytes = ForwardingTableSnapshot::<u64> {
+            id: [0; 16],
+            epoch: 0,
+            routes: Vec::new(),
+            changes: Vec::new(),
+        }
+        .to_bytes()
+        .to_vec();
+        // Replace the empty routes list with one that claims to have u32::MAX routes
+        bytes.truncate(bytes.len() - 2);
+        bytes.extend_from_slice(&[4, 0xff, 0xff, 0xff, 0xff]);
+        assert!(ForwardingTableSnapshot::<u64>::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Snapshots of the route manager's state, used to show operators which routes
+//! it knows about and why it picked the ones it forwards packets through.
+
+use crate::packet::Route;
+use serde::Serialize;
+use std::str;
+
+/// Why `get_best_route_for_prefix` picked a route over the other candidates.
+///
+/// The variants for routes from peers are ordered from the strongest to the weakest
+/// tie breaker, so the reason for a route is the weakest one needed to beat every other candidate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BestRouteReason {
+    /// The route was configured statically
+    Configured,
+    /// The prefix belongs to one of our own accounts
+    Local,
+    /// Only one peer sent us a route for the prefix
+    OnlyRoute,
+    /// The route came from the account with the most preferred routing relation
+    RoutingRelation,
+    /// The route came from the account with the lowest cost out of the equally preferred ones
+    RouteCost,
+    /// The route had the shortest path out of the ones from accounts with equal costs
+    ShortestPath,
+    /// The routes were otherwise equal, so the account with the lowest ID was picked
+    AccountId,
+}
+
+/// A route as we received it from a peer
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RouteStatus<T> {
+    pub prefix: String,
+    pub account_id: T,
+    pub path: Vec<String>,
+    /// The hex-encoded auth field
+    pub auth: String,
+}
+
+impl<T> RouteStatus<T> {
+    pub(crate) fn new(account_id: T, route: &Route) -> Self {
+        RouteStatus {
+            prefix: utf8(&route.prefix),
+            account_id,
+            path: route.path.iter().map(|address| utf8(address)).collect(),
+            auth: hex::encode(&route.auth[..]),
+        }
+    }
+}
+
+/// The routing table we have built up from one peer's Route Update Requests
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IncomingTableStatus<T> {
+    pub account_id: T,
+    /// The hex-encoded ID of the peer's routing table
+    pub routing_table_id: String,
+    pub epoch: u32,
+    pub routes: Vec<RouteStatus<T>>,
+}
+
+/// The route we picked for a prefix, along with every other route we could have used
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PrefixStatus<T> {
+    pub prefix: String,
+    pub account_id: T,
+    pub path: Vec<String>,
+    pub reason: BestRouteReason,
+    /// The routes for this prefix from each of the peers we receive routes from
+    pub candidates: Vec<RouteStatus<T>>,
+}
+
+fn utf8(bytes: &[u8]) -> String {
+    str::from_utf8(bytes).unwrap_or("<not utf8>").to_string()
+}
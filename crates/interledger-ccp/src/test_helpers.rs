@@ -2,10 +2,7 @@
 use super::*;
 use crate::{packet::CCP_RESPONSE, server::CcpRouteManager};
 use bytes::Bytes;
-use futures::{
-    future::{err, ok},
-    Future,
-};
+use futures03::future::{err, ok};
 use interledger_packet::{Address, ErrorCode, RejectBuilder};
 use interledger_service::{
     incoming_service_fn, outgoing_service_fn, BoxedIlpFuture, IncomingService, OutgoingRequest,
@@ -15,6 +12,8 @@ use interledger_service::{
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
 use std::str::FromStr;
 use std::{iter::FromIterator, sync::Arc};
 
@@ -25,6 +24,10 @@ lazy_static! {
         send_routes: true,
         receive_routes: true,
         relation: RoutingRelation::Peer,
+        route_auth_key: None,
+        import_policy: None,
+        export_policy: None,
+        route_cost: 0,
     };
     pub static ref NON_ROUTING_ACCOUNT: TestAccount = TestAccount {
         id: 2,
@@ -32,6 +35,10 @@ lazy_static! {
         send_routes: false,
         receive_routes: false,
         relation: RoutingRelation::Child,
+        route_auth_key: None,
+        import_policy: None,
+        export_policy: None,
+        route_cost: 0,
     };
     pub static ref EXAMPLE_CONNECTOR: Address = Address::from_str("example.connector").unwrap();
     pub static ref ALICE: Username = Username::from_str("alice").unwrap();
@@ -44,6 +51,10 @@ pub struct TestAccount {
     pub receive_routes: bool,
     pub send_routes: bool,
     pub relation: RoutingRelation,
+    pub route_auth_key: Option<Bytes>,
+    pub import_policy: Option<RoutePolicy>,
+    pub export_policy: Option<RoutePolicy>,
+    pub route_cost: u32,
 }
 
 impl TestAccount {
@@ -54,6 +65,10 @@ impl TestAccount {
             receive_routes: true,
             send_routes: true,
             relation: RoutingRelation::Peer,
+            route_auth_key: None,
+            import_policy: None,
+            export_policy: None,
+            route_cost: 0,
         }
     }
 }
@@ -96,6 +111,22 @@ impl CcpRoutingAccount for TestAccount {
     fn should_send_routes(&self) -> bool {
         self.send_routes
     }
+
+    fn route_auth_key(&self) -> Option<&[u8]> {
+        self.route_auth_key.as_ref().map(|key| &key[..])
+    }
+
+    fn route_import_policy(&self) -> Option<&RoutePolicy> {
+        self.import_policy.as_ref()
+    }
+
+    fn route_export_policy(&self) -> Option<&RoutePolicy> {
+        self.export_policy.as_ref()
+    }
+
+    fn route_cost(&self) -> u32 {
+        self.route_cost
+    }
 }
 
 #[derive(Clone)]
@@ -103,6 +134,10 @@ pub struct TestStore {
     pub local: HashMap<Bytes, TestAccount>,
     pub configured: HashMap<Bytes, TestAccount>,
     pub routes: Arc<Mutex<HashMap<Bytes, TestAccount>>>,
+    /// The forwarding table saved by each node
+    pub forwarding_tables: Arc<Mutex<HashMap<String, Bytes>>>,
+    /// If set, saving the forwarding table fails
+    pub fail_forwarding_table_saves: Arc<Mutex<bool>>,
 }
 
 impl TestStore {
@@ -111,6 +146,8 @@ impl TestStore {
             local: HashMap::new(),
             configured: HashMap::new(),
             routes: Arc::new(Mutex::new(HashMap::new())),
+            forwarding_tables: Arc::new(Mutex::new(HashMap::new())),
+            fail_forwarding_table_saves: Arc::new(Mutex::new(false)),
         }
     }
 
@@ -122,6 +159,8 @@ impl TestStore {
             local,
             configured,
             routes: Arc::new(Mutex::new(HashMap::new())),
+            forwarding_tables: Arc::new(Mutex::new(HashMap::new())),
+            fail_forwarding_table_saves: Arc::new(Mutex::new(false)),
         }
     }
 }
@@ -133,16 +172,22 @@ impl RouteManagerStore for TestStore {
 
     fn get_local_and_configured_routes(
         &self,
-    ) -> Box<
-        dyn Future<Item = (RoutingTable<TestAccount>, RoutingTable<TestAccount>), Error = ()>
-            + Send,
+    ) -> Pin<
+        Box<
+            dyn Future<
+                    Output = Result<
+                        (RoutingTable<TestAccount>, RoutingTable<TestAccount>),
+                        StoreError,
+                    >,
+                > + Send,
+        >,
     > {
-        Box::new(ok((self.local.clone(), self.configured.clone())))
+        Box::pin(ok((self.local.clone(), self.configured.clone())))
     }
 
     fn get_accounts_to_send_routes_to(
         &self,
-    ) -> Box<dyn Future<Item = Vec<TestAccount>, Error = ()> + Send> {
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<TestAccount>, StoreError>> + Send>> {
         let mut accounts: Vec<TestAccount> = self
             .local
             .values()
@@ -152,12 +197,12 @@ impl RouteManagerStore for TestStore {
             .cloned()
             .collect();
         accounts.dedup_by_key(|a| a.id());
-        Box::new(ok(accounts))
+        Box::pin(ok(accounts))
     }
 
     fn get_accounts_to_receive_routes_from(
         &self,
-    ) -> Box<dyn Future<Item = Vec<TestAccount>, Error = ()> + Send> {
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<TestAccount>, StoreError>> + Send>> {
         let mut accounts: Vec<TestAccount> = self
             .local
             .values()
@@ -167,15 +212,38 @@ impl RouteManagerStore for TestStore {
             .cloned()
             .collect();
         accounts.dedup_by_key(|a| a.id());
-        Box::new(ok(accounts))
+        Box::pin(ok(accounts))
     }
 
     fn set_routes(
         &mut self,
         routes: impl IntoIterator<Item = (Bytes, TestAccount)>,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>> {
         *self.routes.lock() = HashMap::from_iter(routes.into_iter());
-        Box::new(ok(()))
+        Box::pin(ok(()))
+    }
+
+    fn get_forwarding_table(
+        &self,
+        node_id: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<Bytes>, StoreError>> + Send>> {
+        Box::pin(ok(self.forwarding_tables.lock().get(node_id).cloned()))
+    }
+
+    fn set_forwarding_table(
+        &mut self,
+        node_id: &str,
+        table: Bytes,
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>> {
+        if *self.fail_forwarding_table_saves.lock() {
+            return Box::pin(err(StoreError::Unavailable(
+                "Store unavailable".to_string(),
+            )));
+        }
+        self.forwarding_tables
+            .lock()
+            .insert(node_id.to_string(), table);
+        Box::pin(ok(()))
     }
 }
 
@@ -190,22 +258,22 @@ pub fn test_service() -> CcpRouteManager<
         addr.clone(),
         TestStore::new(),
         outgoing_service_fn(|_request| {
-            Box::new(err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: b"No other outgoing handler!",
                 data: &[],
                 triggered_by: Some(&EXAMPLE_CONNECTOR),
             }
-            .build()))
+            .build())
         }),
         incoming_service_fn(|_request| {
-            Box::new(err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: b"No other incoming handler!",
                 data: &[],
                 triggered_by: Some(&EXAMPLE_CONNECTOR),
             }
-            .build()))
+            .build())
         }),
     )
     .disable_spawn()
@@ -213,7 +281,7 @@ pub fn test_service() -> CcpRouteManager<
     .to_service()
 }
 
-type OutgoingRequests = Arc<Mutex<Vec<OutgoingRequest<TestAccount>>>>;
+pub type OutgoingRequests = Arc<Mutex<Vec<OutgoingRequest<TestAccount>>>>;
 
 pub fn test_service_with_routes() -> (
     CcpRouteManager<
@@ -224,6 +292,30 @@ pub fn test_service_with_routes() -> (
     >,
     OutgoingRequests,
 ) {
+    let outgoing_requests: Arc<Mutex<Vec<OutgoingRequest<TestAccount>>>> =
+        Arc::new(Mutex::new(Vec::new()));
+    let outgoing_requests_clone = outgoing_requests.clone();
+    let outgoing = outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+        (*outgoing_requests_clone.lock()).push(request);
+        ok(CCP_RESPONSE.clone())
+    });
+    (
+        test_service_with_routes_and_outgoing(outgoing),
+        outgoing_requests,
+    )
+}
+
+pub fn test_service_with_routes_and_outgoing<O>(
+    outgoing: O,
+) -> CcpRouteManager<
+    impl IncomingService<TestAccount, Future = BoxedIlpFuture> + Clone,
+    O,
+    TestStore,
+    TestAccount,
+>
+where
+    O: OutgoingService<TestAccount> + Clone + Send + Sync + 'static,
+{
     let local_routes = HashMap::from_iter(vec![
         (
             Bytes::from("example.local.1"),
@@ -237,6 +329,10 @@ pub fn test_service_with_routes() -> (
                 send_routes: false,
                 receive_routes: false,
                 relation: RoutingRelation::Child,
+                route_auth_key: None,
+                import_policy: None,
+                export_policy: None,
+                route_cost: 0,
             },
         ),
     ]);
@@ -245,31 +341,23 @@ pub fn test_service_with_routes() -> (
         TestAccount::new(2, "example.configured.1"),
     )]);
     let store = TestStore::with_routes(local_routes, configured_routes);
-    let outgoing_requests: Arc<Mutex<Vec<OutgoingRequest<TestAccount>>>> =
-        Arc::new(Mutex::new(Vec::new()));
-    let outgoing_requests_clone = outgoing_requests.clone();
-    let outgoing = outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
-        (*outgoing_requests_clone.lock()).push(request);
-        Ok(CCP_RESPONSE.clone())
-    });
     let addr = Address::from_str("example.connector").unwrap();
-    let service = CcpRouteManagerBuilder::new(
+    CcpRouteManagerBuilder::new(
         addr.clone(),
         store,
         outgoing,
         incoming_service_fn(|_request| {
-            Box::new(err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: b"No other incoming handler!",
                 data: &[],
                 triggered_by: Some(&EXAMPLE_CONNECTOR),
             }
-            .build()))
+            .build())
         }),
     )
     .disable_spawn()
     .ilp_address(addr)
-    .to_service();
-    (service, outgoing_requests)
+    .to_service()
 }
 /* kcov-ignore-end */
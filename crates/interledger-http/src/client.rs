@@ -1,9 +1,7 @@
 use super::{HttpAccount, HttpStore};
 use bytes::BytesMut;
-use futures::{
-    future::{err, result},
-    Future, Stream,
-};
+use futures::{future::result, Future, Stream};
+use futures03::{compat::Future01CompatExt, future::err};
 use interledger_packet::{Address, ErrorCode, Fulfill, Packet, Reject, RejectBuilder};
 use interledger_service::*;
 use log::{error, trace};
@@ -73,7 +71,7 @@ where
             let auth = match AuthToken::from_str(token) {
                 Ok(auth) => auth,
                 Err(_) => {
-                    return Box::new(err(RejectBuilder {
+                    return Box::pin(err(RejectBuilder {
                         code: ErrorCode::T00_INTERNAL_ERROR,
                         message: format!("Cannot parse authorization token {}", token)
                             .as_str()
@@ -84,7 +82,7 @@ where
                     .build()))
                 }
             };
-            Box::new(
+            Box::pin(
                 self.client
                     .post(url.as_ref())
                     .header("authorization", auth.to_bearer())
@@ -100,10 +98,11 @@ where
                         }
                         .build()
                     })
-                    .and_then(move |resp| parse_packet_from_response(resp, ilp_address_clone)),
+                    .and_then(move |resp| parse_packet_from_response(resp, ilp_address_clone))
+                    .compat(),
             )
         } else {
-            Box::new(self.next.send_request(request))
+            Box::pin(self.next.send_request(request))
         }
     }
 }
@@ -2,8 +2,8 @@
 //!
 //! Client and server implementations of the [ILP-Over-HTTP](https://github.com/interledger/rfcs/blob/master/0035-ilp-over-http/0035-ilp-over-http.md) bilateral communication protocol.
 //! This protocol is intended primarily for server-to-server communication between peers on the Interledger network.
-use futures::Future;
-use interledger_service::{Account, Username};
+use interledger_service::{Account, StoreError, Username};
+use std::{future::Future, pin::Pin};
 use url::Url;
 
 mod client;
@@ -31,5 +31,5 @@ pub trait HttpStore: Clone + Send + Sync + 'static {
         &self,
         username: &Username,
         token: &str,
-    ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<Self::Account, StoreError>> + Send>>;
 }
@@ -5,6 +5,7 @@ use futures::{
     future::{err, Either},
     Future, Stream,
 };
+use futures03::{FutureExt, TryFutureExt};
 use hyper::{
     body::Body, header::AUTHORIZATION, service::Service as HttpService, Error, Request, Response,
 };
@@ -52,9 +53,26 @@ where
                         &authorization.username(),
                         &authorization.password(),
                     )
-                    .map_err(move |_err| {
-                        error!("Authorization not found in the DB: {:?}", authorization);
-                        Response::builder().status(401).body(Body::empty()).unwrap()
+                    .compat()
+                    .map_err(move |err| {
+                        let status = match err {
+                            StoreError::NotFound(_) => {
+                                error!("Authorization not found in the DB: {:?}", authorization);
+                                401
+                            }
+                            StoreError::Unavailable(_) => {
+                                error!("Error checking authorization: {}", err);
+                                503
+                            }
+                            err => {
+                                error!("Error checking authorization: {}", err);
+                                500
+                            }
+                        };
+                        Response::builder()
+                            .status(status)
+                            .body(Body::empty())
+                            .unwrap()
                     }),
             )
         } else {
@@ -82,7 +100,10 @@ where
                         next.handle_request(IncomingRequest {
                             from: from_account,
                             prepare,
+                            extensions: Extensions::new(),
                         })
+                        .boxed()
+                        .compat()
                         .then(ilp_response_to_http_response)
                     },
                 )
@@ -1,6 +1,6 @@
 use super::packet::*;
 use super::IldcpAccount;
-use futures::future::ok;
+use futures03::future::ok;
 use interledger_packet::*;
 use interledger_service::*;
 use log::debug;
@@ -45,9 +45,9 @@ where
             debug!("Responding to query for ildcp info by account: {:?}", from);
             let response = builder.build();
             let fulfill = Fulfill::from(response);
-            Box::new(ok(fulfill))
+            Box::pin(ok(fulfill))
         } else {
-            Box::new(self.next.handle_request(request))
+            Box::pin(self.next.handle_request(request))
         }
     }
 }
@@ -12,20 +12,23 @@
 //! store can either be configured or populated using the `CcpRouteManager`
 //! (see the `interledger-ccp` crate for more details).
 
-use bytes::Bytes;
 use interledger_service::{Account, AccountStore};
-use std::collections::HashMap;
+use std::sync::Arc;
 
 mod router;
+mod routing_table;
 
-pub use self::router::Router;
+pub use self::router::{RoutedPrefix, Router, RoutingPolicy};
+pub use self::routing_table::{Iter, NextHop, RoutingTable};
 
 /// A trait for Store implmentations that have ILP routing tables.
 pub trait RouterStore: AccountStore + Clone + Send + Sync + 'static {
-    /// **Synchronously** return a copy of the routing table.
+    /// **Synchronously** return a snapshot of the routing table.
     /// Note that this is synchronous because it assumes that Stores should
     /// keep the routing table in memory and use PubSub or polling to keep it updated.
     /// This ensures that individual packets can be routed without hitting the underlying store.
-    // TODO avoid using HashMap because it means it'll be cloned a lot
-    fn routing_table(&self) -> HashMap<Bytes, <Self::Account as Account>::AccountId>;
+    ///
+    /// Stores should build a new table whenever the routes change and hand out clones of
+    /// the same `Arc` in the meantime, so that getting the table does not copy it.
+    fn routing_table(&self) -> Arc<RoutingTable<<Self::Account as Account>::AccountId>>;
 }
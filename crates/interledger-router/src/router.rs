@@ -1,10 +1,43 @@
-use super::RouterStore;
+use super::{NextHop, RouterStore};
 use bytes::Bytes;
-use futures::{future::err, Future};
-use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use futures03::future::err;
+use interledger_packet::{Address, ErrorClass, ErrorCode, Reject, RejectBuilder};
 use interledger_service::*;
-use log::{error, trace};
+use log::{debug, error, trace};
+use serde::Deserialize;
 use std::str;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::SystemTime;
+
+/// The routing table prefix that the `Router` used to choose the next hop.
+///
+/// This is added to the request's `Extensions` so that services after the router can see
+/// which route was taken. For exact routes it is the full destination address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoutedPrefix(pub Bytes);
+
+/// How the `Router` picks between the next hops when a prefix has more than one.
+///
+/// With either policy, the Router tries the other next hops in order if the one it
+/// picked rejects the packet with a temporary (T-family) error from another node or
+/// cannot reach its peer, for as long as the Prepare has not expired.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingPolicy {
+    /// Always try the highest ranked next hop first and only use the others as backups
+    PrimaryBackup,
+    /// Spread packets between the next hops in proportion to their weights
+    Weighted,
+}
+
+impl Default for RoutingPolicy {
+    fn default() -> Self {
+        RoutingPolicy::PrimaryBackup
+    }
+}
 
 /// # Interledger Router
 ///
@@ -22,12 +55,19 @@ use std::str;
 ///   - reduce the Prepare packet's expiry
 ///
 /// That is done by OutgoingServices.
+///
+/// If a prefix has several next hops, the Router picks one according to its `RoutingPolicy`
+/// and fails over to the others when a packet is rejected with a temporary error.
 
 #[derive(Clone)]
 pub struct Router<S, O> {
     ilp_address: Address,
     store: S,
     next: O,
+    policy: RoutingPolicy,
+    /// Shared between clones of the Router so that the weighted policy spreads
+    /// all of the node's packets rather than each clone's separately
+    counter: Arc<AtomicUsize>,
 }
 
 impl<S, O> Router<S, O>
@@ -40,7 +80,45 @@ where
             ilp_address,
             store,
             next,
+            policy: RoutingPolicy::default(),
+            counter: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Set how the Router chooses between multiple next hops for the same prefix.
+    /// Defaults to `RoutingPolicy::PrimaryBackup`.
+    pub fn policy(&mut self, policy: RoutingPolicy) -> &mut Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns the IDs of the accounts to try, in order.
+    fn order_next_hops<T: Copy>(&self, next_hops: &[NextHop<T>]) -> Vec<T> {
+        let mut order: Vec<T> = next_hops.iter().map(|hop| hop.account_id).collect();
+        if self.policy == RoutingPolicy::Weighted && next_hops.len() > 1 {
+            let total_weight: u64 = next_hops.iter().map(|hop| u64::from(hop.weight)).sum();
+            if total_weight > 0 {
+                // Deterministic weighted round robin: each hop gets a run of
+                // `weight` consecutive slots out of every `total_weight` packets
+                let mut slot = self.counter.fetch_add(1, Ordering::Relaxed) as u64 % total_weight;
+                let chosen = next_hops
+                    .iter()
+                    .position(|hop| {
+                        let weight = u64::from(hop.weight);
+                        if slot < weight {
+                            true
+                        } else {
+                            slot -= weight;
+                            false
+                        }
+                    })
+                    .unwrap_or(0);
+                // The rest stay in rank order as backups
+                let primary = order.remove(chosen);
+                order.insert(0, primary);
+            }
         }
+        order
     }
 }
 
@@ -53,99 +131,118 @@ where
 
     /// Figures out the next node to pass the received Prepare packet to.
     ///
-    /// Firstly, it checks if there is a direct path for that account and use that.
-    /// If not it scans through the routing table and checks if the route prefix matches
-    /// the prepare packet's destination or if it's a catch-all address (i.e. empty prefix)
-    fn handle_request(&mut self, request: IncomingRequest<S::Account>) -> Self::Future {
+    /// It looks up the longest route prefix in the routing table that matches the
+    /// prepare packet's destination. An exact route for the destination is the longest
+    /// possible match, while the empty prefix is a catch-all that matches any address.
+    ///
+    /// If the next hop rejects the packet with a temporary error from another node, the request is sent
+    /// to the prefix's other next hops in turn until one of them handles it or the
+    /// Prepare expires.
+    fn handle_request(&mut self, mut request: IncomingRequest<S::Account>) -> Self::Future {
         let destination = request.prepare.destination();
-        let mut next_hop = None;
+        let mut next_hops = Vec::new();
+        let mut routed_prefix = Bytes::new();
         let routing_table = self.store.routing_table();
         let ilp_address = self.ilp_address.clone();
 
-        // Check if we have a direct path for that account or if we need to scan
-        // through the routing table
         let dest: &[u8] = destination.as_ref();
-        if let Some(account_id) = routing_table.get(dest) {
+        if let Some((prefix, hops)) = routing_table.resolve(dest) {
             trace!(
-                "Found direct route for address: \"{}\". Account: {}",
+                "Found matching route for address: \"{}\". Prefix: \"{}\", accounts: {:?}",
                 destination,
-                account_id
+                str::from_utf8(&prefix[..]).unwrap_or("<not utf8>"),
+                hops.iter().map(|hop| hop.account_id).collect::<Vec<_>>(),
             );
-            next_hop = Some(*account_id);
-        } else if !routing_table.is_empty() {
-            let mut matching_prefix = Bytes::new();
-            for route in self.store.routing_table() {
-                trace!(
-                    "Checking route: \"{}\" -> {}",
-                    str::from_utf8(&route.0[..]).unwrap_or("<not utf8>"),
-                    route.1
-                );
-                // Check if the route prefix matches or is empty (meaning it's a catch-all address)
-                if (route.0.is_empty() || dest.starts_with(&route.0[..]))
-                    && route.0.len() >= matching_prefix.len()
-                {
-                    next_hop.replace(route.1);
-                    matching_prefix = route.0;
-                }
-            }
-            if let Some(account_id) = next_hop {
-                trace!(
-                    "Found matching route for address: \"{}\". Prefix: \"{}\", account: {}",
-                    destination,
-                    str::from_utf8(&matching_prefix[..]).unwrap_or("<not utf8>"),
-                    account_id,
-                );
-            }
-        } else {
+            next_hops = self.order_next_hops(hops);
+            routed_prefix = prefix.clone();
+        } else if routing_table.is_empty() {
             error!("Unable to route request because routing table is empty");
         }
 
-        if let Some(account_id) = next_hop {
-            let mut next = self.next.clone();
-            Box::new(
-                self.store
-                    .get_accounts(vec![account_id])
-                    .map_err(move |_| {
-                        error!("No record found for account: {}", account_id);
-                        RejectBuilder {
-                            code: ErrorCode::F02_UNREACHABLE,
-                            message: &[],
-                            triggered_by: Some(&ilp_address),
-                            data: &[],
-                        }
-                        .build()
-                    })
-                    .and_then(move |mut accounts| {
-                        let request = request.into_outgoing(accounts.remove(0));
-                        next.send_request(request)
-                    }),
-            )
-        } else {
+        if next_hops.is_empty() {
             error!("No route found for request: {:?}", request);
-            Box::new(err(RejectBuilder {
+            return Box::pin(err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: &[],
                 triggered_by: Some(&ilp_address),
                 data: &[],
             }
-            .build()))
+            .build()));
         }
+
+        request.extensions.insert(RoutedPrefix(routed_prefix));
+        let mut next = self.next.clone();
+        let store = self.store.clone();
+        Box::pin(async move {
+            let expires_at = request.prepare.expires_at();
+            let mut result = None;
+            for (i, account_id) in next_hops.into_iter().enumerate() {
+                if i > 0 && SystemTime::now() >= expires_at {
+                    debug!("Prepare expired before it could be sent to another next hop");
+                    break;
+                }
+                let mut accounts = match store.get_accounts(vec![account_id]).await {
+                    Ok(accounts) => accounts,
+                    Err(err) => {
+                        error!("Error loading account {}: {}", account_id, err);
+                        // Keep the previous next hop's reject rather than replacing it with our own error
+                        if let Some(result) = result {
+                            return result;
+                        }
+                        return Err(RejectBuilder {
+                            code: err.reject_code(),
+                            message: &[],
+                            triggered_by: Some(&ilp_address),
+                            data: &[],
+                        }
+                        .build());
+                    }
+                };
+                let outgoing = request.clone().into_outgoing(accounts.remove(0));
+                match next.send_request(outgoing).await {
+                    Err(reject) if should_fail_over(&reject, &ilp_address) => {
+                        debug!(
+                            "Next hop {} rejected the packet with {}, trying the next one if there is one",
+                            account_id,
+                            reject.code()
+                        );
+                        result = Some(Err(reject));
+                    }
+                    other => return other,
+                }
+            }
+            result.expect("at least one next hop is tried")
+        })
     }
 }
 
+/// Whether a reject from one next hop means the packet may get through another one.
+///
+/// Only temporary errors are retried. Those raised by our own services (for example the
+/// incoming account's balance limit) would fail in the same way for every next hop, so
+/// apart from an unreachable peer, the reject must have been triggered by another node.
+fn should_fail_over(reject: &Reject, ilp_address: &Address) -> bool {
+    if reject.code().class() != ErrorClass::Temporary {
+        return false;
+    }
+    reject.code() == ErrorCode::T01_PEER_UNREACHABLE
+        || reject.triggered_by().as_ref() != Some(ilp_address)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use futures::future::ok;
+    use crate::{NextHop, RoutingTable};
+    use futures03::{executor::block_on, future::ok};
     use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
     use interledger_service::outgoing_service_fn;
     use lazy_static::lazy_static;
     use parking_lot::Mutex;
-    use std::collections::HashMap;
+    use std::future::Future;
     use std::iter::FromIterator;
+    use std::pin::Pin;
     use std::str::FromStr;
-    use std::sync::Arc;
-    use std::time::UNIX_EPOCH;
+    use std::time::{Duration, UNIX_EPOCH};
 
     #[derive(Debug, Clone)]
     struct TestAccount(u64);
@@ -167,7 +264,7 @@ mod tests {
 
     #[derive(Clone)]
     struct TestStore {
-        routes: HashMap<Bytes, u64>,
+        routes: Arc<RoutingTable<u64>>,
     }
 
     impl AccountStore for TestStore {
@@ -176,34 +273,94 @@ mod tests {
         fn get_accounts(
             &self,
             account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
-        ) -> Box<dyn Future<Item = Vec<TestAccount>, Error = ()> + Send> {
-            Box::new(ok(account_ids.into_iter().map(TestAccount).collect()))
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<TestAccount>, StoreError>> + Send>> {
+            Box::pin(ok(account_ids.into_iter().map(TestAccount).collect()))
         }
 
         // stub implementation (not used in these tests)
         fn get_account_id_from_username(
             &self,
             _username: &Username,
-        ) -> Box<dyn Future<Item = u64, Error = ()> + Send> {
-            Box::new(ok(1))
+        ) -> Pin<Box<dyn Future<Output = Result<u64, StoreError>> + Send>> {
+            Box::pin(ok(1))
         }
     }
 
     impl RouterStore for TestStore {
-        fn routing_table(&self) -> HashMap<Bytes, u64> {
+        fn routing_table(&self) -> Arc<RoutingTable<u64>> {
             self.routes.clone()
         }
     }
 
+    /// Has routes but cannot load any accounts
+    #[derive(Clone)]
+    struct UnavailableStore(TestStore);
+
+    impl AccountStore for UnavailableStore {
+        type Account = TestAccount;
+
+        fn get_accounts(
+            &self,
+            _account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<TestAccount>, StoreError>> + Send>> {
+            Box::pin(err(StoreError::Unavailable("Database is down".to_string())))
+        }
+
+        fn get_account_id_from_username(
+            &self,
+            _username: &Username,
+        ) -> Pin<Box<dyn Future<Output = Result<u64, StoreError>> + Send>> {
+            Box::pin(err(StoreError::Unavailable("Database is down".to_string())))
+        }
+    }
+
+    impl RouterStore for UnavailableStore {
+        fn routing_table(&self) -> Arc<RoutingTable<u64>> {
+            self.0.routing_table()
+        }
+    }
+
+    /// Can only load the first account
+    #[derive(Clone)]
+    struct PartiallyAvailableStore(TestStore);
+
+    impl AccountStore for PartiallyAvailableStore {
+        type Account = TestAccount;
+
+        fn get_accounts(
+            &self,
+            account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<TestAccount>, StoreError>> + Send>> {
+            if account_ids == vec![1] {
+                self.0.get_accounts(account_ids)
+            } else {
+                Box::pin(err(StoreError::Unavailable("Database is down".to_string())))
+            }
+        }
+
+        fn get_account_id_from_username(
+            &self,
+            username: &Username,
+        ) -> Pin<Box<dyn Future<Output = Result<u64, StoreError>> + Send>> {
+            self.0.get_account_id_from_username(username)
+        }
+    }
+
+    impl RouterStore for PartiallyAvailableStore {
+        fn routing_table(&self) -> Arc<RoutingTable<u64>> {
+            self.0.routing_table()
+        }
+    }
+
     #[test]
     fn empty_routing_table() {
         let mut router = Router::new(
             Address::from_str("example.connector").unwrap(),
             TestStore {
-                routes: HashMap::new(),
+                routes: Arc::new(RoutingTable::new()),
             },
             outgoing_service_fn(|_| {
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: &[],
                 }
@@ -211,8 +368,8 @@ mod tests {
             }),
         );
 
-        let result = router
-            .handle_request(IncomingRequest {
+        let result = block_on(
+            router.handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
                     destination: Address::from_str("example.destination").unwrap(),
@@ -222,8 +379,9 @@ mod tests {
                     data: &[],
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
         assert!(result.is_err());
     }
 
@@ -232,10 +390,13 @@ mod tests {
         let mut router = Router::new(
             Address::from_str("example.connector").unwrap(),
             TestStore {
-                routes: HashMap::from_iter(vec![(Bytes::from("example.other"), 1)].into_iter()),
+                routes: Arc::new(RoutingTable::from_iter(vec![(
+                    Bytes::from("example.other"),
+                    1,
+                )])),
             },
             outgoing_service_fn(|_| {
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: &[],
                 }
@@ -243,8 +404,8 @@ mod tests {
             }),
         );
 
-        let result = router
-            .handle_request(IncomingRequest {
+        let result = block_on(
+            router.handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
                     destination: Address::from_str("example.destination").unwrap(),
@@ -254,8 +415,9 @@ mod tests {
                     data: &[],
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
         assert!(result.is_err());
     }
 
@@ -264,12 +426,13 @@ mod tests {
         let mut router = Router::new(
             Address::from_str("example.connector").unwrap(),
             TestStore {
-                routes: HashMap::from_iter(
-                    vec![(Bytes::from("example.destination"), 1)].into_iter(),
-                ),
+                routes: Arc::new(RoutingTable::from_iter(vec![(
+                    Bytes::from("example.destination"),
+                    1,
+                )])),
             },
             outgoing_service_fn(|_| {
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: &[],
                 }
@@ -277,8 +440,8 @@ mod tests {
             }),
         );
 
-        let result = router
-            .handle_request(IncomingRequest {
+        let result = block_on(
+            router.handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
                     destination: Address::from_str("example.destination").unwrap(),
@@ -288,8 +451,9 @@ mod tests {
                     data: &[],
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
         assert!(result.is_ok());
     }
 
@@ -298,10 +462,10 @@ mod tests {
         let mut router = Router::new(
             Address::from_str("example.connector").unwrap(),
             TestStore {
-                routes: HashMap::from_iter(vec![(Bytes::from(""), 0)].into_iter()),
+                routes: Arc::new(RoutingTable::from_iter(vec![(Bytes::from(""), 0)])),
             },
             outgoing_service_fn(|_| {
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: &[],
                 }
@@ -309,8 +473,8 @@ mod tests {
             }),
         );
 
-        let result = router
-            .handle_request(IncomingRequest {
+        let result = block_on(
+            router.handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
                     destination: Address::from_str("example.destination").unwrap(),
@@ -320,8 +484,9 @@ mod tests {
                     data: &[],
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
         assert!(result.is_ok());
     }
 
@@ -330,10 +495,10 @@ mod tests {
         let mut router = Router::new(
             Address::from_str("example.connector").unwrap(),
             TestStore {
-                routes: HashMap::from_iter(vec![(Bytes::from("example."), 1)].into_iter()),
+                routes: Arc::new(RoutingTable::from_iter(vec![(Bytes::from("example."), 1)])),
             },
             outgoing_service_fn(|_| {
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: &[],
                 }
@@ -341,8 +506,8 @@ mod tests {
             }),
         );
 
-        let result = router
-            .handle_request(IncomingRequest {
+        let result = block_on(
+            router.handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
                     destination: Address::from_str("example.destination").unwrap(),
@@ -352,8 +517,9 @@ mod tests {
                     data: &[],
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
         assert!(result.is_ok());
     }
 
@@ -364,19 +530,16 @@ mod tests {
         let mut router = Router::new(
             Address::from_str("example.connector").unwrap(),
             TestStore {
-                routes: HashMap::from_iter(
-                    vec![
-                        (Bytes::from(""), 0),
-                        (Bytes::from("example.destination"), 2),
-                        (Bytes::from("example."), 1),
-                    ]
-                    .into_iter(),
-                ),
+                routes: Arc::new(RoutingTable::from_iter(vec![
+                    (Bytes::from(""), 0),
+                    (Bytes::from("example.destination"), 2),
+                    (Bytes::from("example."), 1),
+                ])),
             },
             outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
                 *to_clone.lock() = Some(request.to.clone());
 
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: &[],
                 }
@@ -384,8 +547,8 @@ mod tests {
             }),
         );
 
-        let result = router
-            .handle_request(IncomingRequest {
+        let result = block_on(
+            router.handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
                     destination: Address::from_str("example.destination").unwrap(),
@@ -395,9 +558,313 @@ mod tests {
                     data: &[],
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
         assert!(result.is_ok());
         assert_eq!(to.lock().take().unwrap().0, 2);
     }
+
+    #[test]
+    fn records_routed_prefix() {
+        let prefix: Arc<Mutex<Option<RoutedPrefix>>> = Arc::new(Mutex::new(None));
+        let prefix_clone = prefix.clone();
+        let mut router = Router::new(
+            Address::from_str("example.connector").unwrap(),
+            TestStore {
+                routes: Arc::new(RoutingTable::from_iter(vec![
+                    (Bytes::from(""), 0),
+                    (Bytes::from("example."), 1),
+                ])),
+            },
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                *prefix_clone.lock() = request.extensions.get::<RoutedPrefix>().cloned();
+
+                ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        let result = block_on(
+            router.handle_request(IncomingRequest {
+                from: TestAccount(0),
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    execution_condition: &[1; 32],
+                    expires_at: UNIX_EPOCH,
+                    data: &[],
+                }
+                .build(),
+                extensions: Extensions::new(),
+            }),
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            prefix.lock().take(),
+            Some(RoutedPrefix(Bytes::from("example.")))
+        );
+    }
+
+    #[test]
+    fn rejects_with_store_error_code() {
+        let mut router = Router::new(
+            Address::from_str("example.connector").unwrap(),
+            UnavailableStore(TestStore {
+                routes: Arc::new(RoutingTable::from_iter(vec![(Bytes::from("example."), 1)])),
+            }),
+            outgoing_service_fn(|_| {
+                ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        let result = block_on(
+            router.handle_request(IncomingRequest {
+                from: TestAccount(0),
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    execution_condition: &[1; 32],
+                    expires_at: UNIX_EPOCH,
+                    data: &[],
+                }
+                .build(),
+                extensions: Extensions::new(),
+            }),
+        );
+        assert_eq!(result.unwrap_err().code(), ErrorCode::T00_INTERNAL_ERROR);
+    }
+
+    fn multipath_routes(policy: RoutingPolicy) -> Arc<RoutingTable<u64>> {
+        let mut table = RoutingTable::new();
+        table.insert(Bytes::from("example."), 1);
+        table.add_next_hop(Bytes::from("example."), NextHop::new(2));
+        if policy == RoutingPolicy::Weighted {
+            table.add_next_hop(
+                Bytes::from("example."),
+                NextHop {
+                    account_id: 3,
+                    weight: 2,
+                },
+            );
+        }
+        Arc::new(table)
+    }
+
+    fn prepare_expiring_at(expires_at: SystemTime) -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount(0),
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                execution_condition: &[1; 32],
+                expires_at,
+                data: &[],
+            }
+            .build(),
+            extensions: Extensions::new(),
+        }
+    }
+
+    /// Rejects packets for the listed accounts with the given code and records every attempt
+    fn rejecting_service(
+        rejecting: &'static [u64],
+        code: ErrorCode,
+        attempts: Arc<Mutex<Vec<u64>>>,
+    ) -> impl OutgoingService<TestAccount> + Clone {
+        outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+            attempts.lock().push(request.to.0);
+            if rejecting.contains(&request.to.0) {
+                err(RejectBuilder {
+                    code,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build())
+            } else {
+                ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }
+        })
+    }
+
+    #[test]
+    fn fails_over_to_backup_on_temporary_error() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new(
+            Address::from_str("example.connector").unwrap(),
+            TestStore {
+                routes: multipath_routes(RoutingPolicy::PrimaryBackup),
+            },
+            rejecting_service(&[1], ErrorCode::T01_PEER_UNREACHABLE, attempts.clone()),
+        );
+
+        let result = block_on(router.handle_request(prepare_expiring_at(
+            SystemTime::now() + Duration::from_secs(30),
+        )));
+        assert!(result.is_ok());
+        assert_eq!(*attempts.lock(), vec![1, 2]);
+    }
+
+    #[test]
+    fn returns_last_reject_if_all_next_hops_fail() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new(
+            Address::from_str("example.connector").unwrap(),
+            TestStore {
+                routes: multipath_routes(RoutingPolicy::PrimaryBackup),
+            },
+            rejecting_service(
+                &[1, 2],
+                ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                attempts.clone(),
+            ),
+        );
+
+        let result = block_on(router.handle_request(prepare_expiring_at(
+            SystemTime::now() + Duration::from_secs(30),
+        )));
+        assert_eq!(
+            result.unwrap_err().code(),
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+        );
+        assert_eq!(*attempts.lock(), vec![1, 2]);
+    }
+
+    #[test]
+    fn does_not_retry_final_errors() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new(
+            Address::from_str("example.connector").unwrap(),
+            TestStore {
+                routes: multipath_routes(RoutingPolicy::PrimaryBackup),
+            },
+            rejecting_service(&[1], ErrorCode::F99_APPLICATION_ERROR, attempts.clone()),
+        );
+
+        let result = block_on(router.handle_request(prepare_expiring_at(
+            SystemTime::now() + Duration::from_secs(30),
+        )));
+        assert_eq!(result.unwrap_err().code(), ErrorCode::F99_APPLICATION_ERROR);
+        assert_eq!(*attempts.lock(), vec![1]);
+    }
+
+    #[test]
+    fn does_not_retry_expired_prepares() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new(
+            Address::from_str("example.connector").unwrap(),
+            TestStore {
+                routes: multipath_routes(RoutingPolicy::PrimaryBackup),
+            },
+            rejecting_service(&[1], ErrorCode::T01_PEER_UNREACHABLE, attempts.clone()),
+        );
+
+        let result = block_on(router.handle_request(prepare_expiring_at(UNIX_EPOCH)));
+        assert_eq!(result.unwrap_err().code(), ErrorCode::T01_PEER_UNREACHABLE);
+        assert_eq!(*attempts.lock(), vec![1]);
+    }
+
+    #[test]
+    fn weighted_policy_spreads_packets_by_weight() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new(
+            Address::from_str("example.connector").unwrap(),
+            TestStore {
+                routes: multipath_routes(RoutingPolicy::Weighted),
+            },
+            rejecting_service(&[], ErrorCode::T01_PEER_UNREACHABLE, attempts.clone()),
+        );
+        router.policy(RoutingPolicy::Weighted);
+
+        for _ in 0..8 {
+            let result = block_on(router.clone().handle_request(prepare_expiring_at(
+                SystemTime::now() + Duration::from_secs(30),
+            )));
+            assert!(result.is_ok());
+        }
+        assert_eq!(*attempts.lock(), vec![1, 2, 3, 3, 1, 2, 3, 3]);
+    }
+
+    #[test]
+    fn weighted_policy_fails_over_in_rank_order() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new(
+            Address::from_str("example.connector").unwrap(),
+            TestStore {
+                routes: multipath_routes(RoutingPolicy::Weighted),
+            },
+            rejecting_service(&[1, 2], ErrorCode::T01_PEER_UNREACHABLE, attempts.clone()),
+        );
+        router.policy(RoutingPolicy::Weighted);
+
+        // The second packet goes to account 2 first, then falls back to 1 and 3
+        for _ in 0..2 {
+            let result = block_on(router.handle_request(prepare_expiring_at(
+                SystemTime::now() + Duration::from_secs(30),
+            )));
+            assert!(result.is_ok());
+        }
+        assert_eq!(*attempts.lock(), vec![1, 2, 3, 2, 1, 3]);
+    }
+
+    #[test]
+    fn does_not_retry_temporary_errors_from_this_node() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let attempts_clone = attempts.clone();
+        let mut router = Router::new(
+            Address::from_str("example.connector").unwrap(),
+            TestStore {
+                routes: multipath_routes(RoutingPolicy::PrimaryBackup),
+            },
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                attempts_clone.lock().push(request.to.0);
+                err(RejectBuilder {
+                    code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: &[],
+                    triggered_by: Some(&Address::from_str("example.connector").unwrap()),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        let result = block_on(router.handle_request(prepare_expiring_at(
+            SystemTime::now() + Duration::from_secs(30),
+        )));
+        assert_eq!(
+            result.unwrap_err().code(),
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+        );
+        assert_eq!(*attempts.lock(), vec![1]);
+    }
+
+    #[test]
+    fn keeps_reject_if_backup_account_cannot_be_loaded() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new(
+            Address::from_str("example.connector").unwrap(),
+            PartiallyAvailableStore(TestStore {
+                routes: multipath_routes(RoutingPolicy::PrimaryBackup),
+            }),
+            rejecting_service(&[1], ErrorCode::T01_PEER_UNREACHABLE, attempts.clone()),
+        );
+
+        let result = block_on(router.handle_request(prepare_expiring_at(
+            SystemTime::now() + Duration::from_secs(30),
+        )));
+        assert_eq!(result.unwrap_err().code(), ErrorCode::T01_PEER_UNREACHABLE);
+        assert_eq!(*attempts.lock(), vec![1]);
+    }
 }
@@ -0,0 +1,518 @@
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Index;
+
+/// A routing table that maps ILP address prefixes to values (usually account IDs).
+///
+/// The prefixes are stored in a radix tree, so finding the longest prefix that matches
+/// a destination address only depends on the length of the address and not on the
+/// number of routes in the table.
+///
+/// Prefixes are matched byte-by-byte, just like `starts_with`. The empty prefix
+/// matches every address and can be used as a catch-all route.
+///
+/// Each prefix has one or more next hops, ranked from most to least preferred.
+/// Methods that return a single value refer to the first (primary) next hop.
+#[derive(Clone)]
+pub struct RoutingTable<T> {
+    root: Node<Vec<NextHop<T>>>,
+    len: usize,
+}
+
+/// One of the accounts that packets for a prefix can be forwarded to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NextHop<T> {
+    pub account_id: T,
+    /// This hop's share of the packets when the Router balances the load between hops
+    pub weight: u32,
+}
+
+impl<T> NextHop<T> {
+    pub fn new(account_id: T) -> Self {
+        NextHop {
+            account_id,
+            weight: 1,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Node<V> {
+    /// The part of the prefix between the parent node and this one
+    label: Bytes,
+    /// The full prefix and its value, if a route ends at this node
+    entry: Option<(Bytes, V)>,
+    /// Sorted by the first byte of their labels, which are never empty
+    children: Vec<Node<V>>,
+}
+
+impl<V> Node<V> {
+    fn new(label: Bytes) -> Self {
+        Node {
+            label,
+            entry: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn child_index(&self, byte: u8) -> Result<usize, usize> {
+        self.children
+            .binary_search_by_key(&byte, |child| child.label[0])
+    }
+
+    fn insert(&mut self, rest: &[u8], prefix: Bytes, value: V) -> Option<V> {
+        if rest.is_empty() {
+            return self.entry.replace((prefix, value)).map(|(_, value)| value);
+        }
+
+        let index = match self.child_index(rest[0]) {
+            Ok(index) => index,
+            Err(index) => {
+                let mut leaf = Node::new(prefix.slice_from(prefix.len() - rest.len()));
+                leaf.entry = Some((prefix, value));
+                self.children.insert(index, leaf);
+                return None;
+            }
+        };
+
+        let child = &mut self.children[index];
+        let common = common_prefix_len(&child.label, rest);
+        if common < child.label.len() {
+            // Split the child's label so the new prefix ends at (or branches off from) the split
+            let mut split = Node::new(child.label.slice_to(common));
+            let mut old_child = std::mem::replace(child, Node::new(Bytes::new()));
+            old_child.label = old_child.label.slice_from(common);
+            split.children.push(old_child);
+            *child = split;
+        }
+        child.insert(&rest[common..], prefix, value)
+    }
+
+    fn remove(&mut self, rest: &[u8]) -> Option<V> {
+        if rest.is_empty() {
+            return self.entry.take().map(|(_, value)| value);
+        }
+
+        let index = self.child_index(rest[0]).ok()?;
+        let child = &mut self.children[index];
+        if !rest.starts_with(&child.label) {
+            return None;
+        }
+        let removed = child.remove(&rest[child.label.len()..]);
+
+        // Keep the tree compressed by dropping or merging nodes that no longer hold a route
+        if child.entry.is_none() {
+            match child.children.len() {
+                0 => {
+                    self.children.remove(index);
+                }
+                1 => {
+                    let mut grandchild = child.children.remove(0);
+                    let mut label = child.label.clone();
+                    label.extend_from_slice(&grandchild.label);
+                    grandchild.label = label;
+                    *child = grandchild;
+                }
+                _ => {}
+            }
+        }
+        removed
+    }
+
+    fn find(&self, prefix: &[u8]) -> Option<&Self> {
+        let mut node = self;
+        let mut rest = prefix;
+        while !rest.is_empty() {
+            node = &node.children[node.child_index(rest[0]).ok()?];
+            if !rest.starts_with(&node.label) {
+                return None;
+            }
+            rest = &rest[node.label.len()..];
+        }
+        Some(node)
+    }
+
+    fn find_mut(&mut self, prefix: &[u8]) -> Option<&mut Self> {
+        let mut node = self;
+        let mut rest = prefix;
+        while !rest.is_empty() {
+            let index = node.child_index(rest[0]).ok()?;
+            node = &mut node.children[index];
+            if !rest.starts_with(&node.label) {
+                return None;
+            }
+            rest = &rest[node.label.len()..];
+        }
+        Some(node)
+    }
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b.iter()).take_while(|(a, b)| a == b).count()
+}
+
+impl<T> RoutingTable<T> {
+    pub fn new() -> Self {
+        RoutingTable {
+            root: Node::new(Bytes::new()),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Add a route with a single next hop, replacing any next hops the prefix already had.
+    /// Returns the previous primary next hop.
+    pub fn insert(&mut self, prefix: Bytes, account_id: T) -> Option<T> {
+        self.set_next_hops(prefix, vec![NextHop::new(account_id)])
+            .map(|mut next_hops| next_hops.swap_remove(0).account_id)
+    }
+
+    /// Set the ranked next hops for a prefix, returning the previous ones.
+    /// Setting an empty list removes the route.
+    pub fn set_next_hops(
+        &mut self,
+        prefix: Bytes,
+        next_hops: Vec<NextHop<T>>,
+    ) -> Option<Vec<NextHop<T>>> {
+        if next_hops.is_empty() {
+            return self.remove_next_hops(&prefix);
+        }
+        let rest = prefix.clone();
+        let previous = self.root.insert(&rest, prefix, next_hops);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    /// Add a next hop for a prefix, ranked below the ones it already has.
+    pub fn add_next_hop(&mut self, prefix: Bytes, next_hop: NextHop<T>) {
+        match self
+            .root
+            .find_mut(&prefix)
+            .and_then(|node| node.entry.as_mut())
+        {
+            Some((_, next_hops)) => next_hops.push(next_hop),
+            None => {
+                self.set_next_hops(prefix, vec![next_hop]);
+            }
+        }
+    }
+
+    /// Remove the route for exactly the given prefix, returning its primary next hop.
+    pub fn remove(&mut self, prefix: &[u8]) -> Option<T> {
+        self.remove_next_hops(prefix)
+            .map(|mut next_hops| next_hops.swap_remove(0).account_id)
+    }
+
+    fn remove_next_hops(&mut self, prefix: &[u8]) -> Option<Vec<NextHop<T>>> {
+        let removed = self.root.remove(prefix);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Get the primary next hop for exactly the given prefix.
+    pub fn get(&self, prefix: &[u8]) -> Option<&T> {
+        self.next_hops(prefix)
+            .map(|next_hops| &next_hops[0].account_id)
+    }
+
+    /// Get all of the next hops for exactly the given prefix.
+    pub fn next_hops(&self, prefix: &[u8]) -> Option<&[NextHop<T>]> {
+        self.root
+            .find(prefix)
+            .and_then(|node| node.entry.as_ref())
+            .map(|(_, next_hops)| &next_hops[..])
+    }
+
+    pub fn contains_key(&self, prefix: &[u8]) -> bool {
+        self.next_hops(prefix).is_some()
+    }
+
+    /// Find the longest prefix in the table that the given address starts with,
+    /// along with its primary next hop.
+    pub fn longest_prefix_match(&self, address: &[u8]) -> Option<(&Bytes, &T)> {
+        self.resolve(address)
+            .map(|(prefix, next_hops)| (prefix, &next_hops[0].account_id))
+    }
+
+    /// Find the longest prefix in the table that the given address starts with,
+    /// along with all of its next hops.
+    pub fn resolve(&self, address: &[u8]) -> Option<(&Bytes, &[NextHop<T>])> {
+        let mut node = &self.root;
+        let mut rest = address;
+        let mut matched = node.entry.as_ref();
+        while !rest.is_empty() {
+            node = match node.child_index(rest[0]) {
+                Ok(index) => &node.children[index],
+                Err(_) => break,
+            };
+            if !rest.starts_with(&node.label) {
+                break;
+            }
+            rest = &rest[node.label.len()..];
+            if node.entry.is_some() {
+                matched = node.entry.as_ref();
+            }
+        }
+        matched.map(|(prefix, next_hops)| (prefix, &next_hops[..]))
+    }
+
+    /// Iterate over all of the routes and their primary next hops, sorted by prefix.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            entries: self.entries(),
+        }
+    }
+
+    fn entries(&self) -> Entries<'_, Vec<NextHop<T>>> {
+        Entries {
+            stack: vec![&self.root],
+        }
+    }
+}
+
+impl<T> Default for RoutingTable<T> {
+    fn default() -> Self {
+        RoutingTable::new()
+    }
+}
+
+impl<T: PartialEq> PartialEq for RoutingTable<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.entries().eq(other.entries())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RoutingTable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.entries()).finish()
+    }
+}
+
+impl<T> FromIterator<(Bytes, T)> for RoutingTable<T> {
+    fn from_iter<I: IntoIterator<Item = (Bytes, T)>>(iter: I) -> Self {
+        let mut table = RoutingTable::new();
+        for (prefix, value) in iter {
+            table.insert(prefix, value);
+        }
+        table
+    }
+}
+
+impl<T: Clone> From<RoutingTable<T>> for HashMap<Bytes, T> {
+    fn from(table: RoutingTable<T>) -> Self {
+        table
+            .iter()
+            .map(|(prefix, value)| (prefix.clone(), value.clone()))
+            .collect()
+    }
+}
+
+impl<T> From<HashMap<Bytes, T>> for RoutingTable<T> {
+    fn from(map: HashMap<Bytes, T>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<T, K> Index<&K> for RoutingTable<T>
+where
+    K: AsRef<[u8]> + ?Sized,
+{
+    type Output = T;
+
+    fn index(&self, prefix: &K) -> &T {
+        self.get(prefix.as_ref()).expect("no route for prefix")
+    }
+}
+
+/// An iterator over the routes in a `RoutingTable` and their primary next hops, sorted by prefix.
+pub struct Iter<'a, T> {
+    entries: Entries<'a, Vec<NextHop<T>>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (&'a Bytes, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries
+            .next()
+            .map(|(prefix, next_hops)| (prefix, &next_hops[0].account_id))
+    }
+}
+
+/// Visits the nodes that hold a route in sorted order
+struct Entries<'a, V> {
+    stack: Vec<&'a Node<V>>,
+}
+
+impl<'a, V> Iterator for Entries<'a, V> {
+    type Item = (&'a Bytes, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            // Push the children in reverse so they are visited in sorted order
+            self.stack.extend(node.children.iter().rev());
+            if let Some((prefix, value)) = node.entry.as_ref() {
+                return Some((prefix, value));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(routes: &[(&'static str, u64)]) -> RoutingTable<u64> {
+        routes
+            .iter()
+            .map(|(prefix, value)| (Bytes::from(*prefix), *value))
+            .collect()
+    }
+
+    #[test]
+    fn gets_exact_prefixes() {
+        let table = table(&[("example.a", 1), ("example.ab", 2), ("example.", 3)]);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get(b"example.a"), Some(&1));
+        assert_eq!(table.get(b"example.ab"), Some(&2));
+        assert_eq!(table.get(b"example."), Some(&3));
+        assert_eq!(table.get(b"example"), None);
+        assert_eq!(table.get(b"example.abc"), None);
+    }
+
+    #[test]
+    fn replaces_existing_prefix() {
+        let mut table = table(&[("example.a", 1)]);
+        assert_eq!(table.insert(Bytes::from("example.a"), 2), Some(1));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table[&b"example.a"[..]], 2);
+    }
+
+    #[test]
+    fn finds_longest_matching_prefix() {
+        let table = table(&[
+            ("example.a", 1),
+            ("example.a.b.c", 2),
+            ("example.a.b", 3),
+            ("", 0),
+        ]);
+        let lookup = |address: &[u8]| {
+            table
+                .longest_prefix_match(address)
+                .map(|(prefix, value)| (prefix.clone(), *value))
+        };
+        assert_eq!(lookup(b"example.a"), Some((Bytes::from("example.a"), 1)));
+        assert_eq!(
+            lookup(b"example.a.b.c"),
+            Some((Bytes::from("example.a.b.c"), 2))
+        );
+        assert_eq!(
+            lookup(b"example.a.b.c.d.e"),
+            Some((Bytes::from("example.a.b.c"), 2))
+        );
+        assert_eq!(
+            lookup(b"example.a.bx"),
+            Some((Bytes::from("example.a.b"), 3))
+        );
+        assert_eq!(lookup(b"example.other"), Some((Bytes::new(), 0)));
+    }
+
+    #[test]
+    fn no_match_without_catch_all() {
+        let table = table(&[("example.a", 1)]);
+        assert!(table.longest_prefix_match(b"example.b").is_none());
+        assert!(table.longest_prefix_match(b"example.").is_none());
+    }
+
+    #[test]
+    fn removes_prefixes_and_keeps_others() {
+        let mut table = table(&[("example.a", 1), ("example.ab", 2), ("example.ac", 3)]);
+        assert_eq!(table.remove(b"example.a"), Some(1));
+        assert_eq!(table.remove(b"example.a"), None);
+        assert_eq!(table.remove(b"example."), None);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(b"example.ab"), Some(&2));
+        assert_eq!(table.get(b"example.ac"), Some(&3));
+        assert_eq!(table.longest_prefix_match(b"example.a.x"), None);
+
+        assert_eq!(table.remove(b"example.ab"), Some(2));
+        assert_eq!(table.remove(b"example.ac"), Some(3));
+        assert!(table.is_empty());
+        assert!(table.root.children.is_empty());
+    }
+
+    #[test]
+    fn keeps_ranked_next_hops() {
+        let mut table = table(&[("example.a", 1)]);
+        table.add_next_hop(Bytes::from("example.a"), NextHop::new(2));
+        table.add_next_hop(
+            Bytes::from("example.b"),
+            NextHop {
+                account_id: 3,
+                weight: 5,
+            },
+        );
+        assert_eq!(table.len(), 2);
+        assert_eq!(
+            table.next_hops(b"example.a").unwrap(),
+            &[NextHop::new(1), NextHop::new(2)][..]
+        );
+        let (prefix, next_hops) = table.resolve(b"example.a.x").unwrap();
+        assert_eq!(prefix, &Bytes::from("example.a"));
+        assert_eq!(next_hops.len(), 2);
+        assert_eq!(table.longest_prefix_match(b"example.a.x").unwrap().1, &1);
+        assert_eq!(table.next_hops(b"example.b").unwrap()[0].weight, 5);
+
+        // Inserting a single next hop replaces all of them
+        assert_eq!(table.insert(Bytes::from("example.a"), 4), Some(1));
+        assert_eq!(
+            table.next_hops(b"example.a").unwrap(),
+            &[NextHop::new(4)][..]
+        );
+
+        assert!(table
+            .set_next_hops(Bytes::from("example.b"), Vec::new())
+            .is_some());
+        assert!(!table.contains_key(b"example.b"));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn iterates_in_sorted_order() {
+        let table = table(&[
+            ("example.b", 2),
+            ("", 0),
+            ("example.a", 1),
+            ("example.ab", 3),
+        ]);
+        let prefixes: Vec<&[u8]> = table.iter().map(|(prefix, _)| &prefix[..]).collect();
+        assert_eq!(
+            prefixes,
+            vec![&b""[..], b"example.a", b"example.ab", b"example.b"]
+        );
+    }
+
+    #[test]
+    fn converts_to_and_from_hash_map() {
+        let mut map = HashMap::new();
+        map.insert(Bytes::from("example.a"), 1);
+        map.insert(Bytes::from("example.b"), 2);
+        let table = RoutingTable::from(map.clone());
+        assert_eq!(table, self::table(&[("example.b", 2), ("example.a", 1)]));
+        assert_eq!(HashMap::from(table), map);
+    }
+}
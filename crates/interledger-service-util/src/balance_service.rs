@@ -1,22 +1,25 @@
 use futures::Future;
+use futures03::{compat::Future01CompatExt, TryFutureExt};
 use interledger_ildcp::IldcpAccount;
-use interledger_packet::{Address, ErrorCode, Fulfill, Reject, RejectBuilder};
+use interledger_packet::{Address, RejectBuilder};
 use interledger_service::*;
 use interledger_settlement::{SettlementAccount, SettlementClient, SettlementStore};
 use log::{debug, error};
-use std::marker::PhantomData;
+use std::{marker::PhantomData, pin::Pin};
 use tokio_executor::spawn;
 
 pub trait BalanceStore: AccountStore {
     /// Fetch the current balance for the given account.
-    fn get_balance(&self, account: Self::Account)
-        -> Box<dyn Future<Item = i64, Error = ()> + Send>;
+    fn get_balance(
+        &self,
+        account: Self::Account,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<i64, StoreError>> + Send>>;
 
     fn update_balances_for_prepare(
         &self,
         from_account: Self::Account,
         incoming_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>>;
 
     /// Increases the account's balance, and returns the updated balance
     /// along with the amount which should be settled
@@ -24,13 +27,13 @@ pub trait BalanceStore: AccountStore {
         &self,
         to_account: Self::Account,
         outgoing_amount: u64,
-    ) -> Box<dyn Future<Item = (i64, u64), Error = ()> + Send>;
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(i64, u64), StoreError>> + Send>>;
 
     fn update_balances_for_reject(
         &self,
         from_account: Self::Account,
         incoming_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>>;
 }
 
 /// # Balance Service
@@ -64,6 +67,32 @@ where
     }
 }
 
+/// A layer that wraps outgoing services in a `BalanceService`.
+#[derive(Clone)]
+pub struct BalanceLayer<S> {
+    ilp_address: Address,
+    store: S,
+}
+
+impl<S> BalanceLayer<S> {
+    pub fn new(ilp_address: Address, store: S) -> Self {
+        BalanceLayer { ilp_address, store }
+    }
+}
+
+impl<S, O, A> OutgoingLayer<A, O> for BalanceLayer<S>
+where
+    S: BalanceStore<Account = A> + SettlementStore<Account = A> + Clone + Send + Sync + 'static,
+    O: OutgoingService<A> + Send + Clone + 'static,
+    A: Account + IldcpAccount + SettlementAccount + 'static,
+{
+    type Service = BalanceService<S, O, A>;
+
+    fn layer_outgoing(&self, next: O) -> Self::Service {
+        BalanceService::new(self.ilp_address.clone(), self.store.clone(), next)
+    }
+}
+
 impl<S, O, A> OutgoingService<A> for BalanceService<S, O, A>
 where
     S: BalanceStore<Account = A> + SettlementStore<Account = A> + Clone + Send + Sync + 'static,
@@ -80,16 +109,13 @@ where
     ///       INDEPENDENTLY of if the call suceeds or fails. This makes a `sendMoney` call if the fulfill puts the account's balance over the `settle_threshold`
     ///     - if it returns an reject calls `store.update_balances_for_reject` and replies with the fulfill
     ///       INDEPENDENTLY of if the call suceeds or fails
-    fn send_request(
-        &mut self,
-        request: OutgoingRequest<A>,
-    ) -> Box<dyn Future<Item = Fulfill, Error = Reject> + Send> {
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
         // Don't bother touching the store for zero-amount packets.
         // Note that it is possible for the original_amount to be >0 while the
         // prepare.amount is 0, because the original amount could be rounded down
         // to 0 when exchange rate and scale change are applied.
         if request.prepare.amount() == 0 && request.original_amount == 0 {
-            return Box::new(self.next.send_request(request));
+            return Box::pin(self.next.send_request(request));
         }
 
         let mut next = self.next.clone();
@@ -117,16 +143,17 @@ where
         //  _eventually_ be completed. Because of this settlement_engine guarantee, the Connector can
         // operate as-if the settlement engine has completed. Finally, if the request to the settlement-engine
         // fails, this amount will be re-added back to balance.
-        Box::new(
+        Box::pin(
             self.store
                 .update_balances_for_prepare(
                     from.clone(),
                     incoming_amount,
                 )
-                .map_err(move |_| {
-                    debug!("Rejecting packet because it would exceed a balance limit");
+                .compat()
+                .map_err(move |err| {
+                    debug!("Rejecting packet because the balance could not be updated: {}", err);
                     RejectBuilder {
-                        code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                        code: err.reject_code(),
                         message: &[],
                         triggered_by: Some(&ilp_address),
                         data: &[],
@@ -134,7 +161,8 @@ where
                     .build()
                 })
                 .and_then(move |_| {
-                    next.send_request(request)
+                    Box::pin(next.send_request(request))
+                        .compat()
                         .and_then(move |fulfill| {
                             // We will spawn a task to update the balances in the database
                             // so that we DO NOT wait for the database before sending the
@@ -148,6 +176,7 @@ where
                                 to.clone(),
                                 outgoing_amount,
                             )
+                            .compat()
                             .map_err(move |_| error!("Error applying balance changes for fulfill from account: {} to account: {}. Incoming amount was: {}, outgoing amount was: {}", from_id, to_id, incoming_amount, outgoing_amount))
                             .and_then(move |(balance, amount_to_settle)| {
                                 debug!("Account balance after fulfill: {}. Amount that needs to be settled: {}", balance, amount_to_settle);
@@ -162,7 +191,8 @@ where
                                     // load on the settlement engine.
                                     spawn(settlement_client
                                         .send_settlement(to, amount_to_settle)
-                                        .or_else(move |_| store.refund_settlement(to_id, amount_to_settle)));
+                                        .or_else(move |_| store.refund_settlement(to_id, amount_to_settle).compat()
+                                            .map_err(move |err| error!("Error refunding settlement for account: {}: {}", to_id, err))));
                                 }
                                 Ok(())
                             });
@@ -184,12 +214,13 @@ where
                             let reject_balance_update = store_clone.update_balances_for_reject(
                                 from_clone.clone(),
                                 incoming_amount,
-                            ).map_err(move |_| error!("Error rolling back balance change for accounts: {} and {}. Incoming amount was: {}, outgoing amount was: {}", from_clone.id(), to_clone.id(), incoming_amount, outgoing_amount));
+                            ).compat().map_err(move |_| error!("Error rolling back balance change for accounts: {} and {}. Incoming amount was: {}, outgoing amount was: {}", from_clone.id(), to_clone.id(), incoming_amount, outgoing_amount));
                             spawn(reject_balance_update);
 
                             Err(reject)
                         })
-                }),
+                })
+                .compat(),
         )
     }
 }
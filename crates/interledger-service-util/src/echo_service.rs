@@ -1,7 +1,7 @@
 use byteorder::ReadBytesExt;
 use bytes::{BufMut, BytesMut};
 use core::borrow::Borrow;
-use futures::future::err;
+use futures03::future::err;
 use interledger_packet::{
     oer::BufOerExt, Address, ErrorCode, Prepare, PrepareBuilder, RejectBuilder,
 };
@@ -49,6 +49,30 @@ where
     }
 }
 
+/// A layer that wraps incoming services in an `EchoService`.
+#[derive(Clone)]
+pub struct EchoLayer {
+    ilp_address: Address,
+}
+
+impl EchoLayer {
+    pub fn new(ilp_address: Address) -> Self {
+        EchoLayer { ilp_address }
+    }
+}
+
+impl<I, A> IncomingLayer<A, I> for EchoLayer
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    type Service = EchoService<I, A>;
+
+    fn layer_incoming(&self, next: I) -> Self::Service {
+        EchoService::new(self.ilp_address.clone(), next)
+    }
+}
+
 impl<I, A> IncomingService<A> for EchoService<I, A>
 where
     I: IncomingService<A>,
@@ -60,7 +84,7 @@ where
         let should_echo = request.prepare.destination() == self.ilp_address
             && request.prepare.data().starts_with(ECHO_PREFIX.as_bytes());
         if !should_echo {
-            return Box::new(self.next.handle_request(request));
+            return Box::pin(self.next.handle_request(request));
         }
         debug!("Responding to Echo protocol request: {:?}", request);
 
@@ -76,7 +100,7 @@ where
             Ok(value) => value,
             Err(error) => {
                 eprintln!("Could not read packet type: {:?}", error);
-                return Box::new(err(RejectBuilder {
+                return Box::pin(err(RejectBuilder {
                     code: ErrorCode::F01_INVALID_PACKET,
                     message: b"Could not read echo packet type.",
                     triggered_by: Some(&self.ilp_address),
@@ -88,11 +112,11 @@ where
         if echo_packet_type == EchoPacketType::Response as u8 {
             // if the echo packet type is Response, just pass it to the next service
             // so that the initiator could handle this packet
-            return Box::new(self.next.handle_request(request));
+            return Box::pin(self.next.handle_request(request));
         }
         if echo_packet_type != EchoPacketType::Request as u8 {
             eprintln!("The packet type is not acceptable: {}", echo_packet_type);
-            return Box::new(err(RejectBuilder {
+            return Box::pin(err(RejectBuilder {
                 code: ErrorCode::F01_INVALID_PACKET,
                 message: format!(
                     "The echo packet type: {} is not acceptable.",
@@ -114,7 +138,7 @@ where
                         "Could not parse source address from echo packet: {:?}",
                         error
                     );
-                    return Box::new(err(RejectBuilder {
+                    return Box::pin(err(RejectBuilder {
                         code: ErrorCode::F01_INVALID_PACKET,
                         message: b"Could not parse source address from Echo packet",
                         triggered_by: Some(&self.ilp_address),
@@ -125,7 +149,7 @@ where
             },
             Err(error) => {
                 eprintln!("Could not read source address: {:?}", error);
-                return Box::new(err(RejectBuilder {
+                return Box::pin(err(RejectBuilder {
                     code: ErrorCode::F01_INVALID_PACKET,
                     message: b"Could not read source address.",
                     triggered_by: Some(&self.ilp_address),
@@ -148,7 +172,7 @@ where
         }
         .build();
 
-        Box::new(self.next.handle_request(request))
+        Box::pin(self.next.handle_request(request))
     }
 }
 
@@ -212,7 +236,7 @@ impl<'a> EchoResponseBuilder<'a> {
 #[cfg(test)]
 mod echo_tests {
     use super::*;
-    use futures::future::Future;
+    use futures03::{executor::block_on, future::ok};
     use interledger_packet::{FulfillBuilder, PrepareBuilder};
     use interledger_service::incoming_service_fn;
     use lazy_static::lazy_static;
@@ -259,7 +283,7 @@ mod echo_tests {
             assert_eq!(request.prepare.execution_condition(), execution_condition);
             assert_eq!(request.prepare.destination(), destination);
             assert_eq!(request.prepare.data(), &data[..]);
-            Ok(FulfillBuilder {
+            ok(FulfillBuilder {
                 fulfillment: &fulfillment,
                 data,
             }
@@ -279,9 +303,11 @@ mod echo_tests {
         let from = TestAccount(1);
 
         // test
-        let result = echo_service
-            .handle_request(IncomingRequest { prepare, from })
-            .wait();
+        let result = block_on(echo_service.handle_request(IncomingRequest {
+            prepare,
+            from,
+            extensions: Extensions::new(),
+        }));
         assert!(result.is_ok());
     }
 
@@ -305,7 +331,7 @@ mod echo_tests {
             assert_eq!(request.prepare.execution_condition(), execution_condition);
             assert_eq!(request.prepare.destination(), dest);
             assert_eq!(request.prepare.data(), &data[..]);
-            Ok(FulfillBuilder {
+            ok(FulfillBuilder {
                 fulfillment: &fulfillment,
                 data: &[],
             }
@@ -325,9 +351,11 @@ mod echo_tests {
         let from = TestAccount(1);
 
         // test
-        let result = echo_service
-            .handle_request(IncomingRequest { prepare, from })
-            .wait();
+        let result = block_on(echo_service.handle_request(IncomingRequest {
+            prepare,
+            from,
+            extensions: Extensions::new(),
+        }));
         assert!(result.is_ok());
     }
 
@@ -351,7 +379,7 @@ mod echo_tests {
             assert_eq!(request.prepare.execution_condition(), execution_condition);
             assert_eq!(request.prepare.destination(), source_address);
             assert_eq!(request.prepare.data(), &data[..]);
-            Ok(FulfillBuilder {
+            ok(FulfillBuilder {
                 fulfillment: &fulfillment,
                 data,
             }
@@ -371,9 +399,11 @@ mod echo_tests {
         let from = TestAccount(1);
 
         // test
-        let result = echo_service
-            .handle_request(IncomingRequest { prepare, from })
-            .wait();
+        let result = block_on(echo_service.handle_request(IncomingRequest {
+            prepare,
+            from,
+            extensions: Extensions::new(),
+        }));
         assert!(result.is_ok());
     }
 
@@ -390,7 +420,7 @@ mod echo_tests {
 
         // setup service
         let handler = incoming_service_fn(|_| {
-            Err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F01_INVALID_PACKET,
                 message: &[],
                 triggered_by: None,
@@ -412,9 +442,11 @@ mod echo_tests {
         let from = TestAccount(1);
 
         // test
-        let result = echo_service
-            .handle_request(IncomingRequest { prepare, from })
-            .wait();
+        let result = block_on(echo_service.handle_request(IncomingRequest {
+            prepare,
+            from,
+            extensions: Extensions::new(),
+        }));
         assert!(result.is_err());
     }
 
@@ -432,7 +464,7 @@ mod echo_tests {
 
         // setup service
         let handler = incoming_service_fn(|_| {
-            Err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F01_INVALID_PACKET,
                 message: &[],
                 triggered_by: None,
@@ -454,9 +486,11 @@ mod echo_tests {
         let from = TestAccount(1);
 
         // test
-        let result = echo_service
-            .handle_request(IncomingRequest { prepare, from })
-            .wait();
+        let result = block_on(echo_service.handle_request(IncomingRequest {
+            prepare,
+            from,
+            extensions: Extensions::new(),
+        }));
         assert!(result.is_err());
     }
 
@@ -1,15 +1,22 @@
-use futures::{future::err, Future};
+use futures03::future::err;
 use interledger_ildcp::IldcpAccount;
-use interledger_packet::{Address, ErrorCode, Fulfill, Reject, RejectBuilder};
+use interledger_packet::{Address, ErrorCode, RejectBuilder};
 use interledger_service::*;
 use interledger_settlement::{Convert, ConvertDetails};
 use log::{error, trace};
 use std::marker::PhantomData;
 
 pub trait ExchangeRateStore {
-    fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, ()>;
+    fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, StoreError>;
 }
 
+/// The exchange rate that the `ExchangeRateService` applied to a request.
+///
+/// This is added to the request's `Extensions`. The rate is the price of the outgoing
+/// asset in the incoming asset, before adjusting for the difference in asset scales.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AppliedExchangeRate(pub f64);
+
 /// # Exchange Rates Service
 ///
 /// Responsible for getting the exchange rates for the two assets in the outgoing request (`request.from.asset_code`, `request.to.asset_code`).
@@ -38,6 +45,32 @@ where
     }
 }
 
+/// A layer that wraps outgoing services in an `ExchangeRateService`.
+#[derive(Clone)]
+pub struct ExchangeRateLayer<S> {
+    ilp_address: Address,
+    store: S,
+}
+
+impl<S> ExchangeRateLayer<S> {
+    pub fn new(ilp_address: Address, store: S) -> Self {
+        ExchangeRateLayer { ilp_address, store }
+    }
+}
+
+impl<S, O, A> OutgoingLayer<A, O> for ExchangeRateLayer<S>
+where
+    S: ExchangeRateStore + Clone + Send + Sync + 'static,
+    O: OutgoingService<A> + Send + Clone + 'static,
+    A: IldcpAccount + Sync + 'static,
+{
+    type Service = ExchangeRateService<S, O, A>;
+
+    fn layer_outgoing(&self, next: O) -> Self::Service {
+        ExchangeRateService::new(self.ilp_address.clone(), self.store.clone(), next)
+    }
+}
+
 impl<S, O, A> OutgoingService<A> for ExchangeRateService<S, O, A>
 where
     // TODO can we make these non-'static?
@@ -53,41 +86,38 @@ where
     ///     - return reject if the call to the store fails
     /// 1. Calculates the exchange rate AND scales it up/down depending on how many decimals each asset requires
     /// 1. Updates the amount in the prepare packet and forwards it
-    fn send_request(
-        &mut self,
-        mut request: OutgoingRequest<A>,
-    ) -> Box<dyn Future<Item = Fulfill, Error = Reject> + Send> {
+    fn send_request(&mut self, mut request: OutgoingRequest<A>) -> Self::Future {
         if request.prepare.amount() > 0 {
             let rate: f64 = if request.from.asset_code() == request.to.asset_code() {
                 1f64
-            } else if let Ok(rates) = self
-                .store
-                .get_exchange_rates(&[&request.from.asset_code(), &request.to.asset_code()])
-            {
-                rates[1] / rates[0]
             } else {
-                error!(
-                    "No exchange rates available for assets: {}, {}",
-                    request.from.asset_code(),
-                    request.to.asset_code()
-                );
-                return Box::new(err(RejectBuilder {
-                    // Unreachable doesn't seem to be the correct code here.
-                    // If the pair was not found, shouldn't we have a unique error code
-                    // for that such as `ErrorCode::F10_PAIRNOTFOUND` ?
-                    // Timeout should still apply we if we add a timeout
-                    // error in the get_exchange_rate call
-                    code: ErrorCode::F02_UNREACHABLE,
-                    message: format!(
-                        "No exchange rate available from asset: {} to: {}",
-                        request.from.asset_code(),
-                        request.to.asset_code()
-                    )
-                    .as_bytes(),
-                    triggered_by: Some(&self.ilp_address),
-                    data: &[],
+                match self
+                    .store
+                    .get_exchange_rates(&[request.from.asset_code(), request.to.asset_code()])
+                {
+                    Ok(rates) => rates[1] / rates[0],
+                    Err(store_err) => {
+                        error!(
+                            "No exchange rates available for assets: {}, {}: {}",
+                            request.from.asset_code(),
+                            request.to.asset_code(),
+                            store_err
+                        );
+                        return Box::pin(err(RejectBuilder {
+                            // A missing pair is F02 Unreachable, while a store failure is a temporary error
+                            code: store_err.reject_code(),
+                            message: format!(
+                                "No exchange rate available from asset: {} to: {}",
+                                request.from.asset_code(),
+                                request.to.asset_code()
+                            )
+                            .as_bytes(),
+                            triggered_by: Some(&self.ilp_address),
+                            data: &[],
+                        }
+                        .build()));
+                    }
                 }
-                .build()));
             };
 
             // Can we overflow here?
@@ -103,7 +133,7 @@ where
                     // is larger than the maximum value for a u64.
                     // When it gets cast to a u64, it will end up being 0.
                     if outgoing_amount != 0.0 && outgoing_amount as u64 == 0 {
-                        return Box::new(err(RejectBuilder {
+                        return Box::pin(err(RejectBuilder {
                             code: ErrorCode::F08_AMOUNT_TOO_LARGE,
                             message: format!(
                                 "Could not cast outgoing amount to u64 {}",
@@ -116,6 +146,7 @@ where
                         .build()));
                     }
                     request.prepare.set_amount(outgoing_amount as u64);
+                    request.extensions.insert(AppliedExchangeRate(rate));
                     trace!("Converted incoming amount of: {} {} (scale {}) from account {} to outgoing amount of: {} {} (scale {}) for account {}",
                         request.original_amount, request.from.asset_code(), request.from.asset_scale(), request.from.id(),
                         outgoing_amount, request.to.asset_code(), request.to.asset_scale(), request.to.id());
@@ -125,7 +156,7 @@ where
                     // returns an error. Happens due to float
                     // multiplication overflow .
                     // (float overflow in Rust produces +inf)
-                    return Box::new(err(RejectBuilder {
+                    return Box::pin(err(RejectBuilder {
                         code: ErrorCode::F08_AMOUNT_TOO_LARGE,
                         message: format!(
                             "Could not convert exchange rate from {}:{} to: {}:{}. Got incoming amount: {}",
@@ -144,16 +175,16 @@ where
             }
         }
 
-        Box::new(self.next.send_request(request))
+        Box::pin(self.next.send_request(request))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use futures::{future::ok, Future};
+    use futures03::{executor::block_on, future::ok};
     use interledger_ildcp::IldcpAccount;
-    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use interledger_packet::{Address, Fulfill, FulfillBuilder, PrepareBuilder, Reject};
     use interledger_service::{outgoing_service_fn, Account};
     use lazy_static::lazy_static;
     use std::collections::HashMap;
@@ -171,6 +202,10 @@ mod tests {
     fn exchange_rate_ok() {
         let ret = exchange_rate(100, 1, 1.0, 1, 2.0);
         assert_eq!(ret.1[0].prepare.amount(), 200);
+        assert_eq!(
+            ret.1[0].extensions.get::<AppliedExchangeRate>(),
+            Some(&AppliedExchangeRate(2.0))
+        );
 
         let ret = exchange_rate(1_000_000, 1, 3.0, 1, 2.0);
         assert_eq!(ret.1[0].prepare.amount(), 666_666);
@@ -204,15 +239,15 @@ mod tests {
         let requests_clone = requests.clone();
         let outgoing = outgoing_service_fn(move |request| {
             requests_clone.lock().unwrap().push(request);
-            Box::new(ok(FulfillBuilder {
+            ok(FulfillBuilder {
                 fulfillment: &[0; 32],
                 data: b"hello!",
             }
-            .build()))
+            .build())
         });
         let mut service = test_service(rate1, rate2, outgoing);
-        let result = service
-            .send_request(OutgoingRequest {
+        let result = block_on(
+            service.send_request(OutgoingRequest {
                 from: TestAccount::new("ABC".to_owned(), scale1),
                 to: TestAccount::new("XYZ".to_owned(), scale2),
                 original_amount: amount,
@@ -224,8 +259,9 @@ mod tests {
                     data: b"hello",
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
 
         let reqs = requests.lock().unwrap();
         (result, reqs.clone())
@@ -279,7 +315,7 @@ mod tests {
     }
 
     impl ExchangeRateStore for TestStore {
-        fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, ()> {
+        fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, StoreError> {
             let mut ret = Vec::new();
             let key = vec![asset_codes[0].to_owned(), asset_codes[1].to_owned()];
             let v = self.rates.get(&key);
@@ -287,7 +323,7 @@ mod tests {
                 ret.push(v.0);
                 ret.push(v.1);
             } else {
-                return Err(());
+                return Err(StoreError::NotFound("No exchange rate".to_string()));
             }
             Ok(ret)
         }
@@ -311,5 +347,4 @@ mod tests {
         let store = test_store(rate1, rate2);
         ExchangeRateService::new(Address::from_str("example.bob").unwrap(), store, handler)
     }
-
 }
@@ -1,5 +1,5 @@
 use chrono::{DateTime, Duration, Utc};
-use interledger_service::{Account, OutgoingRequest, OutgoingService};
+use interledger_service::{Account, OutgoingLayer, OutgoingRequest, OutgoingService};
 use log::debug;
 
 pub const DEFAULT_ROUND_TRIP_TIME: u32 = 500;
@@ -39,6 +39,45 @@ impl<O> ExpiryShortenerService<O> {
     }
 }
 
+/// A layer that wraps outgoing services in an `ExpiryShortenerService`.
+#[derive(Clone)]
+pub struct ExpiryShortenerLayer {
+    max_expiry_duration: u32,
+}
+
+impl ExpiryShortenerLayer {
+    pub fn new() -> Self {
+        ExpiryShortenerLayer {
+            max_expiry_duration: DEFAULT_MAX_EXPIRY_DURATION,
+        }
+    }
+
+    pub fn max_expiry_duration(&mut self, milliseconds: u32) -> &mut Self {
+        self.max_expiry_duration = milliseconds;
+        self
+    }
+}
+
+impl Default for ExpiryShortenerLayer {
+    fn default() -> Self {
+        ExpiryShortenerLayer::new()
+    }
+}
+
+impl<O, A> OutgoingLayer<A, O> for ExpiryShortenerLayer
+where
+    O: OutgoingService<A>,
+    A: RoundTripTimeAccount,
+{
+    type Service = ExpiryShortenerService<O>;
+
+    fn layer_outgoing(&self, next: O) -> Self::Service {
+        let mut service = ExpiryShortenerService::new(next);
+        service.max_expiry_duration(self.max_expiry_duration);
+        service
+    }
+}
+
 impl<O, A> OutgoingService<A> for ExpiryShortenerService<O>
 where
     O: OutgoingService<A>,
@@ -77,9 +116,12 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use futures::Future;
+    use futures03::{
+        executor::block_on,
+        future::{err, ok},
+    };
     use interledger_packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
-    use interledger_service::{outgoing_service_fn, Username};
+    use interledger_service::{outgoing_service_fn, Extensions, Username};
     use std::str::FromStr;
 
     use lazy_static::lazy_static;
@@ -115,13 +157,13 @@ mod tests {
             if DateTime::<Utc>::from(request.prepare.expires_at())
                 == original_expiry - Duration::milliseconds(1300)
             {
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: &[],
                 }
                 .build())
             } else {
-                Err(RejectBuilder {
+                err(RejectBuilder {
                     code: ErrorCode::F00_BAD_REQUEST,
                     message: &[],
                     data: &[],
@@ -130,8 +172,8 @@ mod tests {
                 .build())
             }
         }));
-        service
-            .send_request(OutgoingRequest {
+        block_on(
+            service.send_request(OutgoingRequest {
                 from: TestAccount(0, 600),
                 to: TestAccount(1, 700),
                 prepare: PrepareBuilder {
@@ -143,9 +185,10 @@ mod tests {
                 }
                 .build(),
                 original_amount: 10,
-            })
-            .wait()
-            .expect("Should have shortened expiry");
+                extensions: Extensions::new(),
+            }),
+        )
+        .expect("Should have shortened expiry");
     }
 
     #[test]
@@ -154,13 +197,13 @@ mod tests {
             if DateTime::<Utc>::from(request.prepare.expires_at()) - Utc::now()
                 <= Duration::milliseconds(30000)
             {
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: &[],
                 }
                 .build())
             } else {
-                Err(RejectBuilder {
+                err(RejectBuilder {
                     code: ErrorCode::F00_BAD_REQUEST,
                     message: &[],
                     data: &[],
@@ -169,8 +212,8 @@ mod tests {
                 .build())
             }
         }));
-        service
-            .send_request(OutgoingRequest {
+        block_on(
+            service.send_request(OutgoingRequest {
                 from: TestAccount(0, 500),
                 to: TestAccount(1, 500),
                 prepare: PrepareBuilder {
@@ -182,8 +225,9 @@ mod tests {
                 }
                 .build(),
                 original_amount: 10,
-            })
-            .wait()
-            .expect("Should have shortened expiry");
+                extensions: Extensions::new(),
+            }),
+        )
+        .expect("Should have shortened expiry");
     }
 }
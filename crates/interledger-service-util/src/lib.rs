@@ -1,6 +1,8 @@
 //! # interledger-service-util
 //!
 //! Miscellaneous, small Interledger Services.
+//!
+//! Each service comes with a layer that can be used to add it to a `ServiceBuilder`.
 
 mod balance_service;
 mod echo_service;
@@ -10,14 +12,16 @@ mod max_packet_amount_service;
 mod rate_limit_service;
 mod validator_service;
 
-pub use self::balance_service::{BalanceService, BalanceStore};
-pub use self::echo_service::EchoService;
-pub use self::exchange_rates_service::{ExchangeRateService, ExchangeRateStore};
+pub use self::balance_service::{BalanceLayer, BalanceService, BalanceStore};
+pub use self::echo_service::{EchoLayer, EchoService};
+pub use self::exchange_rates_service::{ExchangeRateLayer, ExchangeRateService, ExchangeRateStore};
 pub use self::expiry_shortener_service::{
-    ExpiryShortenerService, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
+    ExpiryShortenerLayer, ExpiryShortenerService, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
+};
+pub use self::max_packet_amount_service::{
+    MaxPacketAmountAccount, MaxPacketAmountLayer, MaxPacketAmountService,
 };
-pub use self::max_packet_amount_service::{MaxPacketAmountAccount, MaxPacketAmountService};
 pub use self::rate_limit_service::{
-    RateLimitAccount, RateLimitError, RateLimitService, RateLimitStore,
+    RateLimitAccount, RateLimitError, RateLimitLayer, RateLimitService, RateLimitStore,
 };
-pub use self::validator_service::ValidatorService;
+pub use self::validator_service::{ValidatorLayer, ValidatorService};
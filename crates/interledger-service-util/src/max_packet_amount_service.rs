@@ -1,4 +1,4 @@
-use futures::future::err;
+use futures03::future::err;
 use interledger_packet::{Address, ErrorCode, MaxPacketAmountDetails, RejectBuilder};
 use interledger_service::*;
 use log::debug;
@@ -27,6 +27,30 @@ impl<I> MaxPacketAmountService<I> {
     }
 }
 
+/// A layer that wraps incoming services in a `MaxPacketAmountService`.
+#[derive(Clone)]
+pub struct MaxPacketAmountLayer {
+    ilp_address: Address,
+}
+
+impl MaxPacketAmountLayer {
+    pub fn new(ilp_address: Address) -> Self {
+        MaxPacketAmountLayer { ilp_address }
+    }
+}
+
+impl<I, A> IncomingLayer<A, I> for MaxPacketAmountLayer
+where
+    I: IncomingService<A>,
+    A: MaxPacketAmountAccount,
+{
+    type Service = MaxPacketAmountService<I>;
+
+    fn layer_incoming(&self, next: I) -> Self::Service {
+        MaxPacketAmountService::new(self.ilp_address.clone(), next)
+    }
+}
+
 impl<I, A> IncomingService<A> for MaxPacketAmountService<I>
 where
     I: IncomingService<A>,
@@ -39,7 +63,7 @@ where
     fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
         let max_packet_amount = request.from.max_packet_amount();
         if request.prepare.amount() <= max_packet_amount {
-            Box::new(self.next.handle_request(request))
+            Box::pin(self.next.handle_request(request))
         } else {
             debug!(
                 "Prepare amount:{} exceeds max_packet_amount: {}",
@@ -48,7 +72,7 @@ where
             );
             let details =
                 MaxPacketAmountDetails::new(request.prepare.amount(), max_packet_amount).to_bytes();
-            Box::new(err(RejectBuilder {
+            Box::pin(err(RejectBuilder {
                 code: ErrorCode::F08_AMOUNT_TOO_LARGE,
                 message: &[],
                 triggered_by: Some(&self.ilp_address),
@@ -2,10 +2,13 @@ use futures::{
     future::{err, Either},
     Future,
 };
+use futures03::{compat::Future01CompatExt, TryFutureExt};
 use interledger_packet::{Address, ErrorCode, RejectBuilder};
-use interledger_service::{Account, BoxedIlpFuture, IncomingRequest, IncomingService};
+use interledger_service::{
+    Account, BoxedIlpFuture, IncomingLayer, IncomingRequest, IncomingService, StoreError,
+};
 use log::{error, warn};
-use std::marker::PhantomData;
+use std::{marker::PhantomData, pin::Pin};
 
 pub trait RateLimitAccount: Account {
     fn packets_per_minute_limit(&self) -> Option<u32> {
@@ -31,12 +34,12 @@ pub trait RateLimitStore {
         &self,
         account: Self::Account,
         prepare_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = RateLimitError> + Send>;
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), RateLimitError>> + Send>>;
     fn refund_throughput_limit(
         &self,
         account: Self::Account,
         prepare_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>>;
 }
 
 /// # Rate Limit Service
@@ -74,6 +77,32 @@ where
     }
 }
 
+/// A layer that wraps incoming services in a `RateLimitService`.
+#[derive(Clone)]
+pub struct RateLimitLayer<S> {
+    ilp_address: Address,
+    store: S,
+}
+
+impl<S> RateLimitLayer<S> {
+    pub fn new(ilp_address: Address, store: S) -> Self {
+        RateLimitLayer { ilp_address, store }
+    }
+}
+
+impl<S, I, A> IncomingLayer<A, I> for RateLimitLayer<S>
+where
+    S: RateLimitStore<Account = A> + Clone + Send + Sync + 'static,
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: RateLimitAccount + Sync + 'static,
+{
+    type Service = RateLimitService<S, I, A>;
+
+    fn layer_incoming(&self, next: I) -> Self::Service {
+        RateLimitService::new(self.ilp_address.clone(), self.store.clone(), next)
+    }
+}
+
 impl<S, I, A> IncomingService<A> for RateLimitService<S, I, A>
 where
     S: RateLimitStore<Account = A> + Clone + Send + Sync + 'static,
@@ -98,7 +127,8 @@ where
         let has_throughput_limit = account.amount_per_minute_limit().is_some();
         // request.from and request.amount are used for apply_rate_limits, can't the previous service
         // always set the account to have None for both?
-        Box::new(self.store.apply_rate_limits(request.from.clone(), request.prepare.amount())
+        Box::pin(self.store.apply_rate_limits(request.from.clone(), request.prepare.amount())
+            .compat()
             .map_err(move |err| {
                 let code = match err {
                     RateLimitError::PacketLimitExceeded => {
@@ -122,19 +152,21 @@ where
                     data: &[],
                 }.build()
             })
-            .and_then(move |_| next.handle_request(request))
+            .and_then(move |_| Box::pin(next.handle_request(request)).compat())
             .or_else(move |reject| {
                 if has_throughput_limit {
                     Either::A(store.refund_throughput_limit(account_clone, prepare_amount)
+                        .compat()
                         .then(|result| {
-                            if result.is_err() {
-                                error!("Error refunding throughput limit: {:?}", result.unwrap_err());
+                            if let Err(err) = result {
+                                error!("Error refunding throughput limit: {}", err);
                             }
                             Err(reject)
                         }))
                 } else {
                     Either::B(err(reject))
                 }
-            }))
+            })
+            .compat())
     }
 }
@@ -1,5 +1,6 @@
 use chrono::{DateTime, Duration, Utc};
-use futures::{future::err, Future};
+use futures::Future;
+use futures03::{compat::Future01CompatExt, future::err, TryFutureExt};
 use hex;
 use interledger_packet::{Address, ErrorCode, RejectBuilder};
 use interledger_service::*;
@@ -49,6 +50,48 @@ where
     }
 }
 
+/// A layer that wraps services in a `ValidatorService`.
+///
+/// The same layer can be used for both incoming and outgoing services.
+#[derive(Clone)]
+pub struct ValidatorLayer {
+    ilp_address: Address,
+}
+
+impl ValidatorLayer {
+    pub fn incoming(ilp_address: Address) -> Self {
+        ValidatorLayer { ilp_address }
+    }
+
+    pub fn outgoing(ilp_address: Address) -> Self {
+        ValidatorLayer { ilp_address }
+    }
+}
+
+impl<I, A> IncomingLayer<A, I> for ValidatorLayer
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    type Service = ValidatorService<I, A>;
+
+    fn layer_incoming(&self, next: I) -> Self::Service {
+        ValidatorService::incoming(self.ilp_address.clone(), next)
+    }
+}
+
+impl<O, A> OutgoingLayer<A, O> for ValidatorLayer
+where
+    O: OutgoingService<A>,
+    A: Account,
+{
+    type Service = ValidatorService<O, A>;
+
+    fn layer_outgoing(&self, next: O) -> Self::Service {
+        ValidatorService::outgoing(self.ilp_address.clone(), next)
+    }
+}
+
 impl<I, A> IncomingService<A> for ValidatorService<I, A>
 where
     I: IncomingService<A>,
@@ -62,7 +105,7 @@ where
         let expires_at = DateTime::<Utc>::from(request.prepare.expires_at());
         let now = Utc::now();
         if expires_at >= now {
-            Box::new(self.next.handle_request(request))
+            Box::pin(self.next.handle_request(request))
         } else {
             error!(
                 "Incoming packet expired {}ms ago at {:?} (time now: {:?})",
@@ -70,14 +113,13 @@ where
                 expires_at.to_rfc3339(),
                 expires_at.to_rfc3339(),
             );
-            let result = Box::new(err(RejectBuilder {
+            Box::pin(err(RejectBuilder {
                 code: ErrorCode::R00_TRANSFER_TIMED_OUT,
                 message: &[],
                 triggered_by: Some(&self.ilp_address),
                 data: &[],
             }
-            .build()));
-            Box::new(result)
+            .build()))
         }
     }
 }
@@ -107,9 +149,9 @@ where
         let ilp_address = self.ilp_address.clone();
         let ilp_address_clone = ilp_address.clone();
         if time_left > Duration::zero() {
-            Box::new(
-                self.next
-                    .send_request(request)
+            Box::pin(
+                Box::pin(self.next.send_request(request))
+                    .compat()
                     .timeout(time_left.to_std().expect("Time left must be positive"))
                     .map_err(move |err| {
                         // If the error was caused by the timer, into_inner will return None
@@ -144,7 +186,8 @@ where
                             }
                             .build())
                         }
-                    }),
+                    })
+                    .compat(),
             )
         } else {
             error!(
@@ -152,7 +195,7 @@ where
                 (Duration::zero() - time_left).num_milliseconds(),
             );
             // Already expired
-            Box::new(err(RejectBuilder {
+            Box::pin(err(RejectBuilder {
                 code: ErrorCode::R00_TRANSFER_TIMED_OUT,
                 message: &[],
                 triggered_by: Some(&ilp_address),
@@ -190,6 +233,7 @@ impl Account for TestAccount {
 #[cfg(test)]
 mod incoming {
     use super::*;
+    use futures03::{executor::block_on, future::ok};
     use interledger_packet::*;
     use interledger_service::incoming_service_fn;
     use std::{
@@ -205,15 +249,15 @@ mod incoming {
             Address::from_str("example.connector").unwrap(),
             incoming_service_fn(move |request| {
                 requests_clone.lock().unwrap().push(request);
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: b"test data",
                 }
                 .build())
             }),
         );
-        let result = validator
-            .handle_request(IncomingRequest {
+        let result = block_on(
+            validator.handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
                     destination: Address::from_str("example.destination").unwrap(),
@@ -226,8 +270,9 @@ mod incoming {
                     data: b"test data",
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
 
         assert_eq!(requests.lock().unwrap().len(), 1);
         assert!(result.is_ok());
@@ -241,15 +286,15 @@ mod incoming {
             Address::from_str("example.connector").unwrap(),
             incoming_service_fn(move |request| {
                 requests_clone.lock().unwrap().push(request);
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: b"test data",
                 }
                 .build())
             }),
         );
-        let result = validator
-            .handle_request(IncomingRequest {
+        let result = block_on(
+            validator.handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
                     destination: Address::from_str("example.destination").unwrap(),
@@ -262,8 +307,9 @@ mod incoming {
                     data: b"test data",
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
 
         assert!(requests.lock().unwrap().is_empty());
         assert!(result.is_err());
@@ -277,6 +323,7 @@ mod incoming {
 #[cfg(test)]
 mod outgoing {
     use super::*;
+    use futures03::{executor::block_on, future::ok};
     use interledger_packet::*;
     use std::str::FromStr;
     use std::{
@@ -292,15 +339,15 @@ mod outgoing {
             Address::from_str("example.connector").unwrap(),
             outgoing_service_fn(move |request| {
                 requests_clone.lock().unwrap().push(request);
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[0; 32],
                     data: b"test data",
                 }
                 .build())
             }),
         );
-        let result = validator
-            .send_request(OutgoingRequest {
+        let result = block_on(
+            validator.send_request(OutgoingRequest {
                 from: TestAccount(1),
                 to: TestAccount(2),
                 original_amount: 100,
@@ -315,8 +362,9 @@ mod outgoing {
                     data: b"test data",
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
 
         assert_eq!(requests.lock().unwrap().len(), 1);
         assert!(result.is_ok());
@@ -330,15 +378,15 @@ mod outgoing {
             Address::from_str("example.connector").unwrap(),
             outgoing_service_fn(move |request| {
                 requests_clone.lock().unwrap().push(request);
-                Ok(FulfillBuilder {
+                ok(FulfillBuilder {
                     fulfillment: &[1; 32],
                     data: b"test data",
                 }
                 .build())
             }),
         );
-        let result = validator
-            .send_request(OutgoingRequest {
+        let result = block_on(
+            validator.send_request(OutgoingRequest {
                 from: TestAccount(1),
                 to: TestAccount(2),
                 original_amount: 100,
@@ -353,8 +401,9 @@ mod outgoing {
                     data: b"test data",
                 }
                 .build(),
-            })
-            .wait();
+                extensions: Extensions::new(),
+            }),
+        );
 
         assert_eq!(requests.lock().unwrap().len(), 1);
         assert!(result.is_err());
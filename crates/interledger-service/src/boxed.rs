@@ -0,0 +1,210 @@
+use super::{
+    Account, BoxedIlpFuture, IncomingLayer, IncomingRequest, IncomingService, OutgoingLayer,
+    OutgoingRequest, OutgoingService,
+};
+use std::sync::Arc;
+
+/// Object-safe version of `IncomingService` that can also be cloned.
+trait CloneIncomingService<A: Account>: Send + Sync {
+    fn handle_request_boxed(&mut self, request: IncomingRequest<A>) -> BoxedIlpFuture;
+
+    fn clone_boxed(&self) -> Box<dyn CloneIncomingService<A>>;
+}
+
+impl<S, A> CloneIncomingService<A> for S
+where
+    S: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account,
+{
+    fn handle_request_boxed(&mut self, request: IncomingRequest<A>) -> BoxedIlpFuture {
+        Box::pin(self.handle_request(request))
+    }
+
+    fn clone_boxed(&self) -> Box<dyn CloneIncomingService<A>> {
+        Box::new(self.clone())
+    }
+}
+
+/// An `IncomingService` with the concrete type erased.
+///
+/// This is used to build service chains whose shape is only known at runtime,
+/// for example when the stages are read from a config file.
+pub struct BoxedIncomingService<A: Account> {
+    inner: Box<dyn CloneIncomingService<A>>,
+}
+
+impl<A: Account> BoxedIncomingService<A> {
+    pub fn new<S>(service: S) -> Self
+    where
+        S: IncomingService<A> + Clone + Send + Sync + 'static,
+    {
+        BoxedIncomingService {
+            inner: Box::new(service),
+        }
+    }
+}
+
+impl<A: Account> Clone for BoxedIncomingService<A> {
+    fn clone(&self) -> Self {
+        BoxedIncomingService {
+            inner: self.inner.clone_boxed(),
+        }
+    }
+}
+
+impl<A: Account> IncomingService<A> for BoxedIncomingService<A> {
+    type Future = BoxedIlpFuture;
+
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        self.inner.handle_request_boxed(request)
+    }
+}
+
+/// Object-safe version of `OutgoingService` that can also be cloned.
+trait CloneOutgoingService<A: Account>: Send + Sync {
+    fn send_request_boxed(&mut self, request: OutgoingRequest<A>) -> BoxedIlpFuture;
+
+    fn clone_boxed(&self) -> Box<dyn CloneOutgoingService<A>>;
+}
+
+impl<S, A> CloneOutgoingService<A> for S
+where
+    S: OutgoingService<A> + Clone + Send + Sync + 'static,
+    A: Account,
+{
+    fn send_request_boxed(&mut self, request: OutgoingRequest<A>) -> BoxedIlpFuture {
+        Box::pin(self.send_request(request))
+    }
+
+    fn clone_boxed(&self) -> Box<dyn CloneOutgoingService<A>> {
+        Box::new(self.clone())
+    }
+}
+
+/// An `OutgoingService` with the concrete type erased.
+pub struct BoxedOutgoingService<A: Account> {
+    inner: Box<dyn CloneOutgoingService<A>>,
+}
+
+impl<A: Account> BoxedOutgoingService<A> {
+    pub fn new<S>(service: S) -> Self
+    where
+        S: OutgoingService<A> + Clone + Send + Sync + 'static,
+    {
+        BoxedOutgoingService {
+            inner: Box::new(service),
+        }
+    }
+}
+
+impl<A: Account> Clone for BoxedOutgoingService<A> {
+    fn clone(&self) -> Self {
+        BoxedOutgoingService {
+            inner: self.inner.clone_boxed(),
+        }
+    }
+}
+
+impl<A: Account> OutgoingService<A> for BoxedOutgoingService<A> {
+    type Future = BoxedIlpFuture;
+
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        self.inner.send_request_boxed(request)
+    }
+}
+
+type IncomingLayerFn<A> =
+    Arc<dyn Fn(BoxedIncomingService<A>) -> BoxedIncomingService<A> + Send + Sync>;
+
+/// An `IncomingLayer` with the concrete type erased.
+///
+/// A list of these is itself a layer, so a chain whose stages are only known at runtime
+/// can still be added to a `ServiceBuilder`. The first layer in the list is the outermost one.
+pub struct BoxedIncomingLayer<A: Account> {
+    inner: IncomingLayerFn<A>,
+}
+
+impl<A: Account + 'static> BoxedIncomingLayer<A> {
+    pub fn new<L>(layer: L) -> Self
+    where
+        L: IncomingLayer<A, BoxedIncomingService<A>> + Send + Sync + 'static,
+        L::Service: Clone + Send + Sync + 'static,
+    {
+        BoxedIncomingLayer {
+            inner: Arc::new(move |next| BoxedIncomingService::new(layer.layer_incoming(next))),
+        }
+    }
+}
+
+impl<A: Account> Clone for BoxedIncomingLayer<A> {
+    fn clone(&self) -> Self {
+        BoxedIncomingLayer {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<A: Account> IncomingLayer<A, BoxedIncomingService<A>> for BoxedIncomingLayer<A> {
+    type Service = BoxedIncomingService<A>;
+
+    fn layer_incoming(&self, inner: BoxedIncomingService<A>) -> Self::Service {
+        (self.inner)(inner)
+    }
+}
+
+impl<A: Account> IncomingLayer<A, BoxedIncomingService<A>> for Vec<BoxedIncomingLayer<A>> {
+    type Service = BoxedIncomingService<A>;
+
+    fn layer_incoming(&self, inner: BoxedIncomingService<A>) -> Self::Service {
+        // Start with the innermost layer so that the first one in the list is the outermost
+        self.iter()
+            .rev()
+            .fold(inner, |next, layer| layer.layer_incoming(next))
+    }
+}
+
+type OutgoingLayerFn<A> =
+    Arc<dyn Fn(BoxedOutgoingService<A>) -> BoxedOutgoingService<A> + Send + Sync>;
+
+/// An `OutgoingLayer` with the concrete type erased.
+pub struct BoxedOutgoingLayer<A: Account> {
+    inner: OutgoingLayerFn<A>,
+}
+
+impl<A: Account + 'static> BoxedOutgoingLayer<A> {
+    pub fn new<L>(layer: L) -> Self
+    where
+        L: OutgoingLayer<A, BoxedOutgoingService<A>> + Send + Sync + 'static,
+        L::Service: Clone + Send + Sync + 'static,
+    {
+        BoxedOutgoingLayer {
+            inner: Arc::new(move |next| BoxedOutgoingService::new(layer.layer_outgoing(next))),
+        }
+    }
+}
+
+impl<A: Account> Clone for BoxedOutgoingLayer<A> {
+    fn clone(&self) -> Self {
+        BoxedOutgoingLayer {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<A: Account> OutgoingLayer<A, BoxedOutgoingService<A>> for BoxedOutgoingLayer<A> {
+    type Service = BoxedOutgoingService<A>;
+
+    fn layer_outgoing(&self, inner: BoxedOutgoingService<A>) -> Self::Service {
+        (self.inner)(inner)
+    }
+}
+
+impl<A: Account> OutgoingLayer<A, BoxedOutgoingService<A>> for Vec<BoxedOutgoingLayer<A>> {
+    type Service = BoxedOutgoingService<A>;
+
+    fn layer_outgoing(&self, inner: BoxedOutgoingService<A>) -> Self::Service {
+        self.iter()
+            .rev()
+            .fold(inner, |next, layer| layer.layer_outgoing(next))
+    }
+}
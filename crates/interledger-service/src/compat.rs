@@ -0,0 +1,216 @@
+//! Adapters for services written against futures 0.1.
+//!
+//! The `IncomingService` and `OutgoingService` traits return `std::future::Future`s.
+//! Services that still return futures 0.1 can implement `IncomingService01` or
+//! `OutgoingService01` instead and be wrapped in `Compat01As03` to be used in a
+//! std::future-based service chain. `Compat` does the opposite: it wraps a std::future-based
+//! service so that it can be passed as the `next` service to code that expects futures 0.1.
+//!
+//! This makes it possible to migrate a chain of services one service at a time.
+
+use super::{
+    Account, BoxedIlpFuture, IncomingRequest, IncomingService, OutgoingRequest, OutgoingService,
+};
+use futures::Future as Future01;
+use futures03::{compat::Future01CompatExt, FutureExt, TryFutureExt};
+use interledger_packet::{Fulfill, Reject};
+
+/// A futures 0.1 future that returns an ILP Fulfill or Reject packet.
+pub type BoxedIlpFuture01 = Box<dyn Future01<Item = Fulfill, Error = Reject> + Send + 'static>;
+
+/// The futures 0.1 version of `IncomingService`.
+pub trait IncomingService01<A: Account> {
+    type Future: Future01<Item = Fulfill, Error = Reject> + Send + 'static;
+
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future;
+}
+
+/// The futures 0.1 version of `OutgoingService`.
+pub trait OutgoingService01<A: Account> {
+    type Future: Future01<Item = Fulfill, Error = Reject> + Send + 'static;
+
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future;
+}
+
+/// Wraps a futures 0.1 service so that it implements `IncomingService` and/or `OutgoingService`.
+#[derive(Clone)]
+pub struct Compat01As03<S> {
+    inner: S,
+}
+
+impl<S> Compat01As03<S> {
+    pub fn new(inner: S) -> Self {
+        Compat01As03 { inner }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, A> IncomingService<A> for Compat01As03<S>
+where
+    S: IncomingService01<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        Box::pin(self.inner.handle_request(request).compat())
+    }
+}
+
+impl<S, A> OutgoingService<A> for Compat01As03<S>
+where
+    S: OutgoingService01<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        Box::pin(self.inner.send_request(request).compat())
+    }
+}
+
+/// Wraps a std::future-based service so that it implements `IncomingService01` and/or `OutgoingService01`.
+#[derive(Clone)]
+pub struct Compat<S> {
+    inner: S,
+}
+
+impl<S> Compat<S> {
+    pub fn new(inner: S) -> Self {
+        Compat { inner }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, A> IncomingService01<A> for Compat<S>
+where
+    S: IncomingService<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture01;
+
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        Box::new(self.inner.handle_request(request).boxed().compat())
+    }
+}
+
+impl<S, A> OutgoingService01<A> for Compat<S>
+where
+    S: OutgoingService<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture01;
+
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        Box::new(self.inner.send_request(request).boxed().compat())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{incoming_service_fn, Extensions, Username};
+    use futures::future::{err, ok};
+    use futures03::executor::block_on;
+    use interledger_packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
+    use lazy_static::lazy_static;
+    use std::str::FromStr;
+    use std::time::UNIX_EPOCH;
+
+    lazy_static! {
+        static ref ALICE: Username = Username::from_str("alice").unwrap();
+    }
+
+    #[derive(Clone, Debug)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+
+        fn username(&self) -> &Username {
+            &ALICE
+        }
+    }
+
+    #[derive(Clone)]
+    struct OldService;
+
+    impl IncomingService01<TestAccount> for OldService {
+        type Future = BoxedIlpFuture01;
+
+        fn handle_request(&mut self, request: IncomingRequest<TestAccount>) -> Self::Future {
+            if request.prepare.amount() > 0 {
+                Box::new(ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: b"old",
+                }
+                .build()))
+            } else {
+                Box::new(err(RejectBuilder {
+                    code: ErrorCode::F00_BAD_REQUEST,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build()))
+            }
+        }
+    }
+
+    fn test_request(amount: u64) -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount,
+                execution_condition: &[0; 32],
+                expires_at: UNIX_EPOCH,
+                data: &[],
+            }
+            .build(),
+            extensions: Extensions::new(),
+        }
+    }
+
+    #[test]
+    fn wraps_futures_01_service() {
+        let mut service = Compat01As03::new(OldService);
+        let fulfill = block_on(service.handle_request(test_request(1))).unwrap();
+        assert_eq!(fulfill.data(), b"old");
+        let reject = block_on(service.handle_request(test_request(0))).unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F00_BAD_REQUEST);
+    }
+
+    #[test]
+    fn wraps_std_future_service() {
+        let mut service = Compat::new(incoming_service_fn(|request| async move {
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: &request.prepare.amount().to_be_bytes(),
+            }
+            .build())
+        }));
+        let fulfill = IncomingService01::handle_request(&mut service, test_request(5))
+            .wait()
+            .unwrap();
+        assert_eq!(fulfill.data(), &5u64.to_be_bytes());
+    }
+}
@@ -0,0 +1,100 @@
+use interledger_packet::ErrorCode;
+use std::{error::Error, fmt};
+
+/// The error type returned by all of the Store traits.
+///
+/// Each variant includes a message describing what went wrong, which is meant for logging.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StoreError {
+    /// The account or other record does not exist
+    NotFound(String),
+    /// The write conflicts with an existing record, for example a duplicate username
+    Conflict(String),
+    /// The data passed to the store is not valid, for example an account with a malformed URL
+    Invalid(String),
+    /// The operation would violate a limit configured for the account, such as its minimum balance
+    LimitExceeded(String),
+    /// The underlying database could not be reached or returned an error
+    Unavailable(String),
+    /// A record exists but could not be parsed or decrypted
+    Corrupt(String),
+}
+
+impl StoreError {
+    /// The ILP error code that services should use when rejecting a packet because of this error.
+    pub fn reject_code(&self) -> ErrorCode {
+        match self {
+            StoreError::NotFound(_) => ErrorCode::F02_UNREACHABLE,
+            StoreError::Conflict(_) | StoreError::Invalid(_) => ErrorCode::F00_BAD_REQUEST,
+            StoreError::LimitExceeded(_) => ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+            StoreError::Unavailable(_) | StoreError::Corrupt(_) => ErrorCode::T00_INTERNAL_ERROR,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            StoreError::NotFound(message)
+            | StoreError::Conflict(message)
+            | StoreError::Invalid(message)
+            | StoreError::LimitExceeded(message)
+            | StoreError::Unavailable(message)
+            | StoreError::Corrupt(message) => message,
+        }
+    }
+}
+
+/// The HTTP status code APIs should respond with when the store returns the given error
+pub fn store_error_status(err: &StoreError) -> u16 {
+    match err {
+        StoreError::NotFound(_) => 404,
+        StoreError::Conflict(_) => 409,
+        StoreError::Invalid(_) | StoreError::LimitExceeded(_) => 400,
+        StoreError::Unavailable(_) => 503,
+        StoreError::Corrupt(_) => 500,
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self {
+            StoreError::NotFound(_) => "Not found",
+            StoreError::Conflict(_) => "Conflict",
+            StoreError::Invalid(_) => "Invalid",
+            StoreError::LimitExceeded(_) => "Limit exceeded",
+            StoreError::Unavailable(_) => "Store unavailable",
+            StoreError::Corrupt(_) => "Corrupt data",
+        };
+        write!(f, "{}: {}", kind, self.message())
+    }
+}
+
+impl Error for StoreError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_codes() {
+        assert_eq!(
+            StoreError::NotFound("alice".to_string()).reject_code(),
+            ErrorCode::F02_UNREACHABLE
+        );
+        assert_eq!(
+            StoreError::LimitExceeded("min balance".to_string()).reject_code(),
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+        );
+        assert_eq!(
+            StoreError::Unavailable("connection refused".to_string()).reject_code(),
+            ErrorCode::T00_INTERNAL_ERROR
+        );
+    }
+
+    #[test]
+    fn display_includes_kind_and_message() {
+        assert_eq!(
+            StoreError::Corrupt("bad token".to_string()).to_string(),
+            "Corrupt data: bad token"
+        );
+    }
+}
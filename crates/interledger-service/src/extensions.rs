@@ -0,0 +1,153 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+};
+
+/// A value stored in `Extensions`. Values must be clonable because requests are.
+trait Extension: Any + Send + Sync {
+    fn clone_boxed(&self) -> Box<dyn Extension>;
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T> Extension for T
+where
+    T: Any + Clone + Send + Sync,
+{
+    fn clone_boxed(&self) -> Box<dyn Extension> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// A typed map of request-scoped data, modeled after `http::Extensions`.
+///
+/// Services can use this to pass metadata about a request along to the services
+/// after them in the chain, for example the route that was chosen for the packet.
+/// Each type can be stored at most once, so services should use their own types
+/// rather than primitives or strings as the keys.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Extension>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Extensions {
+            map: HashMap::new(),
+        }
+    }
+
+    /// Insert a value, returning the previous value of the same type if there was one.
+    pub fn insert<T>(&mut self, value: T) -> Option<T>
+    where
+        T: Any + Clone + Send + Sync,
+    {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.into_any().downcast().ok().map(|boxed| *boxed))
+    }
+
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| (**value).as_any().downcast_ref())
+    }
+
+    pub fn get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.map
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| (**value).as_any_mut().downcast_mut())
+    }
+
+    pub fn remove<T: Any>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.into_any().downcast().ok().map(|boxed| *boxed))
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+impl Clone for Extensions {
+    fn clone(&self) -> Self {
+        Extensions {
+            map: self
+                .map
+                .iter()
+                .map(|(type_id, value)| (*type_id, (**value).clone_boxed()))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TraceId(u64);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Hop(&'static str);
+
+    #[test]
+    fn insert_get_and_remove() {
+        let mut extensions = Extensions::new();
+        assert!(extensions.is_empty());
+        assert_eq!(extensions.insert(TraceId(1)), None);
+        assert_eq!(extensions.insert(Hop("a")), None);
+        assert_eq!(extensions.insert(TraceId(2)), Some(TraceId(1)));
+        assert_eq!(extensions.len(), 2);
+        assert_eq!(extensions.get::<TraceId>(), Some(&TraceId(2)));
+
+        extensions.get_mut::<Hop>().unwrap().0 = "b";
+        assert_eq!(extensions.remove::<Hop>(), Some(Hop("b")));
+        assert_eq!(extensions.get::<Hop>(), None);
+        assert_eq!(extensions.len(), 1);
+    }
+
+    #[test]
+    fn clones_values() {
+        let mut extensions = Extensions::new();
+        extensions.insert(TraceId(1));
+        let mut cloned = extensions.clone();
+        cloned.insert(TraceId(2));
+        assert_eq!(extensions.get::<TraceId>(), Some(&TraceId(1)));
+        assert_eq!(cloned.get::<TraceId>(), Some(&TraceId(2)));
+    }
+}
@@ -0,0 +1,301 @@
+//! Composable middleware for building service chains.
+//!
+//! Modeled after tower's `Layer` and `ServiceBuilder`. A layer wraps a service
+//! and returns a new service, so instead of nesting constructors by hand a
+//! pipeline can be declared top-down:
+//!
+//! ```ignore
+//! let incoming = ServiceBuilder::new()
+//!     .layer(RateLimitLayer::new(ilp_address.clone(), store.clone()))
+//!     .layer(ValidatorLayer::incoming(ilp_address.clone()))
+//!     .incoming_service(router);
+//! ```
+//!
+//! The first layer added is the outermost one, meaning it sees each request first.
+
+use super::{Account, IncomingService, OutgoingService};
+
+/// Wraps an `IncomingService` in another `IncomingService`.
+pub trait IncomingLayer<A: Account, S> {
+    type Service: IncomingService<A>;
+
+    fn layer_incoming(&self, inner: S) -> Self::Service;
+}
+
+/// Wraps an `OutgoingService` in another `OutgoingService`.
+pub trait OutgoingLayer<A: Account, S> {
+    type Service: OutgoingService<A>;
+
+    fn layer_outgoing(&self, inner: S) -> Self::Service;
+}
+
+/// A layer that returns the service unchanged.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Identity;
+
+impl<A, S> IncomingLayer<A, S> for Identity
+where
+    A: Account,
+    S: IncomingService<A>,
+{
+    type Service = S;
+
+    fn layer_incoming(&self, inner: S) -> S {
+        inner
+    }
+}
+
+impl<A, S> OutgoingLayer<A, S> for Identity
+where
+    A: Account,
+    S: OutgoingService<A>,
+{
+    type Service = S;
+
+    fn layer_outgoing(&self, inner: S) -> S {
+        inner
+    }
+}
+
+/// Two layers applied one after the other: `inner` wraps the service first and
+/// `outer` wraps the result.
+#[derive(Clone, Debug)]
+pub struct Stack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<Inner, Outer> Stack<Inner, Outer> {
+    pub fn new(inner: Inner, outer: Outer) -> Self {
+        Stack { inner, outer }
+    }
+}
+
+impl<A, S, Inner, Outer> IncomingLayer<A, S> for Stack<Inner, Outer>
+where
+    A: Account,
+    Inner: IncomingLayer<A, S>,
+    Outer: IncomingLayer<A, Inner::Service>,
+{
+    type Service = Outer::Service;
+
+    fn layer_incoming(&self, inner: S) -> Self::Service {
+        self.outer.layer_incoming(self.inner.layer_incoming(inner))
+    }
+}
+
+impl<A, S, Inner, Outer> OutgoingLayer<A, S> for Stack<Inner, Outer>
+where
+    A: Account,
+    Inner: OutgoingLayer<A, S>,
+    Outer: OutgoingLayer<A, Inner::Service>,
+{
+    type Service = Outer::Service;
+
+    fn layer_outgoing(&self, inner: S) -> Self::Service {
+        self.outer.layer_outgoing(self.inner.layer_outgoing(inner))
+    }
+}
+
+/// Create a layer from a function that wraps a service.
+///
+/// This is useful for services that do not provide their own layer type, for example
+/// `layer_fn(IldcpService::new)`.
+pub fn layer_fn<F>(f: F) -> LayerFn<F> {
+    LayerFn { f }
+}
+
+/// A layer created by `layer_fn`
+#[derive(Clone)]
+pub struct LayerFn<F> {
+    f: F,
+}
+
+impl<A, S, F, Out> IncomingLayer<A, S> for LayerFn<F>
+where
+    A: Account,
+    F: Fn(S) -> Out,
+    Out: IncomingService<A>,
+{
+    type Service = Out;
+
+    fn layer_incoming(&self, inner: S) -> Out {
+        (self.f)(inner)
+    }
+}
+
+impl<A, S, F, Out> OutgoingLayer<A, S> for LayerFn<F>
+where
+    A: Account,
+    F: Fn(S) -> Out,
+    Out: OutgoingService<A>,
+{
+    type Service = Out;
+
+    fn layer_outgoing(&self, inner: S) -> Out {
+        (self.f)(inner)
+    }
+}
+
+/// Declaratively builds a chain of services out of layers.
+#[derive(Clone, Debug)]
+pub struct ServiceBuilder<L> {
+    layer: L,
+}
+
+impl Default for ServiceBuilder<Identity> {
+    fn default() -> Self {
+        ServiceBuilder::new()
+    }
+}
+
+impl ServiceBuilder<Identity> {
+    pub fn new() -> Self {
+        ServiceBuilder { layer: Identity }
+    }
+}
+
+impl<L> ServiceBuilder<L> {
+    /// Add a layer. It will be applied inside of all of the layers added before it.
+    pub fn layer<T>(self, layer: T) -> ServiceBuilder<Stack<T, L>> {
+        ServiceBuilder {
+            layer: Stack::new(layer, self.layer),
+        }
+    }
+
+    /// Wrap the given service in all of the layers to create an `IncomingService`.
+    pub fn incoming_service<A, S>(&self, service: S) -> <L as IncomingLayer<A, S>>::Service
+    where
+        A: Account,
+        L: IncomingLayer<A, S>,
+    {
+        self.layer.layer_incoming(service)
+    }
+
+    /// Wrap the given service in all of the layers to create an `OutgoingService`.
+    pub fn outgoing_service<A, S>(&self, service: S) -> <L as OutgoingLayer<A, S>>::Service
+    where
+        A: Account,
+        L: OutgoingLayer<A, S>,
+    {
+        self.layer.layer_outgoing(service)
+    }
+
+    pub fn into_inner(self) -> L {
+        self.layer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{incoming_service_fn, BoxedIlpFuture, Extensions, IncomingRequest, Username};
+    use futures03::{executor::block_on, future::ok};
+    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use lazy_static::lazy_static;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use std::time::UNIX_EPOCH;
+
+    lazy_static! {
+        static ref ALICE: Username = Username::from_str("alice").unwrap();
+    }
+
+    #[derive(Clone, Debug)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+
+        fn username(&self) -> &Username {
+            &ALICE
+        }
+    }
+
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+
+    struct LogLayer(&'static str, Log);
+
+    struct LogService<S>(&'static str, Log, S);
+
+    impl<S> IncomingLayer<TestAccount, S> for LogLayer
+    where
+        S: IncomingService<TestAccount>,
+    {
+        type Service = LogService<S>;
+
+        fn layer_incoming(&self, inner: S) -> Self::Service {
+            LogService(self.0, self.1.clone(), inner)
+        }
+    }
+
+    impl<S> IncomingService<TestAccount> for LogService<S>
+    where
+        S: IncomingService<TestAccount>,
+    {
+        type Future = BoxedIlpFuture;
+
+        fn handle_request(&mut self, request: IncomingRequest<TestAccount>) -> Self::Future {
+            self.1.lock().unwrap().push(self.0);
+            Box::pin(self.2.handle_request(request))
+        }
+    }
+
+    fn request() -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                expires_at: UNIX_EPOCH,
+                execution_condition: &[0; 32],
+                data: &[],
+            }
+            .build(),
+            extensions: Extensions::new(),
+        }
+    }
+
+    #[test]
+    fn first_layer_is_outermost() {
+        let log: Log = Arc::new(Mutex::new(Vec::new()));
+        let log_clone = log.clone();
+        let mut service = ServiceBuilder::new()
+            .layer(LogLayer("first", log.clone()))
+            .layer(LogLayer("second", log.clone()))
+            .incoming_service(incoming_service_fn(move |_| {
+                log_clone.lock().unwrap().push("service");
+                ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }));
+
+        assert!(block_on(service.handle_request(request())).is_ok());
+        assert_eq!(*log.lock().unwrap(), vec!["first", "second", "service"]);
+    }
+
+    #[test]
+    fn layer_fn_wraps_service() {
+        let log: Log = Arc::new(Mutex::new(Vec::new()));
+        let log_clone = log.clone();
+        let mut service = ServiceBuilder::new()
+            .layer(layer_fn(|inner| LogService("fn", log.clone(), inner)))
+            .incoming_service(incoming_service_fn(move |_| {
+                log_clone.lock().unwrap().push("service");
+                ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }));
+
+        assert!(block_on(service.handle_request(request())).is_ok());
+        assert_eq!(*log.lock().unwrap(), vec!["fn", "service"]);
+    }
+}
@@ -10,6 +10,11 @@
 //! and asynchronously return either an ILP Fullfill or Reject packet. Implementations of Stores (wrappers around
 //! databases) can attach additional information to the Account records, which are then passed through the service chain.
 //!
+//! Services and Stores return `std::future::Future`s, so they can be written using `async` blocks.
+//! The `compat` module contains adapters for services that still return futures 0.1.
+//!
+//! Chains of services can be declared with the `ServiceBuilder` using `IncomingLayer`s and `OutgoingLayer`s.
+//!
 //! ## Example Service Bundles
 //!
 //! The following examples illustrate how different Services can be chained together to create different bundles of functionality.
@@ -26,13 +31,14 @@
 //!
 //! HttpServerService --> ValidatorService --> StreamReceiverService
 
-use futures::{Future, IntoFuture};
 use interledger_packet::{Fulfill, Prepare, Reject};
 use std::{
     cmp::Eq,
     fmt::{Debug, Display},
+    future::Future,
     hash::Hash,
     marker::PhantomData,
+    pin::Pin,
     str::FromStr,
 };
 
@@ -40,6 +46,17 @@ use serde::Serialize;
 
 mod auth;
 pub use auth::{Auth as AuthToken, Username};
+mod boxed;
+pub use boxed::{
+    BoxedIncomingLayer, BoxedIncomingService, BoxedOutgoingLayer, BoxedOutgoingService,
+};
+mod error;
+pub use error::{store_error_status, StoreError};
+mod extensions;
+pub use extensions::Extensions;
+pub mod compat;
+mod layer;
+pub use layer::{layer_fn, Identity, IncomingLayer, LayerFn, OutgoingLayer, ServiceBuilder, Stack};
 
 /// The base trait that Account types from other Services extend.
 /// This trait only assumes that the account has an ID that can be compared with others.
@@ -59,6 +76,8 @@ pub trait Account: Clone + Send + Sized + Debug {
 pub struct IncomingRequest<A: Account> {
     pub from: A,
     pub prepare: Prepare,
+    /// Request-scoped data added by the services that handled this request
+    pub extensions: Extensions,
 }
 
 /// A struct representing an ILP Prepare packet with the incoming and outgoing accounts set.
@@ -68,6 +87,8 @@ pub struct OutgoingRequest<A: Account> {
     pub to: A,
     pub original_amount: u64,
     pub prepare: Prepare,
+    /// Request-scoped data added by the services that handled this request
+    pub extensions: Extensions,
 }
 
 /// Set the `to` Account and turn this into an OutgoingRequest
@@ -81,26 +102,30 @@ where
             original_amount: self.prepare.amount(),
             prepare: self.prepare,
             to,
+            extensions: self.extensions,
         }
     }
 }
 
+/// The result of sending an ILP Prepare packet: either a Fulfill or a Reject.
+pub type IlpResult = Result<Fulfill, Reject>;
+
 /// Core service trait for handling IncomingRequests that asynchronously returns an ILP Fulfill or Reject packet.
 pub trait IncomingService<A: Account> {
-    type Future: Future<Item = Fulfill, Error = Reject> + Send + 'static;
+    type Future: Future<Output = IlpResult> + Send + 'static;
 
     fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future;
 }
 
 /// Core service trait for sending OutgoingRequests that asynchronously returns an ILP Fulfill or Reject packet.
 pub trait OutgoingService<A: Account> {
-    type Future: Future<Item = Fulfill, Error = Reject> + Send + 'static;
+    type Future: Future<Output = IlpResult> + Send + 'static;
 
     fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future;
 }
 
 /// A future that returns an ILP Fulfill or Reject packet.
-pub type BoxedIlpFuture = Box<dyn Future<Item = Fulfill, Error = Reject> + Send + 'static>;
+pub type BoxedIlpFuture = Pin<Box<dyn Future<Output = IlpResult> + Send + 'static>>;
 
 /// The base Store trait that can load a given account based on the ID.
 pub trait AccountStore {
@@ -109,22 +134,30 @@ pub trait AccountStore {
     fn get_accounts(
         &self,
         account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
-    ) -> Box<dyn Future<Item = Vec<Self::Account>, Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Self::Account>, StoreError>> + Send>>;
 
     fn get_account_id_from_username(
         &self,
         username: &Username,
-    ) -> Box<
-        dyn Future<Item = <<Self as AccountStore>::Account as Account>::AccountId, Error = ()>
-            + Send,
+    ) -> Pin<
+        Box<
+            dyn Future<
+                    Output = Result<
+                        <<Self as AccountStore>::Account as Account>::AccountId,
+                        StoreError,
+                    >,
+                > + Send,
+        >,
     >;
 }
 
 /// Create an IncomingService that calls the given handler for each request.
+///
+/// The handler may be an `async` closure or any function that returns a `Future`.
 pub fn incoming_service_fn<A, B, F>(handler: F) -> ServiceFn<F, A>
 where
     A: Account,
-    B: IntoFuture<Item = Fulfill, Error = Reject>,
+    B: Future<Output = IlpResult> + Send + 'static,
     F: FnMut(IncomingRequest<A>) -> B,
 {
     ServiceFn {
@@ -134,10 +167,12 @@ where
 }
 
 /// Create an OutgoingService that calls the given handler for each request.
+///
+/// The handler may be an `async` closure or any function that returns a `Future`.
 pub fn outgoing_service_fn<A, B, F>(handler: F) -> ServiceFn<F, A>
 where
     A: Account,
-    B: IntoFuture<Item = Fulfill, Error = Reject>,
+    B: Future<Output = IlpResult> + Send + 'static,
     F: FnMut(OutgoingRequest<A>) -> B,
 {
     ServiceFn {
@@ -156,27 +191,25 @@ pub struct ServiceFn<F, A> {
 impl<F, A, B> IncomingService<A> for ServiceFn<F, A>
 where
     A: Account,
-    B: IntoFuture<Item = Fulfill, Error = Reject>,
-    <B as futures::future::IntoFuture>::Future: std::marker::Send + 'static,
+    B: Future<Output = IlpResult> + Send + 'static,
     F: FnMut(IncomingRequest<A>) -> B,
 {
     type Future = BoxedIlpFuture;
 
     fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
-        Box::new((self.handler)(request).into_future())
+        Box::pin((self.handler)(request))
     }
 }
 
 impl<F, A, B> OutgoingService<A> for ServiceFn<F, A>
 where
     A: Account,
-    B: IntoFuture<Item = Fulfill, Error = Reject>,
-    <B as futures::future::IntoFuture>::Future: std::marker::Send + 'static,
+    B: Future<Output = IlpResult> + Send + 'static,
     F: FnMut(OutgoingRequest<A>) -> B,
 {
     type Future = BoxedIlpFuture;
 
     fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
-        Box::new((self.handler)(request).into_future())
+        Box::pin((self.handler)(request))
     }
 }
@@ -59,7 +59,7 @@ fn eth_ledger_settlement() {
 
     let node1_secret = cli::random_secret();
     let node1 = InterledgerNode {
-        ilp_address: Address::from_str("example.alice").unwrap(),
+        ilp_address: Some(Address::from_str("example.alice").unwrap()),
         default_spsp_account: None,
         admin_auth_token: "hi_alice".to_string(),
         redis_connection: connection_info1.clone(),
@@ -68,6 +68,9 @@ fn eth_ledger_settlement() {
         settlement_address: ([127, 0, 0, 1], node1_settlement).into(),
         secret_seed: node1_secret,
         route_broadcast_interval: Some(200),
+        node_id: None,
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     let node1_clone = node1.clone();
     runtime.spawn(
@@ -76,7 +79,7 @@ fn eth_ledger_settlement() {
                 // TODO insert the accounts via HTTP request
                 node1_clone
                     .insert_account(AccountDetails {
-                        ilp_address: Address::from_str("example.alice").unwrap(),
+                        ilp_address: Some(Address::from_str("example.alice").unwrap()),
                         username: Username::from_str("alice").unwrap(),
                         asset_code: "ETH".to_string(),
                         asset_scale: eth_decimals,
@@ -96,10 +99,15 @@ fn eth_ledger_settlement() {
                         packets_per_minute_limit: None,
                         amount_per_minute_limit: None,
                         settlement_engine_url: None,
+                        route_cost: None,
+                        route_export_policy: None,
+                        route_import_policy: None,
+                        route_auth_key: None,
+                        route_weight: None,
                     })
                     .and_then(move |_| {
                         node1_clone.insert_account(AccountDetails {
-                            ilp_address: Address::from_str("example.bob").unwrap(),
+                            ilp_address: Some(Address::from_str("example.bob").unwrap()),
                             username: Username::from_str("bob").unwrap(),
                             asset_code: "ETH".to_string(),
                             asset_scale: eth_decimals,
@@ -122,6 +130,11 @@ fn eth_ledger_settlement() {
                                 "http://localhost:{}",
                                 node1_engine
                             )),
+                            route_cost: None,
+                            route_export_policy: None,
+                            route_import_policy: None,
+                            route_auth_key: None,
+                            route_weight: None,
                         })
                     })
                     .and_then(move |_| node1.serve())
@@ -131,7 +144,7 @@ fn eth_ledger_settlement() {
 
     let node2_secret = cli::random_secret();
     let node2 = InterledgerNode {
-        ilp_address: Address::from_str("example.bob").unwrap(),
+        ilp_address: Some(Address::from_str("example.bob").unwrap()),
         default_spsp_account: None,
         admin_auth_token: "admin".to_string(),
         redis_connection: connection_info2.clone(),
@@ -140,13 +153,16 @@ fn eth_ledger_settlement() {
         settlement_address: ([127, 0, 0, 1], node2_settlement).into(),
         secret_seed: node2_secret,
         route_broadcast_interval: Some(200),
+        node_id: None,
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     runtime.spawn(
         start_eth_engine(connection_info2, node2_engine, bob_key, node2_settlement).and_then(
             move |_| {
                 node2
                     .insert_account(AccountDetails {
-                        ilp_address: Address::from_str("example.bob").unwrap(),
+                        ilp_address: Some(Address::from_str("example.bob").unwrap()),
                         username: Username::from_str("bob").unwrap(),
                         asset_code: "ETH".to_string(),
                         asset_scale: eth_decimals,
@@ -166,11 +182,16 @@ fn eth_ledger_settlement() {
                         packets_per_minute_limit: None,
                         amount_per_minute_limit: None,
                         settlement_engine_url: None,
+                        route_cost: None,
+                        route_export_policy: None,
+                        route_import_policy: None,
+                        route_auth_key: None,
+                        route_weight: None,
                     })
                     .and_then(move |_| {
                         node2
                             .insert_account(AccountDetails {
-                                ilp_address: Address::from_str("example.alice").unwrap(),
+                                ilp_address: Some(Address::from_str("example.alice").unwrap()),
                                 username: Username::from_str("alice").unwrap(),
                                 asset_code: "ETH".to_string(),
                                 asset_scale: eth_decimals,
@@ -193,6 +214,11 @@ fn eth_ledger_settlement() {
                                     "http://localhost:{}",
                                     node2_engine
                                 )),
+                                route_cost: None,
+                                route_export_policy: None,
+                                route_import_policy: None,
+                                route_auth_key: None,
+                                route_weight: None,
                             })
                             .and_then(move |_| node2.serve())
                     })
@@ -99,7 +99,7 @@ fn eth_xrp_interoperable() {
         .unwrap();
 
     let node1 = InterledgerNode {
-        ilp_address: Address::from_str("example.alice").unwrap(),
+        ilp_address: Some(Address::from_str("example.alice").unwrap()),
         default_spsp_account: None,
         admin_auth_token: "admin".to_string(),
         redis_connection: connection_info1,
@@ -108,6 +108,9 @@ fn eth_xrp_interoperable() {
         settlement_address: ([127, 0, 0, 1], node1_settlement).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        node_id: None,
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     let node1_clone = node1.clone();
     runtime.spawn(
@@ -115,7 +118,7 @@ fn eth_xrp_interoperable() {
         node1_eth_engine_fut.and_then(move |_| {
             node1_clone
                 .insert_account(AccountDetails {
-                    ilp_address: Address::from_str("example.alice").unwrap(),
+                    ilp_address: Some(Address::from_str("example.alice").unwrap()),
                     username: Username::from_str("alice").unwrap(),
                     asset_code: "ETH".to_string(),
                     asset_scale: eth_decimals,
@@ -135,12 +138,17 @@ fn eth_xrp_interoperable() {
                     packets_per_minute_limit: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: None,
+                    route_cost: None,
+                    route_export_policy: None,
+                    route_import_policy: None,
+                    route_auth_key: None,
+                    route_weight: None,
                 })
                 .and_then(move |_|
             // TODO insert the accounts via HTTP request
             node1_clone
                 .insert_account(AccountDetails {
-                    ilp_address: Address::from_str("example.bob").unwrap(),
+                    ilp_address: Some(Address::from_str("example.bob").unwrap()),
                     username: Username::from_str("bob").unwrap(),
                     asset_code: "ETH".to_string(),
                     asset_scale: eth_decimals,
@@ -160,13 +168,18 @@ fn eth_xrp_interoperable() {
                     packets_per_minute_limit: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: Some(format!("http://localhost:{}", node1_engine)),
+                    route_cost: None,
+                    route_export_policy: None,
+                    route_import_policy: None,
+                    route_auth_key: None,
+                    route_weight: None,
                 }))
                 .and_then(move |_| node1.serve())
         }),
     );
 
     let node2 = InterledgerNode {
-        ilp_address: Address::from_str("example.bob").unwrap(),
+        ilp_address: Some(Address::from_str("example.bob").unwrap()),
         default_spsp_account: None,
         admin_auth_token: "admin".to_string(),
         redis_connection: connection_info2,
@@ -175,6 +188,9 @@ fn eth_xrp_interoperable() {
         settlement_address: ([127, 0, 0, 1], node2_settlement).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        node_id: None,
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     let node2_clone = node2.clone();
     runtime.spawn(
@@ -182,7 +198,7 @@ fn eth_xrp_interoperable() {
             .and_then(move |_| {
                 node2_clone
                     .insert_account(AccountDetails {
-                        ilp_address: Address::from_str("example.alice").unwrap(),
+                        ilp_address: Some(Address::from_str("example.alice").unwrap()),
                         username: Username::from_str("alice").unwrap(),
                         asset_code: "ETH".to_string(),
                         asset_scale: eth_decimals,
@@ -202,10 +218,15 @@ fn eth_xrp_interoperable() {
                         packets_per_minute_limit: None,
                         amount_per_minute_limit: None,
                         settlement_engine_url: Some(format!("http://localhost:{}", node2_engine)),
+                        route_cost: None,
+                        route_export_policy: None,
+                        route_import_policy: None,
+                        route_auth_key: None,
+                        route_weight: None,
                     })
                     .and_then(move |_| {
                         node2_clone.insert_account(AccountDetails {
-                            ilp_address: Address::from_str("example.bob.charlie").unwrap(),
+                            ilp_address: Some(Address::from_str("example.bob.charlie").unwrap()),
                             username: Username::from_str("charlie").unwrap(),
                             asset_code: "XRP".to_string(),
                             asset_scale: xrp_decimals,
@@ -228,6 +249,11 @@ fn eth_xrp_interoperable() {
                                 "http://localhost:{}",
                                 node2_xrp_engine_port
                             )),
+                            route_cost: None,
+                            route_export_policy: None,
+                            route_import_policy: None,
+                            route_auth_key: None,
+                            route_weight: None,
                         })
                     })
             })
@@ -250,7 +276,7 @@ fn eth_xrp_interoperable() {
     );
 
     let node3 = InterledgerNode {
-        ilp_address: Address::from_str("example.bob.charlie").unwrap(),
+        ilp_address: Some(Address::from_str("example.bob.charlie").unwrap()),
         default_spsp_account: None,
         admin_auth_token: "admin".to_string(),
         redis_connection: connection_info3,
@@ -259,6 +285,9 @@ fn eth_xrp_interoperable() {
         settlement_address: ([127, 0, 0, 1], node3_settlement).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        node_id: None,
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     let node3_clone = node3.clone();
     runtime.spawn(
@@ -268,7 +297,7 @@ fn eth_xrp_interoperable() {
             .and_then(move |_| {
                 node3_clone
                     .insert_account(AccountDetails {
-                        ilp_address: Address::from_str("example.bob.charlie").unwrap(),
+                        ilp_address: Some(Address::from_str("example.bob.charlie").unwrap()),
                         username: Username::from_str("charlie").unwrap(),
                         asset_code: "XRP".to_string(),
                         asset_scale: xrp_decimals,
@@ -288,10 +317,15 @@ fn eth_xrp_interoperable() {
                         packets_per_minute_limit: None,
                         amount_per_minute_limit: None,
                         settlement_engine_url: None,
+                        route_cost: None,
+                        route_export_policy: None,
+                        route_import_policy: None,
+                        route_auth_key: None,
+                        route_weight: None,
                     })
                     .and_then(move |_| {
                         node3_clone.insert_account(AccountDetails {
-                            ilp_address: Address::from_str("example.bob").unwrap(),
+                            ilp_address: Some(Address::from_str("example.bob").unwrap()),
                             username: Username::from_str("bob").unwrap(),
                             asset_code: "XRP".to_string(),
                             asset_scale: xrp_decimals,
@@ -314,6 +348,11 @@ fn eth_xrp_interoperable() {
                                 "http://localhost:{}",
                                 node3_xrp_engine_port
                             )),
+                            route_cost: None,
+                            route_export_policy: None,
+                            route_import_policy: None,
+                            route_auth_key: None,
+                            route_weight: None,
                         })
                     })
             })
@@ -78,7 +78,7 @@ fn xrp_ledger_settlement() {
 
     let node1_secret = cli::random_secret();
     let node1 = InterledgerNode {
-        ilp_address: Address::from_str("example.alice").unwrap(),
+        ilp_address: Some(Address::from_str("example.alice").unwrap()),
         default_spsp_account: None,
         admin_auth_token: "hi_alice".to_string(),
         redis_connection: connection_info1.clone(),
@@ -87,13 +87,16 @@ fn xrp_ledger_settlement() {
         settlement_address: ([127, 0, 0, 1], node1_settlement).into(),
         secret_seed: node1_secret,
         route_broadcast_interval: Some(200),
+        node_id: None,
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     let node1_clone = node1.clone();
     runtime.spawn(
         // TODO insert the accounts via HTTP request
         node1_clone
             .insert_account(AccountDetails {
-                ilp_address: Address::from_str("example.alice").unwrap(),
+                ilp_address: Some(Address::from_str("example.alice").unwrap()),
                 username: Username::from_str("alice").unwrap(),
                 asset_code: "XRP".to_string(),
                 asset_scale: xrp_decimals,
@@ -113,10 +116,15 @@ fn xrp_ledger_settlement() {
                 packets_per_minute_limit: None,
                 amount_per_minute_limit: None,
                 settlement_engine_url: None,
+                route_cost: None,
+                route_export_policy: None,
+                route_import_policy: None,
+                route_auth_key: None,
+                route_weight: None,
             })
             .and_then(move |_| {
                 node1_clone.insert_account(AccountDetails {
-                    ilp_address: Address::from_str("example.bob").unwrap(),
+                    ilp_address: Some(Address::from_str("example.bob").unwrap()),
                     username: Username::from_str("bob").unwrap(),
                     asset_code: "XRP".to_string(),
                     asset_scale: xrp_decimals,
@@ -136,6 +144,11 @@ fn xrp_ledger_settlement() {
                     packets_per_minute_limit: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: Some(format!("http://localhost:{}", node1_engine)),
+                    route_cost: None,
+                    route_export_policy: None,
+                    route_import_policy: None,
+                    route_auth_key: None,
+                    route_weight: None,
                 })
             })
             .and_then(move |_| node1.serve()),
@@ -143,7 +156,7 @@ fn xrp_ledger_settlement() {
 
     let node2_secret = cli::random_secret();
     let node2 = InterledgerNode {
-        ilp_address: Address::from_str("example.bob").unwrap(),
+        ilp_address: Some(Address::from_str("example.bob").unwrap()),
         default_spsp_account: None,
         admin_auth_token: "admin".to_string(),
         redis_connection: connection_info2.clone(),
@@ -152,11 +165,14 @@ fn xrp_ledger_settlement() {
         settlement_address: ([127, 0, 0, 1], node2_settlement).into(),
         secret_seed: node2_secret,
         route_broadcast_interval: Some(200),
+        node_id: None,
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     runtime.spawn(
         node2
             .insert_account(AccountDetails {
-                ilp_address: Address::from_str("example.bob").unwrap(),
+                ilp_address: Some(Address::from_str("example.bob").unwrap()),
                 username: Username::from_str("bob").unwrap(),
                 asset_code: "XRP".to_string(),
                 asset_scale: xrp_decimals,
@@ -176,11 +192,16 @@ fn xrp_ledger_settlement() {
                 packets_per_minute_limit: None,
                 amount_per_minute_limit: None,
                 settlement_engine_url: None,
+                route_cost: None,
+                route_export_policy: None,
+                route_import_policy: None,
+                route_auth_key: None,
+                route_weight: None,
             })
             .and_then(move |_| {
                 node2
                     .insert_account(AccountDetails {
-                        ilp_address: Address::from_str("example.alice").unwrap(),
+                        ilp_address: Some(Address::from_str("example.alice").unwrap()),
                         username: Username::from_str("alice").unwrap(),
                         asset_code: "XRP".to_string(),
                         asset_scale: xrp_decimals,
@@ -200,6 +221,11 @@ fn xrp_ledger_settlement() {
                         packets_per_minute_limit: None,
                         amount_per_minute_limit: None,
                         settlement_engine_url: Some(format!("http://localhost:{}", node2_engine)),
+                        route_cost: None,
+                        route_export_policy: None,
+                        route_import_policy: None,
+                        route_auth_key: None,
+                        route_weight: None,
                     })
                     .and_then(move |_| node2.serve())
             }),
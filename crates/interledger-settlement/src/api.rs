@@ -7,10 +7,13 @@ use futures::{
     future::{err, ok, result, Either},
     Future,
 };
+use futures03::{FutureExt, TryFutureExt};
 use hyper::{Response, StatusCode};
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::PrepareBuilder;
-use interledger_service::{AccountStore, OutgoingRequest, OutgoingService};
+use interledger_service::{
+    store_error_status, AccountStore, Extensions, OutgoingRequest, OutgoingService, StoreError,
+};
 use log::{debug, error};
 use num_bigint::BigUint;
 use num_traits::cast::ToPrimitive;
@@ -58,13 +61,14 @@ impl_web! {
             &self,
             idempotency_key: String,
             input_hash: [u8; 32],
-        ) -> impl Future<Item = Option<(StatusCode, Bytes)>, Error = String> {
+        ) -> impl Future<Item = Option<(StatusCode, Bytes)>, Error = (StatusCode, String)> {
             self.store
                 .load_idempotent_data(idempotency_key.clone())
-                .map_err(move |_| {
-                    let error_msg = "Couldn't load idempotent data".to_owned();
-                    error!("{}", error_msg);
-                    error_msg
+                .compat()
+                .map_err(move |err| {
+                    let error_msg = format!("Couldn't load idempotent data for key {}", idempotency_key);
+                    error!("{}: {}", error_msg, err);
+                    (store_error_status_code(&err), error_msg)
                 })
                 .and_then(move |ret: Option<IdempotentData>| {
                     if let Some(ret) = ret {
@@ -88,9 +92,8 @@ impl_web! {
                 // key, perform the call and save the idempotent return data
                 Either::A(
                     self.check_idempotency(idempotency_key.clone(), input_hash)
-                    .map_err(move |err| {
-                        let status_code = StatusCode::from_u16(500).unwrap();
-                        Response::builder().status(status_code).body(err).unwrap()
+                    .map_err(move |(status_code, error_msg)| {
+                        Response::builder().status(status_code).body(error_msg).unwrap()
                     })
                     .and_then(move |ret: Option<(StatusCode, Bytes)>| {
                         if let Some(ret) = ret {
@@ -108,14 +111,19 @@ impl_web! {
                                 .map_err({let store = store.clone(); let idempotency_key = idempotency_key.clone(); move |ret: (StatusCode, String)| {
                                     let status_code = ret.0;
                                     let data = Bytes::from(ret.1.clone());
-                                    spawn(store.save_idempotent_data(idempotency_key, input_hash, status_code, data));
+                                    spawn(store.save_idempotent_data(idempotency_key.clone(), input_hash, status_code, data).compat().map_err(move |err| {
+                                        error!("Couldn't save idempotent data for key {}: {}", idempotency_key, err)
+                                    }));
                                     Response::builder().status(status_code).body(ret.1).unwrap()
                                 }})
                                 .and_then(move |ret: (StatusCode, Bytes)| {
-                                    store.save_idempotent_data(idempotency_key, input_hash, ret.0, ret.1.clone())
-                                    .map_err({let ret = ret.clone(); move |_| {
-                                        Response::builder().status(ret.0).body(String::from_utf8_lossy(&ret.1).to_string()).unwrap()
-                                    }}).and_then(move |_| {
+                                    store.save_idempotent_data(idempotency_key.clone(), input_hash, ret.0, ret.1.clone())
+                                    .compat()
+                                    .map_err(move |err| {
+                                        let error_msg = format!("Couldn't save idempotent data for key {}", idempotency_key);
+                                        error!("{}: {}", error_msg, err);
+                                        Response::builder().status(store_error_status_code(&err)).body(error_msg).unwrap()
+                                    }).and_then(move |_| {
                                         Ok(Response::builder().status(ret.0).body(ret.1).unwrap())
                                     })
                                 })
@@ -162,10 +170,11 @@ impl_web! {
                 let store = store.clone();
                 move |account_id| {
                 store.get_accounts(vec![account_id])
-                .map_err(move |_err| {
+                .compat()
+                .map_err(move |err| {
                     let error_msg = format!("Error getting account: {}", account_id);
-                    error!("{}", error_msg);
-                    (StatusCode::from_u16(404).unwrap(), error_msg)
+                    error!("{}: {}", error_msg, err);
+                    (store_error_status_code(&err), error_msg)
                 })
             }})
             .and_then(move |accounts| {
@@ -206,10 +215,11 @@ impl_web! {
                             std::u64::MAX
                         };
                         store.update_balance_for_incoming_settlement(account_id, amount, idempotency_key)
-                        .map_err(move |_| {
+                        .compat()
+                        .map_err(move |err| {
                             let error_msg = format!("Error updating balance of account: {} for incoming settlement of amount: {}", account_id, amount);
-                            error!("{}", error_msg);
-                            (StatusCode::from_u16(500).unwrap(), error_msg)
+                            error!("{}: {}", error_msg, err);
+                            (store_error_status_code(&err), error_msg)
                         })
                         .and_then(move |_| {
                             let quantity = json!(Quantity::new(amount, account.asset_scale()));
@@ -246,11 +256,11 @@ impl_web! {
                }))
                .and_then(move |account_id| {
                     store.get_accounts(vec![account_id])
-                    .map_err(move |_| {
+                    .compat()
+                    .map_err(move |err| {
                         let error_msg = format!("Error getting account: {}", account_id);
-                        error!("{}", error_msg);
-                        let status_code = StatusCode::from_u16(404).unwrap();
-                        (status_code, error_msg)
+                        error!("{}: {}", error_msg, err);
+                        (store_error_status_code(&err), error_msg)
                     })
                 })
                .and_then(|accounts| {
@@ -280,8 +290,11 @@ impl_web! {
                            expires_at: SystemTime::now() + Duration::from_secs(30),
                            data: &body,
                            execution_condition: &PEER_PROTOCOL_CONDITION,
-                       }.build()
+                       }.build(),
+                       extensions: Extensions::new(),
                    })
+                   .boxed()
+                   .compat()
                    .map_err(move |reject| {
                        let error_msg = format!("Error sending message to peer settlement engine. Packet rejected with code: {}, message: {}", reject.code(), str::from_utf8(reject.message()).unwrap_or_default());
                        error!("{}", error_msg);
@@ -307,6 +320,10 @@ impl_web! {
 
 }
 
+fn store_error_status_code(err: &StoreError) -> StatusCode {
+    StatusCode::from_u16(store_error_status(err)).unwrap()
+}
+
 fn get_hash_of(preimage: &[u8]) -> [u8; 32] {
     let mut hash = [0; 32];
     hash.copy_from_slice(digest(&SHA256, preimage).as_ref());
@@ -451,7 +468,8 @@ mod tests {
             let ret: Response<_> =
                 block_on(api.receive_settlement(id, SETTLEMENT_DATA.clone(), IDEMPOTENCY.clone()))
                     .unwrap_err();
-            assert_eq!(ret.status().as_u16(), 500);
+            // The test store fails with StoreError::Unavailable
+            assert_eq!(ret.status().as_u16(), 503);
         }
 
         #[test]
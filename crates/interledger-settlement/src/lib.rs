@@ -4,12 +4,11 @@
 extern crate tower_web;
 
 use bytes::Bytes;
-use futures::Future;
 use hyper::StatusCode;
 use interledger_packet::Address;
-use interledger_service::Account;
+use interledger_service::{Account, StoreError};
 use lazy_static::lazy_static;
-use std::str::FromStr;
+use std::{future::Future, pin::Pin, str::FromStr};
 use url::Url;
 
 mod api;
@@ -68,13 +67,13 @@ pub trait SettlementStore {
         account_id: <Self::Account as Account>::AccountId,
         amount: u64,
         idempotency_key: Option<String>,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>>;
 
     fn refund_settlement(
         &self,
         account_id: <Self::Account as Account>::AccountId,
         settle_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>>;
 }
 
 pub type IdempotentData = (StatusCode, Bytes, [u8; 32]);
@@ -85,7 +84,7 @@ pub trait IdempotentStore {
     fn load_idempotent_data(
         &self,
         idempotency_key: String,
-    ) -> Box<dyn Future<Item = Option<IdempotentData>, Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<Option<IdempotentData>, StoreError>> + Send>>;
 
     /// Saves the data that was passed along with the api request for later
     /// The store MUST also save a hash of the input, so that it errors out on requests
@@ -95,7 +94,7 @@ pub trait IdempotentStore {
         input_hash: [u8; 32],
         status_code: StatusCode,
         data: Bytes,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>>;
 }
 
 #[derive(Debug)]
@@ -3,6 +3,7 @@ use futures::{
     future::{err, Either},
     Future, Stream,
 };
+use futures03::compat::Future01CompatExt;
 use interledger_packet::{Address, ErrorCode, FulfillBuilder, RejectBuilder};
 use interledger_service::{BoxedIlpFuture, IncomingRequest, IncomingService};
 use log::error;
@@ -71,7 +72,7 @@ where
                         .send()
                 };
 
-                return Box::new(Retry::spawn(ExponentialBackoff::from_millis(10).take(10), action)
+                return Box::pin(Retry::spawn(ExponentialBackoff::from_millis(10).take(10), action)
                 .map_err(move |error| {
                     error!("Error sending message to settlement engine: {:?}", error);
                     RejectBuilder {
@@ -113,10 +114,11 @@ where
                             triggered_by: Some(&SE_ILP_ADDRESS),
                         }.build()))
                     }
-                }));
+                })
+                .compat());
             }
         }
-        Box::new(self.next.handle_request(request))
+        Box::pin(self.next.handle_request(request))
     }
 }
 
@@ -125,7 +127,9 @@ mod tests {
     use super::*;
     use crate::fixtures::{BODY, DATA, SERVICE_ADDRESS, TEST_ACCOUNT_0};
     use crate::test_helpers::{block_on, mock_message, test_service};
+    use futures03::TryFutureExt;
     use interledger_packet::{Fulfill, PrepareBuilder, Reject};
+    use interledger_service::Extensions;
     use std::str::FromStr;
     use std::time::SystemTime;
 
@@ -135,17 +139,20 @@ mod tests {
         let m = mock_message(200).create();
         let mut settlement = test_service();
         let fulfill: Fulfill = block_on(
-            settlement.handle_request(IncomingRequest {
-                from: TEST_ACCOUNT_0.clone(),
-                prepare: PrepareBuilder {
-                    amount: 0,
-                    expires_at: SystemTime::now(),
-                    destination: SE_ILP_ADDRESS.clone(),
-                    data: DATA.as_bytes(),
-                    execution_condition: &[0; 32],
-                }
-                .build(),
-            }),
+            settlement
+                .handle_request(IncomingRequest {
+                    from: TEST_ACCOUNT_0.clone(),
+                    prepare: PrepareBuilder {
+                        amount: 0,
+                        expires_at: SystemTime::now(),
+                        destination: SE_ILP_ADDRESS.clone(),
+                        data: DATA.as_bytes(),
+                        execution_condition: &[0; 32],
+                    }
+                    .build(),
+                    extensions: Extensions::new(),
+                })
+                .compat(),
         )
         .unwrap();
 
@@ -160,17 +167,20 @@ mod tests {
         let mut settlement = test_service();
         let destination = Address::from_str("example.some.address").unwrap();
         let reject: Reject = block_on(
-            settlement.handle_request(IncomingRequest {
-                from: TEST_ACCOUNT_0.clone(),
-                prepare: PrepareBuilder {
-                    amount: 0,
-                    expires_at: SystemTime::now(),
-                    destination,
-                    data: DATA.as_bytes(),
-                    execution_condition: &[0; 32],
-                }
-                .build(),
-            }),
+            settlement
+                .handle_request(IncomingRequest {
+                    from: TEST_ACCOUNT_0.clone(),
+                    prepare: PrepareBuilder {
+                        amount: 0,
+                        expires_at: SystemTime::now(),
+                        destination,
+                        data: DATA.as_bytes(),
+                        execution_condition: &[0; 32],
+                    }
+                    .build(),
+                    extensions: Extensions::new(),
+                })
+                .compat(),
         )
         .unwrap_err();
 
@@ -187,17 +197,20 @@ mod tests {
         let mut acc = TEST_ACCOUNT_0.clone();
         acc.no_details = true; // Hide the settlement engine data from the account
         let reject: Reject = block_on(
-            settlement.handle_request(IncomingRequest {
-                from: acc.clone(),
-                prepare: PrepareBuilder {
-                    amount: 0,
-                    expires_at: SystemTime::now(),
-                    destination: acc.ilp_address,
-                    data: DATA.as_bytes(),
-                    execution_condition: &[0; 32],
-                }
-                .build(),
-            }),
+            settlement
+                .handle_request(IncomingRequest {
+                    from: acc.clone(),
+                    prepare: PrepareBuilder {
+                        amount: 0,
+                        expires_at: SystemTime::now(),
+                        destination: acc.ilp_address,
+                        data: DATA.as_bytes(),
+                        execution_condition: &[0; 32],
+                    }
+                    .build(),
+                    extensions: Extensions::new(),
+                })
+                .compat(),
         )
         .unwrap_err();
 
@@ -215,17 +228,20 @@ mod tests {
         let m = mock_message(error_code).create();
         let mut settlement = test_service();
         let reject: Reject = block_on(
-            settlement.handle_request(IncomingRequest {
-                from: TEST_ACCOUNT_0.clone(),
-                prepare: PrepareBuilder {
-                    amount: 0,
-                    expires_at: SystemTime::now(),
-                    destination: SE_ILP_ADDRESS.clone(),
-                    data: DATA.as_bytes(),
-                    execution_condition: &[0; 32],
-                }
-                .build(),
-            }),
+            settlement
+                .handle_request(IncomingRequest {
+                    from: TEST_ACCOUNT_0.clone(),
+                    prepare: PrepareBuilder {
+                        amount: 0,
+                        expires_at: SystemTime::now(),
+                        destination: SE_ILP_ADDRESS.clone(),
+                        data: DATA.as_bytes(),
+                        execution_condition: &[0; 32],
+                    }
+                    .build(),
+                    extensions: Extensions::new(),
+                })
+                .compat(),
         )
         .unwrap_err();
 
@@ -243,5 +259,4 @@ mod tests {
             .as_bytes(),
         );
     }
-
 }
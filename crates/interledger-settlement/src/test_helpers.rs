@@ -1,9 +1,6 @@
 use super::*;
 use crate::SettlementEngineDetails;
-use futures::{
-    future::{err, ok},
-    Future,
-};
+use futures03::future::{err, ok};
 use interledger_ildcp::IldcpAccount;
 use interledger_service::{
     incoming_service_fn, outgoing_service_fn, Account, AccountStore, IncomingService,
@@ -88,18 +85,26 @@ impl SettlementStore for TestStore {
         _account_id: <Self::Account as Account>::AccountId,
         _amount: u64,
         _idempotency_key: Option<String>,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
-        let ret = if self.should_fail { err(()) } else { ok(()) };
-        Box::new(ret)
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>> {
+        let ret = if self.should_fail {
+            err(StoreError::Unavailable("Store failure".to_string()))
+        } else {
+            ok(())
+        };
+        Box::pin(ret)
     }
 
     fn refund_settlement(
         &self,
         _account_id: <Self::Account as Account>::AccountId,
         _settle_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
-        let ret = if self.should_fail { err(()) } else { ok(()) };
-        Box::new(ret)
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>> {
+        let ret = if self.should_fail {
+            err(StoreError::Unavailable("Store failure".to_string()))
+        } else {
+            ok(())
+        };
+        Box::pin(ret)
     }
 }
 
@@ -107,14 +112,14 @@ impl IdempotentStore for TestStore {
     fn load_idempotent_data(
         &self,
         idempotency_key: String,
-    ) -> Box<dyn Future<Item = Option<IdempotentData>, Error = ()> + Send> {
+    ) -> Pin<Box<dyn Future<Output = Result<Option<IdempotentData>, StoreError>> + Send>> {
         let cache = self.cache.read();
         if let Some(data) = cache.get(&idempotency_key) {
             let mut guard = self.cache_hits.write();
             *guard += 1; // used to test how many times this branch gets executed
-            Box::new(ok(Some((data.0, data.1.clone(), data.2))))
+            Box::pin(ok(Some((data.0, data.1.clone(), data.2))))
         } else {
-            Box::new(ok(None))
+            Box::pin(ok(None))
         }
     }
 
@@ -124,10 +129,10 @@ impl IdempotentStore for TestStore {
         input_hash: [u8; 32],
         status_code: StatusCode,
         data: Bytes,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    ) -> Pin<Box<dyn Future<Output = Result<(), StoreError>> + Send>> {
         let mut cache = self.cache.write();
         cache.insert(idempotency_key, (status_code, data, input_hash));
-        Box::new(ok(()))
+        Box::pin(ok(()))
     }
 }
 
@@ -137,7 +142,7 @@ impl AccountStore for TestStore {
     fn get_accounts(
         &self,
         account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
-    ) -> Box<dyn Future<Item = Vec<Self::Account>, Error = ()> + Send> {
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Self::Account>, StoreError>> + Send>> {
         let accounts: Vec<TestAccount> = self
             .accounts
             .iter()
@@ -150,9 +155,9 @@ impl AccountStore for TestStore {
             })
             .collect();
         if accounts.len() == account_ids.len() {
-            Box::new(ok(accounts))
+            Box::pin(ok(accounts))
         } else {
-            Box::new(err(()))
+            Box::pin(err(StoreError::NotFound("Accounts not found".to_string())))
         }
     }
 
@@ -160,8 +165,8 @@ impl AccountStore for TestStore {
     fn get_account_id_from_username(
         &self,
         _username: &Username,
-    ) -> Box<dyn Future<Item = u64, Error = ()> + Send> {
-        Box::new(ok(1))
+    ) -> Pin<Box<dyn Future<Output = Result<u64, StoreError>> + Send>> {
+        Box::pin(ok(1))
     }
 }
 
@@ -209,7 +214,7 @@ pub fn mock_message(status_code: usize) -> mockito::Mock {
 // Futures helper taken from the store_helpers in interledger-store-redis.
 pub fn block_on<F>(f: F) -> Result<F::Item, F::Error>
 where
-    F: Future + Send + 'static,
+    F: futures::Future + Send + 'static,
     F::Item: Send,
     F::Error: Send,
 {
@@ -224,13 +229,13 @@ pub fn test_service(
     SettlementMessageService::new(
         SERVICE_ADDRESS.clone(),
         incoming_service_fn(|_request| {
-            Box::new(err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: b"No other incoming handler!",
                 data: &[],
                 triggered_by: Some(&SERVICE_ADDRESS),
             }
-            .build()))
+            .build())
         }),
     )
 }
@@ -248,7 +253,7 @@ pub fn test_api(
 ) -> SettlementApi<TestStore, impl OutgoingService<TestAccount> + Clone + Send + Sync, TestAccount>
 {
     let outgoing = outgoing_service_fn(move |_| {
-        Box::new(if should_fulfill {
+        if should_fulfill {
             ok(FulfillBuilder {
                 fulfillment: &[0; 32],
                 data: b"hello!",
@@ -262,7 +267,7 @@ pub fn test_api(
                 triggered_by: Some(&SERVICE_ADDRESS),
             }
             .build())
-        })
+        }
     });
     SettlementApi::new(test_store, outgoing)
 }
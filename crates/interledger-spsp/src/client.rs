@@ -2,7 +2,9 @@ use super::{Error, SpspResponse};
 use futures::{future::result, Future};
 use interledger_packet::Address;
 use interledger_service::{Account, IncomingService};
-use interledger_stream::send_money;
+use interledger_stream::{
+    quote as stream_quote, send_destination_amount, send_money, Quote, StreamDelivery,
+};
 use log::{debug, error, trace};
 use reqwest::r#async::Client;
 use std::convert::TryFrom;
@@ -47,7 +49,8 @@ where
             debug!("Sending SPSP payment to address: {}", addr);
 
             send_money(service, &from_account, addr, &shared_secret, source_amount)
-                .map(move |(amount_delivered, _plugin)| {
+                .map(move |(delivery, _plugin)| {
+                    let amount_delivered = delivery.delivered_amount;
                     debug!(
                         "Sent SPSP payment of {} and delivered {} of the receiver's units",
                         source_amount, amount_delivered
@@ -62,6 +65,88 @@ where
     })
 }
 
+/// Query the details of the given Payment Pointer and send enough money using the STREAM protocol
+/// to deliver `destination_amount` of the receiver's units.
+///
+/// The payment is stopped if it would cost more than `max_source_amount` or if the exchange rate
+/// is, or slips, below `min_exchange_rate`.
+pub fn pay_destination_amount<S, A>(
+    service: S,
+    from_account: A,
+    receiver: &str,
+    destination_amount: u64,
+    max_source_amount: u64,
+    min_exchange_rate: f64,
+) -> impl Future<Item = StreamDelivery, Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    query(receiver).and_then(move |spsp| {
+        let shared_secret = spsp.shared_secret;
+        let addr = spsp.destination_account;
+        debug!(
+            "Sending SPSP payment to deliver {} to address: {}",
+            destination_amount, addr
+        );
+
+        send_destination_amount(
+            service,
+            &from_account,
+            addr,
+            &shared_secret,
+            destination_amount,
+            max_source_amount,
+            min_exchange_rate,
+        )
+        .map(move |(delivery, _plugin)| {
+            debug!(
+                "Sent SPSP payment of {} and delivered {} of the receiver's units",
+                delivery.sent_amount, delivery.delivered_amount
+            );
+            delivery
+        })
+        .map_err(move |err| {
+            error!("Error sending payment: {:?}", err);
+            Error::StreamError(err)
+        })
+    })
+}
+
+/// Query the details of the given Payment Pointer and get a quote for sending it `source_amount`.
+///
+/// No money is sent; the quote can be used to estimate how much a payment would deliver.
+pub fn quote<S, A>(
+    service: S,
+    from_account: A,
+    receiver: &str,
+    source_amount: u64,
+) -> impl Future<Item = Quote, Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    query(receiver).and_then(move |spsp| {
+        let addr = spsp.destination_account;
+        debug!(
+            "Getting quote for sending {} to address: {}",
+            source_amount, addr
+        );
+
+        stream_quote(
+            service,
+            &from_account,
+            addr,
+            &spsp.shared_secret,
+            source_amount,
+        )
+        .map_err(move |err| {
+            error!("Error getting quote: {:?}", err);
+            Error::StreamError(err)
+        })
+    })
+}
+
 fn payment_pointer_to_url(payment_pointer: &str) -> String {
     let mut url: String = if payment_pointer.starts_with('$') {
         let mut url = "https://".to_string();
@@ -13,7 +13,7 @@ use serde::{Deserialize, Serialize};
 mod client;
 mod server;
 
-pub use client::{pay, query};
+pub use client::{pay, pay_destination_amount, query, quote};
 pub use server::SpspResponder;
 
 #[derive(Fail, Debug)]
@@ -1,11 +1,21 @@
 use bytes::Bytes;
+use interledger_api::AccountDetails as ApiAccountDetails;
 use interledger_btp::BtpAccount;
+use interledger_ccp::{CcpRoutingAccount, RoutePolicy, RoutingRelation};
 use interledger_http::HttpAccount;
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::Address;
-use interledger_service::{Account as AccountTrait, Username};
-use interledger_service_util::MaxPacketAmountAccount;
-use std::{fmt, str, sync::Arc};
+use interledger_service::{Account as AccountTrait, StoreError, Username};
+use interledger_service_util::{
+    MaxPacketAmountAccount, RateLimitAccount, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
+};
+use interledger_settlement::{SettlementAccount, SettlementEngineDetails};
+use serde::{Serialize, Serializer};
+use std::{
+    fmt,
+    str::{self, FromStr},
+    sync::Arc,
+};
 use url::Url;
 
 /// A helper to create Accounts.
@@ -29,6 +39,21 @@ impl AccountBuilder {
             btp_uri: None,
             btp_incoming_token: None,
             btp_outgoing_token: None,
+            min_balance: None,
+            settle_threshold: None,
+            settle_to: None,
+            routing_relation: RoutingRelation::Child,
+            send_routes: false,
+            receive_routes: false,
+            round_trip_time: DEFAULT_ROUND_TRIP_TIME,
+            packets_per_minute_limit: None,
+            amount_per_minute_limit: None,
+            settlement_engine_url: None,
+            route_weight: None,
+            route_auth_key: None,
+            route_import_policy: None,
+            route_export_policy: None,
+            route_cost: None,
         };
         AccountBuilder { details }
     }
@@ -101,23 +126,128 @@ impl AccountBuilder {
         self.details.max_packet_amount = amount;
         self
     }
+
+    pub fn min_balance(mut self, min_balance: i64) -> Self {
+        self.details.min_balance = Some(min_balance);
+        self
+    }
+
+    pub fn settle_threshold(mut self, settle_threshold: i64) -> Self {
+        self.details.settle_threshold = Some(settle_threshold);
+        self
+    }
+
+    pub fn settle_to(mut self, settle_to: i64) -> Self {
+        self.details.settle_to = Some(settle_to);
+        self
+    }
+
+    pub fn routing_relation(mut self, routing_relation: RoutingRelation) -> Self {
+        self.details.routing_relation = routing_relation;
+        self
+    }
+
+    pub fn send_routes(mut self, send_routes: bool) -> Self {
+        self.details.send_routes = send_routes;
+        self
+    }
+
+    pub fn receive_routes(mut self, receive_routes: bool) -> Self {
+        self.details.receive_routes = receive_routes;
+        self
+    }
+
+    pub fn round_trip_time(mut self, round_trip_time: u32) -> Self {
+        self.details.round_trip_time = round_trip_time;
+        self
+    }
+
+    pub fn packets_per_minute_limit(mut self, limit: u32) -> Self {
+        self.details.packets_per_minute_limit = Some(limit);
+        self
+    }
+
+    pub fn amount_per_minute_limit(mut self, limit: u64) -> Self {
+        self.details.amount_per_minute_limit = Some(limit);
+        self
+    }
+
+    pub fn settlement_engine_url(mut self, url: Url) -> Self {
+        self.details.settlement_engine_url = Some(url);
+        self
+    }
+
+    pub fn route_weight(mut self, weight: u32) -> Self {
+        self.details.route_weight = Some(weight);
+        self
+    }
+
+    pub fn route_auth_key(mut self, key: String) -> Self {
+        self.details.route_auth_key = Some(key);
+        self
+    }
+
+    pub fn route_import_policy(mut self, policy: RoutePolicy) -> Self {
+        self.details.route_import_policy = Some(policy);
+        self
+    }
+
+    pub fn route_export_policy(mut self, policy: RoutePolicy) -> Self {
+        self.details.route_export_policy = Some(policy);
+        self
+    }
+
+    pub fn route_cost(mut self, cost: u32) -> Self {
+        self.details.route_cost = Some(cost);
+        self
+    }
 }
 
-#[derive(Clone)]
+fn address_to_string<S>(address: &Address, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(str::from_utf8(address.as_ref()).unwrap_or(""))
+}
+
+// The auth tokens are left out when the account is serialized (for example, by the node's API)
+#[derive(Clone, Serialize)]
 pub(crate) struct AccountDetails {
     pub(crate) id: u64,
     pub(crate) username: Username,
+    #[serde(serialize_with = "address_to_string")]
     pub(crate) ilp_address: Address,
+    #[serde(skip)]
     pub(crate) additional_routes: Vec<Bytes>,
     pub(crate) asset_code: String,
     pub(crate) asset_scale: u8,
     pub(crate) http_endpoint: Option<Url>,
+    #[serde(skip)]
     pub(crate) http_incoming_token: Option<String>,
+    #[serde(skip)]
     pub(crate) http_outgoing_token: Option<String>,
     pub(crate) btp_uri: Option<Url>,
+    #[serde(skip)]
     pub(crate) btp_outgoing_token: Option<String>,
+    #[serde(skip)]
     pub(crate) btp_incoming_token: Option<String>,
     pub(crate) max_packet_amount: u64,
+    pub(crate) min_balance: Option<i64>,
+    pub(crate) settle_threshold: Option<i64>,
+    pub(crate) settle_to: Option<i64>,
+    pub(crate) routing_relation: RoutingRelation,
+    pub(crate) send_routes: bool,
+    pub(crate) receive_routes: bool,
+    pub(crate) round_trip_time: u32,
+    pub(crate) packets_per_minute_limit: Option<u32>,
+    pub(crate) amount_per_minute_limit: Option<u64>,
+    pub(crate) settlement_engine_url: Option<Url>,
+    pub(crate) route_weight: Option<u32>,
+    #[serde(skip)]
+    pub(crate) route_auth_key: Option<String>,
+    pub(crate) route_import_policy: Option<RoutePolicy>,
+    pub(crate) route_export_policy: Option<RoutePolicy>,
+    pub(crate) route_cost: Option<u32>,
 }
 
 impl AccountDetails {
@@ -135,6 +265,80 @@ pub struct Account {
     pub(crate) inner: Arc<AccountDetails>,
 }
 
+impl Account {
+    /// Build an account from the details submitted through the node's API
+    pub fn try_from(id: u64, details: ApiAccountDetails) -> Result<Account, StoreError> {
+        let ilp_address = details
+            .ilp_address
+            .ok_or_else(|| StoreError::Invalid("An ILP address is required".to_string()))?;
+        let mut builder = AccountBuilder::new(ilp_address, details.username)
+            .id(id)
+            .asset_code(details.asset_code.to_uppercase())
+            .asset_scale(details.asset_scale)
+            .max_packet_amount(details.max_packet_amount)
+            .send_routes(details.send_routes)
+            .receive_routes(details.receive_routes);
+        builder.details.min_balance = details.min_balance;
+        builder.details.settle_threshold = details.settle_threshold;
+        builder.details.settle_to = details.settle_to;
+        builder.details.packets_per_minute_limit = details.packets_per_minute_limit;
+        builder.details.amount_per_minute_limit = details.amount_per_minute_limit;
+        builder.details.http_incoming_token = details.http_incoming_token;
+        builder.details.http_outgoing_token = details.http_outgoing_token;
+        builder.details.btp_incoming_token = details.btp_incoming_token;
+        builder.details.route_weight = details.route_weight;
+        builder.details.route_auth_key = details.route_auth_key;
+        builder.details.route_import_policy = details.route_import_policy;
+        builder.details.route_export_policy = details.route_export_policy;
+        builder.details.route_cost = details.route_cost;
+
+        if let Some(ref url) = details.http_endpoint {
+            let url = Url::parse(url)
+                .map_err(|err| StoreError::Invalid(format!("Invalid HTTP endpoint: {:?}", err)))?;
+            builder = builder.http_endpoint(url);
+        }
+        if let Some(ref url) = details.btp_uri {
+            let mut btp_uri = Url::parse(url)
+                .map_err(|err| StoreError::Invalid(format!("Invalid BTP URI: {:?}", err)))?;
+            // The credentials in the URI are sent as the outgoing token
+            if !btp_uri.username().is_empty() {
+                if let Some(password) = btp_uri.password() {
+                    builder.details.btp_outgoing_token =
+                        Some(format!("{}:{}", btp_uri.username(), password));
+                }
+            }
+            btp_uri.set_username("").unwrap();
+            btp_uri.set_password(None).unwrap();
+            builder = builder.btp_uri(btp_uri);
+        }
+        if let Some(ref relation) = details.routing_relation {
+            let relation = RoutingRelation::from_str(relation).map_err(|_| {
+                StoreError::Invalid(format!("Invalid routing relation: {}", relation))
+            })?;
+            builder = builder.routing_relation(relation);
+        }
+        if let Some(round_trip_time) = details.round_trip_time {
+            builder = builder.round_trip_time(round_trip_time);
+        }
+        if let Some(ref url) = details.settlement_engine_url {
+            let url = Url::parse(url).map_err(|err| {
+                StoreError::Invalid(format!("Invalid settlement engine URL: {:?}", err))
+            })?;
+            builder = builder.settlement_engine_url(url);
+        }
+        Ok(builder.build())
+    }
+}
+
+impl Serialize for Account {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.inner.serialize(serializer)
+    }
+}
+
 impl fmt::Debug for Account {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -201,6 +405,61 @@ impl BtpAccount for Account {
     }
 }
 
+impl CcpRoutingAccount for Account {
+    fn routing_relation(&self) -> RoutingRelation {
+        self.inner.routing_relation
+    }
+
+    fn should_send_routes(&self) -> bool {
+        self.inner.send_routes
+    }
+
+    fn should_receive_routes(&self) -> bool {
+        self.inner.receive_routes
+    }
+
+    fn route_auth_key(&self) -> Option<&[u8]> {
+        self.inner.route_auth_key.as_ref().map(|key| key.as_bytes())
+    }
+
+    fn route_import_policy(&self) -> Option<&RoutePolicy> {
+        self.inner.route_import_policy.as_ref()
+    }
+
+    fn route_export_policy(&self) -> Option<&RoutePolicy> {
+        self.inner.route_export_policy.as_ref()
+    }
+
+    fn route_cost(&self) -> u32 {
+        self.inner.route_cost.unwrap_or(0)
+    }
+}
+
+impl RoundTripTimeAccount for Account {
+    fn round_trip_time(&self) -> u32 {
+        self.inner.round_trip_time
+    }
+}
+
+impl RateLimitAccount for Account {
+    fn packets_per_minute_limit(&self) -> Option<u32> {
+        self.inner.packets_per_minute_limit
+    }
+
+    fn amount_per_minute_limit(&self) -> Option<u64> {
+        self.inner.amount_per_minute_limit
+    }
+}
+
+impl SettlementAccount for Account {
+    fn settlement_engine_details(&self) -> Option<SettlementEngineDetails> {
+        self.inner
+            .settlement_engine_url
+            .as_ref()
+            .map(|url| SettlementEngineDetails { url: url.clone() })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -225,6 +484,16 @@ mod tests {
             Address::from_str("example.address").unwrap()
         );
         assert_eq!(account.username(), &Username::from_str("username").unwrap());
+        assert_eq!(account.routing_relation(), RoutingRelation::Child);
+        assert!(!account.should_send_routes());
+        assert!(!account.should_receive_routes());
+        assert_eq!(account.round_trip_time(), DEFAULT_ROUND_TRIP_TIME);
+        assert_eq!(account.packets_per_minute_limit(), None);
+        assert_eq!(account.amount_per_minute_limit(), None);
+        assert!(account.settlement_engine_details().is_none());
+        assert_eq!(account.route_auth_key(), None);
+        assert_eq!(account.route_import_policy(), None);
+        assert_eq!(account.route_cost(), 0);
     }
 
     #[test]
@@ -245,6 +514,18 @@ mod tests {
         .http_outgoing_token("sodgiuoixfugoiudf".to_string())
         .btp_incoming_token("asdflkjsaldkfjoi".to_string())
         .max_packet_amount(7777)
+        .routing_relation(RoutingRelation::Parent)
+        .receive_routes(true)
+        .round_trip_time(200)
+        .packets_per_minute_limit(10)
+        .amount_per_minute_limit(1000)
+        .settlement_engine_url(Url::parse("http://localhost:3000").unwrap())
+        .route_auth_key("route secret".to_string())
+        .route_export_policy(RoutePolicy {
+            non_transitive: true,
+            ..RoutePolicy::default()
+        })
+        .route_cost(3)
         .build();
         assert_eq!(account.id(), 1);
         assert_eq!(account.asset_code(), "XYZ");
@@ -256,7 +537,19 @@ mod tests {
         assert_eq!(account.get_btp_token(), Some(&b"token"[..]));
         assert_eq!(account.get_http_auth_token(), Some("sodgiuoixfugoiudf"));
         assert_eq!(account.max_packet_amount(), 7777);
+        assert_eq!(account.route_auth_key(), Some(&b"route secret"[..]));
+        assert!(account.route_export_policy().unwrap().non_transitive);
+        assert_eq!(account.route_cost(), 3);
         assert_eq!(account.client_address(), &b"example.address"[..]);
         assert_eq!(account.username(), &Username::from_str("username").unwrap());
+        assert_eq!(account.routing_relation(), RoutingRelation::Parent);
+        assert!(account.should_receive_routes());
+        assert_eq!(account.round_trip_time(), 200);
+        assert_eq!(account.packets_per_minute_limit(), Some(10));
+        assert_eq!(account.amount_per_minute_limit(), Some(1000));
+        assert_eq!(
+            account.settlement_engine_details().unwrap().url,
+            Url::parse("http://localhost:3000").unwrap()
+        );
     }
 }
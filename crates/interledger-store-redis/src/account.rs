@@ -2,11 +2,11 @@ use super::crypto::{decrypt_token, encrypt_token};
 use bytes::Bytes;
 use interledger_api::AccountDetails;
 use interledger_btp::BtpAccount;
-use interledger_ccp::{CcpRoutingAccount, RoutingRelation};
+use interledger_ccp::{CcpRoutingAccount, RoutePolicy, RoutingRelation};
 use interledger_http::HttpAccount;
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::Address;
-use interledger_service::{Account as AccountTrait, Username};
+use interledger_service::{Account as AccountTrait, StoreError, Username};
 use interledger_service_util::{
     MaxPacketAmountAccount, RateLimitAccount, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
 };
@@ -22,13 +22,12 @@ use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::{
     collections::HashMap,
-    convert::TryFrom,
     str::{self, FromStr},
 };
 use uuid::{parser::ParseError, Uuid};
 
 use url::Url;
-const ACCOUNT_DETAILS_FIELDS: usize = 21;
+const ACCOUNT_DETAILS_FIELDS: usize = 26;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Account {
@@ -60,6 +59,12 @@ pub struct Account {
     pub(crate) packets_per_minute_limit: Option<u32>,
     pub(crate) amount_per_minute_limit: Option<u64>,
     pub(crate) settlement_engine_url: Option<Url>,
+    pub(crate) route_weight: Option<u32>,
+    #[serde(skip)]
+    pub(crate) route_auth_key: Option<Bytes>,
+    pub(crate) route_import_policy: Option<RoutePolicy>,
+    pub(crate) route_export_policy: Option<RoutePolicy>,
+    pub(crate) route_cost: Option<u32>,
 }
 
 fn address_to_string<S>(address: &Address, serializer: S) -> Result<S::Ok, S::Error>
@@ -80,16 +85,26 @@ where
     }
 }
 
+/// Log an error about the given account details and convert it into a `StoreError::Invalid`
+fn invalid(message: String) -> StoreError {
+    error!("{}", message);
+    StoreError::Invalid(message)
+}
+
 impl Account {
-    pub fn try_from(id: AccountId, details: AccountDetails) -> Result<Account, ()> {
+    pub fn try_from(id: AccountId, details: AccountDetails) -> Result<Account, StoreError> {
         let http_endpoint = if let Some(ref url) = details.http_endpoint {
-            Some(Url::parse(url).map_err(|err| error!("Invalid URL: {:?}", err))?)
+            Some(
+                Url::parse(url)
+                    .map_err(|err| invalid(format!("Invalid HTTP endpoint: {:?}", err)))?,
+            )
         } else {
             None
         };
 
         let (btp_uri, btp_outgoing_token) = if let Some(ref url) = details.btp_uri {
-            let mut btp_uri = Url::parse(url).map_err(|err| error!("Invalid URL: {:?}", err))?;
+            let mut btp_uri =
+                Url::parse(url).map_err(|err| invalid(format!("Invalid BTP URI: {:?}", err)))?;
             let username = btp_uri.username();
             let btp_outgoing_token = if username != "" {
                 btp_uri
@@ -109,7 +124,8 @@ impl Account {
         let btp_incoming_token = details.btp_incoming_token.map(Bytes::from);
         let http_outgoing_token = details.http_outgoing_token.map(Bytes::from);
         let routing_relation = if let Some(ref relation) = details.routing_relation {
-            RoutingRelation::from_str(relation)?
+            RoutingRelation::from_str(relation)
+                .map_err(|_| invalid(format!("Invalid routing relation: {}", relation)))?
         } else {
             RoutingRelation::Child
         };
@@ -122,9 +138,9 @@ impl Account {
         Ok(Account {
             id,
             username: details.username,
-            ilp_address: Address::try_from(details.ilp_address.as_ref()).map_err(|err| {
-                error!("Invalid ILP Address when creating Redis account: {:?}", err)
-            })?,
+            ilp_address: details
+                .ilp_address
+                .ok_or_else(|| invalid("An ILP address is required".to_string()))?,
             asset_code: details.asset_code.to_uppercase(),
             asset_scale: details.asset_scale,
             max_packet_amount: details.max_packet_amount,
@@ -144,6 +160,11 @@ impl Account {
             packets_per_minute_limit: details.packets_per_minute_limit,
             amount_per_minute_limit: details.amount_per_minute_limit,
             settlement_engine_url,
+            route_weight: details.route_weight,
+            route_auth_key: details.route_auth_key.map(Bytes::from),
+            route_import_policy: details.route_import_policy,
+            route_export_policy: details.route_export_policy,
+            route_cost: details.route_cost,
         })
     }
 
@@ -157,6 +178,9 @@ impl Account {
         if let Some(ref token) = self.http_outgoing_token {
             self.http_outgoing_token = Some(encrypt_token(encryption_key, token));
         }
+        if let Some(ref key) = self.route_auth_key {
+            self.route_auth_key = Some(encrypt_token(encryption_key, key));
+        }
         AccountWithEncryptedTokens { account: self }
     }
 }
@@ -166,15 +190,33 @@ pub struct AccountWithEncryptedTokens {
 }
 
 impl AccountWithEncryptedTokens {
-    pub fn decrypt_tokens(mut self, decryption_key: &aead::OpeningKey) -> Account {
+    pub fn decrypt_tokens(
+        mut self,
+        decryption_key: &aead::OpeningKey,
+    ) -> Result<Account, StoreError> {
+        let id = self.account.id;
+        let undecryptable =
+            |token| StoreError::Corrupt(format!("Unable to decrypt {} for account {}", token, id));
         if let Some(ref encrypted) = self.account.btp_outgoing_token {
-            self.account.btp_outgoing_token = decrypt_token(decryption_key, encrypted);
+            self.account.btp_outgoing_token = Some(
+                decrypt_token(decryption_key, encrypted)
+                    .ok_or_else(|| undecryptable("BTP outgoing token"))?,
+            );
         }
         if let Some(ref encrypted) = self.account.http_outgoing_token {
-            self.account.http_outgoing_token = decrypt_token(decryption_key, encrypted);
+            self.account.http_outgoing_token = Some(
+                decrypt_token(decryption_key, encrypted)
+                    .ok_or_else(|| undecryptable("HTTP outgoing token"))?,
+            );
+        }
+        if let Some(ref encrypted) = self.account.route_auth_key {
+            self.account.route_auth_key = Some(
+                decrypt_token(decryption_key, encrypted)
+                    .ok_or_else(|| undecryptable("route auth key"))?,
+            );
         }
 
-        self.account
+        Ok(self.account)
     }
 }
 
@@ -312,6 +354,26 @@ impl ToRedisArgs for AccountWithEncryptedTokens {
             "settlement_engine_url".write_redis_args(&mut rv);
             settlement_engine_url.as_str().write_redis_args(&mut rv);
         }
+        if let Some(route_weight) = account.route_weight {
+            "route_weight".write_redis_args(&mut rv);
+            route_weight.write_redis_args(&mut rv);
+        }
+        if let Some(route_auth_key) = account.route_auth_key.as_ref() {
+            "route_auth_key".write_redis_args(&mut rv);
+            route_auth_key.as_ref().write_redis_args(&mut rv);
+        }
+        if let Some(policy) = account.route_import_policy.as_ref() {
+            "route_import_policy".write_redis_args(&mut rv);
+            policy_to_json(policy).write_redis_args(&mut rv);
+        }
+        if let Some(policy) = account.route_export_policy.as_ref() {
+            "route_export_policy".write_redis_args(&mut rv);
+            policy_to_json(policy).write_redis_args(&mut rv);
+        }
+        if let Some(route_cost) = account.route_cost {
+            "route_cost".write_redis_args(&mut rv);
+            route_cost.write_redis_args(&mut rv);
+        }
 
         debug_assert!(rv.len() <= ACCOUNT_DETAILS_FIELDS * 2);
         debug_assert!((rv.len() % 2) == 0);
@@ -363,6 +425,11 @@ impl FromRedisValue for AccountWithEncryptedTokens {
                 packets_per_minute_limit: get_value_option("packets_per_minute_limit", &hash)?,
                 amount_per_minute_limit: get_value_option("amount_per_minute_limit", &hash)?,
                 settlement_engine_url: get_url_option("settlement_engine_url", &hash)?,
+                route_weight: get_value_option("route_weight", &hash)?,
+                route_auth_key: get_bytes_option("route_auth_key", &hash)?,
+                route_import_policy: get_policy_option("route_import_policy", &hash)?,
+                route_export_policy: get_policy_option("route_export_policy", &hash)?,
+                route_cost: get_value_option("route_cost", &hash)?,
             },
         })
     }
@@ -416,6 +483,24 @@ fn get_url_option(key: &str, map: &HashMap<String, Value>) -> Result<Option<Url>
     }
 }
 
+fn get_policy_option(
+    key: &str,
+    map: &HashMap<String, Value>,
+) -> Result<Option<RoutePolicy>, RedisError> {
+    if let Some(value) = map.get(key) {
+        let value: String = from_redis_value(value)?;
+        serde_json::from_str(&value)
+            .map(Some)
+            .map_err(|_| RedisError::from((ErrorKind::TypeError, "Invalid route policy")))
+    } else {
+        Ok(None)
+    }
+}
+
+fn policy_to_json(policy: &RoutePolicy) -> String {
+    serde_json::to_string(policy).expect("Route policies can always be serialized")
+}
+
 fn get_bool(key: &str, map: &HashMap<String, Value>) -> bool {
     if let Some(ref value) = map.get(key) {
         if let Ok(value) = from_redis_value(value) as Result<String, RedisError> {
@@ -497,6 +582,22 @@ impl CcpRoutingAccount for Account {
     fn should_receive_routes(&self) -> bool {
         self.receive_routes
     }
+
+    fn route_auth_key(&self) -> Option<&[u8]> {
+        self.route_auth_key.as_ref().map(|key| key.as_ref())
+    }
+
+    fn route_import_policy(&self) -> Option<&RoutePolicy> {
+        self.route_import_policy.as_ref()
+    }
+
+    fn route_export_policy(&self) -> Option<&RoutePolicy> {
+        self.route_export_policy.as_ref()
+    }
+
+    fn route_cost(&self) -> u32 {
+        self.route_cost.unwrap_or(0)
+    }
 }
 
 impl RoundTripTimeAccount for Account {
@@ -531,7 +632,7 @@ mod redis_account {
 
     lazy_static! {
         static ref ACCOUNT_DETAILS: AccountDetails = AccountDetails {
-            ilp_address: Address::from_str("example.alice").unwrap(),
+            ilp_address: Some(Address::from_str("example.alice").unwrap()),
             username: Username::from_str("alice").unwrap(),
             asset_scale: 6,
             asset_code: "XYZ".to_string(),
@@ -552,6 +653,17 @@ mod redis_account {
             amount_per_minute_limit: None,
             packets_per_minute_limit: None,
             settlement_engine_url: None,
+            route_cost: Some(5),
+            route_export_policy: Some(RoutePolicy {
+                non_transitive: true,
+                ..RoutePolicy::default()
+            }),
+            route_import_policy: Some(RoutePolicy {
+                deny_prefixes: vec!["example.private".to_string()],
+                ..RoutePolicy::default()
+            }),
+            route_auth_key: Some("route_auth_key".to_string()),
+            route_weight: None,
         };
     }
 
@@ -573,5 +685,12 @@ mod redis_account {
             "btp+ws://example.com/btp",
         );
         assert_eq!(account.routing_relation(), RoutingRelation::Peer);
+        assert_eq!(account.route_auth_key().unwrap(), b"route_auth_key");
+        assert!(account.route_export_policy().unwrap().non_transitive);
+        assert_eq!(account.route_cost(), 5);
+        assert!(!account
+            .route_import_policy()
+            .unwrap()
+            .allows_prefix(b"example.private.a"));
     }
 }
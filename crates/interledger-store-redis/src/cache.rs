@@ -0,0 +1,239 @@
+//! An in-process cache of account details, so that the store does not need to
+//! make a round trip to Redis to look up the accounts for every packet.
+//!
+//! Entries are removed whenever an account is updated or deleted. The store
+//! publishes the ID of every changed account over Redis pub/sub so that
+//! other connector instances sharing the same Redis database drop their
+//! copies too. Entries also expire after a fixed TTL and the number of cached
+//! accounts is bounded, so a missed notification cannot keep a stale account
+//! around forever.
+
+use super::account::{Account, AccountId};
+use interledger_service::{Account as AccountTrait, Username};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+pub(crate) struct AccountCache {
+    inner: RwLock<CacheEntries>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl Default for AccountCache {
+    fn default() -> Self {
+        AccountCache::new(DEFAULT_TTL, DEFAULT_MAX_ENTRIES)
+    }
+}
+
+// Usernames are case folded when they are parsed, so they can be used as keys directly.
+// They are only ever cached alongside the account they point to,
+// so removing an account also removes the username that resolves to it.
+// The generation is bumped on every invalidation so that a read from Redis
+// that started before the invalidation does not re-insert what it loaded.
+#[derive(Default)]
+struct CacheEntries {
+    accounts: HashMap<AccountId, (Account, Instant)>,
+    usernames: HashMap<String, AccountId>,
+    generation: u64,
+}
+
+impl CacheEntries {
+    fn get(&self, account_id: &AccountId, ttl: Duration) -> Option<&Account> {
+        self.accounts
+            .get(account_id)
+            .filter(|(_, inserted_at)| inserted_at.elapsed() < ttl)
+            .map(|(account, _)| account)
+    }
+
+    fn remove(&mut self, account_id: &AccountId) {
+        if let Some((account, _)) = self.accounts.remove(account_id) {
+            self.usernames.remove(account.username().as_ref() as &str);
+        }
+    }
+}
+
+impl AccountCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        AccountCache {
+            inner: RwLock::new(CacheEntries::default()),
+            ttl,
+            max_entries,
+        }
+    }
+
+    /// Returns the cached accounts, or `None` if any of them are missing or expired.
+    pub fn get_many(&self, account_ids: &[AccountId]) -> Option<Vec<Account>> {
+        let entries = self.inner.read();
+        account_ids
+            .iter()
+            .map(|id| entries.get(id, self.ttl).cloned())
+            .collect()
+    }
+
+    pub fn get_by_username(&self, username: &Username) -> Option<Account> {
+        let entries = self.inner.read();
+        entries
+            .usernames
+            .get(username.as_ref() as &str)
+            .and_then(|id| entries.get(id, self.ttl))
+            .cloned()
+    }
+
+    /// Returns the current generation. It must be read before loading the
+    /// accounts from Redis and passed to `insert_many` afterwards.
+    pub fn generation(&self) -> u64 {
+        self.inner.read().generation
+    }
+
+    /// Caches the given accounts unless an entry was invalidated since `generation`
+    /// was read, in which case the accounts may already be out of date.
+    pub fn insert_many<'a>(
+        &self,
+        generation: u64,
+        accounts: impl IntoIterator<Item = &'a Account>,
+    ) {
+        let mut entries = self.inner.write();
+        if entries.generation != generation {
+            return;
+        }
+        let now = Instant::now();
+        for account in accounts {
+            if entries.accounts.len() >= self.max_entries
+                && !entries.accounts.contains_key(&account.id)
+            {
+                let ttl = self.ttl;
+                let expired: Vec<AccountId> = entries
+                    .accounts
+                    .iter()
+                    .filter(|(_, (_, inserted_at))| inserted_at.elapsed() >= ttl)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in expired.iter() {
+                    entries.remove(id);
+                }
+                if entries.accounts.len() >= self.max_entries {
+                    continue;
+                }
+            }
+            entries
+                .usernames
+                .insert(account.username().to_string(), account.id);
+            entries.accounts.insert(account.id, (account.clone(), now));
+        }
+    }
+
+    pub fn remove(&self, account_id: AccountId) {
+        let mut entries = self.inner.write();
+        entries.generation += 1;
+        entries.remove(&account_id);
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.inner.write();
+        entries.generation += 1;
+        entries.accounts.clear();
+        entries.usernames.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_api::AccountDetails;
+    use interledger_packet::Address;
+    use std::str::FromStr;
+
+    fn account(username: &str) -> Account {
+        Account::try_from(
+            AccountId::new(),
+            AccountDetails {
+                ilp_address: Some(Address::from_str("example.alice").unwrap()),
+                username: Username::from_str(username).unwrap(),
+                asset_scale: 6,
+                asset_code: "XYZ".to_string(),
+                max_packet_amount: 1000,
+                min_balance: None,
+                http_endpoint: None,
+                http_incoming_token: None,
+                http_outgoing_token: None,
+                btp_uri: None,
+                btp_incoming_token: None,
+                settle_threshold: None,
+                settle_to: None,
+                send_routes: false,
+                receive_routes: false,
+                routing_relation: None,
+                round_trip_time: None,
+                amount_per_minute_limit: None,
+                packets_per_minute_limit: None,
+                settlement_engine_url: None,
+                route_cost: None,
+                route_export_policy: None,
+                route_import_policy: None,
+                route_auth_key: None,
+                route_weight: None,
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn returns_none_unless_all_accounts_are_cached() {
+        let cache = AccountCache::default();
+        let alice = account("alice");
+        let bob = account("bob");
+        cache.insert_many(cache.generation(), Some(&alice));
+        assert_eq!(cache.get_many(&[alice.id]).unwrap()[0].id, alice.id);
+        assert!(cache.get_many(&[alice.id, bob.id]).is_none());
+    }
+
+    #[test]
+    fn removing_an_account_removes_its_username() {
+        let cache = AccountCache::default();
+        let alice = account("alice");
+        cache.insert_many(cache.generation(), Some(&alice));
+        assert_eq!(
+            cache.get_by_username(alice.username()).unwrap().id,
+            alice.id
+        );
+        cache.remove(alice.id);
+        assert!(cache.get_by_username(alice.username()).is_none());
+        assert!(cache.get_many(&[alice.id]).is_none());
+    }
+
+    #[test]
+    fn does_not_insert_accounts_read_before_an_invalidation() {
+        let cache = AccountCache::default();
+        let alice = account("alice");
+        let generation = cache.generation();
+        cache.remove(alice.id);
+        cache.insert_many(generation, Some(&alice));
+        assert!(cache.get_many(&[alice.id]).is_none());
+
+        cache.insert_many(cache.generation(), Some(&alice));
+        assert!(cache.get_many(&[alice.id]).is_some());
+    }
+
+    #[test]
+    fn expires_entries_after_the_ttl() {
+        let cache = AccountCache::new(Duration::from_millis(0), 10);
+        let alice = account("alice");
+        cache.insert_many(cache.generation(), Some(&alice));
+        assert!(cache.get_many(&[alice.id]).is_none());
+        assert!(cache.get_by_username(alice.username()).is_none());
+    }
+
+    #[test]
+    fn does_not_grow_past_the_max_entries() {
+        let cache = AccountCache::new(Duration::from_secs(60), 1);
+        let alice = account("alice");
+        let bob = account("bob");
+        cache.insert_many(cache.generation(), vec![&alice, &bob]);
+        assert!(cache.get_many(&[alice.id]).is_some());
+        assert!(cache.get_many(&[bob.id]).is_none());
+    }
+}
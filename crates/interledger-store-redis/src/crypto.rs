@@ -1,3 +1,6 @@
+//! Helpers for deriving the store's keys from the node secret, and for encrypting
+//! the outgoing auth tokens that are saved in the database.
+
 use bytes::Bytes;
 use log::error;
 use ring::{
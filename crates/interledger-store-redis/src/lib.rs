@@ -3,7 +3,8 @@
 //! A Store that uses [Redis](https://redis.io/) as the database for storing account details, balances, the routing table, etc.
 
 mod account;
-mod crypto;
+mod cache;
+pub mod crypto;
 mod store;
 
 pub use account::{Account, AccountId};
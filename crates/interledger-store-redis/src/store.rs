@@ -2,6 +2,7 @@
 //   send_routes_to         set         used for CCP routing
 //   receive_routes_from    set         used for CCP routing
 //   next_account_id        string      unique ID for each new account
+//   ilp_address            string      the node's own address, which child addresses are derived from
 //   rates:current          hash        exchange rates
 //   routes:current         hash        dynamic routing table
 //   routes:static          hash        static routing table
@@ -9,6 +10,10 @@
 //   http_auth              hash        maps hmac of cryptographic credentials to an account
 //   btp_auth               hash        maps hmac of cryptographic credentials to an account
 //   btp_outgoing
+// and the pub/sub channels used to notify other instances sharing the database:
+//   accounts:updates      channel     the ID of each account that was updated or deleted
+//   rates:updates         channel     published when the exchange rates change
+//   routes:updates        channel     published when the dynamic or static routing table changes
 // For interactive exploration of the store,
 // use the redis-cli tool included with your redis install.
 // Within redis-cli:
@@ -18,42 +23,50 @@
 //    hgetall <key>         the flattened list of every key/value entry within a hash
 
 use super::account::*;
+use super::cache::AccountCache;
 use super::crypto::generate_keys;
 use bytes::Bytes;
 use futures::{
     future::{err, ok, result, Either},
+    sync::mpsc::{unbounded, UnboundedSender},
     Future, Stream,
 };
+use futures03::{compat::Future01CompatExt, future::ready, TryFutureExt};
 use log::{debug, error, trace, warn};
 use std::collections::{HashMap, HashSet};
 
 use super::account::AccountId;
 use http::StatusCode;
-use interledger_api::{AccountDetails, NodeStore};
+use interledger_api::{child_address, AccountDetails, NodeStore, StaticNextHop};
 use interledger_btp::BtpStore;
-use interledger_ccp::RouteManagerStore;
+use interledger_ccp::{RouteManagerStore, RoutingRelation};
 use interledger_http::HttpStore;
-use interledger_router::RouterStore;
-use interledger_service::{Account as AccountTrait, AccountStore, Username};
+use interledger_packet::Address;
+use interledger_router::{NextHop, RouterStore, RoutingTable as PrefixTable};
+use interledger_service::{Account as AccountTrait, AccountStore, StoreError, Username};
 use interledger_service_util::{BalanceStore, ExchangeRateStore, RateLimitError, RateLimitStore};
 use interledger_settlement::{IdempotentData, IdempotentStore, SettlementStore};
 use lazy_static::lazy_static;
 use parking_lot::RwLock;
 use redis::{
-    self, aio::SharedConnection, cmd, Client, ConnectionInfo, PipelineCommands, Script, Value,
+    self, aio::SharedConnection, cmd, Client, ConnectionInfo, ErrorKind, FromRedisValue,
+    PipelineCommands, RedisError, Script, Value,
 };
-use ring::{aead, hmac};
+use ring::{aead, constant_time::verify_slices_are_equal, hmac};
 use std::{
     iter::FromIterator,
+    pin::Pin,
     str,
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, Weak},
+    thread,
     time::{Duration, Instant},
 };
 use tokio_executor::spawn;
 use tokio_timer::Interval;
 
 const DEFAULT_POLL_INTERVAL: u64 = 30000; // 30 seconds
+const SUBSCRIBER_READ_TIMEOUT: u64 = 1000; // 1 second
 
 // The following are Lua scripts that are used to atomically execute the given logic
 // inside Redis. This allows for more complex logic without needing multiple round
@@ -70,6 +83,9 @@ lazy_static! {
     local token_type = ARGV[1]
     local acc_id = redis.call('HGET', 'usernames', ARGV[2])
     local provided_token = ARGV[3]
+    if not acc_id then
+        return nil
+    end
 
     local id_key = 'accounts:' .. acc_id
     local token = redis.call('HGET', id_key, token_type)
@@ -90,7 +106,7 @@ lazy_static! {
     if min_balance then
         min_balance = tonumber(min_balance)
         if balance + prepaid_amount - from_amount < min_balance then
-            error('Incoming prepare of ' .. from_amount .. ' would bring account ' .. from_id .. ' under its minimum balance. Current balance: ' .. balance .. ', min balance: ' .. min_balance)
+            return redis.error_reply('MIN_BALANCE Incoming prepare of ' .. from_amount .. ' would bring account ' .. from_id .. ' under its minimum balance. Current balance: ' .. balance .. ', min balance: ' .. min_balance)
         end
     end
 
@@ -180,6 +196,20 @@ lazy_static! {
 static ROUTES_KEY: &str = "routes:current";
 static RATES_KEY: &str = "rates:current";
 static STATIC_ROUTES_KEY: &str = "routes:static";
+// The ranked next hops of each static route. The primary is also saved under the
+// STATIC_ROUTES_KEY, which is what the route manager and older nodes read.
+static STATIC_NEXT_HOPS_KEY: &str = "routes:static:next_hops";
+static ILP_ADDRESS_KEY: &str = "ilp_address";
+static ACCOUNTS_CHANNEL: &str = "accounts:updates";
+static RATES_CHANNEL: &str = "rates:updates";
+static ROUTES_CHANNEL: &str = "routes:updates";
+
+/// Tells the store which in-memory copy to reload after a change was published.
+#[derive(Debug, Clone, Copy)]
+enum Notification {
+    Rates,
+    Routes,
+}
 
 fn prefixed_idempotency_key(idempotency_key: String) -> String {
     format!("idempotency-key:{}", idempotency_key)
@@ -189,6 +219,24 @@ fn accounts_key(account_id: AccountId) -> String {
     format!("accounts:{}", account_id)
 }
 
+// Each node sharing the database saves its own forwarding table
+fn forwarding_table_key(node_id: &str) -> String {
+    format!("routes:forwarding:{}", node_id)
+}
+
+/// Log an error returned by Redis and convert it into a `StoreError`.
+///
+/// Replies that could not be parsed mean the stored data is malformed, while any
+/// other error means Redis could not be reached or failed to run the command.
+fn store_error(err: &RedisError, message: String) -> StoreError {
+    error!("{}", message);
+    if err.kind() == ErrorKind::TypeError {
+        StoreError::Corrupt(message)
+    } else {
+        StoreError::Unavailable(message)
+    }
+}
+
 pub struct RedisStoreBuilder {
     redis_uri: ConnectionInfo,
     secret: [u8; 32],
@@ -220,12 +268,14 @@ impl RedisStoreBuilder {
                 client
                     .get_shared_async_connection()
                     .map_err(|err| error!("Error connecting to Redis: {:?}", err))
+                    .map(move |connection| (client, connection))
             })
-            .and_then(move |connection| {
+            .and_then(move |(client, connection)| {
                 let store = RedisStore {
                     connection: Arc::new(connection),
                     exchange_rates: Arc::new(RwLock::new(HashMap::new())),
-                    routes: Arc::new(RwLock::new(HashMap::new())),
+                    routes: Arc::new(RwLock::new(Arc::new(PrefixTable::new()))),
+                    account_cache: Arc::new(AccountCache::default()),
                     hmac_key: Arc::new(hmac_key),
                     encryption_key: Arc::new(encryption_key),
                     decryption_key: Arc::new(decryption_key),
@@ -240,10 +290,13 @@ impl RedisStoreBuilder {
                         .map_err(|err| error!("Interval error: {:?}", err))
                         .for_each(move |_| {
                             if let Some(connection) = connection_clone.upgrade() {
-                                Either::A(update_rates(
-                                    connection.as_ref().clone(),
-                                    exchange_rates.clone(),
-                                ))
+                                Either::A(
+                                    update_rates(
+                                        connection.as_ref().clone(),
+                                        exchange_rates.clone(),
+                                    )
+                                    .map_err(|_| ()),
+                                )
                             } else {
                                 debug!("Not polling rates anymore because connection was closed");
                                 // TODO make sure the interval stops
@@ -261,10 +314,13 @@ impl RedisStoreBuilder {
                         .map_err(|err| error!("Interval error: {:?}", err))
                         .for_each(move |_| {
                             if let Some(connection) = connection_clone.upgrade() {
-                                Either::A(update_routes(
-                                    connection.as_ref().clone(),
-                                    routing_table.clone(),
-                                ))
+                                Either::A(
+                                    update_routes(
+                                        connection.as_ref().clone(),
+                                        routing_table.clone(),
+                                    )
+                                    .map_err(|_| ()),
+                                )
                             } else {
                                 debug!("Not polling routes anymore because connection was closed");
                                 // TODO make sure the interval stops
@@ -273,6 +329,39 @@ impl RedisStoreBuilder {
                         });
                 spawn(poll_routes);
 
+                // Reload the rates or routes whenever the subscriber is notified of a change
+                let (notification_sender, notification_receiver) = unbounded();
+                let connection_clone = Arc::downgrade(&store.connection);
+                let exchange_rates = store.exchange_rates.clone();
+                let routing_table = store.routes.clone();
+                let handle_notifications = notification_receiver.for_each(move |notification| {
+                    if let Some(connection) = connection_clone.upgrade() {
+                        let connection = connection.as_ref().clone();
+                        let update = match notification {
+                            Notification::Rates => {
+                                Either::A(update_rates(connection, exchange_rates.clone()))
+                            }
+                            Notification::Routes => {
+                                Either::B(update_routes(connection, routing_table.clone()))
+                            }
+                        };
+                        // Errors are already logged and polling will catch up eventually,
+                        // so keep listening for the next notification
+                        Either::A(update.then(|_| Ok(())))
+                    } else {
+                        debug!("Not handling notifications anymore because connection was closed");
+                        Either::B(err(()))
+                    }
+                });
+                spawn(handle_notifications);
+
+                // Listen for changes made by other instances sharing this database.
+                // redis-rs only supports pub/sub on synchronous connections, so this gets its own thread
+                let account_cache = Arc::downgrade(&store.account_cache);
+                thread::spawn(move || {
+                    subscribe_to_updates(client, account_cache, notification_sender)
+                });
+
                 Ok(store)
             })
     }
@@ -282,31 +371,73 @@ impl RedisStoreBuilder {
 ///
 /// This store leverages atomic Redis transactions to do operations such as balance updates.
 ///
-/// Currently the RedisStore polls the database for the routing table and rate updates, but
-/// future versions of it will use PubSub to subscribe to updates.
+/// Account details, the routing table and exchange rates are kept in memory. Changes are
+/// published over Redis pub/sub so that every RedisStore connected to the same database
+/// updates its copy right away. The routing table and rates are also polled every
+/// `poll_interval` in case a notification is missed.
 #[derive(Clone)]
 pub struct RedisStore {
     connection: Arc<SharedConnection>,
     exchange_rates: Arc<RwLock<HashMap<String, f64>>>,
-    routes: Arc<RwLock<HashMap<Bytes, AccountId>>>,
+    routes: Arc<RwLock<Arc<PrefixTable<AccountId>>>>,
+    account_cache: Arc<AccountCache>,
     hmac_key: Arc<hmac::SigningKey>, // redisstore stores a key, this must be protected
     encryption_key: Arc<aead::SealingKey>,
     decryption_key: Arc<aead::OpeningKey>,
 }
 
 impl RedisStore {
-    pub fn get_all_accounts_ids(&self) -> impl Future<Item = Vec<AccountId>, Error = ()> {
+    pub fn get_all_accounts_ids(&self) -> impl Future<Item = Vec<AccountId>, Error = StoreError> {
         let mut pipe = redis::pipe();
         pipe.smembers("accounts");
         pipe.query_async(self.connection.as_ref().clone())
-            .map_err(|err| error!("Error getting account IDs: {:?}", err))
+            .map_err(|err| store_error(&err, format!("Error getting account IDs: {:?}", err)))
             .and_then(|(_conn, account_ids): (_, Vec<Vec<AccountId>>)| Ok(account_ids[0].clone()))
     }
 
+    /// The node's own ILP address, if it has been saved
+    fn get_ilp_address(&self) -> impl Future<Item = Option<Address>, Error = StoreError> {
+        cmd("GET")
+            .arg(ILP_ADDRESS_KEY)
+            .query_async(self.connection.as_ref().clone())
+            .map_err(|err| {
+                store_error(
+                    &err,
+                    format!("Error getting the node's ILP address: {:?}", err),
+                )
+            })
+            .and_then(
+                |(_connection, address): (_, Option<String>)| match address {
+                    Some(address) => Address::from_str(&address).map(Some).map_err(|err| {
+                        StoreError::Corrupt(format!(
+                            "Invalid node ILP address {}: {:?}",
+                            address, err
+                        ))
+                    }),
+                    None => Ok(None),
+                },
+            )
+    }
+
+    /// Fill in the address of a child account that was submitted without one,
+    /// using the node's saved address
+    fn derive_ilp_address(
+        &self,
+        mut details: AccountDetails,
+    ) -> impl Future<Item = AccountDetails, Error = StoreError> {
+        if details.ilp_address.is_some() {
+            return Either::A(ok(details));
+        }
+        Either::B(self.get_ilp_address().and_then(move |node_address| {
+            details.derive_ilp_address(node_address.as_ref())?;
+            Ok(details)
+        }))
+    }
+
     fn redis_insert_account(
         &self,
         account: AccountDetails,
-    ) -> Box<dyn Future<Item = Account, Error = ()> + Send> {
+    ) -> Box<dyn Future<Item = Account, Error = StoreError> + Send> {
         let connection = self.connection.clone();
         let routing_table = self.routes.clone();
         let encryption_key = self.encryption_key.clone();
@@ -314,7 +445,8 @@ impl RedisStore {
         let id = AccountId::new();
         debug!("Generated account: {}", id);
         Box::new(
-            result(Account::try_from(id, account))
+            self.derive_ilp_address(account)
+                .and_then(move |account| Account::try_from(id, account))
                 .and_then(move |account| {
                     // Check that there isn't already an account with values that MUST be unique
                     let mut pipe = redis::pipe();
@@ -323,13 +455,13 @@ impl RedisStore {
 
                     pipe.query_async(connection.as_ref().clone())
                         .map_err(|err| {
-                            error!("Error checking whether account details already exist: {:?}", err)
+                            store_error(&err, format!("Error checking whether account details already exist: {:?}", err))
                         })
                         .and_then(
                             move |(connection, results): (SharedConnection, Vec<bool>)| {
                                 if results.iter().any(|val| *val) {
                                     warn!("An account already exists with the same {}. Cannot insert account: {:?}", id, account);
-                                    Err(())
+                                    Err(StoreError::Conflict(format!("An account already exists with the same ID or username as {}", id)))
                                 } else {
                                     Ok((connection, account))
                                 }
@@ -368,9 +500,10 @@ impl RedisStore {
                     // Add route to routing table
                     pipe.hset(ROUTES_KEY, account.ilp_address.to_bytes().to_vec(), account.id)
                         .ignore();
+                    pipe.publish(ROUTES_CHANNEL, "").ignore();
 
                     pipe.query_async(connection)
-                        .map_err(|err| error!("Error inserting account into DB: {:?}", err))
+                        .map_err(|err| store_error(&err, format!("Error inserting account into DB: {:?}", err)))
                         .and_then(move |(connection, _ret): (SharedConnection, Value)| {
                             update_routes(connection, routing_table)
                         })
@@ -386,10 +519,12 @@ impl RedisStore {
         &self,
         id: AccountId,
         account: AccountDetails,
-    ) -> Box<dyn Future<Item = Account, Error = ()> + Send> {
+    ) -> Box<dyn Future<Item = Account, Error = StoreError> + Send> {
         let connection = self.connection.clone();
         let routing_table = self.routes.clone();
         let encryption_key = self.encryption_key.clone();
+        let account_cache = self.account_cache.clone();
+        let store = self.clone();
 
         // Instead of storing the incoming secrets, we store the HMAC digest of them
         // (This is better than encrypting because the output is deterministic so we can look
@@ -411,17 +546,26 @@ impl RedisStore {
                 .arg(accounts_key(id))
                 // TODO this needs to be atomic with the insertions later, waiting on #186
                 .query_async(connection.as_ref().clone())
-                .map_err(|err| error!("Error checking whether ID exists: {:?}", err))
+                .map_err(|err| {
+                    store_error(&err, format!("Error checking whether ID exists: {:?}", err))
+                })
                 .and_then(move |(connection, result): (SharedConnection, bool)| {
                     if result {
-                        Account::try_from(id, account)
-                            .and_then(move |account| Ok((connection, account)))
+                        Either::A(
+                            store
+                                .derive_ilp_address(account)
+                                .and_then(move |account| Account::try_from(id, account))
+                                .and_then(move |account| Ok((connection, account))),
+                        )
                     } else {
                         warn!(
                             "No account exists with ID {}, cannot update account {:?}",
                             id, account
                         );
-                        Err(())
+                        Either::B(err(StoreError::NotFound(format!(
+                            "No account exists with ID {}",
+                            id
+                        ))))
                     }
                 })
                 .and_then(move |(connection, account)| {
@@ -466,9 +610,17 @@ impl RedisStore {
                     )
                     .ignore();
 
+                    // Tell other instances to drop their cached copy of the account
+                    // and reload their routing tables
+                    pipe.publish(ACCOUNTS_CHANNEL, account.id).ignore();
+                    pipe.publish(ROUTES_CHANNEL, "").ignore();
+
                     pipe.query_async(connection)
-                        .map_err(|err| error!("Error inserting account into DB: {:?}", err))
+                        .map_err(|err| {
+                            store_error(&err, format!("Error inserting account into DB: {:?}", err))
+                        })
                         .and_then(move |(connection, _ret): (SharedConnection, Value)| {
+                            account_cache.remove(id);
                             update_routes(connection, routing_table)
                         })
                         .and_then(move |_| {
@@ -487,15 +639,20 @@ impl RedisStore {
     fn redis_delete_account(
         &self,
         id: AccountId,
-    ) -> Box<dyn Future<Item = Account, Error = ()> + Send> {
+    ) -> Box<dyn Future<Item = Account, Error = StoreError> + Send> {
         let connection = self.connection.as_ref().clone();
         let routing_table = self.routes.clone();
+        let account_cache = self.account_cache.clone();
 
         Box::new(
             // TODO: a get_account API to avoid making Vecs which we only need one element of
             self.redis_get_accounts(vec![id])
-                .and_then(|accounts| accounts.get(0).cloned().ok_or(()))
-                .and_then(|account| {
+                .and_then(move |accounts| {
+                    accounts.get(0).cloned().ok_or_else(|| {
+                        StoreError::NotFound(format!("No account exists with ID {}", id))
+                    })
+                })
+                .and_then(move |account| {
                     let mut pipe = redis::pipe();
                     pipe.atomic();
 
@@ -519,9 +676,15 @@ impl RedisStore {
                     pipe.hdel(ROUTES_KEY, account.ilp_address.to_bytes().to_vec())
                         .ignore();
 
+                    pipe.publish(ACCOUNTS_CHANNEL, account.id).ignore();
+                    pipe.publish(ROUTES_CHANNEL, "").ignore();
+
                     pipe.query_async(connection)
-                        .map_err(|err| error!("Error deleting account from DB: {:?}", err))
+                        .map_err(|err| {
+                            store_error(&err, format!("Error deleting account from DB: {:?}", err))
+                        })
                         .and_then(move |(connection, _ret): (SharedConnection, Value)| {
+                            account_cache.remove(id);
                             update_routes(connection, routing_table)
                         })
                         .and_then(move |_| {
@@ -535,8 +698,15 @@ impl RedisStore {
     fn redis_get_accounts(
         &self,
         account_ids: Vec<AccountId>,
-    ) -> Box<dyn Future<Item = Vec<Account>, Error = ()> + Send> {
+    ) -> Box<dyn Future<Item = Vec<Account>, Error = StoreError> + Send> {
+        if let Some(accounts) = self.account_cache.get_many(&account_ids) {
+            trace!("Loaded accounts from cache: {:?}", account_ids);
+            return Box::new(ok(accounts));
+        }
+
         let decryption_key = self.decryption_key.clone();
+        let account_cache = self.account_cache.clone();
+        let cache_generation = account_cache.generation();
         let num_accounts = account_ids.len();
         let mut pipe = redis::pipe();
         for account_id in account_ids.iter() {
@@ -545,50 +715,98 @@ impl RedisStore {
         Box::new(
             pipe.query_async(self.connection.as_ref().clone())
                 .map_err(move |err| {
-                    error!(
-                        "Error querying details for accounts: {:?} {:?}",
-                        account_ids, err
+                    store_error(
+                        &err,
+                        format!("Error querying details for accounts: {:?}", err),
                     )
                 })
-                .and_then(
-                    move |(_conn, accounts): (_, Vec<AccountWithEncryptedTokens>)| {
-                        if accounts.len() == num_accounts {
-                            let accounts = accounts
-                                .into_iter()
-                                .map(|account| account.decrypt_tokens(&decryption_key))
-                                .collect();
-                            Ok(accounts)
-                        } else {
-                            Err(())
-                        }
-                    },
-                ),
+                .and_then(move |(_conn, accounts): (_, Vec<Value>)| {
+                    if accounts.len() != num_accounts {
+                        return Err(StoreError::Corrupt(format!(
+                            "Expected {} accounts but Redis returned {}",
+                            num_accounts,
+                            accounts.len()
+                        )));
+                    }
+                    let accounts = account_ids
+                        .iter()
+                        .zip(accounts.iter())
+                        .map(|(id, account)| {
+                            // HGETALL returns an empty list for keys that do not exist
+                            if let Value::Bulk(ref fields) = account {
+                                if fields.is_empty() {
+                                    return Err(StoreError::NotFound(format!(
+                                        "No account exists with ID {}",
+                                        id
+                                    )));
+                                }
+                            }
+                            AccountWithEncryptedTokens::from_redis_value(account)
+                                .map_err(|err| {
+                                    store_error(
+                                        &err,
+                                        format!("Error parsing account {}: {:?}", id, err),
+                                    )
+                                })
+                                .and_then(|account| account.decrypt_tokens(&decryption_key))
+                        })
+                        .collect::<Result<Vec<Account>, StoreError>>()?;
+                    account_cache.insert_many(cache_generation, &accounts);
+                    Ok(accounts)
+                }),
         )
     }
+
+    /// Returns the cached account with the given username if its incoming token matches.
+    /// A mismatch is not treated as an auth failure because the token may have been
+    /// changed since the account was cached, so the caller should fall back to Redis.
+    fn cached_account_from_token(
+        &self,
+        username: &Username,
+        token: &str,
+        incoming_token: fn(&Account) -> Option<&Bytes>,
+    ) -> Option<Account> {
+        self.account_cache
+            .get_by_username(username)
+            .filter(|account| match incoming_token(account) {
+                Some(expected) => verify_slices_are_equal(expected, token.as_bytes()).is_ok(),
+                None => false,
+            })
+    }
 }
 
 impl AccountStore for RedisStore {
     type Account = Account;
 
-    // TODO cache results to avoid hitting Redis for each packet
     fn get_accounts(
         &self,
         account_ids: Vec<<Self::Account as AccountTrait>::AccountId>,
-    ) -> Box<dyn Future<Item = Vec<Account>, Error = ()> + Send> {
-        self.redis_get_accounts(account_ids)
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Vec<Account>, StoreError>> + Send>> {
+        Box::pin(self.redis_get_accounts(account_ids).compat())
     }
 
     fn get_account_id_from_username(
         &self,
         username: &Username,
-    ) -> Box<dyn Future<Item = AccountId, Error = ()> + Send> {
-        Box::new(
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<AccountId, StoreError>> + Send>> {
+        let username = username.clone();
+        Box::pin(
             cmd("HGET")
                 .arg("usernames")
                 .arg(username.as_ref())
                 .query_async(self.connection.as_ref().clone())
-                .map_err(move |err| error!("Error getting account id: {:?}", err))
-                .and_then(|(_connection, id): (_, AccountId)| Ok(id)),
+                .map_err(move |err| {
+                    store_error(&err, format!("Error getting account id: {:?}", err))
+                })
+                .and_then(move |(_connection, id): (_, Option<AccountId>)| {
+                    id.ok_or_else(|| {
+                        StoreError::NotFound(format!(
+                            "No account exists with username {}",
+                            username
+                        ))
+                    })
+                })
+                .compat(),
         )
     }
 }
@@ -596,23 +814,30 @@ impl AccountStore for RedisStore {
 impl BalanceStore for RedisStore {
     /// Returns the balance **from the account holder's perspective**, meaning the sum of
     /// the Payable Balance and Pending Outgoing minus the Receivable Balance and the Pending Incoming.
-    fn get_balance(&self, account: Account) -> Box<dyn Future<Item = i64, Error = ()> + Send> {
-        Box::new(
+    fn get_balance(
+        &self,
+        account: Account,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<i64, StoreError>> + Send>> {
+        Box::pin(
             cmd("HMGET")
                 .arg(accounts_key(account.id))
                 .arg(&["balance", "prepaid_amount"])
                 .query_async(self.connection.as_ref().clone())
                 .map_err(move |err| {
-                    error!(
-                        "Error getting balance for account: {} {:?}",
-                        account.id, err
+                    store_error(
+                        &err,
+                        format!(
+                            "Error getting balance for account: {} {:?}",
+                            account.id, err
+                        ),
                     )
                 })
                 .and_then(|(_connection, values): (_, Vec<i64>)| {
                     let balance = values[0];
                     let prepaid_amount = values[1];
                     Ok(balance + prepaid_amount)
-                }),
+                })
+                .compat(),
         )
     }
 
@@ -620,19 +845,25 @@ impl BalanceStore for RedisStore {
         &self,
         from_account: Account, // TODO: Make this take only the id
         incoming_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
         if incoming_amount > 0 {
             let from_account_id = from_account.id;
-            Box::new(
+            Box::pin(
                 PROCESS_PREPARE
                     .arg(from_account_id)
                     .arg(incoming_amount)
                     .invoke_async(self.connection.as_ref().clone())
                     .map_err(move |err| {
-                        warn!(
-                            "Error handling prepare from account: {}:  {:?}",
+                        let message = format!(
+                            "Error handling prepare from account: {}: {:?}",
                             from_account_id, err
-                        )
+                        );
+                        if err.extension_error_code() == Some("MIN_BALANCE") {
+                            warn!("{}", message);
+                            StoreError::LimitExceeded(message)
+                        } else {
+                            store_error(&err, message)
+                        }
                     })
                     .and_then(move |(_connection, balance): (_, i64)| {
                         trace!(
@@ -640,10 +871,11 @@ impl BalanceStore for RedisStore {
                             incoming_amount, from_account_id, balance
                         );
                         Ok(())
-                    }),
+                    })
+                    .compat(),
             )
         } else {
-            Box::new(ok(()))
+            Box::pin(ready(Ok(())))
         }
     }
 
@@ -651,23 +883,23 @@ impl BalanceStore for RedisStore {
         &self,
         to_account: Account, // TODO: Make this take only the id
         outgoing_amount: u64,
-    ) -> Box<dyn Future<Item = (i64, u64), Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(i64, u64), StoreError>> + Send>> {
         if outgoing_amount > 0 {
             debug!(
                 "To: {}, Amount paid: {}",
                 to_account.ilp_address, outgoing_amount
             );
             let to_account_id = to_account.id;
-            Box::new(
+            Box::pin(
                 PROCESS_FULFILL
                     .arg(to_account_id)
                     .arg(outgoing_amount)
                     .invoke_async(self.connection.as_ref().clone())
                     .map_err(move |err| {
-                        error!(
+                        store_error(&err, format!(
                             "Error handling Fulfill received from account: {}: {:?}",
                             to_account_id, err
-                        )
+                        ))
                     })
                     .and_then(move |(_connection, (balance, amount_to_settle)): (_, (i64, u64))| {
                         trace!("Processed fulfill for account {} for outgoing amount {}. Fulfill call result: {} {}",
@@ -678,9 +910,9 @@ impl BalanceStore for RedisStore {
                         );
                         Ok((balance, amount_to_settle))
                     })
-            )
+            .compat())
         } else {
-            Box::new(ok((0, 0)))
+            Box::pin(ready(Ok((0, 0))))
         }
     }
 
@@ -688,18 +920,21 @@ impl BalanceStore for RedisStore {
         &self,
         from_account: Account, // TODO: Make this take only the id
         incoming_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
         if incoming_amount > 0 {
             let from_account_id = from_account.id;
-            Box::new(
+            Box::pin(
                 PROCESS_REJECT
                     .arg(from_account_id)
                     .arg(incoming_amount)
                     .invoke_async(self.connection.as_ref().clone())
                     .map_err(move |err| {
-                        warn!(
-                            "Error handling reject for packet from account: {}: {:?}",
-                            from_account_id, err
+                        store_error(
+                            &err,
+                            format!(
+                                "Error handling reject for packet from account: {}: {:?}",
+                                from_account_id, err
+                            ),
                         )
                     })
                     .and_then(move |(_connection, balance): (_, i64)| {
@@ -708,29 +943,26 @@ impl BalanceStore for RedisStore {
                             incoming_amount, from_account_id, balance
                         );
                         Ok(())
-                    }),
+                    })
+                    .compat(),
             )
         } else {
-            Box::new(ok(()))
+            Box::pin(ready(Ok(())))
         }
     }
 }
 
 impl ExchangeRateStore for RedisStore {
-    fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, ()> {
-        let rates: Vec<f64> = asset_codes
+    fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, StoreError> {
+        let exchange_rates = self.exchange_rates.read();
+        asset_codes
             .iter()
-            .filter_map(|code| {
-                (*self.exchange_rates.read())
-                    .get(&code.to_string())
-                    .cloned()
+            .map(|code| {
+                exchange_rates.get(*code).cloned().ok_or_else(|| {
+                    StoreError::NotFound(format!("No exchange rate for asset: {}", code))
+                })
             })
-            .collect();
-        if rates.len() == asset_codes.len() {
-            Ok(rates)
-        } else {
-            Err(())
-        }
+            .collect()
     }
 }
 
@@ -741,40 +973,62 @@ impl BtpStore for RedisStore {
         &self,
         username: &Username,
         token: &str,
-    ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Self::Account, StoreError>> + Send>> {
+        if let Some(account) = self.cached_account_from_token(username, token, |account| {
+            account.btp_incoming_token.as_ref()
+        }) {
+            return Box::pin(ready(Ok(account)));
+        }
+
         // TODO make sure it can't do script injection!
-        // TODO cache the result so we don't hit redis for every packet (is that necessary if redis is often used as a cache?)
         let decryption_key = self.decryption_key.clone();
-        Box::new(
+        let account_cache = self.account_cache.clone();
+        let cache_generation = account_cache.generation();
+        Box::pin(
             ACCOUNT_FROM_TOKEN
                 .arg("btp_incoming_token")
                 .arg(username.as_ref())
                 .arg(token)
                 .invoke_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error getting account from BTP token: {:?}", err))
+                .map_err(|err| {
+                    store_error(
+                        &err,
+                        format!("Error getting account from BTP token: {:?}", err),
+                    )
+                })
                 .and_then(
                     move |(_connection, account): (_, Option<AccountWithEncryptedTokens>)| {
                         if let Some(account) = account {
-                            let account = account.decrypt_tokens(&decryption_key);
+                            let account = account.decrypt_tokens(&decryption_key)?;
+                            account_cache.insert_many(cache_generation, Some(&account));
                             Ok(account)
                         } else {
                             warn!("No account found with BTP token");
-                            Err(())
+                            Err(StoreError::NotFound(
+                                "No account found with BTP token".to_string(),
+                            ))
                         }
                     },
-                ),
+                )
+                .compat(),
         )
     }
 
     fn get_btp_outgoing_accounts(
         &self,
-    ) -> Box<dyn Future<Item = Vec<Self::Account>, Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Vec<Self::Account>, StoreError>> + Send>>
+    {
         let decryption_key = self.decryption_key.clone();
-        Box::new(
+        Box::pin(
             cmd("SMEMBERS")
                 .arg("btp_outgoing")
                 .query_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error getting members of set btp_outgoing: {:?}", err))
+                .map_err(|err| {
+                    store_error(
+                        &err,
+                        format!("Error getting members of set btp_outgoing: {:?}", err),
+                    )
+                })
                 .and_then(
                     |(connection, account_ids): (SharedConnection, Vec<AccountId>)| {
                         if account_ids.is_empty() {
@@ -787,29 +1041,32 @@ impl BtpStore for RedisStore {
                             Either::B(
                                 pipe.query_async(connection)
                                     .map_err(|err| {
-                                        error!(
+                                        store_error(
+                                            &err,
+                                            format!(
                                         "Error getting accounts with outgoing BTP details: {:?}",
                                         err
-                                    )
+                                    ),
+                                        )
                                     })
                                     .and_then(
                                         move |(_connection, accounts): (
                                             SharedConnection,
                                             Vec<AccountWithEncryptedTokens>,
                                         )| {
-                                            let accounts: Vec<Account> = accounts
+                                            accounts
                                                 .into_iter()
                                                 .map(|account| {
                                                     account.decrypt_tokens(&decryption_key)
                                                 })
-                                                .collect();
-                                            Ok(accounts)
+                                                .collect::<Result<Vec<Account>, StoreError>>()
                                         },
                                     ),
                             )
                         }
                     },
-                ),
+                )
+                .compat(),
         )
     }
 }
@@ -823,33 +1080,50 @@ impl HttpStore for RedisStore {
         &self,
         username: &Username,
         token: &str,
-    ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Self::Account, StoreError>> + Send>> {
+        if let Some(account) = self.cached_account_from_token(username, token, |account| {
+            account.http_incoming_token.as_ref()
+        }) {
+            return Box::pin(ready(Ok(account)));
+        }
+
         // TODO make sure it can't do script injection!
         let decryption_key = self.decryption_key.clone();
-        Box::new(
+        let account_cache = self.account_cache.clone();
+        let cache_generation = account_cache.generation();
+        Box::pin(
             ACCOUNT_FROM_TOKEN
                 .arg("http_incoming_token")
                 .arg(username.as_ref())
                 .arg(token)
                 .invoke_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error getting account from HTTP auth: {:?}", err))
+                .map_err(|err| {
+                    store_error(
+                        &err,
+                        format!("Error getting account from HTTP auth: {:?}", err),
+                    )
+                })
                 .and_then(
                     move |(_connection, account): (_, Option<AccountWithEncryptedTokens>)| {
                         if let Some(account) = account {
-                            let account = account.decrypt_tokens(&decryption_key);
+                            let account = account.decrypt_tokens(&decryption_key)?;
+                            account_cache.insert_many(cache_generation, Some(&account));
                             Ok(account)
                         } else {
                             warn!("No account found with given HTTP auth");
-                            Err(())
+                            Err(StoreError::NotFound(
+                                "No account found with given HTTP auth".to_string(),
+                            ))
                         }
                     },
-                ),
+                )
+                .compat(),
         )
     }
 }
 
 impl RouterStore for RedisStore {
-    fn routing_table(&self) -> HashMap<Bytes, <Self::Account as AccountTrait>::AccountId> {
+    fn routing_table(&self) -> Arc<PrefixTable<<Self::Account as AccountTrait>::AccountId>> {
         self.routes.read().clone()
     }
 }
@@ -860,50 +1134,64 @@ impl NodeStore for RedisStore {
     fn insert_account(
         &self,
         account: AccountDetails,
-    ) -> Box<dyn Future<Item = Account, Error = ()> + Send> {
-        self.redis_insert_account(account)
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Account, StoreError>> + Send>> {
+        Box::pin(self.redis_insert_account(account).compat())
     }
 
-    fn delete_account(&self, id: AccountId) -> Box<dyn Future<Item = Account, Error = ()> + Send> {
-        self.redis_delete_account(id)
+    fn delete_account(
+        &self,
+        id: AccountId,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Account, StoreError>> + Send>> {
+        Box::pin(self.redis_delete_account(id).compat())
     }
 
     fn update_account(
         &self,
         id: AccountId,
         account: AccountDetails,
-    ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send> {
-        self.redis_update_account(id, account)
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Self::Account, StoreError>> + Send>> {
+        Box::pin(self.redis_update_account(id, account).compat())
     }
 
     // TODO limit the number of results and page through them
-    fn get_all_accounts(&self) -> Box<dyn Future<Item = Vec<Self::Account>, Error = ()> + Send> {
+    fn get_all_accounts(
+        &self,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Vec<Self::Account>, StoreError>> + Send>>
+    {
         let decryption_key = self.decryption_key.clone();
         let mut pipe = redis::pipe();
         let connection = self.connection.clone();
         pipe.smembers("accounts");
-        Box::new(self.get_all_accounts_ids().and_then(move |account_ids| {
-            let mut pipe = redis::pipe();
-            for account_id in account_ids {
-                pipe.hgetall(accounts_key(account_id));
-            }
+        Box::pin(
+            self.get_all_accounts_ids()
+                .and_then(move |account_ids| {
+                    let mut pipe = redis::pipe();
+                    for account_id in account_ids {
+                        pipe.hgetall(accounts_key(account_id));
+                    }
 
-            pipe.query_async(connection.as_ref().clone())
-                .map_err(|err| error!("Error getting account ids: {:?}", err))
-                .and_then(
-                    move |(_, accounts): (_, Vec<Option<AccountWithEncryptedTokens>>)| {
-                        let accounts: Vec<Account> = accounts
-                            .into_iter()
-                            .filter_map(|a| a)
-                            .map(|account| account.decrypt_tokens(&decryption_key))
-                            .collect();
-                        Ok(accounts)
-                    },
-                )
-        }))
+                    pipe.query_async(connection.as_ref().clone())
+                        .map_err(|err| {
+                            store_error(&err, format!("Error getting account ids: {:?}", err))
+                        })
+                        .and_then(
+                            move |(_, accounts): (_, Vec<Option<AccountWithEncryptedTokens>>)| {
+                                accounts
+                                    .into_iter()
+                                    .filter_map(|a| a)
+                                    .map(|account| account.decrypt_tokens(&decryption_key))
+                                    .collect::<Result<Vec<Account>, StoreError>>()
+                            },
+                        )
+                })
+                .compat(),
+        )
     }
 
-    fn set_rates<R>(&self, rates: R) -> Box<dyn Future<Item = (), Error = ()> + Send>
+    fn set_rates<R>(
+        &self,
+        rates: R,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>>
     where
         R: IntoIterator<Item = (String, f64)>,
     {
@@ -914,88 +1202,200 @@ impl NodeStore for RedisStore {
             .del(RATES_KEY)
             .ignore()
             .hset_multiple(RATES_KEY, &rates)
+            .ignore()
+            .publish(RATES_CHANNEL, "")
             .ignore();
-        Box::new(
+        Box::pin(
             pipe.query_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error setting rates: {:?}", err))
+                .map_err(|err| store_error(&err, format!("Error setting rates: {:?}", err)))
                 .and_then(move |(connection, _): (SharedConnection, Value)| {
                     trace!("Set exchange rates: {:?}", exchange_rates);
                     update_rates(connection, exchange_rates)
-                }),
+                })
+                .compat(),
         )
     }
 
     // TODO fix inconsistency betwen this method and set_routes which
     // takes the prefixes as Bytes and the account as an Account object
-    fn set_static_routes<R>(&self, routes: R) -> Box<dyn Future<Item = (), Error = ()> + Send>
+    fn set_static_routes<R>(
+        &self,
+        routes: R,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>>
     where
-        R: IntoIterator<Item = (String, AccountId)>,
+        R: IntoIterator<Item = (String, Vec<StaticNextHop<AccountId>>)>,
     {
-        let routes: Vec<(String, AccountId)> = routes.into_iter().collect();
-        let accounts: HashSet<_> =
-            HashSet::from_iter(routes.iter().map(|(_prefix, account_id)| account_id));
+        let routes: Vec<(String, Vec<StaticNextHop<AccountId>>)> = routes.into_iter().collect();
+        if routes
+            .iter()
+            .any(|(_prefix, next_hops)| next_hops.is_empty())
+        {
+            return Box::pin(ready(Err(StoreError::Invalid(
+                "A static route needs at least one next hop".to_string(),
+            ))));
+        }
+        let accounts: HashSet<_> = HashSet::from_iter(
+            routes
+                .iter()
+                .flat_map(|(_prefix, next_hops)| next_hops.iter().map(|hop| hop.account_id)),
+        );
         let mut pipe = redis::pipe();
         for account_id in accounts {
-            pipe.exists(accounts_key(*account_id));
+            pipe.exists(accounts_key(account_id));
         }
 
         let routing_table = self.routes.clone();
-        Box::new(pipe.query_async(self.connection.as_ref().clone())
-            .map_err(|err| error!("Error checking if accounts exist while setting static routes: {:?}", err))
+        Box::pin(pipe.query_async(self.connection.as_ref().clone())
+            .map_err(|err| store_error(&err, format!("Error checking if accounts exist while setting static routes: {:?}", err)))
             .and_then(|(connection, accounts_exist): (SharedConnection, Vec<bool>)| {
                 if accounts_exist.iter().all(|a| *a) {
                     Ok(connection)
                 } else {
                     error!("Error setting static routes because not all of the given accounts exist");
-                    Err(())
+                    Err(StoreError::NotFound("Not all of the accounts for the static routes exist".to_string()))
                 }
             })
             .and_then(move |connection| {
-        let mut pipe = redis::pipe();
-        pipe.atomic()
-            .del(STATIC_ROUTES_KEY)
-            .ignore()
-            .hset_multiple(STATIC_ROUTES_KEY, &routes)
-            .ignore();
-            pipe.query_async(connection)
-                .map_err(|err| error!("Error setting static routes: {:?}", err))
-                .and_then(move |(connection, _): (SharedConnection, Value)| {
-                    update_routes(connection, routing_table)
-                })
-            }))
+                let primaries: Vec<(&str, AccountId)> = routes
+                    .iter()
+                    .map(|(prefix, next_hops)| (prefix.as_str(), next_hops[0].account_id))
+                    .collect();
+                let next_hops: Vec<(&str, String)> = routes
+                    .iter()
+                    .map(|(prefix, next_hops)| (prefix.as_str(), encode_next_hops(next_hops)))
+                    .collect();
+                let mut pipe = redis::pipe();
+                pipe.atomic()
+                    .del(STATIC_ROUTES_KEY)
+                    .ignore()
+                    .del(STATIC_NEXT_HOPS_KEY)
+                    .ignore();
+                if !routes.is_empty() {
+                    pipe.hset_multiple(STATIC_ROUTES_KEY, &primaries)
+                        .ignore()
+                        .hset_multiple(STATIC_NEXT_HOPS_KEY, &next_hops)
+                        .ignore();
+                }
+                pipe.publish(ROUTES_CHANNEL, "").ignore();
+                pipe.query_async(connection)
+                    .map_err(|err| store_error(&err, format!("Error setting static routes: {:?}", err)))
+                    .and_then(move |(connection, _): (SharedConnection, Value)| {
+                        update_routes(connection, routing_table)
+                    })
+            }).compat())
     }
 
-    fn set_static_route(
+    fn set_static_next_hops(
         &self,
         prefix: String,
-        account_id: AccountId,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        next_hops: Vec<StaticNextHop<AccountId>>,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
+        if next_hops.is_empty() {
+            return Box::pin(ready(Err(StoreError::Invalid(
+                "A static route needs at least one next hop".to_string(),
+            ))));
+        }
         let routing_table = self.routes.clone();
         let prefix_clone = prefix.clone();
-        Box::new(
-        cmd("EXISTS")
-            .arg(accounts_key(account_id))
-            .query_async(self.connection.as_ref().clone())
-            .map_err(|err| error!("Error checking if account exists before setting static route: {:?}", err))
-            .and_then(move |(connection, exists): (SharedConnection, bool)| {
-                if exists {
-                    Ok(connection)
-                } else {
-                    error!("Cannot set static route for prefix: {} because account {} does not exist", prefix_clone, account_id);
-                    Err(())
+        let mut pipe = redis::pipe();
+        for hop in next_hops.iter() {
+            pipe.exists(accounts_key(hop.account_id));
+        }
+        Box::pin(
+        pipe.query_async(self.connection.as_ref().clone())
+            .map_err(|err| store_error(&err, format!("Error checking if accounts exist before setting static route: {:?}", err)))
+            .and_then(move |(connection, accounts_exist): (SharedConnection, Vec<bool>)| {
+                match next_hops.iter().zip(accounts_exist.iter()).find(|(_hop, exists)| !**exists) {
+                    None => Ok((connection, next_hops)),
+                    Some((hop, _)) => {
+                        error!("Cannot set static route for prefix: {} because account {} does not exist", prefix_clone, hop.account_id);
+                        Err(StoreError::NotFound(format!("No account exists with ID {}", hop.account_id)))
+                    }
                 }
             })
-            .and_then(move |connection| {
-                cmd("HSET")
-                    .arg(STATIC_ROUTES_KEY)
-                    .arg(prefix)
-                    .arg(account_id)
-                    .query_async(connection)
-                    .map_err(|err| error!("Error setting static route: {:?}", err))
+            .and_then(move |(connection, next_hops)| {
+                let mut pipe = redis::pipe();
+                pipe.atomic()
+                    .hset(STATIC_ROUTES_KEY, prefix.as_str(), next_hops[0].account_id)
+                    .ignore()
+                    .hset(STATIC_NEXT_HOPS_KEY, prefix.as_str(), encode_next_hops(&next_hops))
+                    .ignore()
+                    .publish(ROUTES_CHANNEL, "")
+                    .ignore();
+                pipe.query_async(connection)
+                    .map_err(|err| store_error(&err, format!("Error setting static route: {:?}", err)))
                     .and_then(move |(connection, _): (SharedConnection, Value)| {
                         update_routes(connection, routing_table)
                     })
             })
+        .compat())
+    }
+
+    fn set_ilp_address(
+        &self,
+        ilp_address: Address,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
+        let connection = self.connection.clone();
+        let routing_table = self.routes.clone();
+        let account_cache = self.account_cache.clone();
+        Box::pin(
+            self.get_ilp_address()
+                .join(self.get_all_accounts().compat())
+                .and_then(move |(old_address, accounts)| {
+                    let mut pipe = redis::pipe();
+                    pipe.atomic();
+                    pipe.set(ILP_ADDRESS_KEY, ilp_address.to_bytes().to_vec())
+                        .ignore();
+
+                    // Move the children whose addresses were derived from the old address
+                    let mut moved_children = Vec::new();
+                    if let Some(old_address) = old_address.filter(|old| *old != ilp_address) {
+                        for account in accounts
+                            .iter()
+                            .filter(|account| account.routing_relation == RoutingRelation::Child)
+                        {
+                            match child_address(&old_address, &account.username) {
+                                Ok(derived) if derived == account.ilp_address => {}
+                                _ => continue,
+                            }
+                            let new_address = child_address(&ilp_address, &account.username)?;
+                            pipe.hset(
+                                accounts_key(account.id),
+                                "ilp_address",
+                                new_address.to_bytes().to_vec(),
+                            )
+                            .ignore();
+                            pipe.hdel(ROUTES_KEY, account.ilp_address.to_bytes().to_vec())
+                                .ignore();
+                            pipe.hset(ROUTES_KEY, new_address.to_bytes().to_vec(), account.id)
+                                .ignore();
+                            pipe.publish(ACCOUNTS_CHANNEL, account.id).ignore();
+                            moved_children.push(account.id);
+                        }
+                        pipe.publish(ROUTES_CHANNEL, "").ignore();
+                    }
+                    Ok((pipe, moved_children))
+                })
+                .and_then(move |(pipe, moved_children)| {
+                    pipe.query_async(connection.as_ref().clone())
+                        .map_err(|err| {
+                            store_error(
+                                &err,
+                                format!("Error setting the node's ILP address: {:?}", err),
+                            )
+                        })
+                        .and_then(move |(connection, _): (SharedConnection, Value)| {
+                            debug!(
+                                "Saved the node's ILP address and moved {} child accounts under it",
+                                moved_children.len()
+                            );
+                            for account_id in moved_children {
+                                account_cache.remove(account_id);
+                            }
+                            update_routes(connection, routing_table)
+                        })
+                })
+                .compat(),
         )
     }
 }
@@ -1007,13 +1407,18 @@ impl RouteManagerStore for RedisStore {
 
     fn get_accounts_to_send_routes_to(
         &self,
-    ) -> Box<dyn Future<Item = Vec<Account>, Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Vec<Account>, StoreError>> + Send>> {
         let decryption_key = self.decryption_key.clone();
-        Box::new(
+        Box::pin(
             cmd("SMEMBERS")
                 .arg("send_routes_to")
                 .query_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error getting members of set send_routes_to: {:?}", err))
+                .map_err(|err| {
+                    store_error(
+                        &err,
+                        format!("Error getting members of set send_routes_to: {:?}", err),
+                    )
+                })
                 .and_then(
                     |(connection, account_ids): (SharedConnection, Vec<AccountId>)| {
                         if account_ids.is_empty() {
@@ -1026,9 +1431,12 @@ impl RouteManagerStore for RedisStore {
                             Either::B(
                                 pipe.query_async(connection)
                                     .map_err(|err| {
-                                        error!(
-                                            "Error getting accounts to send routes to: {:?}",
-                                            err
+                                        store_error(
+                                            &err,
+                                            format!(
+                                                "Error getting accounts to send routes to: {:?}",
+                                                err
+                                            ),
                                         )
                                     })
                                     .and_then(
@@ -1036,34 +1444,37 @@ impl RouteManagerStore for RedisStore {
                                             SharedConnection,
                                             Vec<AccountWithEncryptedTokens>,
                                         )| {
-                                            let accounts: Vec<Account> = accounts
+                                            accounts
                                                 .into_iter()
                                                 .map(|account| {
                                                     account.decrypt_tokens(&decryption_key)
                                                 })
-                                                .collect();
-                                            Ok(accounts)
+                                                .collect::<Result<Vec<Account>, StoreError>>()
                                         },
                                     ),
                             )
                         }
                     },
-                ),
+                )
+                .compat(),
         )
     }
 
     fn get_accounts_to_receive_routes_from(
         &self,
-    ) -> Box<dyn Future<Item = Vec<Account>, Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Vec<Account>, StoreError>> + Send>> {
         let decryption_key = self.decryption_key.clone();
-        Box::new(
+        Box::pin(
             cmd("SMEMBERS")
                 .arg("receive_routes_from")
                 .query_async(self.connection.as_ref().clone())
                 .map_err(|err| {
-                    error!(
-                        "Error getting members of set receive_routes_from: {:?}",
-                        err
+                    store_error(
+                        &err,
+                        format!(
+                            "Error getting members of set receive_routes_from: {:?}",
+                            err
+                        ),
                     )
                 })
                 .and_then(
@@ -1078,9 +1489,12 @@ impl RouteManagerStore for RedisStore {
                             Either::B(
                                 pipe.query_async(connection)
                                     .map_err(|err| {
-                                        error!(
+                                        store_error(
+                                            &err,
+                                            format!(
                                             "Error getting accounts to receive routes from: {:?}",
                                             err
+                                        ),
                                         )
                                     })
                                     .and_then(
@@ -1088,36 +1502,41 @@ impl RouteManagerStore for RedisStore {
                                             SharedConnection,
                                             Vec<AccountWithEncryptedTokens>,
                                         )| {
-                                            let accounts: Vec<Account> = accounts
+                                            accounts
                                                 .into_iter()
                                                 .map(|account| {
                                                     account.decrypt_tokens(&decryption_key)
                                                 })
-                                                .collect();
-                                            Ok(accounts)
+                                                .collect::<Result<Vec<Account>, StoreError>>()
                                         },
                                     ),
                             )
                         }
                     },
-                ),
+                )
+                .compat(),
         )
     }
 
     fn get_local_and_configured_routes(
         &self,
-    ) -> Box<dyn Future<Item = (RoutingTable<Account>, RoutingTable<Account>), Error = ()> + Send>
-    {
+    ) -> Pin<
+        Box<
+            dyn std::future::Future<
+                    Output = Result<(RoutingTable<Account>, RoutingTable<Account>), StoreError>,
+                > + Send,
+        >,
+    > {
         let get_static_routes = cmd("HGETALL")
             .arg(STATIC_ROUTES_KEY)
             .query_async(self.connection.as_ref().clone())
-            .map_err(|err| error!("Error getting static routes: {:?}", err))
+            .map_err(|err| store_error(&err, format!("Error getting static routes: {:?}", err)))
             .and_then(
                 |(_, static_routes): (SharedConnection, Vec<(String, AccountId)>)| {
                     Ok(static_routes)
                 },
             );
-        Box::new(self.get_all_accounts().join(get_static_routes).and_then(
+        Box::pin(self.get_all_accounts().compat().join(get_static_routes).and_then(
             |(accounts, static_routes)| {
                 let local_table = HashMap::from_iter(
                     accounts
@@ -1138,13 +1557,13 @@ impl RouteManagerStore for RedisStore {
 
                 Ok((local_table, configured_table))
             },
-        ))
+        ).compat())
     }
 
     fn set_routes(
         &mut self,
         routes: impl IntoIterator<Item = (Bytes, Account)>,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
         let routes: Vec<(String, AccountId)> = routes
             .into_iter()
             .filter_map(|(prefix, account)| {
@@ -1164,14 +1583,55 @@ impl RouteManagerStore for RedisStore {
             .del(ROUTES_KEY)
             .ignore()
             .hset_multiple(ROUTES_KEY, &routes)
+            .ignore()
+            .publish(ROUTES_CHANNEL, "")
             .ignore();
-        Box::new(
+        Box::pin(
             pipe.query_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error setting routes: {:?}", err))
+                .map_err(|err| store_error(&err, format!("Error setting routes: {:?}", err)))
                 .and_then(move |(connection, _): (SharedConnection, Value)| {
                     trace!("Saved {} routes to Redis", num_routes);
                     update_routes(connection, routing_tale)
-                }),
+                })
+                .compat(),
+        )
+    }
+
+    fn get_forwarding_table(
+        &self,
+        node_id: &str,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Option<Bytes>, StoreError>> + Send>> {
+        Box::pin(
+            cmd("GET")
+                .arg(forwarding_table_key(node_id))
+                .query_async(self.connection.as_ref().clone())
+                .map_err(|err| {
+                    store_error(&err, format!("Error loading forwarding table: {:?}", err))
+                })
+                .and_then(|(_connection, table): (_, Option<Vec<u8>>)| Ok(table.map(Bytes::from)))
+                .compat(),
+        )
+    }
+
+    fn set_forwarding_table(
+        &mut self,
+        node_id: &str,
+        table: Bytes,
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
+        let table_len = table.len();
+        Box::pin(
+            cmd("SET")
+                .arg(forwarding_table_key(node_id))
+                .arg(table.to_vec())
+                .query_async(self.connection.as_ref().clone())
+                .map_err(|err| {
+                    store_error(&err, format!("Error saving forwarding table: {:?}", err))
+                })
+                .and_then(move |(_connection, _): (SharedConnection, Value)| {
+                    trace!("Saved {} byte forwarding table to Redis", table_len);
+                    Ok(())
+                })
+                .compat(),
         )
     }
 }
@@ -1186,7 +1646,7 @@ impl RateLimitStore for RedisStore {
         &self,
         account: Account,
         prepare_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = RateLimitError> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), RateLimitError>> + Send>> {
         if account.amount_per_minute_limit.is_some() || account.packets_per_minute_limit.is_some() {
             let mut pipe = redis::pipe();
             let packet_limit = account.packets_per_minute_limit.is_some();
@@ -1212,7 +1672,7 @@ impl RateLimitStore for RedisStore {
                     .arg(60)
                     .arg(prepare_amount);
             }
-            Box::new(
+            Box::pin(
                 pipe.query_async(self.connection.as_ref().clone())
                     .map_err(|err| {
                         error!("Error applying rate limits: {:?}", err);
@@ -1234,10 +1694,11 @@ impl RateLimitStore for RedisStore {
                         } else {
                             Ok(())
                         }
-                    }),
+                    })
+                    .compat(),
             )
         } else {
-            Box::new(ok(()))
+            Box::pin(ready(Ok(())))
         }
     }
 
@@ -1245,10 +1706,10 @@ impl RateLimitStore for RedisStore {
         &self,
         account: Account,
         prepare_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
         if let Some(limit) = account.amount_per_minute_limit {
             let limit = limit - 1;
-            Box::new(
+            Box::pin(
                 cmd("CL.THROTTLE")
                     .arg(format!("limit:throughput:{}", account.id))
                     .arg(limit)
@@ -1257,11 +1718,14 @@ impl RateLimitStore for RedisStore {
                     // TODO make sure this doesn't overflow
                     .arg(0i64 - (prepare_amount as i64))
                     .query_async(self.connection.as_ref().clone())
-                    .map_err(|err| error!("Error refunding throughput limit: {:?}", err))
-                    .and_then(|(_, _): (_, Value)| Ok(())),
+                    .map_err(|err| {
+                        store_error(&err, format!("Error refunding throughput limit: {:?}", err))
+                    })
+                    .and_then(|(_, _): (_, Value)| Ok(()))
+                    .compat(),
             )
         } else {
-            Box::new(ok(()))
+            Box::pin(ready(Ok(())))
         }
     }
 }
@@ -1270,16 +1734,20 @@ impl IdempotentStore for RedisStore {
     fn load_idempotent_data(
         &self,
         idempotency_key: String,
-    ) -> Box<dyn Future<Item = Option<IdempotentData>, Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<Option<IdempotentData>, StoreError>> + Send>>
+    {
         let idempotency_key_clone = idempotency_key.clone();
-        Box::new(
+        Box::pin(
             cmd("HGETALL")
                 .arg(prefixed_idempotency_key(idempotency_key.clone()))
                 .query_async(self.connection.as_ref().clone())
                 .map_err(move |err| {
-                    error!(
-                        "Error loading idempotency key {}: {:?}",
-                        idempotency_key_clone, err
+                    store_error(
+                        &err,
+                        format!(
+                            "Error loading idempotency key {}: {:?}",
+                            idempotency_key_clone, err
+                        ),
                     )
                 })
                 .and_then(move |(_connection, ret): (_, HashMap<String, String>)| {
@@ -1289,17 +1757,20 @@ impl IdempotentStore for RedisStore {
                         ret.get("input_hash"),
                     ) {
                         trace!("Loaded idempotency key {:?} - {:?}", idempotency_key, ret);
+                        let status_code = StatusCode::from_str(status_code).map_err(|_| {
+                            StoreError::Corrupt(format!("Invalid status code: {}", status_code))
+                        })?;
+                        if input_hash_slice.len() != 32 {
+                            return Err(StoreError::Corrupt("Invalid input hash".to_string()));
+                        }
                         let mut input_hash: [u8; 32] = Default::default();
                         input_hash.copy_from_slice(input_hash_slice.as_ref());
-                        Ok(Some((
-                            StatusCode::from_str(status_code).unwrap(),
-                            Bytes::from(data.clone()),
-                            input_hash,
-                        )))
+                        Ok(Some((status_code, Bytes::from(data.clone()), input_hash)))
                     } else {
                         Ok(None)
                     }
-                }),
+                })
+                .compat(),
         )
     }
 
@@ -1309,7 +1780,7 @@ impl IdempotentStore for RedisStore {
         input_hash: [u8; 32],
         status_code: StatusCode,
         data: Bytes,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
         let mut pipe = redis::pipe();
         pipe.atomic()
             .cmd("HMSET") // cannot use hset_multiple since data and status_code have different types
@@ -1323,9 +1794,9 @@ impl IdempotentStore for RedisStore {
             .ignore()
             .expire(&prefixed_idempotency_key(idempotency_key.clone()), 86400)
             .ignore();
-        Box::new(
+        Box::pin(
             pipe.query_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error caching: {:?}", err))
+                .map_err(|err| store_error(&err, format!("Error caching: {:?}", err)))
                 .and_then(move |(_connection, _): (_, Vec<String>)| {
                     trace!(
                         "Cached {:?}: {:?}, {:?}",
@@ -1334,7 +1805,8 @@ impl IdempotentStore for RedisStore {
                         data,
                     );
                     Ok(())
-                }),
+                })
+                .compat(),
         )
     }
 }
@@ -1347,40 +1819,43 @@ impl SettlementStore for RedisStore {
         account_id: AccountId,
         amount: u64,
         idempotency_key: Option<String>,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
         let idempotency_key = idempotency_key.unwrap();
-        Box::new(
+        Box::pin(
             PROCESS_INCOMING_SETTLEMENT
             .arg(account_id)
             .arg(amount)
             .arg(idempotency_key)
             .invoke_async(self.connection.as_ref().clone())
-            .map_err(move |err| error!("Error processing incoming settlement from account: {} for amount: {}: {:?}", account_id, amount, err))
+            .map_err(move |err| store_error(&err, format!("Error processing incoming settlement from account: {} for amount: {}: {:?}", account_id, amount, err)))
             .and_then(move |(_connection, balance): (_, i64)| {
                 trace!("Processed incoming settlement from account: {} for amount: {}. Balance is now: {}", account_id, amount, balance);
                 Ok(())
-            }))
+            }).compat())
     }
 
     fn refund_settlement(
         &self,
         account_id: AccountId,
         settle_amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    ) -> Pin<Box<dyn std::future::Future<Output = Result<(), StoreError>> + Send>> {
         trace!(
             "Refunding settlement for account: {} of amount: {}",
             account_id,
             settle_amount
         );
-        Box::new(
+        Box::pin(
             REFUND_SETTLEMENT
                 .arg(account_id)
                 .arg(settle_amount)
                 .invoke_async(self.connection.as_ref().clone())
                 .map_err(move |err| {
-                    error!(
-                        "Error refunding settlement for account: {} of amount: {}: {:?}",
-                        account_id, settle_amount, err
+                    store_error(
+                        &err,
+                        format!(
+                            "Error refunding settlement for account: {} of amount: {}: {:?}",
+                            account_id, settle_amount, err
+                        ),
                     )
                 })
                 .and_then(move |(_connection, balance): (_, i64)| {
@@ -1391,20 +1866,112 @@ impl SettlementStore for RedisStore {
                         balance
                     );
                     Ok(())
-                }),
+                })
+                .compat(),
         )
     }
 }
 
-// TODO replace this with pubsub when async pubsub is added upstream: https://github.com/mitsuhiko/redis-rs/issues/183
+/// Listens for changes published by any store connected to the same database.
+///
+/// Accounts published on the `ACCOUNTS_CHANNEL` are removed from the cache, while messages on
+/// the `RATES_CHANNEL` and `ROUTES_CHANNEL` are forwarded so the rates or routes get reloaded.
+///
+/// This blocks, so it must be run on its own thread. It returns once the store that owns
+/// the cache has been dropped. Whenever the subscription is (re)established, the cache is
+/// cleared and the rates and routes are reloaded because changes may have been missed.
+fn subscribe_to_updates(
+    client: Client,
+    account_cache: Weak<AccountCache>,
+    notifications: UnboundedSender<Notification>,
+) {
+    let retry_delay = Duration::from_millis(SUBSCRIBER_READ_TIMEOUT);
+    let notify = |notification| notifications.unbounded_send(notification).is_ok();
+    loop {
+        let mut connection = match client.get_connection() {
+            Ok(connection) => connection,
+            Err(err) => {
+                error!(
+                    "Error connecting to Redis to subscribe to updates: {:?}",
+                    err
+                );
+                match account_cache.upgrade() {
+                    Some(cache) => cache.clear(),
+                    None => return,
+                }
+                thread::sleep(retry_delay);
+                continue;
+            }
+        };
+        let mut pubsub = connection.as_pubsub();
+        // The read timeout lets us periodically check whether the store has been dropped
+        if let Err(err) = pubsub
+            .subscribe(&[ACCOUNTS_CHANNEL, RATES_CHANNEL, ROUTES_CHANNEL][..])
+            .and_then(|_| pubsub.set_read_timeout(Some(retry_delay)))
+        {
+            error!("Error subscribing to updates: {:?}", err);
+            thread::sleep(retry_delay);
+            continue;
+        }
+        debug!("Subscribed to account, rate and route updates");
+        match account_cache.upgrade() {
+            Some(cache) => cache.clear(),
+            None => return,
+        }
+        if !notify(Notification::Rates) || !notify(Notification::Routes) {
+            return;
+        }
+
+        loop {
+            let message = pubsub.get_message();
+            let cache = match account_cache.upgrade() {
+                Some(cache) => cache,
+                None => {
+                    debug!("Not listening for updates anymore because the store was dropped");
+                    return;
+                }
+            };
+            let message = match message {
+                Ok(message) => message,
+                Err(ref err) if err.is_timeout() => continue,
+                Err(err) => {
+                    error!("Lost subscription to updates: {:?}", err);
+                    cache.clear();
+                    break;
+                }
+            };
+            let channel = message.get_channel_name();
+            if channel == ACCOUNTS_CHANNEL {
+                match message.get_payload::<AccountId>() {
+                    Ok(account_id) => {
+                        trace!("Removing account {} from the cache", account_id);
+                        cache.remove(account_id);
+                    }
+                    Err(err) => warn!("Received invalid account update message: {:?}", err),
+                }
+            } else if channel == RATES_CHANNEL {
+                trace!("Exchange rates changed, reloading them");
+                if !notify(Notification::Rates) {
+                    return;
+                }
+            } else if channel == ROUTES_CHANNEL {
+                trace!("Routing table changed, reloading it");
+                if !notify(Notification::Routes) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
 fn update_rates(
     connection: SharedConnection,
     exchange_rates: Arc<RwLock<HashMap<String, f64>>>,
-) -> impl Future<Item = (), Error = ()> {
+) -> impl Future<Item = (), Error = StoreError> {
     cmd("HGETALL")
         .arg(RATES_KEY)
         .query_async(connection)
-        .map_err(|err| error!("Error polling for exchange rates: {:?}", err))
+        .map_err(|err| store_error(&err, format!("Error polling for exchange rates: {:?}", err)))
         .and_then(move |(_connection, rates): (_, Vec<(String, f64)>)| {
             let num_assets = rates.len();
             let rates = HashMap::from_iter(rates.into_iter());
@@ -1414,37 +1981,156 @@ fn update_rates(
         })
 }
 
-// TODO replace this with pubsub when async pubsub is added upstream: https://github.com/mitsuhiko/redis-rs/issues/183
 type RouteVec = Vec<(String, AccountId)>;
 
+/// Encode a static route's next hops as `<account ID>[*<weight>]`, separated by commas
+fn encode_next_hops(next_hops: &[StaticNextHop<AccountId>]) -> String {
+    next_hops
+        .iter()
+        .map(|hop| match hop.weight {
+            Some(weight) => format!("{}*{}", hop.account_id, weight),
+            None => hop.account_id.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn decode_next_hops(encoded: &str) -> Result<Vec<StaticNextHop<AccountId>>, StoreError> {
+    encoded
+        .split(',')
+        .map(|hop| {
+            let mut parts = hop.splitn(2, '*');
+            let account_id = parts
+                .next()
+                .and_then(|account_id| AccountId::from_str(account_id).ok());
+            let weight = parts.next().map(u32::from_str).transpose().ok();
+            match (account_id, weight) {
+                (Some(account_id), Some(weight)) => Ok(StaticNextHop { account_id, weight }),
+                _ => Err(StoreError::Corrupt(format!(
+                    "Invalid next hop for static route: {}",
+                    hop
+                ))),
+            }
+        })
+        .collect()
+}
+
 fn update_routes(
     connection: SharedConnection,
-    routing_table: Arc<RwLock<HashMap<Bytes, AccountId>>>,
-) -> impl Future<Item = (), Error = ()> {
+    routing_table: Arc<RwLock<Arc<PrefixTable<AccountId>>>>,
+) -> impl Future<Item = (), Error = StoreError> {
     let mut pipe = redis::pipe();
-    pipe.hgetall(ROUTES_KEY).hgetall(STATIC_ROUTES_KEY);
+    pipe.hgetall(ROUTES_KEY)
+        .hgetall(STATIC_ROUTES_KEY)
+        .hgetall(STATIC_NEXT_HOPS_KEY);
     pipe.query_async(connection)
-        .map_err(|err| error!("Error polling for routing table updates: {:?}", err))
+        .map_err(|err| {
+            store_error(
+                &err,
+                format!("Error polling for routing table updates: {:?}", err),
+            )
+        })
         .and_then(
-            move |(_connection, (routes, static_routes)): (_, (RouteVec, RouteVec))| {
+            move |(connection, (routes, static_routes, next_hops)): (
+                _,
+                (RouteVec, RouteVec, HashMap<String, String>),
+            )| {
                 trace!(
-                    "Loaded routes from redis. Static routes: {:?}, other routes: {:?}",
+                    "Loaded routes from redis. Static routes: {:?}, next hops: {:?}, other routes: {:?}",
                     static_routes,
+                    next_hops,
                     routes
                 );
-                let routes = HashMap::from_iter(
-                    routes
-                        .into_iter()
-                        // Having the static_routes inserted after ensures that they will overwrite
-                        // any routes with the same prefix from the first set
-                        .chain(static_routes.into_iter())
-                        .map(|(prefix, account_id)| (Bytes::from(prefix), account_id)),
-                );
-                trace!("Routing table is: {:?}", routes);
-                *routing_table.write() = routes;
-                Ok(())
+                // The next hops are only used if they still start with the primary, because
+                // a node that does not know about them may have replaced the static route since
+                let static_routes = static_routes
+                    .into_iter()
+                    .map(|(prefix, account_id)| {
+                        let next_hops = match next_hops.get(&prefix) {
+                            Some(encoded) => decode_next_hops(encoded)?,
+                            None => Vec::new(),
+                        };
+                        if next_hops.first().map(|hop| hop.account_id) == Some(account_id) {
+                            Ok((prefix, next_hops))
+                        } else {
+                            Ok((prefix, vec![StaticNextHop::new(account_id)]))
+                        }
+                    })
+                    .collect::<Result<Vec<(String, Vec<StaticNextHop<AccountId>>)>, StoreError>>()?;
+                Ok((connection, routes, static_routes))
             },
         )
+        .and_then(|(connection, routes, static_routes)| {
+            // Look up which of the static routes' accounts still exist, along with their weights
+            let account_ids: Vec<AccountId> = static_routes
+                .iter()
+                .flat_map(|(_prefix, next_hops)| next_hops.iter().map(|hop| hop.account_id))
+                .collect::<HashSet<AccountId>>()
+                .into_iter()
+                .collect();
+            if account_ids.is_empty() {
+                return Either::A(ok((routes, static_routes, HashMap::new())));
+            }
+            let mut pipe = redis::pipe();
+            for account_id in account_ids.iter() {
+                pipe.cmd("HMGET")
+                    .arg(accounts_key(*account_id))
+                    .arg("id")
+                    .arg("route_weight");
+            }
+            Either::B(
+                pipe.query_async(connection)
+                    .map_err(|err| {
+                        store_error(
+                            &err,
+                            format!("Error loading the weights of the static routes: {:?}", err),
+                        )
+                    })
+                    .map(
+                        move |(_connection, accounts): (
+                            _,
+                            Vec<(Option<AccountId>, Option<u32>)>,
+                        )| {
+                            let weights = HashMap::from_iter(
+                                account_ids.into_iter().zip(accounts).filter_map(
+                                    |(account_id, (exists, weight))| {
+                                        exists.map(|_| (account_id, weight.unwrap_or(1)))
+                                    },
+                                ),
+                            );
+                            (routes, static_routes, weights)
+                        },
+                    ),
+            )
+        })
+        .and_then(move |(routes, static_routes, weights)| {
+            let mut routes = PrefixTable::from_iter(
+                routes
+                    .into_iter()
+                    .map(|(prefix, account_id)| (Bytes::from(prefix), account_id)),
+            );
+            // The static routes replace any routes with the same prefix from the first set.
+            // Next hops whose accounts have been deleted are left out.
+            for (prefix, static_next_hops) in static_routes {
+                let next_hops: Vec<NextHop<AccountId>> = static_next_hops
+                    .into_iter()
+                    .filter_map(|hop| {
+                        weights.get(&hop.account_id).map(|weight| NextHop {
+                            account_id: hop.account_id,
+                            weight: hop.weight.unwrap_or(*weight),
+                        })
+                    })
+                    .collect();
+                if next_hops.is_empty() {
+                    warn!("No accounts exist for the static route to prefix: {}", prefix);
+                } else {
+                    routes.set_next_hops(Bytes::from(prefix), next_hops);
+                }
+            }
+            trace!("Routing table is: {:?}", routes);
+            *routing_table.write() = Arc::new(routes);
+            Ok(())
+        })
 }
 
 #[cfg(test)]
@@ -2,22 +2,27 @@ mod common;
 
 use common::*;
 
+use futures03::TryFutureExt;
 use interledger_api::NodeStore;
 use interledger_btp::{BtpAccount, BtpStore};
+use interledger_ccp::{CcpRoutingAccount, RoutePolicy};
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::Address;
 use interledger_service::Account as AccountTrait;
-use interledger_service::{AccountStore, Username};
+use interledger_service::{AccountStore, StoreError, Username};
 use interledger_service_util::BalanceStore;
 use interledger_store_redis::AccountId;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
 
 #[test]
 fn insert_accounts() {
     block_on(test_store().and_then(|(store, context, _accs)| {
         store
             .insert_account(ACCOUNT_DETAILS_2.clone())
+            .compat()
             .and_then(move |account| {
                 assert_eq!(
                     *account.client_address(),
@@ -30,13 +35,50 @@ fn insert_accounts() {
     .unwrap();
 }
 
+#[test]
+fn saves_routing_settings() {
+    let mut details = ACCOUNT_DETAILS_2.clone();
+    details.route_auth_key = Some("route secret".to_string());
+    details.route_cost = Some(5);
+    details.route_import_policy = Some(RoutePolicy {
+        deny_prefixes: vec!["example.private".to_string()],
+        ..RoutePolicy::default()
+    });
+    details.route_export_policy = Some(RoutePolicy {
+        non_transitive: true,
+        ..RoutePolicy::default()
+    });
+    block_on(test_store().and_then(move |(store, context, _accs)| {
+        store
+            .insert_account(details.clone())
+            .and_then(move |account| store.get_accounts(vec![account.id()]))
+            .compat()
+            .and_then(move |accounts| {
+                let account = &accounts[0];
+                assert_eq!(account.route_auth_key().unwrap(), b"route secret");
+                assert_eq!(account.route_cost(), 5);
+                assert_eq!(
+                    account.route_import_policy(),
+                    details.route_import_policy.as_ref()
+                );
+                assert_eq!(
+                    account.route_export_policy(),
+                    details.route_export_policy.as_ref()
+                );
+                let _ = context;
+                Ok(())
+            })
+    }))
+    .unwrap();
+}
+
 #[test]
 fn delete_accounts() {
     block_on(test_store().and_then(|(store, context, _accs)| {
-        store.get_all_accounts().and_then(move |accounts| {
+        store.get_all_accounts().compat().and_then(move |accounts| {
             let id = accounts[0].id();
-            store.delete_account(id).and_then(move |_| {
-                store.get_all_accounts().and_then(move |accounts| {
+            store.delete_account(id).compat().and_then(move |_| {
+                store.get_all_accounts().compat().and_then(move |accounts| {
                     for a in accounts {
                         assert_ne!(id, a.id());
                     }
@@ -52,45 +94,151 @@ fn delete_accounts() {
 #[test]
 fn update_accounts() {
     block_on(test_store().and_then(|(store, context, accounts)| {
-        context
-            .async_connection()
-            .map_err(|err| panic!(err))
-            .and_then(move |connection| {
-                let id = accounts[0].id();
-                redis::cmd("HMSET")
-                    .arg(format!("accounts:{}", id))
-                    .arg("balance")
-                    .arg(600)
-                    .arg("prepaid_amount")
-                    .arg(400)
-                    .query_async(connection)
-                    .map_err(|err| panic!(err))
-                    .and_then(move |(_, _): (_, redis::Value)| {
+        context.async_connection().and_then(move |connection| {
+            let id = accounts[0].id();
+            redis::cmd("HMSET")
+                .arg(format!("accounts:{}", id))
+                .arg("balance")
+                .arg(600)
+                .arg("prepaid_amount")
+                .arg(400)
+                .query_async(connection)
+                .map_err(|err| panic!(err))
+                .and_then(move |(_, _): (_, redis::Value)| {
+                    let mut new = ACCOUNT_DETAILS_0.clone();
+                    new.asset_code = String::from("TUV");
+                    store
+                        .update_account(id, new)
+                        .compat()
+                        .and_then(move |account| {
+                            assert_eq!(account.asset_code(), "TUV");
+                            store
+                                .get_balance(account)
+                                .compat()
+                                .and_then(move |balance| {
+                                    assert_eq!(balance, 1000);
+                                    let _ = context;
+                                    Ok(())
+                                })
+                        })
+                })
+        })
+    }))
+    .unwrap();
+}
+
+#[test]
+fn get_accounts_returns_updated_account() {
+    block_on(test_store().and_then(|(store, context, accounts)| {
+        let id = accounts[0].id();
+        // The first lookup puts the account in the cache
+        store.get_accounts(vec![id]).compat().and_then(move |_| {
+            let mut new = ACCOUNT_DETAILS_0.clone();
+            new.asset_code = String::from("TUV");
+            store.update_account(id, new).compat().and_then(move |_| {
+                store
+                    .get_accounts(vec![id])
+                    .compat()
+                    .and_then(move |accounts| {
+                        assert_eq!(accounts[0].asset_code(), "TUV");
+                        let _ = context;
+                        Ok(())
+                    })
+            })
+        })
+    }))
+    .unwrap();
+}
+
+#[test]
+fn get_accounts_errors_for_deleted_account() {
+    let result = block_on(test_store().and_then(|(store, context, accounts)| {
+        let id = accounts[0].id();
+        store.get_accounts(vec![id]).compat().and_then(move |_| {
+            store.delete_account(id).compat().and_then(move |_| {
+                store.get_accounts(vec![id]).compat().then(move |result| {
+                    let _ = context;
+                    result
+                })
+            })
+        })
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn other_instances_see_updated_accounts() {
+    block_on(test_store().and_then(|(store, context, accounts)| {
+        let id = accounts[0].id();
+        RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
+            .connect()
+            .map_err(|_| StoreError::Unavailable("Unable to connect to Redis".to_string()))
+            .and_then(move |other_store| {
+                other_store
+                    .get_accounts(vec![id])
+                    .compat()
+                    .and_then(move |_| {
                         let mut new = ACCOUNT_DETAILS_0.clone();
                         new.asset_code = String::from("TUV");
-                        store.update_account(id, new).and_then(move |account| {
-                            assert_eq!(account.asset_code(), "TUV");
-                            store.get_balance(account).and_then(move |balance| {
-                                assert_eq!(balance, 1000);
+                        store.update_account(id, new).compat()
+                    })
+                    .and_then(|_| {
+                        // Give the other store time to receive the published update
+                        Delay::new(Instant::now() + Duration::from_millis(100))
+                            .map_err(|err| panic!(err))
+                    })
+                    .and_then(move |_| {
+                        other_store
+                            .get_accounts(vec![id])
+                            .compat()
+                            .and_then(move |accounts| {
+                                assert_eq!(accounts[0].asset_code(), "TUV");
                                 let _ = context;
                                 Ok(())
                             })
-                        })
                     })
             })
     }))
     .unwrap();
 }
 
+#[test]
+fn http_auth_rejects_replaced_token() {
+    let result = block_on(test_store().and_then(|(store, context, accounts)| {
+        let id = accounts[0].id();
+        let username = Username::from_str("alice").unwrap();
+        store
+            .get_account_from_http_auth(&username, "incoming_auth_token")
+            .compat()
+            .and_then(move |_| {
+                let mut new = ACCOUNT_DETAILS_0.clone();
+                new.http_incoming_token = Some("new_auth_token".to_string());
+                store.update_account(id, new).compat().and_then(move |_| {
+                    store
+                        .get_account_from_http_auth(&username, "incoming_auth_token")
+                        .compat()
+                        .then(move |result| {
+                            let _ = context;
+                            result
+                        })
+                })
+            })
+    }));
+    assert!(result.is_err());
+}
+
 #[test]
 fn starts_with_zero_balance() {
     block_on(test_store().and_then(|(store, context, accs)| {
         let account0 = accs[0].clone();
-        store.get_balance(account0).and_then(move |balance| {
-            assert_eq!(balance, 0);
-            let _ = context;
-            Ok(())
-        })
+        store
+            .get_balance(account0)
+            .compat()
+            .and_then(move |balance| {
+                assert_eq!(balance, 0);
+                let _ = context;
+                Ok(())
+            })
     }))
     .unwrap();
 }
@@ -100,6 +248,7 @@ fn fetches_account_from_username() {
     block_on(test_store().and_then(|(store, context, accs)| {
         store
             .get_account_id_from_username(&Username::from_str("alice").unwrap())
+            .compat()
             .and_then(move |account_id| {
                 assert_eq!(account_id, accs[0].id());
                 let _ = context;
@@ -109,6 +258,24 @@ fn fetches_account_from_username() {
     .unwrap();
 }
 
+#[test]
+fn errors_for_unknown_username() {
+    block_on(test_store().and_then(|(store, context, _accs)| {
+        store
+            .get_account_id_from_username(&Username::from_str("nobody").unwrap())
+            .compat()
+            .then(move |result| {
+                match result {
+                    Err(StoreError::NotFound(_)) => {}
+                    result => panic!("Unexpected result: {:?}", result),
+                }
+                let _ = context;
+                Ok(())
+            })
+    }))
+    .unwrap();
+}
+
 #[test]
 fn duplicate_http_incoming_auth_works() {
     let mut duplicate = ACCOUNT_DETAILS_2.clone();
@@ -116,29 +283,36 @@ fn duplicate_http_incoming_auth_works() {
     block_on(test_store().and_then(|(store, context, accs)| {
         let original = accs[0].clone();
         let original_id = original.id();
-        store.insert_account(duplicate).and_then(move |duplicate| {
-            let duplicate_id = duplicate.id();
-            assert_ne!(original_id, duplicate_id);
-            futures::future::join_all(vec![
-                store.get_account_from_http_auth(
-                    &Username::from_str("alice").unwrap(),
-                    "incoming_auth_token",
-                ),
-                store.get_account_from_http_auth(
-                    &Username::from_str("charlie").unwrap(),
-                    "incoming_auth_token",
-                ),
-            ])
-            .and_then(move |accs| {
-                // Alice and Charlie had the same auth token, but they had a
-                // different username/account id, so no problem.
-                assert_ne!(accs[0].id(), accs[1].id());
-                assert_eq!(accs[0].id(), original_id);
-                assert_eq!(accs[1].id(), duplicate_id);
-                let _ = context;
-                Ok(())
+        store
+            .insert_account(duplicate)
+            .compat()
+            .and_then(move |duplicate| {
+                let duplicate_id = duplicate.id();
+                assert_ne!(original_id, duplicate_id);
+                futures::future::join_all(vec![
+                    store
+                        .get_account_from_http_auth(
+                            &Username::from_str("alice").unwrap(),
+                            "incoming_auth_token",
+                        )
+                        .compat(),
+                    store
+                        .get_account_from_http_auth(
+                            &Username::from_str("charlie").unwrap(),
+                            "incoming_auth_token",
+                        )
+                        .compat(),
+                ])
+                .and_then(move |accs| {
+                    // Alice and Charlie had the same auth token, but they had a
+                    // different username/account id, so no problem.
+                    assert_ne!(accs[0].id(), accs[1].id());
+                    assert_eq!(accs[0].id(), original_id);
+                    assert_eq!(accs[1].id(), duplicate_id);
+                    let _ = context;
+                    Ok(())
+                })
             })
-        })
     }))
     .unwrap();
 }
@@ -150,6 +324,7 @@ fn gets_account_from_btp_auth() {
         // account's information
         store
             .get_account_from_btp_auth(&Username::from_str("alice").unwrap(), "btp_token")
+            .compat()
             .and_then(move |acc| {
                 assert_eq!(acc.id(), accs[0].id());
                 let _ = context;
@@ -167,6 +342,7 @@ fn gets_account_from_http_auth() {
                 &Username::from_str("alice").unwrap(),
                 "incoming_auth_token",
             )
+            .compat()
             .and_then(move |acc| {
                 assert_eq!(acc.id(), accs[0].id());
                 let _ = context;
@@ -183,24 +359,34 @@ fn duplicate_btp_incoming_auth_works() {
     block_on(test_store().and_then(|(store, context, accs)| {
         let alice = accs[0].clone();
         let alice_id = alice.id();
-        store.insert_account(charlie).and_then(move |charlie| {
-            let charlie_id = charlie.id();
-            assert_ne!(alice_id, charlie_id);
-            futures::future::join_all(vec![
-                store.get_account_from_btp_auth(&Username::from_str("alice").unwrap(), "btp_token"),
-                store.get_account_from_btp_auth(
-                    &Username::from_str("charlie").unwrap(),
-                    "btp_token",
-                ),
-            ])
-            .and_then(move |accs| {
-                assert_ne!(accs[0].id(), accs[1].id());
-                assert_eq!(accs[0].id(), alice_id);
-                assert_eq!(accs[1].id(), charlie_id);
-                let _ = context;
-                Ok(())
+        store
+            .insert_account(charlie)
+            .compat()
+            .and_then(move |charlie| {
+                let charlie_id = charlie.id();
+                assert_ne!(alice_id, charlie_id);
+                futures::future::join_all(vec![
+                    store
+                        .get_account_from_btp_auth(
+                            &Username::from_str("alice").unwrap(),
+                            "btp_token",
+                        )
+                        .compat(),
+                    store
+                        .get_account_from_btp_auth(
+                            &Username::from_str("charlie").unwrap(),
+                            "btp_token",
+                        )
+                        .compat(),
+                ])
+                .and_then(move |accs| {
+                    assert_ne!(accs[0].id(), accs[1].id());
+                    assert_eq!(accs[0].id(), alice_id);
+                    assert_eq!(accs[1].id(), charlie_id);
+                    let _ = context;
+                    Ok(())
+                })
             })
-        })
     }))
     .unwrap();
 }
@@ -208,7 +394,7 @@ fn duplicate_btp_incoming_auth_works() {
 #[test]
 fn get_all_accounts() {
     block_on(test_store().and_then(|(store, context, _accs)| {
-        store.get_all_accounts().and_then(move |accounts| {
+        store.get_all_accounts().compat().and_then(move |accounts| {
             assert_eq!(accounts.len(), 2);
             let _ = context;
             Ok(())
@@ -224,6 +410,7 @@ fn gets_single_account() {
         let acc = accs[0].clone();
         store_clone
             .get_accounts(vec![acc.id()])
+            .compat()
             .and_then(move |accounts| {
                 assert_eq!(accounts[0].client_address(), acc.client_address(),);
                 let _ = context;
@@ -241,6 +428,7 @@ fn gets_multiple() {
         let account_ids: Vec<AccountId> = accs.iter().rev().map(|a| a.id()).collect::<_>();
         store_clone
             .get_accounts(account_ids)
+            .compat()
             .and_then(move |accounts| {
                 // note reverse order is intentional
                 assert_eq!(accounts[0].client_address(), accs[1].client_address());
@@ -258,6 +446,7 @@ fn decrypts_outgoing_tokens_acc() {
         let acc = accs[0].clone();
         store
             .get_accounts(vec![acc.id()])
+            .compat()
             .and_then(move |accounts| {
                 let account = accounts[0].clone();
                 assert_eq!(
@@ -280,6 +469,7 @@ fn errors_for_unknown_accounts() {
     let result = block_on(test_store().and_then(|(store, context, _accs)| {
         store
             .get_accounts(vec![AccountId::new(), AccountId::new()])
+            .compat()
             .then(move |result| {
                 let _ = context;
                 result
@@ -287,3 +477,36 @@ fn errors_for_unknown_accounts() {
     }));
     assert!(result.is_err());
 }
+
+#[test]
+fn derives_child_addresses_from_node_address() {
+    block_on(test_store().and_then(|(store, context, _accs)| {
+        let mut details = ACCOUNT_DETAILS_2.clone();
+        details.ilp_address = None;
+        details.routing_relation = Some("Child".to_string());
+        let store_clone = store.clone();
+        store
+            .set_ilp_address(Address::from_str("example.node").unwrap())
+            .compat()
+            .and_then(move |_| store.insert_account(details).compat())
+            .and_then(move |account| {
+                assert_eq!(
+                    *account.client_address(),
+                    Address::from_str("example.node.charlie").unwrap()
+                );
+                store_clone
+                    .set_ilp_address(Address::from_str("example.new_node").unwrap())
+                    .compat()
+                    .and_then(move |_| store_clone.get_accounts(vec![account.id()]).compat())
+            })
+            .and_then(move |accounts| {
+                assert_eq!(
+                    *accounts[0].client_address(),
+                    Address::from_str("example.new_node.charlie").unwrap()
+                );
+                let _ = context;
+                Ok(())
+            })
+    }))
+    .unwrap();
+}
@@ -2,6 +2,7 @@ mod common;
 
 use common::*;
 use futures::future::{self, Either};
+use futures03::TryFutureExt;
 use interledger_api::NodeStore;
 use interledger_packet::Address;
 use interledger_service::{AccountStore, Username};
@@ -15,28 +16,27 @@ use interledger_store_redis::{Account, AccountId};
 fn get_balance() {
     block_on(test_store().and_then(|(store, context, _accs)| {
         let account_id = AccountId::new();
-        context
-            .async_connection()
-            .map_err(move |err| panic!(err))
-            .and_then(move |connection| {
-                redis::cmd("HMSET")
-                    .arg(format!("accounts:{}", account_id))
-                    .arg("balance")
-                    .arg(600)
-                    .arg("prepaid_amount")
-                    .arg(400)
-                    .query_async(connection)
-                    .map_err(|err| panic!(err))
-                    .and_then(move |(_, _): (_, redis::Value)| {
-                        let account =
-                            Account::try_from(account_id, ACCOUNT_DETAILS_0.clone()).unwrap();
-                        store.get_balance(account).and_then(move |balance| {
+        context.async_connection().and_then(move |connection| {
+            redis::cmd("HMSET")
+                .arg(format!("accounts:{}", account_id))
+                .arg("balance")
+                .arg(600)
+                .arg("prepaid_amount")
+                .arg(400)
+                .query_async(connection)
+                .map_err(|err| panic!(err))
+                .and_then(move |(_, _): (_, redis::Value)| {
+                    let account = Account::try_from(account_id, ACCOUNT_DETAILS_0.clone()).unwrap();
+                    store
+                        .get_balance(account)
+                        .compat()
+                        .and_then(move |balance| {
                             assert_eq!(balance, 1000);
                             let _ = context;
                             Ok(())
                         })
-                    })
-            })
+                })
+        })
     }))
     .unwrap();
 }
@@ -49,6 +49,7 @@ fn prepare_then_fulfill_with_settlement() {
         store
             .clone()
             .get_accounts(vec![accs[0].id(), accs[1].id()])
+            .compat()
             .map_err(|_err| panic!("Unable to get accounts"))
             .and_then(move |accounts| {
                 let account0 = accounts[0].clone();
@@ -56,11 +57,18 @@ fn prepare_then_fulfill_with_settlement() {
                 store
                     // reduce account 0's balance by 100
                     .update_balances_for_prepare(accounts[0].clone(), 100)
+                    .compat()
                     .and_then(move |_| {
                         store_clone_1
                             .clone()
                             .get_balance(accounts[0].clone())
-                            .join(store_clone_1.clone().get_balance(accounts[1].clone()))
+                            .compat()
+                            .join(
+                                store_clone_1
+                                    .clone()
+                                    .get_balance(accounts[1].clone())
+                                    .compat(),
+                            )
                             .and_then(|(balance0, balance1)| {
                                 assert_eq!(balance0, -100);
                                 assert_eq!(balance1, 0);
@@ -71,11 +79,18 @@ fn prepare_then_fulfill_with_settlement() {
                         store_clone_2
                             .clone()
                             .update_balances_for_fulfill(account1.clone(), 100)
+                            .compat()
                             .and_then(move |_| {
                                 store_clone_2
                                     .clone()
                                     .get_balance(account0.clone())
-                                    .join(store_clone_2.clone().get_balance(account1.clone()))
+                                    .compat()
+                                    .join(
+                                        store_clone_2
+                                            .clone()
+                                            .get_balance(account1.clone())
+                                            .compat(),
+                                    )
                                     .and_then(move |(balance0, balance1)| {
                                         assert_eq!(balance0, -100);
                                         assert_eq!(balance1, -1000); // the account must be settled down to -1000
@@ -95,7 +110,7 @@ fn process_fulfill_no_settle_to() {
     let acc = {
         let mut acc = ACCOUNT_DETAILS_1.clone();
         acc.username = Username::from_str("charlie").unwrap();
-        acc.ilp_address = Address::from_str("example.charlie").unwrap();
+        acc.ilp_address = Some(Address::from_str("example.charlie").unwrap());
         acc.http_incoming_token = None;
         acc.http_outgoing_token = None;
         acc.btp_incoming_token = None;
@@ -104,23 +119,29 @@ fn process_fulfill_no_settle_to() {
     };
     block_on(test_store().and_then(|(store, context, _accs)| {
         let store_clone = store.clone();
-        store.clone().insert_account(acc).and_then(move |account| {
-            let id = account.id();
-            store_clone
-                .get_accounts(vec![id])
-                .and_then(move |accounts| {
-                    let acc = accounts[0].clone();
-                    store_clone
-                        .clone()
-                        .update_balances_for_fulfill(acc.clone(), 100)
-                        .and_then(move |(balance, amount_to_settle)| {
-                            assert_eq!(balance, 100);
-                            assert_eq!(amount_to_settle, 0);
-                            let _ = context;
-                            Ok(())
-                        })
-                })
-        })
+        store
+            .clone()
+            .insert_account(acc)
+            .compat()
+            .and_then(move |account| {
+                let id = account.id();
+                store_clone
+                    .get_accounts(vec![id])
+                    .compat()
+                    .and_then(move |accounts| {
+                        let acc = accounts[0].clone();
+                        store_clone
+                            .clone()
+                            .update_balances_for_fulfill(acc.clone(), 100)
+                            .compat()
+                            .and_then(move |(balance, amount_to_settle)| {
+                                assert_eq!(balance, 100);
+                                assert_eq!(amount_to_settle, 0);
+                                let _ = context;
+                                Ok(())
+                            })
+                    })
+            })
     }))
     .unwrap();
 }
@@ -131,7 +152,7 @@ fn process_fulfill_settle_to_over_threshold() {
     let acc = {
         let mut acc = ACCOUNT_DETAILS_1.clone();
         acc.username = Username::from_str("charlie").unwrap();
-        acc.ilp_address = Address::from_str("example.b").unwrap();
+        acc.ilp_address = Some(Address::from_str("example.b").unwrap());
         acc.settle_to = Some(101);
         acc.settle_threshold = Some(100);
         acc.http_incoming_token = None;
@@ -141,23 +162,29 @@ fn process_fulfill_settle_to_over_threshold() {
     };
     block_on(test_store().and_then(|(store, context, _accs)| {
         let store_clone = store.clone();
-        store.clone().insert_account(acc).and_then(move |acc| {
-            let id = acc.id();
-            store_clone
-                .get_accounts(vec![id])
-                .and_then(move |accounts| {
-                    let acc = accounts[0].clone();
-                    store_clone
-                        .clone()
-                        .update_balances_for_fulfill(acc.clone(), 1000)
-                        .and_then(move |(balance, amount_to_settle)| {
-                            assert_eq!(balance, 1000);
-                            assert_eq!(amount_to_settle, 0);
-                            let _ = context;
-                            Ok(())
-                        })
-                })
-        })
+        store
+            .clone()
+            .insert_account(acc)
+            .compat()
+            .and_then(move |acc| {
+                let id = acc.id();
+                store_clone
+                    .get_accounts(vec![id])
+                    .compat()
+                    .and_then(move |accounts| {
+                        let acc = accounts[0].clone();
+                        store_clone
+                            .clone()
+                            .update_balances_for_fulfill(acc.clone(), 1000)
+                            .compat()
+                            .and_then(move |(balance, amount_to_settle)| {
+                                assert_eq!(balance, 1000);
+                                assert_eq!(amount_to_settle, 0);
+                                let _ = context;
+                                Ok(())
+                            })
+                    })
+            })
     }))
     .unwrap();
 }
@@ -168,7 +195,7 @@ fn process_fulfill_ok() {
     let acc = {
         let mut acc = ACCOUNT_DETAILS_1.clone();
         acc.username = Username::from_str("charlie").unwrap();
-        acc.ilp_address = Address::from_str("example.c").unwrap();
+        acc.ilp_address = Some(Address::from_str("example.c").unwrap());
         acc.settle_to = Some(0);
         acc.settle_threshold = Some(100);
         acc.http_incoming_token = None;
@@ -178,23 +205,29 @@ fn process_fulfill_ok() {
     };
     block_on(test_store().and_then(|(store, context, _accs)| {
         let store_clone = store.clone();
-        store.clone().insert_account(acc).and_then(move |account| {
-            let id = account.id();
-            store_clone
-                .get_accounts(vec![id])
-                .and_then(move |accounts| {
-                    let acc = accounts[0].clone();
-                    store_clone
-                        .clone()
-                        .update_balances_for_fulfill(acc.clone(), 101)
-                        .and_then(move |(balance, amount_to_settle)| {
-                            assert_eq!(balance, 0);
-                            assert_eq!(amount_to_settle, 101);
-                            let _ = context;
-                            Ok(())
-                        })
-                })
-        })
+        store
+            .clone()
+            .insert_account(acc)
+            .compat()
+            .and_then(move |account| {
+                let id = account.id();
+                store_clone
+                    .get_accounts(vec![id])
+                    .compat()
+                    .and_then(move |accounts| {
+                        let acc = accounts[0].clone();
+                        store_clone
+                            .clone()
+                            .update_balances_for_fulfill(acc.clone(), 101)
+                            .compat()
+                            .and_then(move |(balance, amount_to_settle)| {
+                                assert_eq!(balance, 0);
+                                assert_eq!(amount_to_settle, 101);
+                                let _ = context;
+                                Ok(())
+                            })
+                    })
+            })
     }))
     .unwrap();
 }
@@ -207,17 +240,25 @@ fn prepare_then_reject() {
         store
             .clone()
             .get_accounts(vec![accs[0].id(), accs[1].id()])
+            .compat()
             .map_err(|_err| panic!("Unable to get accounts"))
             .and_then(move |accounts| {
                 let account0 = accounts[0].clone();
                 let account1 = accounts[1].clone();
                 store
                     .update_balances_for_prepare(accounts[0].clone(), 100)
+                    .compat()
                     .and_then(move |_| {
                         store_clone_1
                             .clone()
                             .get_balance(accounts[0].clone())
-                            .join(store_clone_1.clone().get_balance(accounts[1].clone()))
+                            .compat()
+                            .join(
+                                store_clone_1
+                                    .clone()
+                                    .get_balance(accounts[1].clone())
+                                    .compat(),
+                            )
                             .and_then(|(balance0, balance1)| {
                                 assert_eq!(balance0, -100);
                                 assert_eq!(balance1, 0);
@@ -228,11 +269,18 @@ fn prepare_then_reject() {
                         store_clone_2
                             .clone()
                             .update_balances_for_reject(account0.clone(), 100)
+                            .compat()
                             .and_then(move |_| {
                                 store_clone_2
                                     .clone()
                                     .get_balance(account0.clone())
-                                    .join(store_clone_2.clone().get_balance(account1.clone()))
+                                    .compat()
+                                    .join(
+                                        store_clone_2
+                                            .clone()
+                                            .get_balance(account1.clone())
+                                            .compat(),
+                                    )
                                     .and_then(move |(balance0, balance1)| {
                                         assert_eq!(balance0, 0);
                                         assert_eq!(balance1, 0);
@@ -252,10 +300,12 @@ fn enforces_minimum_balance() {
         store
             .clone()
             .get_accounts(vec![accs[0].id(), accs[1].id()])
+            .compat()
             .map_err(|_err| panic!("Unable to get accounts"))
             .and_then(move |accounts| {
                 store
                     .update_balances_for_prepare(accounts[0].clone(), 10000)
+                    .compat()
                     .then(move |result| {
                         assert!(result.is_err());
                         let _ = context;
@@ -276,10 +326,12 @@ fn netting_fulfilled_balances() {
         store
             .clone()
             .insert_account(ACCOUNT_DETAILS_2.clone())
+            .compat()
             .and_then(move |acc| {
                 store
                     .clone()
                     .get_accounts(vec![accs[0].id(), acc.id()])
+                    .compat()
                     .map_err(|_err| panic!("Unable to get accounts"))
                     .and_then(move |accounts| {
                         let account0 = accounts[0].clone();
@@ -287,10 +339,15 @@ fn netting_fulfilled_balances() {
                         let account0_clone = account0.clone();
                         let account1_clone = account1.clone();
                         future::join_all(vec![
-                            Either::A(store.clone().update_balances_for_prepare(
-                                account0.clone(),
-                                100, // decrement account 0 by 100
-                            )),
+                            Either::A(
+                                store
+                                    .clone()
+                                    .update_balances_for_prepare(
+                                        account0.clone(),
+                                        100, // decrement account 0 by 100
+                                    )
+                                    .compat(),
+                            ),
                             Either::B(
                                 store
                                     .clone()
@@ -298,6 +355,7 @@ fn netting_fulfilled_balances() {
                                         account1.clone(), // increment account 1 by 100
                                         100,
                                     )
+                                    .compat()
                                     .and_then(|_| Ok(())),
                             ),
                         ])
@@ -306,12 +364,14 @@ fn netting_fulfilled_balances() {
                                 Either::A(
                                     store_clone1
                                         .clone()
-                                        .update_balances_for_prepare(account1.clone(), 80),
+                                        .update_balances_for_prepare(account1.clone(), 80)
+                                        .compat(),
                                 ),
                                 Either::B(
                                     store_clone1
                                         .clone()
                                         .update_balances_for_fulfill(account0.clone(), 80)
+                                        .compat()
                                         .and_then(|_| Ok(())),
                                 ),
                             ])
@@ -320,7 +380,8 @@ fn netting_fulfilled_balances() {
                             store_clone2
                                 .clone()
                                 .get_balance(account0_clone)
-                                .join(store_clone2.get_balance(account1_clone))
+                                .compat()
+                                .join(store_clone2.get_balance(account1_clone).compat())
                                 .and_then(move |(balance0, balance1)| {
                                     assert_eq!(balance0, -20);
                                     assert_eq!(balance1, 20);
@@ -1,6 +1,7 @@
 mod common;
 
 use common::*;
+use futures03::TryFutureExt;
 use interledger_btp::{BtpAccount, BtpStore};
 use interledger_http::HttpAccount;
 use interledger_ildcp::IldcpAccount;
@@ -13,6 +14,7 @@ fn gets_account_from_btp_auth() {
     block_on(test_store().and_then(|(store, context, _accs)| {
         store
             .get_account_from_btp_auth(&Username::from_str("bob").unwrap(), "other_btp_token")
+            .compat()
             .and_then(move |account| {
                 assert_eq!(
                     *account.client_address(),
@@ -30,6 +32,7 @@ fn decrypts_outgoing_tokens_btp() {
     block_on(test_store().and_then(|(store, context, _accs)| {
         store
             .get_account_from_btp_auth(&Username::from_str("bob").unwrap(), "other_btp_token")
+            .compat()
             .and_then(move |account| {
                 // the account is created on Dylan's connector
                 assert_eq!(
@@ -55,6 +58,7 @@ fn errors_on_unknown_btp_token() {
                 &Username::from_str("someuser").unwrap(),
                 "unknown_btp_token",
             )
+            .compat()
             .then(move |result| {
                 let _ = context;
                 result
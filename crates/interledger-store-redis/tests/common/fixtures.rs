@@ -7,7 +7,7 @@ use std::str::FromStr;
 lazy_static! {
     // We are dylan starting a connection with all these accounts
     pub static ref ACCOUNT_DETAILS_0: AccountDetails = AccountDetails {
-        ilp_address: Address::from_str("example.alice").unwrap(),
+        ilp_address: Some(Address::from_str("example.alice").unwrap()),
         username: Username::from_str("alice").unwrap(),
         asset_scale: 6,
         asset_code: "XYZ".to_string(),
@@ -27,9 +27,14 @@ lazy_static! {
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(2),
         settlement_engine_url: None,
+        route_cost: None,
+        route_export_policy: None,
+        route_import_policy: None,
+        route_auth_key: None,
+        route_weight: None,
     };
     pub static ref ACCOUNT_DETAILS_1: AccountDetails = AccountDetails {
-        ilp_address: Address::from_str("example.bob").unwrap(),
+        ilp_address: Some(Address::from_str("example.bob").unwrap()),
         username: Username::from_str("bob").unwrap(),
         asset_scale: 9,
         asset_code: "ABC".to_string(),
@@ -50,9 +55,14 @@ lazy_static! {
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(20),
         settlement_engine_url: None,
+        route_cost: None,
+        route_export_policy: None,
+        route_import_policy: None,
+        route_auth_key: None,
+        route_weight: Some(2),
     };
     pub static ref ACCOUNT_DETAILS_2: AccountDetails = AccountDetails {
-        ilp_address: Address::from_str("example.charlie").unwrap(),
+        ilp_address: Some(Address::from_str("example.charlie").unwrap()),
         username: Username::from_str("charlie").unwrap(),
         asset_scale: 9,
         asset_code: "XRP".to_string(),
@@ -72,5 +82,10 @@ lazy_static! {
         amount_per_minute_limit: None,
         packets_per_minute_limit: None,
         settlement_engine_url: None,
+        route_cost: None,
+        route_export_policy: None,
+        route_import_policy: None,
+        route_auth_key: None,
+        route_weight: None,
     };
 }
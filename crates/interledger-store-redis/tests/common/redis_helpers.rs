@@ -7,11 +7,15 @@ use std::env;
 use std::fs;
 use std::process;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use std::path::PathBuf;
 
-use futures::Future;
+use futures::{
+    future::{err, loop_fn, ok, Either, Loop},
+    Future,
+};
+use tokio_timer::Delay;
 
 use redis::RedisError;
 
@@ -165,10 +169,27 @@ impl TestContext {
         self.client.get_connection().unwrap()
     }
 
-    pub fn async_connection(&self) -> impl Future<Item = redis::aio::Connection, Error = ()> {
+    pub fn async_connection<E>(&self) -> impl Future<Item = redis::aio::Connection, Error = E> {
         self.client
             .get_async_connection()
-            .map_err(|err| panic!(err))
+            .map_err(|err| -> E { panic!(err) })
+    }
+
+    /// Waits until the given number of clients have subscribed to the channel
+    pub fn wait_for_subscribers<E>(
+        &self,
+        channel: &'static str,
+        count: usize,
+    ) -> impl Future<Item = (), Error = E> {
+        let mut con = self.connection();
+        wait_until(move || {
+            let (_, subscribers): (String, usize) = redis::cmd("PUBSUB")
+                .arg("NUMSUB")
+                .arg(channel)
+                .query(&mut con)
+                .unwrap();
+            subscribers >= count
+        })
     }
 
     pub fn stop_server(&mut self) {
@@ -181,3 +202,25 @@ impl TestContext {
         self.client.get_shared_async_connection()
     }
 }
+
+/// Polls the condition every few milliseconds until it holds, panicking if it does not
+/// within a few seconds
+pub fn wait_until<F, E>(mut condition: F) -> impl Future<Item = (), Error = E>
+where
+    F: FnMut() -> bool,
+{
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop_fn((), move |_| {
+        if condition() {
+            Either::A(ok(Loop::Break(())))
+        } else if Instant::now() >= deadline {
+            Either::A(err(()))
+        } else {
+            Either::B(
+                Delay::new(Instant::now() + Duration::from_millis(5))
+                    .then(|_| Ok(Loop::Continue(()))),
+            )
+        }
+    })
+    .map_err(|_| -> E { panic!("Timed out waiting for condition") })
+}
@@ -2,7 +2,9 @@ use super::fixtures::*;
 use super::redis_helpers::*;
 use env_logger;
 use futures::Future;
+use futures03::TryFutureExt;
 use interledger_api::NodeStore;
+use interledger_service::StoreError;
 use interledger_store_redis::{Account, RedisStore, RedisStoreBuilder};
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
@@ -12,20 +14,24 @@ lazy_static! {
     static ref TEST_MUTEX: Mutex<()> = Mutex::new(());
 }
 
-pub fn test_store() -> impl Future<Item = (RedisStore, TestContext, Vec<Account>), Error = ()> {
+pub fn test_store(
+) -> impl Future<Item = (RedisStore, TestContext, Vec<Account>), Error = StoreError> {
     let context = TestContext::new();
     RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
         .connect()
+        .map_err(|_| StoreError::Unavailable("Unable to connect to Redis".to_string()))
         .and_then(|store| {
             let store_clone = store.clone();
             let mut accs = Vec::new();
             store
                 .clone()
                 .insert_account(ACCOUNT_DETAILS_0.clone())
+                .compat()
                 .and_then(move |acc| {
                     accs.push(acc.clone());
                     store_clone
                         .insert_account(ACCOUNT_DETAILS_1.clone())
+                        .compat()
                         .and_then(move |acc| {
                             accs.push(acc.clone());
                             Ok((store, context, accs))
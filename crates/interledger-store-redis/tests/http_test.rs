@@ -1,6 +1,7 @@
 mod common;
 
 use common::*;
+use futures03::TryFutureExt;
 use interledger_btp::BtpAccount;
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_ildcp::IldcpAccount;
@@ -16,6 +17,7 @@ fn gets_account_from_http_bearer_token() {
                 &Username::from_str("alice").unwrap(),
                 "incoming_auth_token",
             )
+            .compat()
             .and_then(move |account| {
                 assert_eq!(
                     *account.client_address(),
@@ -45,6 +47,7 @@ fn decrypts_outgoing_tokens_http() {
                 &Username::from_str("alice").unwrap(),
                 "incoming_auth_token",
             )
+            .compat()
             .and_then(move |account| {
                 assert_eq!(
                     account.get_http_auth_token().unwrap(),
@@ -66,6 +69,7 @@ fn errors_on_unknown_http_auth() {
     let result = block_on(test_store().and_then(|(store, context, _accs)| {
         store
             .get_account_from_http_auth(&Username::from_str("someuser").unwrap(), "unknown_token")
+            .compat()
             .then(move |result| {
                 let _ = context;
                 result
@@ -1,6 +1,7 @@
 mod common;
 use common::*;
 use futures::future::join_all;
+use futures03::TryFutureExt;
 use interledger_service_util::{RateLimitError, RateLimitStore};
 use interledger_store_redis::AccountId;
 
@@ -9,9 +10,18 @@ fn rate_limits_number_of_packets() {
     block_on(test_store().and_then(|(store, context, _accs)| {
         let account = Account::try_from(AccountId::new(), ACCOUNT_DETAILS_0.clone()).unwrap();
         join_all(vec![
-            store.clone().apply_rate_limits(account.clone(), 10),
-            store.clone().apply_rate_limits(account.clone(), 10),
-            store.clone().apply_rate_limits(account.clone(), 10),
+            store
+                .clone()
+                .apply_rate_limits(account.clone(), 10)
+                .compat(),
+            store
+                .clone()
+                .apply_rate_limits(account.clone(), 10)
+                .compat(),
+            store
+                .clone()
+                .apply_rate_limits(account.clone(), 10)
+                .compat(),
         ])
         .then(move |result| {
             assert!(result.is_err());
@@ -28,9 +38,15 @@ fn limits_amount_throughput() {
     block_on(test_store().and_then(|(store, context, _accs)| {
         let account = Account::try_from(AccountId::new(), ACCOUNT_DETAILS_1.clone()).unwrap();
         join_all(vec![
-            store.clone().apply_rate_limits(account.clone(), 500),
-            store.clone().apply_rate_limits(account.clone(), 500),
-            store.clone().apply_rate_limits(account.clone(), 1),
+            store
+                .clone()
+                .apply_rate_limits(account.clone(), 500)
+                .compat(),
+            store
+                .clone()
+                .apply_rate_limits(account.clone(), 500)
+                .compat(),
+            store.clone().apply_rate_limits(account.clone(), 1).compat(),
         ])
         .then(move |result| {
             assert!(result.is_err());
@@ -47,8 +63,14 @@ fn refunds_throughput_limit_for_rejected_packets() {
     block_on(test_store().and_then(|(store, context, _accs)| {
         let account = Account::try_from(AccountId::new(), ACCOUNT_DETAILS_1.clone()).unwrap();
         join_all(vec![
-            store.clone().apply_rate_limits(account.clone(), 500),
-            store.clone().apply_rate_limits(account.clone(), 500),
+            store
+                .clone()
+                .apply_rate_limits(account.clone(), 500)
+                .compat(),
+            store
+                .clone()
+                .apply_rate_limits(account.clone(), 500)
+                .compat(),
         ])
         .map_err(|err| panic!(err))
         .and_then(move |_| {
@@ -57,15 +79,19 @@ fn refunds_throughput_limit_for_rejected_packets() {
             store
                 .clone()
                 .refund_throughput_limit(account.clone(), 500)
+                .compat()
+                .map_err(|err| panic!(err))
                 .and_then(move |_| {
                     store
                         .clone()
                         .apply_rate_limits(account.clone(), 500)
+                        .compat()
                         .map_err(|err| panic!(err))
                 })
                 .and_then(move |_| {
                     store_clone
                         .apply_rate_limits(account_clone, 1)
+                        .compat()
                         .then(move |result| {
                             assert!(result.is_err());
                             assert_eq!(
@@ -1,7 +1,9 @@
 mod common;
 
 use common::*;
+use futures03::TryFutureExt;
 use interledger_api::NodeStore;
+use interledger_service::StoreError;
 use interledger_service_util::ExchangeRateStore;
 use std::time::Duration;
 use tokio_timer::sleep;
@@ -14,6 +16,7 @@ fn set_rates() {
         assert!(rates.is_err());
         store
             .set_rates(vec![("ABC".to_string(), 500.0), ("XYZ".to_string(), 0.005)])
+            .compat()
             .and_then(move |_| {
                 let rates = store_clone.get_exchange_rates(&["XYZ", "ABC"]).unwrap();
                 assert_eq!(rates[0].to_string(), "0.005");
@@ -31,6 +34,7 @@ fn polls_for_rate_updates() {
         RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
             .poll_interval(1)
             .connect()
+            .map_err(|_| StoreError::Unavailable("Unable to connect to Redis".to_string()))
             .and_then(|store| {
                 assert!(store.get_exchange_rates(&["ABC", "XYZ"]).is_err());
                 store
@@ -39,6 +43,7 @@ fn polls_for_rate_updates() {
                         ("ABC".to_string(), 0.5f64),
                         ("DEF".to_string(), 9_999_999_999.0f64),
                     ])
+                    .compat()
                     .and_then(|_| sleep(Duration::from_millis(10)).then(|_| Ok(())))
                     .and_then(move |_| {
                         assert_eq!(store.get_exchange_rates(&["ABC"]).unwrap(), vec![0.5]);
@@ -54,3 +59,33 @@ fn polls_for_rate_updates() {
     )
     .unwrap();
 }
+
+#[test]
+fn other_instances_receive_rate_updates() {
+    block_on(test_store().and_then(|(store, context, _accs)| {
+        // The default poll interval is long enough that only a notification can update the rates
+        RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
+            .connect()
+            .map_err(|_| StoreError::Unavailable("Unable to connect to Redis".to_string()))
+            .and_then(move |other_store| {
+                // Wait for both stores to subscribe
+                context
+                    .wait_for_subscribers("rates:updates", 2)
+                    .and_then(move |_| store.set_rates(vec![("ABC".to_string(), 500.0)]).compat())
+                    .and_then(move |_| {
+                        let other_store_clone = other_store.clone();
+                        wait_until(move || other_store_clone.get_exchange_rates(&["ABC"]).is_ok())
+                            .map(move |_| other_store)
+                    })
+                    .and_then(move |other_store| {
+                        assert_eq!(
+                            other_store.get_exchange_rates(&["ABC"]).unwrap(),
+                            vec![500.0]
+                        );
+                        let _ = context;
+                        Ok(())
+                    })
+            })
+    }))
+    .unwrap();
+}
@@ -2,12 +2,13 @@ mod common;
 
 use bytes::Bytes;
 use common::*;
-use interledger_api::{AccountDetails, NodeStore};
+use futures03::TryFutureExt;
+use interledger_api::{AccountDetails, NodeStore, StaticNextHop};
 use interledger_ccp::RouteManagerStore;
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::Address;
-use interledger_router::RouterStore;
-use interledger_service::{Account as AccountTrait, Username};
+use interledger_router::{NextHop, RouterStore};
+use interledger_service::{Account as AccountTrait, StoreError, Username};
 use interledger_store_redis::AccountId;
 use std::str::FromStr;
 use std::{collections::HashMap, time::Duration};
@@ -20,6 +21,7 @@ fn polls_for_route_updates() {
         RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
             .poll_interval(1)
             .connect()
+            .map_err(|_| StoreError::Unavailable("Unable to connect to Redis".to_string()))
             .and_then(|store| {
                 let connection = context.async_connection();
                 assert_eq!(store.routing_table().len(), 0);
@@ -28,6 +30,7 @@ fn polls_for_route_updates() {
                 store
                     .clone()
                     .insert_account(ACCOUNT_DETAILS_0.clone())
+                    .compat()
                     .and_then(move |alice| {
                         let routing_table = store_clone_1.routing_table();
                         assert_eq!(routing_table.len(), 1);
@@ -37,7 +40,7 @@ fn polls_for_route_updates() {
                         );
                         store_clone_1
                             .insert_account(AccountDetails {
-                                ilp_address: Address::from_str("example.bob").unwrap(),
+                                ilp_address: Some(Address::from_str("example.bob").unwrap()),
                                 username: Username::from_str("bob").unwrap(),
                                 asset_scale: 6,
                                 asset_code: "XYZ".to_string(),
@@ -57,7 +60,13 @@ fn polls_for_route_updates() {
                                 amount_per_minute_limit: None,
                                 packets_per_minute_limit: None,
                                 settlement_engine_url: None,
+                                route_cost: None,
+                                route_export_policy: None,
+                                route_import_policy: None,
+                                route_auth_key: None,
+                                route_weight: None,
                             })
+                            .compat()
                             .and_then(move |bob| {
                                 let routing_table = store_clone_2.routing_table();
                                 assert_eq!(routing_table.len(), 2);
@@ -68,7 +77,6 @@ fn polls_for_route_updates() {
                                 let alice_id = alice.id();
                                 let bob_id = bob.id();
                                 connection
-                                    .map_err(|err| panic!(err))
                                     .and_then(move |connection| {
                                         redis::cmd("HMSET")
                                             .arg("routes:current")
@@ -124,6 +132,7 @@ fn gets_accounts_to_send_routes_to() {
     block_on(test_store().and_then(|(store, context, _accs)| {
         store
             .get_accounts_to_send_routes_to()
+            .compat()
             .and_then(move |accounts| {
                 assert_eq!(
                     *accounts[0].client_address(),
@@ -142,6 +151,7 @@ fn gets_accounts_to_receive_routes_from() {
     block_on(test_store().and_then(|(store, context, _accs)| {
         store
             .get_accounts_to_receive_routes_from()
+            .compat()
             .and_then(move |accounts| {
                 assert_eq!(
                     *accounts[0].client_address(),
@@ -160,6 +170,7 @@ fn gets_local_and_configured_routes() {
     block_on(test_store().and_then(|(store, context, _accs)| {
         store
             .get_local_and_configured_routes()
+            .compat()
             .and_then(move |(local, configured)| {
                 assert_eq!(local.len(), 2);
                 assert!(configured.is_empty());
@@ -184,6 +195,7 @@ fn saves_routes_to_db() {
                 (Bytes::from("example.b"), account0.clone()),
                 (Bytes::from("example.c"), account1.clone()),
             ])
+            .compat()
             .and_then(move |_| {
                 get_connection.and_then(move |connection| {
                     redis::cmd("HGETALL")
@@ -221,6 +233,7 @@ fn updates_local_routes() {
                 (Bytes::from("example.b"), account0.clone()),
                 (Bytes::from("example.c"), account1.clone()),
             ])
+            .compat()
             .and_then(move |_| {
                 let routes = store.routing_table();
                 assert_eq!(routes[&b"example.a"[..]], account0_id);
@@ -244,10 +257,20 @@ fn adds_static_routes_to_redis() {
         store
             .clone()
             .set_static_routes(vec![
-                ("example.a".to_string(), accs[0].id()),
-                ("example.b".to_string(), accs[0].id()),
-                ("example.c".to_string(), accs[1].id()),
+                (
+                    "example.a".to_string(),
+                    vec![StaticNextHop::new(accs[0].id())],
+                ),
+                (
+                    "example.b".to_string(),
+                    vec![StaticNextHop::new(accs[0].id())],
+                ),
+                (
+                    "example.c".to_string(),
+                    vec![StaticNextHop::new(accs[1].id())],
+                ),
             ])
+            .compat()
             .and_then(move |_| {
                 get_connection.and_then(|connection| {
                     redis::cmd("HGETALL")
@@ -268,6 +291,132 @@ fn adds_static_routes_to_redis() {
     .unwrap()
 }
 
+#[test]
+fn saves_weighted_static_next_hops() {
+    block_on(test_store().and_then(|(store, context, accs)| {
+        let store_clone = store.clone();
+        let get_connection = context.async_connection();
+        store
+            .set_static_next_hops(
+                "example.shared".to_string(),
+                vec![
+                    StaticNextHop::with_weight(accs[0].id(), 3),
+                    StaticNextHop::new(accs[1].id()),
+                ],
+            )
+            .compat()
+            .and_then(move |_| {
+                // The second hop gets the account's route_weight
+                let routes = store_clone.routing_table();
+                assert_eq!(
+                    routes.next_hops(b"example.shared").unwrap(),
+                    &[
+                        NextHop {
+                            account_id: accs[0].id(),
+                            weight: 3
+                        },
+                        NextHop {
+                            account_id: accs[1].id(),
+                            weight: 2
+                        }
+                    ][..]
+                );
+                get_connection.and_then(move |connection| {
+                    redis::cmd("HGET")
+                        .arg("routes:static")
+                        .arg("example.shared")
+                        .query_async(connection)
+                        .map_err(|err| panic!(err))
+                        .and_then(move |(_, primary): (_, AccountId)| {
+                            assert_eq!(primary, accs[0].id());
+                            let _ = context;
+                            Ok(())
+                        })
+                })
+            })
+    }))
+    .unwrap()
+}
+
+#[test]
+fn leaves_deleted_accounts_out_of_static_next_hops() {
+    block_on(test_store().and_then(|(store, context, accs)| {
+        let store_clone = store.clone();
+        let store_clone_2 = store.clone();
+        store
+            .set_static_next_hops(
+                "example.shared".to_string(),
+                vec![
+                    StaticNextHop::new(accs[0].id()),
+                    StaticNextHop::new(accs[1].id()),
+                ],
+            )
+            .compat()
+            .and_then(move |_| {
+                store_clone
+                    .delete_account(accs[0].id())
+                    .compat()
+                    .map(|_| accs)
+            })
+            .and_then(move |accs| {
+                store_clone_2
+                    .set_static_route("example.other".to_string(), accs[1].id())
+                    .compat()
+                    .map(move |_| (store_clone_2, accs))
+            })
+            .and_then(move |(store, accs)| {
+                let routes = store.routing_table();
+                assert_eq!(
+                    routes.next_hops(b"example.shared").unwrap(),
+                    &[NextHop {
+                        account_id: accs[1].id(),
+                        weight: 2
+                    }][..]
+                );
+                let _ = context;
+                Ok(())
+            })
+    }))
+    .unwrap()
+}
+
+#[test]
+fn other_instances_receive_static_route_updates() {
+    block_on(test_store().and_then(|(store, context, accs)| {
+        // The default poll interval is long enough that only a notification can update the routes
+        RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
+            .connect()
+            .map_err(|_| StoreError::Unavailable("Unable to connect to Redis".to_string()))
+            .and_then(move |other_store| {
+                // Wait for both stores to subscribe
+                context
+                    .wait_for_subscribers("routes:updates", 2)
+                    .and_then(move |_| {
+                        store
+                            .set_static_route("example.d".to_string(), accs[1].id())
+                            .compat()
+                            .map(move |_| accs)
+                    })
+                    .and_then(move |accs| {
+                        let other_store_clone = other_store.clone();
+                        wait_until(move || {
+                            other_store_clone
+                                .routing_table()
+                                .contains_key(&b"example.d"[..])
+                        })
+                        .map(move |_| (other_store, accs))
+                    })
+                    .and_then(move |(other_store, accs)| {
+                        let routes = other_store.routing_table();
+                        assert_eq!(routes[&b"example.d"[..]], accs[1].id());
+                        let _ = context;
+                        Ok(())
+                    })
+            })
+    }))
+    .unwrap()
+}
+
 #[test]
 fn static_routes_override_others() {
     block_on(test_store().and_then(|(store, context, accs)| {
@@ -275,9 +424,16 @@ fn static_routes_override_others() {
         store
             .clone()
             .set_static_routes(vec![
-                ("example.a".to_string(), accs[0].id()),
-                ("example.b".to_string(), accs[0].id()),
+                (
+                    "example.a".to_string(),
+                    vec![StaticNextHop::new(accs[0].id())],
+                ),
+                (
+                    "example.b".to_string(),
+                    vec![StaticNextHop::new(accs[0].id())],
+                ),
             ])
+            .compat()
             .and_then(move |_| {
                 let account1_id = AccountId::new();
                 let account1 = Account::try_from(account1_id, ACCOUNT_DETAILS_1.clone()).unwrap();
@@ -287,6 +443,7 @@ fn static_routes_override_others() {
                         (Bytes::from("example.b"), account1.clone()),
                         (Bytes::from("example.c"), account1),
                     ])
+                    .compat()
                     .and_then(move |_| {
                         let routes = store.routing_table();
                         assert_eq!(routes[&b"example.a"[..]], accs[0].id());
@@ -307,10 +464,17 @@ fn returns_configured_routes_for_route_manager() {
         store
             .clone()
             .set_static_routes(vec![
-                ("example.a".to_string(), accs[0].id()),
-                ("example.b".to_string(), accs[1].id()),
+                (
+                    "example.a".to_string(),
+                    vec![StaticNextHop::new(accs[0].id())],
+                ),
+                (
+                    "example.b".to_string(),
+                    vec![StaticNextHop::new(accs[1].id())],
+                ),
             ])
-            .and_then(move |_| store.get_local_and_configured_routes())
+            .compat()
+            .and_then(move |_| store.get_local_and_configured_routes().compat())
             .and_then(move |(_local, configured)| {
                 assert_eq!(configured.len(), 2);
                 assert_eq!(configured[&b"example.a"[..]].id(), accs[0].id());
@@ -321,3 +485,39 @@ fn returns_configured_routes_for_route_manager() {
     }))
     .unwrap()
 }
+
+#[test]
+fn saves_forwarding_table() {
+    block_on(test_store().and_then(|(mut store, context, _accs)| {
+        let store_clone = store.clone();
+        let mut other_store = store.clone();
+        store
+            .get_forwarding_table("example.node")
+            .compat()
+            .and_then(move |table| {
+                assert!(table.is_none());
+                store
+                    .set_forwarding_table("example.node", Bytes::from("table"))
+                    .compat()
+            })
+            .and_then(move |_| {
+                // Another node sharing the database keeps its own table
+                other_store
+                    .set_forwarding_table("example.other", Bytes::from("other table"))
+                    .compat()
+            })
+            .and_then(move |_| {
+                store_clone
+                    .get_forwarding_table("example.node")
+                    .compat()
+                    .join(store_clone.get_forwarding_table("example.other").compat())
+            })
+            .and_then(move |(table, other_table)| {
+                assert_eq!(table, Some(Bytes::from("table")));
+                assert_eq!(other_table, Some(Bytes::from("other table")));
+                let _ = context;
+                Ok(())
+            })
+    }))
+    .unwrap()
+}
@@ -2,6 +2,7 @@ mod common;
 
 use bytes::Bytes;
 use common::*;
+use futures03::TryFutureExt;
 use http::StatusCode;
 use interledger_service::Account;
 use interledger_settlement::{IdempotentStore, SettlementStore};
@@ -14,93 +15,124 @@ lazy_static! {
 
 #[test]
 fn credits_prepaid_amount() {
-    block_on(test_store().and_then(|(store, context, accs)| {
-        let id = accs[0].id();
-        context.async_connection().and_then(move |conn| {
-            store
-                .update_balance_for_incoming_settlement(id, 100, Some(IDEMPOTENCY_KEY.clone()))
-                .and_then(move |_| {
-                    cmd("HMGET")
-                        .arg(format!("accounts:{}", id))
-                        .arg("balance")
-                        .arg("prepaid_amount")
-                        .query_async(conn)
-                        .map_err(|err| eprintln!("Redis error: {:?}", err))
-                        .and_then(move |(_conn, (balance, prepaid_amount)): (_, (i64, i64))| {
-                            assert_eq!(balance, 0);
-                            assert_eq!(prepaid_amount, 100);
-                            let _ = context;
-                            Ok(())
+    block_on(
+        test_store()
+            .map_err(|err| eprintln!("Store error: {:?}", err))
+            .and_then(|(store, context, accs)| {
+                let id = accs[0].id();
+                context.async_connection().and_then(move |conn| {
+                    store
+                        .update_balance_for_incoming_settlement(
+                            id,
+                            100,
+                            Some(IDEMPOTENCY_KEY.clone()),
+                        )
+                        .compat()
+                        .map_err(|err| eprintln!("Store error: {:?}", err))
+                        .and_then(move |_| {
+                            cmd("HMGET")
+                                .arg(format!("accounts:{}", id))
+                                .arg("balance")
+                                .arg("prepaid_amount")
+                                .query_async(conn)
+                                .map_err(|err| eprintln!("Redis error: {:?}", err))
+                                .and_then(
+                                    move |(_conn, (balance, prepaid_amount)): (_, (i64, i64))| {
+                                        assert_eq!(balance, 0);
+                                        assert_eq!(prepaid_amount, 100);
+                                        let _ = context;
+                                        Ok(())
+                                    },
+                                )
                         })
                 })
-        })
-    }))
+            }),
+    )
     .unwrap()
 }
 
 #[test]
 fn saves_and_loads_idempotency_key_data_properly() {
-    block_on(test_store().and_then(|(store, context, _accs)| {
-        let input_hash: [u8; 32] = Default::default();
-        store
-            .save_idempotent_data(
-                IDEMPOTENCY_KEY.clone(),
-                input_hash,
-                StatusCode::OK,
-                Bytes::from("TEST"),
-            )
-            .map_err(|err| eprintln!("Redis error: {:?}", err))
-            .and_then(move |_| {
+    block_on(
+        test_store()
+            .map_err(|err| eprintln!("Store error: {:?}", err))
+            .and_then(|(store, context, _accs)| {
+                let input_hash: [u8; 32] = Default::default();
                 store
-                    .load_idempotent_data(IDEMPOTENCY_KEY.clone())
+                    .save_idempotent_data(
+                        IDEMPOTENCY_KEY.clone(),
+                        input_hash,
+                        StatusCode::OK,
+                        Bytes::from("TEST"),
+                    )
+                    .compat()
                     .map_err(|err| eprintln!("Redis error: {:?}", err))
-                    .and_then(move |data1| {
-                        assert_eq!(
-                            data1.unwrap(),
-                            (StatusCode::OK, Bytes::from("TEST"), input_hash)
-                        );
-                        let _ = context;
-
+                    .and_then(move |_| {
                         store
-                            .load_idempotent_data("asdf".to_string())
+                            .load_idempotent_data(IDEMPOTENCY_KEY.clone())
+                            .compat()
                             .map_err(|err| eprintln!("Redis error: {:?}", err))
-                            .and_then(move |data2| {
-                                assert!(data2.is_none());
+                            .and_then(move |data1| {
+                                assert_eq!(
+                                    data1.unwrap(),
+                                    (StatusCode::OK, Bytes::from("TEST"), input_hash)
+                                );
                                 let _ = context;
-                                Ok(())
+
+                                store
+                                    .load_idempotent_data("asdf".to_string())
+                                    .compat()
+                                    .map_err(|err| eprintln!("Redis error: {:?}", err))
+                                    .and_then(move |data2| {
+                                        assert!(data2.is_none());
+                                        let _ = context;
+                                        Ok(())
+                                    })
                             })
                     })
-            })
-    }))
+            }),
+    )
     .unwrap();
 }
 
 #[test]
 fn idempotent_settlement_calls() {
-    block_on(test_store().and_then(|(store, context, accs)| {
-        let id = accs[0].id();
-        context.async_connection().and_then(move |conn| {
-            store
-                .update_balance_for_incoming_settlement(id, 100, Some(IDEMPOTENCY_KEY.clone()))
-                .and_then(move |_| {
-                    cmd("HMGET")
-                        .arg(format!("accounts:{}", id))
-                        .arg("balance")
-                        .arg("prepaid_amount")
-                        .query_async(conn)
-                        .map_err(|err| eprintln!("Redis error: {:?}", err))
-                        .and_then(move |(conn, (balance, prepaid_amount)): (_, (i64, i64))| {
-                            assert_eq!(balance, 0);
-                            assert_eq!(prepaid_amount, 100);
+    block_on(
+        test_store()
+            .map_err(|err| eprintln!("Store error: {:?}", err))
+            .and_then(|(store, context, accs)| {
+                let id = accs[0].id();
+                context.async_connection().and_then(move |conn| {
+                    store
+                        .update_balance_for_incoming_settlement(
+                            id,
+                            100,
+                            Some(IDEMPOTENCY_KEY.clone()),
+                        )
+                        .compat()
+                        .map_err(|err| eprintln!("Store error: {:?}", err))
+                        .and_then(move |_| {
+                            cmd("HMGET")
+                                .arg(format!("accounts:{}", id))
+                                .arg("balance")
+                                .arg("prepaid_amount")
+                                .query_async(conn)
+                                .map_err(|err| eprintln!("Redis error: {:?}", err))
+                                .and_then(
+                                    move |(conn, (balance, prepaid_amount)): (_, (i64, i64))| {
+                                        assert_eq!(balance, 0);
+                                        assert_eq!(prepaid_amount, 100);
 
-                            store
-                                .update_balance_for_incoming_settlement(
-                                    id,
-                                    100,
-                                    Some(IDEMPOTENCY_KEY.clone()), // Reuse key to make idempotent request.
-                                )
-                                .and_then(move |_| {
-                                    cmd("HMGET")
+                                        store
+                                            .update_balance_for_incoming_settlement(
+                                                id,
+                                                100,
+                                                Some(IDEMPOTENCY_KEY.clone()), // Reuse key to make idempotent request.
+                                            )
+                                            .compat()
+                                            .map_err(|err| eprintln!("Store error: {:?}", err))
+                                            .and_then(move |_| {
+                                                cmd("HMGET")
                                         .arg(format!("accounts:{}", id))
                                         .arg("balance")
                                         .arg("prepaid_amount")
@@ -120,148 +152,168 @@ fn idempotent_settlement_calls() {
                                                 Ok(())
                                             },
                                         )
-                                })
+                                            })
+                                    },
+                                )
                         })
                 })
-        })
-    }))
+            }),
+    )
     .unwrap()
 }
 
 #[test]
 fn credits_balance_owed() {
-    block_on(test_store().and_then(|(store, context, accs)| {
-        let id = accs[0].id();
-        context
-            .shared_async_connection()
-            .map_err(|err| panic!(err))
-            .and_then(move |conn| {
-                cmd("HSET")
-                    .arg(format!("accounts:{}", id))
-                    .arg("balance")
-                    .arg(-200)
-                    .query_async(conn)
+    block_on(
+        test_store()
+            .map_err(|err| eprintln!("Store error: {:?}", err))
+            .and_then(|(store, context, accs)| {
+                let id = accs[0].id();
+                context
+                    .shared_async_connection()
                     .map_err(|err| panic!(err))
-                    .and_then(move |(conn, _balance): (SharedConnection, i64)| {
-                        store
-                            .update_balance_for_incoming_settlement(
-                                id,
-                                100,
-                                Some(IDEMPOTENCY_KEY.clone()),
-                            )
-                            .and_then(move |_| {
-                                cmd("HMGET")
-                                    .arg(format!("accounts:{}", id))
-                                    .arg("balance")
-                                    .arg("prepaid_amount")
-                                    .query_async(conn)
-                                    .map_err(|err| panic!(err))
-                                    .and_then(
-                                        move |(_conn, (balance, prepaid_amount)): (
-                                            _,
-                                            (i64, i64),
-                                        )| {
-                                            assert_eq!(balance, -100);
-                                            assert_eq!(prepaid_amount, 0);
-                                            let _ = context;
-                                            Ok(())
-                                        },
+                    .and_then(move |conn| {
+                        cmd("HSET")
+                            .arg(format!("accounts:{}", id))
+                            .arg("balance")
+                            .arg(-200)
+                            .query_async(conn)
+                            .map_err(|err| panic!(err))
+                            .and_then(move |(conn, _balance): (SharedConnection, i64)| {
+                                store
+                                    .update_balance_for_incoming_settlement(
+                                        id,
+                                        100,
+                                        Some(IDEMPOTENCY_KEY.clone()),
                                     )
+                                    .compat()
+                                    .map_err(|err| eprintln!("Store error: {:?}", err))
+                                    .and_then(move |_| {
+                                        cmd("HMGET")
+                                            .arg(format!("accounts:{}", id))
+                                            .arg("balance")
+                                            .arg("prepaid_amount")
+                                            .query_async(conn)
+                                            .map_err(|err| panic!(err))
+                                            .and_then(
+                                                move |(_conn, (balance, prepaid_amount)): (
+                                                    _,
+                                                    (i64, i64),
+                                                )| {
+                                                    assert_eq!(balance, -100);
+                                                    assert_eq!(prepaid_amount, 0);
+                                                    let _ = context;
+                                                    Ok(())
+                                                },
+                                            )
+                                    })
                             })
                     })
-            })
-    }))
+            }),
+    )
     .unwrap()
 }
 
 #[test]
 fn clears_balance_owed() {
-    block_on(test_store().and_then(|(store, context, accs)| {
-        let id = accs[0].id();
-        context
-            .shared_async_connection()
-            .map_err(|err| panic!(err))
-            .and_then(move |conn| {
-                cmd("HSET")
-                    .arg(format!("accounts:{}", id))
-                    .arg("balance")
-                    .arg(-100)
-                    .query_async(conn)
+    block_on(
+        test_store()
+            .map_err(|err| eprintln!("Store error: {:?}", err))
+            .and_then(|(store, context, accs)| {
+                let id = accs[0].id();
+                context
+                    .shared_async_connection()
                     .map_err(|err| panic!(err))
-                    .and_then(move |(conn, _balance): (SharedConnection, i64)| {
-                        store
-                            .update_balance_for_incoming_settlement(
-                                id,
-                                100,
-                                Some(IDEMPOTENCY_KEY.clone()),
-                            )
-                            .and_then(move |_| {
-                                cmd("HMGET")
-                                    .arg(format!("accounts:{}", id))
-                                    .arg("balance")
-                                    .arg("prepaid_amount")
-                                    .query_async(conn)
-                                    .map_err(|err| panic!(err))
-                                    .and_then(
-                                        move |(_conn, (balance, prepaid_amount)): (
-                                            _,
-                                            (i64, i64),
-                                        )| {
-                                            assert_eq!(balance, 0);
-                                            assert_eq!(prepaid_amount, 0);
-                                            let _ = context;
-                                            Ok(())
-                                        },
+                    .and_then(move |conn| {
+                        cmd("HSET")
+                            .arg(format!("accounts:{}", id))
+                            .arg("balance")
+                            .arg(-100)
+                            .query_async(conn)
+                            .map_err(|err| panic!(err))
+                            .and_then(move |(conn, _balance): (SharedConnection, i64)| {
+                                store
+                                    .update_balance_for_incoming_settlement(
+                                        id,
+                                        100,
+                                        Some(IDEMPOTENCY_KEY.clone()),
                                     )
+                                    .compat()
+                                    .map_err(|err| eprintln!("Store error: {:?}", err))
+                                    .and_then(move |_| {
+                                        cmd("HMGET")
+                                            .arg(format!("accounts:{}", id))
+                                            .arg("balance")
+                                            .arg("prepaid_amount")
+                                            .query_async(conn)
+                                            .map_err(|err| panic!(err))
+                                            .and_then(
+                                                move |(_conn, (balance, prepaid_amount)): (
+                                                    _,
+                                                    (i64, i64),
+                                                )| {
+                                                    assert_eq!(balance, 0);
+                                                    assert_eq!(prepaid_amount, 0);
+                                                    let _ = context;
+                                                    Ok(())
+                                                },
+                                            )
+                                    })
                             })
                     })
-            })
-    }))
+            }),
+    )
     .unwrap()
 }
 
 #[test]
 fn clears_balance_owed_and_puts_remainder_as_prepaid() {
-    block_on(test_store().and_then(|(store, context, accs)| {
-        let id = accs[0].id();
-        context
-            .shared_async_connection()
-            .map_err(|err| panic!(err))
-            .and_then(move |conn| {
-                cmd("HSET")
-                    .arg(format!("accounts:{}", id))
-                    .arg("balance")
-                    .arg(-40)
-                    .query_async(conn)
+    block_on(
+        test_store()
+            .map_err(|err| eprintln!("Store error: {:?}", err))
+            .and_then(|(store, context, accs)| {
+                let id = accs[0].id();
+                context
+                    .shared_async_connection()
                     .map_err(|err| panic!(err))
-                    .and_then(move |(conn, _balance): (SharedConnection, i64)| {
-                        store
-                            .update_balance_for_incoming_settlement(
-                                id,
-                                100,
-                                Some(IDEMPOTENCY_KEY.clone()),
-                            )
-                            .and_then(move |_| {
-                                cmd("HMGET")
-                                    .arg(format!("accounts:{}", id))
-                                    .arg("balance")
-                                    .arg("prepaid_amount")
-                                    .query_async(conn)
-                                    .map_err(|err| panic!(err))
-                                    .and_then(
-                                        move |(_conn, (balance, prepaid_amount)): (
-                                            _,
-                                            (i64, i64),
-                                        )| {
-                                            assert_eq!(balance, 0);
-                                            assert_eq!(prepaid_amount, 60);
-                                            let _ = context;
-                                            Ok(())
-                                        },
+                    .and_then(move |conn| {
+                        cmd("HSET")
+                            .arg(format!("accounts:{}", id))
+                            .arg("balance")
+                            .arg(-40)
+                            .query_async(conn)
+                            .map_err(|err| panic!(err))
+                            .and_then(move |(conn, _balance): (SharedConnection, i64)| {
+                                store
+                                    .update_balance_for_incoming_settlement(
+                                        id,
+                                        100,
+                                        Some(IDEMPOTENCY_KEY.clone()),
                                     )
+                                    .compat()
+                                    .map_err(|err| eprintln!("Store error: {:?}", err))
+                                    .and_then(move |_| {
+                                        cmd("HMGET")
+                                            .arg(format!("accounts:{}", id))
+                                            .arg("balance")
+                                            .arg("prepaid_amount")
+                                            .query_async(conn)
+                                            .map_err(|err| panic!(err))
+                                            .and_then(
+                                                move |(_conn, (balance, prepaid_amount)): (
+                                                    _,
+                                                    (i64, i64),
+                                                )| {
+                                                    assert_eq!(balance, 0);
+                                                    assert_eq!(prepaid_amount, 60);
+                                                    let _ = context;
+                                                    Ok(())
+                                                },
+                                            )
+                                    })
                             })
                     })
-            })
-    }))
+            }),
+    )
     .unwrap()
 }
@@ -0,0 +1,502 @@
+//! A thin wrapper around the supported database clients so that the store's
+//! queries can be written once and run against either PostgreSQL or SQLite.
+//!
+//! Queries use Postgres-style numbered placeholders (`$1`, `$2`, ...). These are
+//! rewritten to SQLite's `?1`, `?2`, ... form before being sent to SQLite.
+
+use futures::{future::poll_fn, Async, Future};
+use interledger_service::StoreError;
+use log::trace;
+#[cfg(feature = "postgres")]
+use postgres_client::{
+    error::SqlState,
+    types::{private::BytesMut, to_sql_checked, IsNull, ToSql, Type},
+    Config as PostgresConfig, NoTls,
+};
+#[cfg(feature = "postgres")]
+use r2d2_postgres::PostgresConnectionManager;
+#[cfg(feature = "sqlite")]
+use r2d2_sqlite::SqliteConnectionManager;
+use std::{error::Error, fmt};
+
+/// A single value that can be passed as a query parameter or read out of a row.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Real(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(value: Vec<u8>) -> Self {
+        Value::Blob(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+/// One row returned by a query, with the values in the order they were selected.
+#[derive(Debug)]
+pub(crate) struct Row(Vec<Value>);
+
+fn unexpected(index: usize, value: &Value, expected: &str) -> StoreError {
+    StoreError::Corrupt(format!(
+        "Expected {} in column {} but found {:?}",
+        expected, index, value
+    ))
+}
+
+impl From<Vec<Value>> for Row {
+    fn from(values: Vec<Value>) -> Self {
+        Row(values)
+    }
+}
+
+impl Row {
+    fn value(&self, index: usize) -> Result<&Value, StoreError> {
+        self.0
+            .get(index)
+            .ok_or_else(|| StoreError::Corrupt(format!("Row is missing column {}", index)))
+    }
+
+    pub fn get_opt_i64(&self, index: usize) -> Result<Option<i64>, StoreError> {
+        match self.value(index)? {
+            Value::Null => Ok(None),
+            Value::Integer(value) => Ok(Some(*value)),
+            value => Err(unexpected(index, value, "an integer")),
+        }
+    }
+
+    pub fn get_i64(&self, index: usize) -> Result<i64, StoreError> {
+        self.get_opt_i64(index)?
+            .ok_or_else(|| unexpected(index, &Value::Null, "an integer"))
+    }
+
+    pub fn get_f64(&self, index: usize) -> Result<f64, StoreError> {
+        match self.value(index)? {
+            Value::Real(value) => Ok(*value),
+            Value::Integer(value) => Ok(*value as f64),
+            value => Err(unexpected(index, value, "a float")),
+        }
+    }
+
+    /// SQLite does not have a boolean type so booleans come back from it as integers.
+    pub fn get_bool(&self, index: usize) -> Result<bool, StoreError> {
+        match self.value(index)? {
+            Value::Bool(value) => Ok(*value),
+            Value::Integer(value) => Ok(*value != 0),
+            value => Err(unexpected(index, value, "a boolean")),
+        }
+    }
+
+    pub fn get_opt_string(&self, index: usize) -> Result<Option<String>, StoreError> {
+        match self.value(index)? {
+            Value::Null => Ok(None),
+            Value::Text(value) => Ok(Some(value.clone())),
+            value => Err(unexpected(index, value, "text")),
+        }
+    }
+
+    pub fn get_string(&self, index: usize) -> Result<String, StoreError> {
+        self.get_opt_string(index)?
+            .ok_or_else(|| unexpected(index, &Value::Null, "text"))
+    }
+
+    pub fn get_opt_bytes(&self, index: usize) -> Result<Option<Vec<u8>>, StoreError> {
+        match self.value(index)? {
+            Value::Null => Ok(None),
+            Value::Blob(value) => Ok(Some(value.clone())),
+            value => Err(unexpected(index, value, "binary data")),
+        }
+    }
+
+    pub fn get_bytes(&self, index: usize) -> Result<Vec<u8>, StoreError> {
+        self.get_opt_bytes(index)?
+            .ok_or_else(|| unexpected(index, &Value::Null, "binary data"))
+    }
+}
+
+/// A pool of connections to one of the supported databases.
+#[derive(Clone)]
+pub(crate) enum Pool {
+    #[cfg(feature = "postgres")]
+    Postgres(r2d2::Pool<PostgresConnectionManager<NoTls>>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(r2d2::Pool<SqliteConnectionManager>),
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "postgres")]
+            Pool::Postgres(pool) => write!(f, "Pool::Postgres({:?})", pool.state()),
+            #[cfg(feature = "sqlite")]
+            Pool::Sqlite(pool) => write!(f, "Pool::Sqlite({:?})", pool.state()),
+        }
+    }
+}
+
+impl Pool {
+    /// Open a pool of connections to the database at the given URL.
+    ///
+    /// Supported URLs are `postgres://...` (or `postgresql://...`),
+    /// `sqlite://<path>` and `sqlite::memory:`.
+    pub fn connect(database_url: &str, max_size: u32) -> Result<Pool, StoreError> {
+        let unavailable = |err: &dyn fmt::Display| StoreError::Unavailable(format!("{}", err));
+
+        #[cfg(feature = "postgres")]
+        {
+            if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://")
+            {
+                let config: PostgresConfig = database_url
+                    .parse()
+                    .map_err(|err| StoreError::Invalid(format!("Invalid Postgres URL: {}", err)))?;
+                let manager = PostgresConnectionManager::new(config, NoTls);
+                return r2d2::Pool::builder()
+                    .max_size(max_size)
+                    .build(manager)
+                    .map(Pool::Postgres)
+                    .map_err(|err| unavailable(&err));
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        {
+            let init = |conn: &mut rusqlite::Connection| {
+                conn.busy_timeout(std::time::Duration::from_secs(5))
+            };
+            if database_url == "sqlite::memory:" {
+                // Every connection to an in-memory database gets its own empty database,
+                // so all queries have to go through a single connection
+                let manager = SqliteConnectionManager::memory().with_init(init);
+                return r2d2::Pool::builder()
+                    .max_size(1)
+                    .build(manager)
+                    .map(Pool::Sqlite)
+                    .map_err(|err| unavailable(&err));
+            } else if let Some(path) = database_url.strip_prefix("sqlite://") {
+                let manager = SqliteConnectionManager::file(path).with_init(init);
+                return r2d2::Pool::builder()
+                    .max_size(max_size)
+                    .build(manager)
+                    .map(Pool::Sqlite)
+                    .map_err(|err| unavailable(&err));
+            }
+        }
+
+        Err(StoreError::Invalid(format!(
+            "Unsupported database URL: {}",
+            database_url
+        )))
+    }
+
+    /// The name of the column type used to store binary data
+    pub fn binary_type(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "postgres")]
+            Pool::Postgres(_) => "BYTEA",
+            #[cfg(feature = "sqlite")]
+            Pool::Sqlite(_) => "BLOB",
+        }
+    }
+
+    pub fn get(&self) -> Result<Connection, StoreError> {
+        let unavailable = |err: r2d2::Error| {
+            StoreError::Unavailable(format!("Unable to get a database connection: {}", err))
+        };
+        match self {
+            #[cfg(feature = "postgres")]
+            Pool::Postgres(pool) => pool.get().map(Connection::Postgres).map_err(unavailable),
+            #[cfg(feature = "sqlite")]
+            Pool::Sqlite(pool) => pool.get().map(Connection::Sqlite).map_err(unavailable),
+        }
+    }
+}
+
+/// A connection checked out of the `Pool`. It is returned to the pool when dropped.
+pub(crate) enum Connection {
+    #[cfg(feature = "postgres")]
+    Postgres(r2d2::PooledConnection<PostgresConnectionManager<NoTls>>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(r2d2::PooledConnection<SqliteConnectionManager>),
+}
+
+impl Connection {
+    /// Run one or more statements that do not take any parameters
+    pub fn batch_execute(&mut self, sql: &str) -> Result<(), StoreError> {
+        trace!("Executing: {}", sql);
+        match self {
+            #[cfg(feature = "postgres")]
+            Connection::Postgres(conn) => conn.batch_execute(sql).map_err(postgres_error),
+            #[cfg(feature = "sqlite")]
+            Connection::Sqlite(conn) => conn.execute_batch(sql).map_err(sqlite_error),
+        }
+    }
+
+    /// Run a single statement and return the number of rows it modified
+    pub fn execute(&mut self, sql: &str, params: &[Value]) -> Result<u64, StoreError> {
+        trace!("Executing: {} with params: {:?}", sql, params);
+        match self {
+            #[cfg(feature = "postgres")]
+            Connection::Postgres(conn) => {
+                let params: Vec<&(dyn ToSql + Sync)> =
+                    params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+                conn.execute(sql, &params[..]).map_err(postgres_error)
+            }
+            #[cfg(feature = "sqlite")]
+            Connection::Sqlite(conn) => conn
+                .execute(&sql.replace('$', "?"), params)
+                .map(|rows| rows as u64)
+                .map_err(sqlite_error),
+        }
+    }
+
+    /// Run a query and return all of the rows it selected
+    pub fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Row>, StoreError> {
+        trace!("Querying: {} with params: {:?}", sql, params);
+        match self {
+            #[cfg(feature = "postgres")]
+            Connection::Postgres(conn) => {
+                let params: Vec<&(dyn ToSql + Sync)> =
+                    params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+                conn.query(sql, &params[..])
+                    .map_err(postgres_error)?
+                    .iter()
+                    .map(postgres_row)
+                    .collect()
+            }
+            #[cfg(feature = "sqlite")]
+            Connection::Sqlite(conn) => {
+                let mut statement = conn.prepare(&sql.replace('$', "?")).map_err(sqlite_error)?;
+                let columns = statement.column_count();
+                let mut rows = statement.query(params).map_err(sqlite_error)?;
+                let mut results = Vec::new();
+                while let Some(row) = rows.next().map_err(sqlite_error)? {
+                    results.push(sqlite_row(row, columns)?);
+                }
+                Ok(results)
+            }
+        }
+    }
+
+    /// Run the given closure inside of a database transaction.
+    ///
+    /// The transaction is committed if the closure returns `Ok` and rolled back otherwise.
+    /// SQLite transactions take the database's write lock immediately so that concurrent
+    /// read-modify-write operations (such as balance updates) cannot interleave.
+    pub fn transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, E>,
+        E: From<StoreError>,
+    {
+        let begin = match self {
+            #[cfg(feature = "postgres")]
+            Connection::Postgres(_) => "BEGIN",
+            #[cfg(feature = "sqlite")]
+            Connection::Sqlite(_) => "BEGIN IMMEDIATE",
+        };
+        self.batch_execute(begin)?;
+        match f(self) {
+            Ok(result) => {
+                self.batch_execute("COMMIT")?;
+                Ok(result)
+            }
+            Err(err) => {
+                // The original error is more useful than any error from rolling back
+                let _ = self.batch_execute("ROLLBACK");
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Run a closure that makes (synchronous) database calls without blocking the executor.
+///
+/// When called from a Tokio threadpool the closure is run using `tokio_threadpool::blocking`
+/// so that other tasks can be moved to another thread in the meantime. Otherwise (for
+/// example when the future is driven by `block_on`) it is simply run on the current thread.
+pub(crate) fn blocking<F, T>(f: F) -> impl Future<Item = T, Error = StoreError>
+where
+    F: FnOnce() -> Result<T, StoreError>,
+{
+    let mut f = Some(f);
+    poll_fn(move || {
+        match tokio_threadpool::blocking(|| (f.take().expect("polled after completion"))()) {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => (f.take().expect("polled after completion"))().map(Async::Ready),
+        }
+    })
+}
+
+#[cfg(feature = "postgres")]
+impl ToSql for Value {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        match self {
+            Value::Null => Ok(IsNull::Yes),
+            Value::Bool(value) => value.to_sql(ty, out),
+            Value::Integer(value) => value.to_sql(ty, out),
+            Value::Real(value) => value.to_sql(ty, out),
+            Value::Text(value) => value.to_sql(ty, out),
+            Value::Blob(value) => value.to_sql(ty, out),
+        }
+    }
+
+    // Only the column types the store's values are written to
+    fn accepts(ty: &Type) -> bool {
+        <bool as ToSql>::accepts(ty)
+            || <i64 as ToSql>::accepts(ty)
+            || <f64 as ToSql>::accepts(ty)
+            || <String as ToSql>::accepts(ty)
+            || <Vec<u8> as ToSql>::accepts(ty)
+    }
+
+    to_sql_checked!();
+}
+
+#[cfg(feature = "postgres")]
+fn postgres_error(err: postgres_client::Error) -> StoreError {
+    if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+        StoreError::Conflict(format!("{}", err))
+    } else {
+        StoreError::Unavailable(format!("{}", err))
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn postgres_row(row: &postgres_client::Row) -> Result<Row, StoreError> {
+    let corrupt = |err: postgres_client::Error| StoreError::Corrupt(format!("{}", err));
+    let mut values = Vec::with_capacity(row.len());
+    for (index, column) in row.columns().iter().enumerate() {
+        let value = match *column.type_() {
+            Type::BOOL => row
+                .try_get::<_, Option<bool>>(index)
+                .map_err(corrupt)?
+                .into(),
+            Type::INT2 => row
+                .try_get::<_, Option<i16>>(index)
+                .map_err(corrupt)?
+                .map(i64::from)
+                .into(),
+            Type::INT4 => row
+                .try_get::<_, Option<i32>>(index)
+                .map_err(corrupt)?
+                .map(i64::from)
+                .into(),
+            Type::INT8 => row
+                .try_get::<_, Option<i64>>(index)
+                .map_err(corrupt)?
+                .into(),
+            Type::FLOAT8 => row
+                .try_get::<_, Option<f64>>(index)
+                .map_err(corrupt)?
+                .into(),
+            Type::TEXT | Type::VARCHAR => row
+                .try_get::<_, Option<String>>(index)
+                .map_err(corrupt)?
+                .into(),
+            Type::BYTEA => row
+                .try_get::<_, Option<Vec<u8>>>(index)
+                .map_err(corrupt)?
+                .into(),
+            ref other => {
+                return Err(StoreError::Corrupt(format!(
+                    "Unsupported column type {} for column {}",
+                    other,
+                    column.name()
+                )))
+            }
+        };
+        values.push(value);
+    }
+    Ok(Row(values))
+}
+
+#[cfg(feature = "sqlite")]
+impl rusqlite::ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        use rusqlite::types::{ToSqlOutput, Value as SqliteValue};
+        let value = match self {
+            Value::Null => SqliteValue::Null,
+            Value::Bool(value) => SqliteValue::Integer(*value as i64),
+            Value::Integer(value) => SqliteValue::Integer(*value),
+            Value::Real(value) => SqliteValue::Real(*value),
+            Value::Text(value) => SqliteValue::Text(value.clone()),
+            Value::Blob(value) => SqliteValue::Blob(value.clone()),
+        };
+        Ok(ToSqlOutput::Owned(value))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_error(err: rusqlite::Error) -> StoreError {
+    match err {
+        rusqlite::Error::SqliteFailure(ref code, _)
+            if code.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            StoreError::Conflict(format!("{}", err))
+        }
+        _ => StoreError::Unavailable(format!("{}", err)),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_row(row: &rusqlite::Row, columns: usize) -> Result<Row, StoreError> {
+    use rusqlite::types::ValueRef;
+    let mut values = Vec::with_capacity(columns);
+    for index in 0..columns {
+        let value = match row.get_raw_checked(index).map_err(sqlite_error)? {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(value) => Value::Integer(value),
+            ValueRef::Real(value) => Value::Real(value),
+            ValueRef::Text(value) => Value::Text(
+                String::from_utf8(value.to_vec())
+                    .map_err(|err| StoreError::Corrupt(format!("{}", err)))?,
+            ),
+            ValueRef::Blob(value) => Value::Blob(value.to_vec()),
+        };
+        values.push(value);
+    }
+    Ok(Row(values))
+}
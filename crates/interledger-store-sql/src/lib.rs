@@ -0,0 +1,18 @@
+//! # interledger-store-sql
+//!
+//! A Store that uses a SQL database for storing account details, balances, the routing table, etc.
+//!
+//! [PostgreSQL](https://www.postgresql.org/) is supported with the `postgres` feature and
+//! [SQLite](https://www.sqlite.org/) with the `sqlite` feature (both are enabled by default).
+//! The store creates the tables it needs when it connects.
+
+#[cfg(not(any(feature = "postgres", feature = "sqlite")))]
+compile_error!("At least one of the `postgres` or `sqlite` features must be enabled");
+
+mod account;
+mod backend;
+mod schema;
+mod store;
+
+pub use account::{Account, AccountId};
+pub use store::{SqlStore, SqlStoreBuilder};
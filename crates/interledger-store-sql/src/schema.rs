@@ -0,0 +1,107 @@
+// The schema of our data in the database:
+//   accounts                       account details and balances (one row per account)
+//   routes                         dynamic routing table (local accounts and routes learned over CCP)
+//   static_routes                  configured routing table, overrides the dynamic routes
+//                                  (one row per next hop, ranked by position)
+//   forwarding_table               the CCP route manager's saved forwarding table (one row per node ID)
+//   settings                       node-wide values, such as the node's own ILP address
+//   rates                          exchange rates
+//   rate_limits                    packets and amount sent by each account in the current minute
+//   settlement_idempotency_keys    idempotency keys of incoming settlements that were already credited
+//   idempotent_data                cached responses of the settlement API
+//
+// Every statement is idempotent so the schema can be (re)applied each time the store connects.
+// The SQL is limited to what both PostgreSQL and SQLite understand, with the exception of the
+// type used for binary data, which is passed in.
+
+/// The statements that create the store's tables, using the given type for binary columns
+pub(crate) fn create_tables(binary_type: &str) -> String {
+    format!(
+        "
+    CREATE TABLE IF NOT EXISTS accounts (
+        id TEXT PRIMARY KEY,
+        username TEXT NOT NULL UNIQUE,
+        ilp_address TEXT NOT NULL,
+        asset_code TEXT NOT NULL,
+        asset_scale BIGINT NOT NULL,
+        max_packet_amount BIGINT NOT NULL,
+        min_balance BIGINT,
+        http_endpoint TEXT,
+        http_outgoing_token {binary},
+        btp_uri TEXT,
+        btp_outgoing_token {binary},
+        settle_threshold BIGINT,
+        settle_to BIGINT,
+        routing_relation TEXT NOT NULL,
+        send_routes BOOLEAN NOT NULL,
+        receive_routes BOOLEAN NOT NULL,
+        round_trip_time BIGINT NOT NULL,
+        packets_per_minute_limit BIGINT,
+        amount_per_minute_limit BIGINT,
+        settlement_engine_url TEXT,
+        http_incoming_token {binary},
+        btp_incoming_token {binary},
+        route_weight BIGINT,
+        route_auth_key {binary},
+        route_import_policy TEXT,
+        route_export_policy TEXT,
+        route_cost BIGINT,
+        balance BIGINT NOT NULL DEFAULT 0,
+        prepaid_amount BIGINT NOT NULL DEFAULT 0
+    );
+
+    CREATE INDEX IF NOT EXISTS accounts_http_incoming_token ON accounts (http_incoming_token);
+    CREATE INDEX IF NOT EXISTS accounts_btp_incoming_token ON accounts (btp_incoming_token);
+
+    CREATE TABLE IF NOT EXISTS routes (
+        prefix TEXT PRIMARY KEY,
+        account_id TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS static_routes (
+        prefix TEXT NOT NULL,
+        position BIGINT NOT NULL,
+        account_id TEXT NOT NULL,
+        weight BIGINT,
+        PRIMARY KEY (prefix, position)
+    );
+
+    CREATE TABLE IF NOT EXISTS forwarding_table (
+        name TEXT PRIMARY KEY,
+        data {binary} NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS settings (
+        name TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS rates (
+        asset_code TEXT PRIMARY KEY,
+        rate DOUBLE PRECISION NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS rate_limits (
+        account_id TEXT NOT NULL,
+        limit_type TEXT NOT NULL,
+        window_start BIGINT NOT NULL,
+        used BIGINT NOT NULL,
+        PRIMARY KEY (account_id, limit_type)
+    );
+
+    CREATE TABLE IF NOT EXISTS settlement_idempotency_keys (
+        idempotency_key TEXT PRIMARY KEY,
+        expires_at BIGINT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS idempotent_data (
+        idempotency_key TEXT PRIMARY KEY,
+        status_code BIGINT NOT NULL,
+        data {binary} NOT NULL,
+        input_hash {binary} NOT NULL,
+        expires_at BIGINT NOT NULL
+    );
+    ",
+        binary = binary_type
+    )
+}
@@ -0,0 +1,260 @@
+mod common;
+
+use common::*;
+use futures03::executor::block_on;
+use interledger_api::NodeStore;
+use interledger_btp::{BtpAccount, BtpStore};
+use interledger_ccp::{CcpRoutingAccount, RoutePolicy};
+use interledger_http::{HttpAccount, HttpStore};
+use interledger_ildcp::IldcpAccount;
+use interledger_packet::Address;
+use interledger_router::RouterStore;
+use interledger_service::{Account as AccountTrait, AccountStore, StoreError, Username};
+use interledger_service_util::BalanceStore;
+use std::str::FromStr;
+
+#[test]
+fn insert_accounts() {
+    let (store, _runtime, _accs) = test_store();
+    let account = block_on(store.insert_account(ACCOUNT_DETAILS_2.clone())).unwrap();
+    assert_eq!(
+        *account.client_address(),
+        Address::from_str("example.charlie").unwrap()
+    );
+    assert_eq!(account.asset_code(), "XRP");
+    let accounts = block_on(store.get_accounts(vec![account.id()])).unwrap();
+    assert_eq!(accounts[0].username().as_ref(), "charlie");
+}
+
+#[test]
+fn saves_routing_settings() {
+    let (store, _runtime, _accs) = test_store();
+    let mut details = ACCOUNT_DETAILS_2.clone();
+    details.route_auth_key = Some("route secret".to_string());
+    details.route_cost = Some(5);
+    details.route_import_policy = Some(RoutePolicy {
+        deny_prefixes: vec!["example.private".to_string()],
+        ..RoutePolicy::default()
+    });
+    details.route_export_policy = Some(RoutePolicy {
+        non_transitive: true,
+        ..RoutePolicy::default()
+    });
+    let account = block_on(store.insert_account(details.clone())).unwrap();
+    let account = block_on(store.get_accounts(vec![account.id()]))
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert_eq!(account.route_auth_key().unwrap(), b"route secret");
+    assert_eq!(account.route_cost(), 5);
+    assert_eq!(
+        account.route_import_policy(),
+        details.route_import_policy.as_ref()
+    );
+    assert_eq!(
+        account.route_export_policy(),
+        details.route_export_policy.as_ref()
+    );
+}
+
+#[test]
+fn rejects_duplicate_usernames() {
+    let (store, _runtime, _accs) = test_store();
+    let result = block_on(store.insert_account(ACCOUNT_DETAILS_0.clone()));
+    match result {
+        Err(StoreError::Conflict(_)) => {}
+        other => panic!("Expected a conflict, got: {:?}", other),
+    }
+}
+
+#[test]
+fn delete_accounts() {
+    let (store, _runtime, accs) = test_store();
+    let deleted = block_on(store.delete_account(accs[0].id())).unwrap();
+    assert_eq!(deleted.id(), accs[0].id());
+    let accounts = block_on(store.get_all_accounts()).unwrap();
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].id(), accs[1].id());
+
+    match block_on(store.delete_account(accs[0].id())) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+}
+
+#[test]
+fn update_accounts() {
+    let (store, _runtime, accs) = test_store();
+    let mut new = ACCOUNT_DETAILS_0.clone();
+    new.asset_code = String::from("TUV");
+    new.http_incoming_token = Some("new_incoming_token".to_string());
+    let account = block_on(store.update_account(accs[0].id(), new)).unwrap();
+    assert_eq!(account.asset_code(), "TUV");
+
+    let accounts = block_on(store.get_accounts(vec![accs[0].id()])).unwrap();
+    assert_eq!(accounts[0].asset_code(), "TUV");
+    let account =
+        block_on(store.get_account_from_http_auth(
+            &Username::from_str("alice").unwrap(),
+            "new_incoming_token",
+        ))
+        .unwrap();
+    assert_eq!(account.id(), accs[0].id());
+}
+
+#[test]
+fn update_keeps_balance() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.update_balances_for_prepare(accs[0].clone(), 100)).unwrap();
+    block_on(store.update_account(accs[0].id(), ACCOUNT_DETAILS_0.clone())).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), -100);
+}
+
+#[test]
+fn errors_when_updating_unknown_account() {
+    let (store, _runtime, _accs) = test_store();
+    match block_on(store.update_account(AccountId::new(), ACCOUNT_DETAILS_2.clone())) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+}
+
+#[test]
+fn starts_with_zero_balance() {
+    let (store, _runtime, accs) = test_store();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), 0);
+}
+
+#[test]
+fn fetches_account_from_username() {
+    let (store, _runtime, accs) = test_store();
+    let id = block_on(store.get_account_id_from_username(&Username::from_str("alice").unwrap()))
+        .unwrap();
+    assert_eq!(id, accs[0].id());
+
+    match block_on(store.get_account_id_from_username(&Username::from_str("nobody").unwrap())) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+}
+
+#[test]
+fn gets_account_from_btp_auth() {
+    let (store, _runtime, accs) = test_store();
+    let account = block_on(
+        store.get_account_from_btp_auth(&Username::from_str("bob").unwrap(), "other_btp_token"),
+    )
+    .unwrap();
+    assert_eq!(account.id(), accs[1].id());
+    assert_eq!(
+        account.get_btp_uri().unwrap().to_string(),
+        "btp+ws://example.com/btp"
+    );
+    assert_eq!(account.get_btp_token().unwrap(), b"dylan:btp_token");
+
+    // The token has to belong to the given user
+    match block_on(
+        store.get_account_from_btp_auth(&Username::from_str("alice").unwrap(), "other_btp_token"),
+    ) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+}
+
+#[test]
+fn gets_account_from_http_auth() {
+    let (store, _runtime, accs) = test_store();
+    let account =
+        block_on(store.get_account_from_http_auth(
+            &Username::from_str("alice").unwrap(),
+            "incoming_auth_token",
+        ))
+        .unwrap();
+    assert_eq!(account.id(), accs[0].id());
+    assert_eq!(
+        account.get_http_auth_token().unwrap(),
+        "dylan:outgoing_auth_token"
+    );
+
+    match block_on(
+        store.get_account_from_http_auth(&Username::from_str("alice").unwrap(), "wrong_token"),
+    ) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+}
+
+#[test]
+fn gets_btp_outgoing_accounts() {
+    let (store, _runtime, _accs) = test_store();
+    block_on(store.insert_account(ACCOUNT_DETAILS_2.clone())).unwrap();
+    let accounts = block_on(store.get_btp_outgoing_accounts()).unwrap();
+    // Charlie has no BTP URI
+    assert_eq!(accounts.len(), 2);
+}
+
+#[test]
+fn gets_multiple_in_requested_order() {
+    let (store, _runtime, accs) = test_store();
+    let accounts = block_on(store.get_accounts(vec![accs[1].id(), accs[0].id()])).unwrap();
+    assert_eq!(accounts[0].id(), accs[1].id());
+    assert_eq!(accounts[1].id(), accs[0].id());
+}
+
+#[test]
+fn errors_for_unknown_accounts() {
+    let (store, _runtime, accs) = test_store();
+    match block_on(store.get_accounts(vec![accs[0].id(), AccountId::new()])) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+}
+
+#[test]
+fn derives_child_addresses_from_node_address() {
+    let (store, _runtime, _accs) = test_store();
+    let mut details = ACCOUNT_DETAILS_2.clone();
+    details.ilp_address = None;
+    details.routing_relation = Some("Child".to_string());
+    match block_on(store.insert_account(details.clone())) {
+        Err(StoreError::Invalid(_)) => {}
+        other => panic!("Expected the account to be invalid, got: {:?}", other),
+    }
+
+    block_on(store.set_ilp_address(Address::from_str("example.node").unwrap())).unwrap();
+    let account = block_on(store.insert_account(details)).unwrap();
+    assert_eq!(
+        *account.client_address(),
+        Address::from_str("example.node.charlie").unwrap()
+    );
+}
+
+#[test]
+fn moves_derived_child_addresses_when_node_address_changes() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.set_ilp_address(Address::from_str("example.node").unwrap())).unwrap();
+    let mut details = ACCOUNT_DETAILS_2.clone();
+    details.ilp_address = None;
+    details.routing_relation = Some("Child".to_string());
+    let child = block_on(store.insert_account(details)).unwrap();
+
+    block_on(store.set_ilp_address(Address::from_str("example.new_node").unwrap())).unwrap();
+    let accounts = block_on(store.get_accounts(vec![child.id(), accs[0].id()])).unwrap();
+    assert_eq!(
+        *accounts[0].client_address(),
+        Address::from_str("example.new_node.charlie").unwrap()
+    );
+    // Accounts with configured addresses stay where they are
+    assert_eq!(
+        *accounts[1].client_address(),
+        Address::from_str("example.alice").unwrap()
+    );
+    assert_eq!(
+        store.routing_table().get(&b"example.new_node.charlie"[..]),
+        Some(&child.id())
+    );
+    assert!(store
+        .routing_table()
+        .get(&b"example.node.charlie"[..])
+        .is_none());
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::*;
+use futures03::executor::block_on;
+use interledger_api::NodeStore;
+use interledger_service::{Account as AccountTrait, StoreError};
+use interledger_service_util::BalanceStore;
+use interledger_settlement::SettlementStore;
+
+#[test]
+fn prepare_then_fulfill_with_settlement() {
+    let (store, _runtime, accs) = test_store();
+    // reduce account 0's balance by 100
+    block_on(store.update_balances_for_prepare(accs[0].clone(), 100)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), -100);
+    assert_eq!(block_on(store.get_balance(accs[1].clone())).unwrap(), 0);
+
+    // increase account 1's balance by 110, which is over its settle threshold of 0
+    let (balance, amount_to_settle) =
+        block_on(store.update_balances_for_fulfill(accs[1].clone(), 110)).unwrap();
+    assert_eq!(balance, -1000);
+    assert_eq!(amount_to_settle, 1110);
+    assert_eq!(block_on(store.get_balance(accs[1].clone())).unwrap(), -1000);
+}
+
+#[test]
+fn process_fulfill_no_settle_to() {
+    let (store, _runtime, _accs) = test_store();
+    let mut details = ACCOUNT_DETAILS_2.clone();
+    details.settle_to = None;
+    let account = block_on(store.insert_account(details)).unwrap();
+    let (balance, amount_to_settle) =
+        block_on(store.update_balances_for_fulfill(account, 100)).unwrap();
+    assert_eq!(balance, 100);
+    assert_eq!(amount_to_settle, 0);
+}
+
+#[test]
+fn process_fulfill_settle_to_over_threshold() {
+    let (store, _runtime, _accs) = test_store();
+    let mut details = ACCOUNT_DETAILS_2.clone();
+    details.settle_threshold = Some(100);
+    details.settle_to = Some(200);
+    let account = block_on(store.insert_account(details)).unwrap();
+    let (balance, amount_to_settle) =
+        block_on(store.update_balances_for_fulfill(account, 1000)).unwrap();
+    assert_eq!(balance, 1000);
+    assert_eq!(amount_to_settle, 0);
+}
+
+#[test]
+fn prepare_then_reject() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.update_balances_for_prepare(accs[0].clone(), 100)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), -100);
+    block_on(store.update_balances_for_reject(accs[0].clone(), 100)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), 0);
+}
+
+#[test]
+fn enforces_minimum_balance() {
+    let (store, _runtime, accs) = test_store();
+    // Account 0 has a min balance of -1000
+    block_on(store.update_balances_for_prepare(accs[0].clone(), 1000)).unwrap();
+    match block_on(store.update_balances_for_prepare(accs[0].clone(), 1)) {
+        Err(StoreError::LimitExceeded(_)) => {}
+        other => panic!("Expected the limit to be exceeded, got: {:?}", other),
+    }
+    // The rejected prepare must not have changed the balance
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), -1000);
+}
+
+#[test]
+fn prepare_uses_prepaid_amount_first() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.update_balance_for_incoming_settlement(accs[0].id(), 100, None)).unwrap();
+    block_on(store.update_balances_for_prepare(accs[0].clone(), 150)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), -50);
+    // The prepaid amount was used up, so a reject only refunds the balance
+    block_on(store.update_balances_for_reject(accs[0].clone(), 150)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), 100);
+}
+
+#[test]
+fn netting_fulfilled_balances() {
+    let (store, _runtime, _accs) = test_store();
+    let account = block_on(store.insert_account(ACCOUNT_DETAILS_2.clone())).unwrap();
+    // Charlie has a settle threshold of 0 but no settle_to, so no settlement is triggered
+    block_on(store.update_balances_for_fulfill(account.clone(), 100)).unwrap();
+    block_on(store.update_balances_for_prepare(account.clone(), 80)).unwrap();
+    assert_eq!(block_on(store.get_balance(account.clone())).unwrap(), 20);
+    match block_on(store.update_balances_for_prepare(account.clone(), 21)) {
+        Err(StoreError::LimitExceeded(_)) => {}
+        other => panic!("Expected the limit to be exceeded, got: {:?}", other),
+    }
+}
+
+#[test]
+fn errors_for_unknown_account() {
+    let (store, _runtime, _accs) = test_store();
+    let account = Account::try_from(AccountId::new(), ACCOUNT_DETAILS_2.clone()).unwrap();
+    match block_on(store.update_balances_for_prepare(account, 1)) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+}
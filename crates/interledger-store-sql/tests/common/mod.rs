@@ -0,0 +1,9 @@
+mod fixtures;
+mod store_helpers;
+
+// Not every test file uses all of the helpers
+#[allow(unused_imports)]
+pub use fixtures::*;
+#[allow(unused_imports)]
+pub use interledger_store_sql::*;
+pub use store_helpers::*;
@@ -0,0 +1,97 @@
+#![allow(dead_code)]
+
+use super::fixtures::*;
+use futures::Future;
+use futures03::executor::block_on;
+use interledger_api::NodeStore;
+use interledger_store_sql::{Account, SqlStore, SqlStoreBuilder};
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+use tokio::{runtime::Runtime, timer::Delay};
+
+/// Connect to the database at the given URL. The runtime runs the store's
+/// background polling and must be kept alive for as long as the store is used
+pub fn connect(database_url: &str, poll_interval: u64) -> (SqlStore, Runtime) {
+    let _ = env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let store = runtime
+        .block_on(
+            SqlStoreBuilder::new(database_url, [0; 32])
+                .poll_interval(poll_interval)
+                .connect(),
+        )
+        .expect("Unable to connect to the database");
+    (store, runtime)
+}
+
+/// A store backed by a fresh in-memory SQLite database, with two accounts already inserted
+pub fn test_store() -> (SqlStore, Runtime, Vec<Account>) {
+    let (store, runtime) = connect("sqlite::memory:", 30000);
+    let accs = vec![
+        block_on(store.insert_account(ACCOUNT_DETAILS_0.clone())).unwrap(),
+        block_on(store.insert_account(ACCOUNT_DETAILS_1.clone())).unwrap(),
+    ];
+    (store, runtime, accs)
+}
+
+/// A store using a fresh schema in the Postgres database given by the `DATABASE_URL`
+/// environment variable, with two accounts already inserted.
+///
+/// Returns `None` (and the test should be skipped) if `DATABASE_URL` is not a Postgres URL.
+/// Only the schema named after the test is dropped and recreated, so other data in the
+/// database is left alone.
+#[cfg(feature = "postgres")]
+pub fn postgres_test_store(name: &str) -> Option<(SqlStore, Runtime, Vec<Account>)> {
+    let database_url = match env::var("DATABASE_URL") {
+        Ok(url) if url.starts_with("postgres://") || url.starts_with("postgresql://") => url,
+        _ => {
+            eprintln!("DATABASE_URL is not a Postgres URL, skipping test");
+            return None;
+        }
+    };
+    let schema = format!("interledger_test_{}", name);
+    let mut client = postgres_client::Client::connect(&database_url, postgres_client::NoTls)
+        .expect("Unable to connect to Postgres");
+    client
+        .batch_execute(&format!(
+            "DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema};",
+            schema = schema
+        ))
+        .unwrap();
+
+    let separator = if database_url.contains('?') { '&' } else { '?' };
+    let database_url = format!(
+        "{}{}options=-c%20search_path%3D{}",
+        database_url, separator, schema
+    );
+    let (store, runtime) = connect(&database_url, 30000);
+    let accs = vec![
+        block_on(store.insert_account(ACCOUNT_DETAILS_0.clone())).unwrap(),
+        block_on(store.insert_account(ACCOUNT_DETAILS_1.clone())).unwrap(),
+    ];
+    Some((store, runtime, accs))
+}
+
+/// The URL of a new SQLite database file, for tests that need multiple stores to share one database
+pub fn temp_database_url(name: &str) -> String {
+    let mut path = env::temp_dir();
+    path.push(format!(
+        "interledger-store-sql-{}-{}.db",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    format!("sqlite://{}", path.display())
+}
+
+/// Wait for the runtime's background tasks (such as polling) to run for the given time
+pub fn sleep(runtime: &mut Runtime, millis: u64) {
+    runtime
+        .block_on(
+            Delay::new(Instant::now() + Duration::from_millis(millis))
+                .map_err(|err| panic!("Timer error: {:?}", err)),
+        )
+        .unwrap();
+}
@@ -0,0 +1,103 @@
+//! The same store operations as the other tests, run against PostgreSQL.
+//!
+//! These only run if `DATABASE_URL` is set to a Postgres database, for example:
+//! `DATABASE_URL=postgres://postgres@localhost/interledger cargo test -p interledger-store-sql`
+#![cfg(feature = "postgres")]
+
+mod common;
+
+use bytes::Bytes;
+use common::*;
+use futures03::executor::block_on;
+use interledger_api::{NodeStore, StaticNextHop};
+use interledger_http::HttpStore;
+use interledger_router::{NextHop, RouterStore};
+use interledger_service::{Account as AccountTrait, AccountStore, StoreError, Username};
+use interledger_service_util::{BalanceStore, ExchangeRateStore};
+use std::str::FromStr;
+
+#[test]
+fn inserts_and_gets_accounts() {
+    let (store, _runtime, accs) = match postgres_test_store("accounts") {
+        Some(store) => store,
+        None => return,
+    };
+    let accounts = block_on(store.get_accounts(vec![accs[1].id(), accs[0].id()])).unwrap();
+    assert_eq!(accounts[0].username().as_ref(), "bob");
+    assert_eq!(accounts[1].username().as_ref(), "alice");
+
+    let id = block_on(store.get_account_id_from_username(&Username::from_str("alice").unwrap()))
+        .unwrap();
+    assert_eq!(id, accs[0].id());
+    let account =
+        block_on(store.get_account_from_http_auth(
+            &Username::from_str("alice").unwrap(),
+            "incoming_auth_token",
+        ))
+        .unwrap();
+    assert_eq!(account.id(), accs[0].id());
+
+    match block_on(store.insert_account(ACCOUNT_DETAILS_0.clone())) {
+        Err(StoreError::Conflict(_)) => {}
+        other => panic!("Expected a conflict, got: {:?}", other),
+    }
+    block_on(store.delete_account(accs[0].id())).unwrap();
+    match block_on(store.get_accounts(vec![accs[0].id()])) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+}
+
+#[test]
+fn updates_balances() {
+    let (store, _runtime, accs) = match postgres_test_store("balances") {
+        Some(store) => store,
+        None => return,
+    };
+    block_on(store.update_balances_for_prepare(accs[0].clone(), 100)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), -100);
+    block_on(store.update_balances_for_reject(accs[0].clone(), 100)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), 0);
+
+    let (balance, amount_to_settle) =
+        block_on(store.update_balances_for_fulfill(accs[1].clone(), 110)).unwrap();
+    assert_eq!(balance, -1000);
+    assert_eq!(amount_to_settle, 1110);
+}
+
+#[test]
+fn saves_rates_and_routes() {
+    let (store, _runtime, accs) = match postgres_test_store("rates_and_routes") {
+        Some(store) => store,
+        None => return,
+    };
+    block_on(store.set_rates(vec![("ABC".to_string(), 500.0)])).unwrap();
+    assert_eq!(store.get_exchange_rates(&["ABC"]).unwrap(), vec![500.0]);
+
+    block_on(store.set_static_route("example.other".to_string(), accs[1].id())).unwrap();
+    let routing_table = store.routing_table();
+    assert_eq!(routing_table[&Bytes::from("example.alice")], accs[0].id());
+    assert_eq!(routing_table[&Bytes::from("example.other")], accs[1].id());
+
+    block_on(store.set_static_next_hops(
+        "example.shared".to_string(),
+        vec![
+            StaticNextHop::with_weight(accs[0].id(), 3),
+            StaticNextHop::new(accs[1].id()),
+        ],
+    ))
+    .unwrap();
+    assert_eq!(
+        store.routing_table().next_hops(b"example.shared").unwrap(),
+        &[
+            NextHop {
+                account_id: accs[0].id(),
+                weight: 3,
+            },
+            NextHop {
+                account_id: accs[1].id(),
+                weight: 2,
+            },
+        ][..]
+    );
+}
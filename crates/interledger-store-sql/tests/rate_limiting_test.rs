@@ -0,0 +1,55 @@
+mod common;
+
+use common::*;
+use futures03::executor::block_on;
+use interledger_service_util::{RateLimitError, RateLimitStore};
+
+#[test]
+fn rate_limits_number_of_packets() {
+    let (store, _runtime, accs) = test_store();
+    // Account 0 can send 2 packets per minute
+    block_on(store.apply_rate_limits(accs[0].clone(), 10)).unwrap();
+    block_on(store.apply_rate_limits(accs[0].clone(), 10)).unwrap();
+    assert_eq!(
+        block_on(store.apply_rate_limits(accs[0].clone(), 10)).unwrap_err(),
+        RateLimitError::PacketLimitExceeded
+    );
+}
+
+#[test]
+fn limits_amount_throughput() {
+    let (store, _runtime, accs) = test_store();
+    // Account 1 can send 1000 units per minute
+    block_on(store.apply_rate_limits(accs[1].clone(), 500)).unwrap();
+    block_on(store.apply_rate_limits(accs[1].clone(), 500)).unwrap();
+    assert_eq!(
+        block_on(store.apply_rate_limits(accs[1].clone(), 1)).unwrap_err(),
+        RateLimitError::ThroughputLimitExceeded
+    );
+}
+
+#[test]
+fn refunds_throughput_limit_for_rejected_packets() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.apply_rate_limits(accs[1].clone(), 500)).unwrap();
+    block_on(store.apply_rate_limits(accs[1].clone(), 500)).unwrap();
+    block_on(store.refund_throughput_limit(accs[1].clone(), 500)).unwrap();
+    block_on(store.apply_rate_limits(accs[1].clone(), 500)).unwrap();
+}
+
+#[test]
+fn exceeded_limits_are_not_counted() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.apply_rate_limits(accs[1].clone(), 900)).unwrap();
+    assert!(block_on(store.apply_rate_limits(accs[1].clone(), 200)).is_err());
+    block_on(store.apply_rate_limits(accs[1].clone(), 100)).unwrap();
+}
+
+#[test]
+fn ignores_accounts_without_limits() {
+    let (store, _runtime, _accs) = test_store();
+    let account = Account::try_from(AccountId::new(), ACCOUNT_DETAILS_2.clone()).unwrap();
+    for _ in 0..10 {
+        block_on(store.apply_rate_limits(account.clone(), 1_000_000)).unwrap();
+    }
+}
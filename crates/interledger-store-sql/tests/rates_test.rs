@@ -0,0 +1,34 @@
+mod common;
+
+use common::*;
+use futures03::executor::block_on;
+use interledger_api::NodeStore;
+use interledger_service_util::ExchangeRateStore;
+
+#[test]
+fn set_rates() {
+    let (store, _runtime, _accs) = test_store();
+    assert!(store.get_exchange_rates(&["ABC", "XYZ"]).is_err());
+    block_on(store.set_rates(vec![("ABC".to_string(), 500.0), ("XYZ".to_string(), 0.005)]))
+        .unwrap();
+    assert_eq!(
+        store.get_exchange_rates(&["XYZ", "ABC"]).unwrap(),
+        vec![0.005, 500.0]
+    );
+
+    // Setting the rates replaces the previous ones
+    block_on(store.set_rates(vec![("ABC".to_string(), 2.0)])).unwrap();
+    assert!(store.get_exchange_rates(&["XYZ"]).is_err());
+    assert_eq!(store.get_exchange_rates(&["ABC"]).unwrap(), vec![2.0]);
+}
+
+#[test]
+fn polls_for_rate_updates() {
+    let database_url = temp_database_url("rates");
+    let (store, _runtime) = connect(&database_url, 30000);
+    let (other_store, mut runtime) = connect(&database_url, 50);
+    block_on(store.set_rates(vec![("ABC".to_string(), 0.5)])).unwrap();
+
+    sleep(&mut runtime, 200);
+    assert_eq!(other_store.get_exchange_rates(&["ABC"]).unwrap(), vec![0.5]);
+}
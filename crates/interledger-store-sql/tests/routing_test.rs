@@ -0,0 +1,215 @@
+mod common;
+
+use bytes::Bytes;
+use common::*;
+use futures03::executor::block_on;
+use interledger_api::{NodeStore, StaticNextHop};
+use interledger_ccp::RouteManagerStore;
+use interledger_router::{NextHop, RouterStore, RoutingTable};
+use interledger_service::{Account as AccountTrait, StoreError};
+use std::{collections::HashMap, str::FromStr};
+
+#[test]
+fn adds_local_routes_for_accounts() {
+    let (store, _runtime, accs) = test_store();
+    let routing_table = store.routing_table();
+    assert_eq!(routing_table.len(), 2);
+    assert_eq!(routing_table[&Bytes::from("example.alice")], accs[0].id());
+    assert_eq!(routing_table[&Bytes::from("example.bob")], accs[1].id());
+}
+
+#[test]
+fn updates_local_routes() {
+    let (store, _runtime, accs) = test_store();
+    let mut details = ACCOUNT_DETAILS_0.clone();
+    details.ilp_address = Some(interledger_packet::Address::from_str("example.alicia").unwrap());
+    block_on(store.update_account(accs[0].id(), details)).unwrap();
+    let routing_table = store.routing_table();
+    assert!(!routing_table.contains_key(&Bytes::from("example.alice")));
+    assert_eq!(routing_table[&Bytes::from("example.alicia")], accs[0].id());
+
+    block_on(store.delete_account(accs[0].id())).unwrap();
+    assert!(!store
+        .routing_table()
+        .contains_key(&Bytes::from("example.alicia")));
+}
+
+#[test]
+fn saves_routes_to_db() {
+    let (mut store, _runtime, accs) = test_store();
+    block_on(store.set_routes(vec![
+        (Bytes::from("example.a"), accs[0].clone()),
+        (Bytes::from("example.b"), accs[0].clone()),
+        (Bytes::from("example.c"), accs[1].clone()),
+    ]))
+    .unwrap();
+    let routing_table = store.routing_table();
+    // The routes replace the previous routing table
+    assert_eq!(routing_table.len(), 3);
+    assert_eq!(routing_table[&Bytes::from("example.a")], accs[0].id());
+    assert_eq!(routing_table[&Bytes::from("example.c")], accs[1].id());
+}
+
+#[test]
+fn static_routes_override_others() {
+    let (mut store, _runtime, accs) = test_store();
+    block_on(store.set_routes(vec![
+        (Bytes::from("example.a"), accs[0].clone()),
+        (Bytes::from("example.b"), accs[0].clone()),
+    ]))
+    .unwrap();
+    block_on(store.set_static_routes(vec![(
+        "example.a".to_string(),
+        vec![StaticNextHop::new(accs[1].id())],
+    )]))
+    .unwrap();
+    block_on(store.set_static_route("example.b".to_string(), accs[1].id())).unwrap();
+
+    let routing_table = store.routing_table();
+    assert_eq!(routing_table[&Bytes::from("example.a")], accs[1].id());
+    assert_eq!(routing_table[&Bytes::from("example.b")], accs[1].id());
+}
+
+#[test]
+fn saves_weighted_static_next_hops() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.set_static_next_hops(
+        "example.shared".to_string(),
+        vec![
+            StaticNextHop::with_weight(accs[0].id(), 3),
+            StaticNextHop::new(accs[1].id()),
+        ],
+    ))
+    .unwrap();
+
+    // The second hop gets the account's route_weight
+    let expected = [
+        NextHop {
+            account_id: accs[0].id(),
+            weight: 3,
+        },
+        NextHop {
+            account_id: accs[1].id(),
+            weight: 2,
+        },
+    ];
+    assert_eq!(
+        store.routing_table().next_hops(b"example.shared").unwrap(),
+        &expected[..]
+    );
+    let (_local, configured) = block_on(store.get_local_and_configured_routes()).unwrap();
+    assert_eq!(
+        configured[&Bytes::from("example.shared")].id(),
+        accs[0].id()
+    );
+
+    block_on(store.delete_account(accs[0].id())).unwrap();
+    assert_eq!(
+        store.routing_table().next_hops(b"example.shared").unwrap(),
+        &expected[1..]
+    );
+    match block_on(store.set_static_next_hops("example.a".to_string(), Vec::new())) {
+        Err(StoreError::Invalid(_)) => {}
+        other => panic!("Expected invalid, got: {:?}", other),
+    }
+}
+
+#[test]
+fn static_routes_require_existing_accounts() {
+    let (store, _runtime, accs) = test_store();
+    match block_on(store.set_static_routes(vec![
+        (
+            "example.a".to_string(),
+            vec![StaticNextHop::new(accs[0].id())],
+        ),
+        (
+            "example.b".to_string(),
+            vec![StaticNextHop::new(AccountId::new())],
+        ),
+    ])) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+    match block_on(store.set_static_route("example.b".to_string(), AccountId::new())) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+    assert!(!store
+        .routing_table()
+        .contains_key(&Bytes::from("example.a")));
+}
+
+#[test]
+fn returns_configured_routes_for_route_manager() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.set_static_routes(vec![
+        (
+            "example.a".to_string(),
+            vec![StaticNextHop::new(accs[0].id())],
+        ),
+        (
+            "example.b".to_string(),
+            vec![StaticNextHop::new(accs[1].id())],
+        ),
+    ]))
+    .unwrap();
+    let (local, configured) = block_on(store.get_local_and_configured_routes()).unwrap();
+    assert_eq!(local.len(), 2);
+    assert_eq!(local[&Bytes::from("example.alice")].id(), accs[0].id());
+    assert_eq!(configured.len(), 2);
+    assert_eq!(configured[&Bytes::from("example.b")].id(), accs[1].id());
+}
+
+#[test]
+fn gets_accounts_to_send_routes_to() {
+    let (store, _runtime, accs) = test_store();
+    let accounts = block_on(store.get_accounts_to_send_routes_to()).unwrap();
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].id(), accs[1].id());
+}
+
+#[test]
+fn gets_accounts_to_receive_routes_from() {
+    let (store, _runtime, accs) = test_store();
+    let accounts = block_on(store.get_accounts_to_receive_routes_from()).unwrap();
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].id(), accs[0].id());
+}
+
+#[test]
+fn polls_for_route_updates() {
+    let database_url = temp_database_url("routes");
+    let (mut store, _runtime) = connect(&database_url, 30000);
+    let (other_store, mut runtime) = connect(&database_url, 50);
+    let account = block_on(store.insert_account(ACCOUNT_DETAILS_0.clone())).unwrap();
+    block_on(store.set_routes(vec![(Bytes::from("example.a"), account.clone())])).unwrap();
+
+    sleep(&mut runtime, 200);
+    let expected: HashMap<Bytes, AccountId> = vec![(Bytes::from("example.a"), account.id())]
+        .into_iter()
+        .collect();
+    assert_eq!(*other_store.routing_table(), RoutingTable::from(expected));
+}
+
+#[test]
+fn saves_forwarding_table_across_connections() {
+    let database_url = temp_database_url("forwarding");
+    let (mut store, _runtime) = connect(&database_url, 30000);
+    assert!(block_on(store.get_forwarding_table("example.node"))
+        .unwrap()
+        .is_none());
+    block_on(store.set_forwarding_table("example.node", Bytes::from("old table"))).unwrap();
+    block_on(store.set_forwarding_table("example.node", Bytes::from("new table"))).unwrap();
+    // Another node sharing the database keeps its own table
+    block_on(store.set_forwarding_table("example.other", Bytes::from("other table"))).unwrap();
+
+    let (restarted, _runtime) = connect(&database_url, 30000);
+    assert_eq!(
+        block_on(restarted.get_forwarding_table("example.node")).unwrap(),
+        Some(Bytes::from("new table"))
+    );
+    assert_eq!(
+        block_on(restarted.get_forwarding_table("example.other")).unwrap(),
+        Some(Bytes::from("other table"))
+    );
+}
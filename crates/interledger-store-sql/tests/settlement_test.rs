@@ -0,0 +1,99 @@
+mod common;
+
+use bytes::Bytes;
+use common::*;
+use futures03::executor::block_on;
+use http::StatusCode;
+use interledger_service::{Account as AccountTrait, StoreError};
+use interledger_service_util::BalanceStore;
+use interledger_settlement::{IdempotentStore, SettlementStore};
+
+#[test]
+fn credits_prepaid_amount() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.update_balance_for_incoming_settlement(
+        accs[0].id(),
+        100,
+        Some("key1".to_string()),
+    ))
+    .unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), 100);
+}
+
+#[test]
+fn idempotent_settlement_calls() {
+    let (store, _runtime, accs) = test_store();
+    for _ in 0..3 {
+        block_on(store.update_balance_for_incoming_settlement(
+            accs[0].id(),
+            100,
+            Some("key1".to_string()),
+        ))
+        .unwrap();
+    }
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), 100);
+
+    block_on(store.update_balance_for_incoming_settlement(
+        accs[0].id(),
+        100,
+        Some("key2".to_string()),
+    ))
+    .unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), 200);
+}
+
+#[test]
+fn credits_balance_owed() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.update_balances_for_prepare(accs[0].clone(), 500)).unwrap();
+    block_on(store.update_balance_for_incoming_settlement(accs[0].id(), 100, None)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), -400);
+}
+
+#[test]
+fn clears_balance_owed_and_puts_remainder_as_prepaid() {
+    let (store, _runtime, accs) = test_store();
+    block_on(store.update_balances_for_prepare(accs[0].clone(), 40)).unwrap();
+    block_on(store.update_balance_for_incoming_settlement(accs[0].id(), 100, None)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), 60);
+    // A reject only adds to the balance, leaving the 60 prepaid
+    block_on(store.update_balances_for_reject(accs[0].clone(), 40)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[0].clone())).unwrap(), 100);
+}
+
+#[test]
+fn refunds_settlement() {
+    let (store, _runtime, accs) = test_store();
+    let (_balance, amount_to_settle) =
+        block_on(store.update_balances_for_fulfill(accs[1].clone(), 100)).unwrap();
+    assert_eq!(amount_to_settle, 1100);
+    block_on(store.refund_settlement(accs[1].id(), amount_to_settle)).unwrap();
+    assert_eq!(block_on(store.get_balance(accs[1].clone())).unwrap(), 100);
+
+    match block_on(store.refund_settlement(AccountId::new(), 1)) {
+        Err(StoreError::NotFound(_)) => {}
+        other => panic!("Expected not found, got: {:?}", other),
+    }
+}
+
+#[test]
+fn saves_and_loads_idempotency_key_data_properly() {
+    let (store, _runtime, _accs) = test_store();
+    let input_hash: [u8; 32] = Default::default();
+    block_on(store.save_idempotent_data(
+        "AJKJNUjM0oyiAN46".to_string(),
+        input_hash,
+        StatusCode::OK,
+        Bytes::from("TEST"),
+    ))
+    .unwrap();
+
+    let data = block_on(store.load_idempotent_data("AJKJNUjM0oyiAN46".to_string())).unwrap();
+    assert_eq!(
+        data,
+        Some((StatusCode::OK, Bytes::from("TEST"), input_hash))
+    );
+
+    let data = block_on(store.load_idempotent_data("asdf".to_string())).unwrap();
+    assert!(data.is_none());
+}
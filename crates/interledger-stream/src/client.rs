@@ -2,8 +2,10 @@ use super::congestion::CongestionController;
 use super::crypto::*;
 use super::error::Error;
 use super::packet::*;
-use bytes::Bytes;
+use super::quote::probe_exchange_rate;
+use bytes::{Bytes, BytesMut};
 use futures::{Async, Future, Poll};
+use futures03::{compat::Compat, TryFutureExt};
 use interledger_ildcp::get_ildcp_info;
 use interledger_packet::{
     Address, ErrorClass, ErrorCode as IlpErrorCode, Fulfill, PacketType as IlpPacketType,
@@ -18,41 +20,152 @@ use std::{
     time::{Duration, SystemTime},
 };
 
+/// The amounts sent and delivered by a STREAM payment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamDelivery {
+    /// Amount sent, in the sending account's units
+    pub sent_amount: u64,
+    /// Amount delivered, as reported by the receiver and in the receiver's asset's units
+    pub delivered_amount: u64,
+    /// The receiver's asset code, if it sent its asset details
+    pub destination_asset_code: Option<String>,
+    /// The receiver's asset scale, if it sent its asset details
+    pub destination_asset_scale: Option<u8>,
+}
+
 /// Send a given amount of money using the STREAM transport protocol.
 ///
-/// This returns the amount delivered, as reported by the receiver and in the receiver's asset's units.
+/// This returns the amount delivered, as reported by the receiver and in the receiver's asset's units,
+/// along with the receiver's asset code and scale.
 pub fn send_money<S, A>(
     service: S,
     from_account: &A,
     destination_account: Address,
     shared_secret: &[u8],
     source_amount: u64,
-) -> impl Future<Item = (u64, S), Error = Error>
+) -> impl Future<Item = (StreamDelivery, S), Error = Error>
 where
     S: IncomingService<A> + Clone,
     A: Account,
 {
     let shared_secret = Bytes::from(shared_secret);
     let from_account = from_account.clone();
-    // TODO can/should we avoid cloning the account?
-    get_ildcp_info(&mut service.clone(), from_account.clone())
-        .map_err(|_err| Error::ConnectionError("Unable to get ILDCP info: {:?}".to_string()))
-        .and_then(move |account_details| SendMoneyFuture {
-            state: SendMoneyFutureState::SendMoney,
-            next: Some(service),
+    get_source_account(&service, &from_account).and_then(move |source_account| {
+        SendMoneyFuture::new(
+            service,
             from_account,
-            source_account: account_details.client_address(),
+            source_account,
             destination_account,
             shared_secret,
             source_amount,
-            congestion_controller: CongestionController::default(),
-            pending_requests: Cell::new(Vec::new()),
-            delivered_amount: 0,
-            should_send_source_account: true,
-            sequence: 1,
-            rejected_packets: 0,
-            error: None,
+        )
+    })
+}
+
+/// Send enough money using the STREAM transport protocol to deliver the given amount to the receiver.
+///
+/// The exchange rate is first probed using unfulfillable packets. The payment is not started if
+/// the rate is below `min_exchange_rate` (in the receiver's units per unit of the sending account)
+/// or if delivering the amount would cost more than `max_source_amount`. While sending, each packet
+/// must arrive with at least the minimum rate; if the rate slips further, the payment is stopped,
+/// the connection is closed and an `ExchangeRateError` is returned.
+///
+/// Slightly more than `destination_amount` may be delivered if the exchange rate requires rounding.
+pub fn send_destination_amount<S, A>(
+    service: S,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: &[u8],
+    destination_amount: u64,
+    max_source_amount: u64,
+    min_exchange_rate: f64,
+) -> impl Future<Item = (StreamDelivery, S), Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    let shared_secret = Bytes::from(shared_secret);
+    let from_account = from_account.clone();
+    let mut probe_service = service.clone();
+    let probe_from_account = from_account.clone();
+    let probe_destination_account = destination_account.clone();
+    let probe_shared_secret = shared_secret.clone();
+    let probe = async move {
+        probe_exchange_rate(
+            &mut probe_service,
+            probe_from_account,
+            probe_destination_account,
+            probe_shared_secret,
+            max_source_amount,
+        )
+        .await
+    };
+    Box::pin(probe)
+        .compat()
+        .and_then(move |(quote, next_sequence)| {
+            let exchange_rate = quote.exchange_rate;
+            // Nothing would arrive, however much we sent
+            if exchange_rate <= 0.0 {
+                return Err(Error::ExchangeRateError(format!(
+                    "Exchange rate of {} to the receiver is not positive",
+                    exchange_rate
+                )));
+            }
+            if exchange_rate < min_exchange_rate {
+                return Err(Error::ExchangeRateError(format!(
+                    "Exchange rate of {} is below the minimum of {}",
+                    exchange_rate, min_exchange_rate
+                )));
+            }
+            let source_amount = (destination_amount as f64 / exchange_rate).ceil();
+            if source_amount > max_source_amount as f64 {
+                return Err(Error::ExchangeRateError(format!(
+                    "Delivering {} would cost about {}, which is more than the maximum of {}",
+                    destination_amount, source_amount, max_source_amount
+                )));
+            }
+            debug!(
+                "Sending about {} to deliver {} at an exchange rate of {}",
+                source_amount, destination_amount, exchange_rate
+            );
+            Ok((exchange_rate, next_sequence))
         })
+        .and_then(move |(exchange_rate, next_sequence)| {
+            get_source_account(&service, &from_account).and_then(move |source_account| {
+                let mut send_money = SendMoneyFuture::new(
+                    service,
+                    from_account,
+                    source_account,
+                    destination_account,
+                    shared_secret,
+                    max_source_amount,
+                );
+                send_money.destination_amount = Some(destination_amount);
+                send_money.min_exchange_rate = min_exchange_rate;
+                send_money.exchange_rate = exchange_rate;
+                // The test packets were sent on the same connection
+                send_money.sequence = next_sequence;
+                send_money
+            })
+        })
+}
+
+/// Get our ILP address, which is sent to the receiver
+fn get_source_account<S, A>(
+    service: &S,
+    from_account: &A,
+) -> impl Future<Item = Address, Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    // TODO can/should we avoid cloning the account?
+    let mut ildcp_service = service.clone();
+    let ildcp_account = from_account.clone();
+    Box::pin(async move { get_ildcp_info(&mut ildcp_service, ildcp_account).await })
+        .compat()
+        .map_err(|_err| Error::ConnectionError("Unable to get ILDCP info: {:?}".to_string()))
+        .map(|account_details| account_details.client_address())
 }
 
 struct SendMoneyFuture<S: IncomingService<A>, A: Account> {
@@ -63,19 +176,26 @@ struct SendMoneyFuture<S: IncomingService<A>, A: Account> {
     destination_account: Address,
     shared_secret: Bytes,
     source_amount: u64,
+    destination_amount: Option<u64>,
+    min_exchange_rate: f64,
+    exchange_rate: f64,
     congestion_controller: CongestionController,
     pending_requests: Cell<Vec<PendingRequest>>,
+    sent_amount: u64,
     delivered_amount: u64,
+    destination_asset: Option<(String, u8)>,
     should_send_source_account: bool,
     sequence: u64,
     rejected_packets: u64,
     error: Option<Error>,
+    abort_error: Option<Error>,
 }
 
 struct PendingRequest {
     sequence: u64,
     amount: u64,
-    future: BoxedIlpFuture,
+    min_destination_amount: u64,
+    future: Compat<BoxedIlpFuture>,
 }
 
 #[derive(PartialEq)]
@@ -91,19 +211,78 @@ where
     S: IncomingService<A>,
     A: Account,
 {
+    fn new(
+        service: S,
+        from_account: A,
+        source_account: Address,
+        destination_account: Address,
+        shared_secret: Bytes,
+        source_amount: u64,
+    ) -> Self {
+        SendMoneyFuture {
+            state: SendMoneyFutureState::SendMoney,
+            next: Some(service),
+            from_account,
+            source_account,
+            destination_account,
+            shared_secret,
+            source_amount,
+            destination_amount: None,
+            min_exchange_rate: 0.0,
+            exchange_rate: 1.0,
+            congestion_controller: CongestionController::default(),
+            pending_requests: Cell::new(Vec::new()),
+            sent_amount: 0,
+            delivered_amount: 0,
+            destination_asset: None,
+            should_send_source_account: true,
+            sequence: 1,
+            rejected_packets: 0,
+            error: None,
+            abort_error: None,
+        }
+    }
+
+    /// Whether all of the money was sent or delivered, or the payment was aborted
+    fn is_finished(&self) -> bool {
+        if self.abort_error.is_some() || self.source_amount == 0 {
+            return true;
+        }
+        match self.destination_amount {
+            Some(destination_amount) => self.delivered_amount >= destination_amount,
+            None => false,
+        }
+    }
+
     fn try_send_money(&mut self) -> Result<bool, Error> {
         // Fire off requests until the congestion controller tells us to stop or we've sent the total amount
         let mut sent_packets = false;
-        loop {
+        while self.abort_error.is_none() {
             // Determine the amount to send
-            let amount = min(
+            let mut amount = min(
                 self.source_amount,
                 self.congestion_controller.get_max_amount(),
             );
+            if let Some(destination_amount) = self.destination_amount {
+                // Only send what should be needed to deliver the rest, counting what is in flight
+                let in_flight: u64 = self
+                    .pending_requests
+                    .get_mut()
+                    .iter()
+                    .map(|request| request.amount)
+                    .sum();
+                let expected_delivery =
+                    self.delivered_amount + (in_flight as f64 * self.exchange_rate) as u64;
+                let left_to_deliver = destination_amount.saturating_sub(expected_delivery);
+                let needed = (left_to_deliver as f64 / self.exchange_rate).ceil() as u64;
+                amount = min(amount, needed);
+            }
             if amount == 0 {
                 break;
             }
             self.source_amount -= amount;
+            // The receiver rejects packets that arrive with less than this
+            let min_destination_amount = (amount as f64 * self.min_exchange_rate) as u64;
 
             // Load up the STREAM packet
             let sequence = self.next_sequence();
@@ -118,8 +297,7 @@ where
             }
             let stream_packet = StreamPacketBuilder {
                 ilp_packet_type: IlpPacketType::Prepare,
-                // TODO enforce min exchange rate
-                prepare_amount: 0,
+                prepare_amount: min_destination_amount,
                 sequence,
                 frames: &frames,
             }
@@ -148,11 +326,13 @@ where
                 let send_request = next.handle_request(IncomingRequest {
                     from: self.from_account.clone(),
                     prepare,
+                    extensions: Extensions::new(),
                 });
                 self.pending_requests.get_mut().push(PendingRequest {
                     sequence,
                     amount,
-                    future: Box::new(send_request),
+                    min_destination_amount,
+                    future: Compat::new(Box::pin(send_request)),
                 });
                 sent_packets = true;
             } else {
@@ -191,11 +371,13 @@ where
             let send_request = next.handle_request(IncomingRequest {
                 from: self.from_account.clone(),
                 prepare,
+                extensions: Extensions::new(),
             });
             self.pending_requests.get_mut().push(PendingRequest {
                 sequence,
                 amount: 0,
-                future: Box::new(send_request),
+                min_destination_amount: 0,
+                future: Compat::new(Box::pin(send_request)),
             });
         } else {
             panic!("Polled after finish");
@@ -214,7 +396,7 @@ where
                     None
                 }
                 Err(reject) => {
-                    self.handle_reject(pending_request.sequence, pending_request.amount, reject);
+                    self.handle_reject(&pending_request, reject);
                     None
                 }
             })
@@ -235,11 +417,16 @@ where
         // TODO should we check the fulfillment and expiry or can we assume the plugin does that?
         self.congestion_controller.fulfill(amount);
         self.should_send_source_account = false;
+        self.sent_amount += amount;
 
         if let Ok(packet) = StreamPacket::from_encrypted(&self.shared_secret, fulfill.into_data()) {
+            self.handle_asset_details(&packet);
             if packet.ilp_packet_type() == IlpPacketType::Fulfill {
                 // TODO check that the sequence matches our outgoing packet
                 self.delivered_amount += packet.prepare_amount();
+                if self.delivered_amount > 0 {
+                    self.exchange_rate = self.delivered_amount as f64 / self.sent_amount as f64;
+                }
             }
         } else {
             warn!(
@@ -254,7 +441,20 @@ where
         );
     }
 
-    fn handle_reject(&mut self, sequence: u64, amount: u64, reject: Reject) {
+    /// Keep the receiver's asset details, which it sends in its first reply
+    fn handle_asset_details(&mut self, packet: &StreamPacket) {
+        for frame in packet.frames() {
+            if let Frame::ConnectionAssetDetails(frame) = frame {
+                self.destination_asset = Some((
+                    frame.source_asset_code.to_string(),
+                    frame.source_asset_scale,
+                ));
+            }
+        }
+    }
+
+    fn handle_reject(&mut self, request: &PendingRequest, reject: Reject) {
+        let (sequence, amount) = (request.sequence, request.amount);
         self.source_amount += amount;
         self.congestion_controller.reject(amount, &reject);
         self.rejected_packets += 1;
@@ -272,7 +472,24 @@ where
                 // Handled by the congestion controller
             }
             (_, IlpErrorCode::F99_APPLICATION_ERROR) => {
-                // TODO handle STREAM errors
+                let packet = StreamPacket::from_encrypted(
+                    &self.shared_secret,
+                    BytesMut::from(reject.data()),
+                );
+                if let Ok(packet) = packet {
+                    self.handle_asset_details(&packet);
+                    if let Some(error) = receiver_error(&packet) {
+                        self.error = Some(error);
+                    } else if packet.prepare_amount() < request.min_destination_amount {
+                        // Finish the packets in flight and close the connection before returning the error
+                        self.abort_error = Some(Error::ExchangeRateError(format!(
+                            "Packet of {} delivered {} when at least {} was required by the minimum exchange rate",
+                            amount,
+                            packet.prepare_amount(),
+                            request.min_destination_amount
+                        )));
+                    }
+                }
             }
             _ => {
                 self.error = Some(Error::SendMoneyError(format!(
@@ -291,12 +508,37 @@ where
     }
 }
 
+/// Check whether the receiver rejected the packet because it won't accept any more money
+fn receiver_error(packet: &StreamPacket) -> Option<Error> {
+    packet.frames().find_map(|frame| match frame {
+        Frame::ConnectionClose(frame) => Some(Error::SendMoneyError(format!(
+            "Receiver closed the connection: {:?} {}",
+            frame.code, frame.message
+        ))),
+        Frame::StreamClose(ref frame) if frame.stream_id == 1 => {
+            Some(Error::SendMoneyError(format!(
+                "Receiver closed the stream: {:?} {}",
+                frame.code, frame.message
+            )))
+        }
+        Frame::StreamMaxMoney(ref frame)
+            if frame.stream_id == 1 && frame.receive_max <= frame.total_received =>
+        {
+            Some(Error::SendMoneyError(format!(
+                "Receiver cannot accept more than the {} it already received",
+                frame.total_received
+            )))
+        }
+        _ => None,
+    })
+}
+
 impl<S, A> Future for SendMoneyFuture<S, A>
 where
     S: IncomingService<A>,
     A: Account,
 {
-    type Item = (u64, S);
+    type Item = (StreamDelivery, S);
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -304,7 +546,7 @@ where
         loop {
             self.poll_pending_requests()?;
 
-            if self.source_amount == 0 && self.pending_requests.get_mut().is_empty() {
+            if self.is_finished() && self.pending_requests.get_mut().is_empty() {
                 if self.state == SendMoneyFutureState::SendMoney {
                     self.state = SendMoneyFutureState::Closing;
                     self.try_send_connection_close()?;
@@ -313,8 +555,31 @@ where
                     debug!(
                         "Send money future finished. Delivered: {} ({} packets fulfilled, {} packets rejected)", self.delivered_amount, self.sequence - 1, self.rejected_packets,
                     );
+                    if let Some(error) = self.abort_error.take() {
+                        error!("Send money stopped because of error: {:?}", error);
+                        return Err(error);
+                    }
+                    if let Some(destination_amount) = self.destination_amount {
+                        if self.delivered_amount < destination_amount {
+                            return Err(Error::SendMoneyError(format!(
+                                "Reached the maximum source amount after delivering {} of {}",
+                                self.delivered_amount, destination_amount
+                            )));
+                        }
+                    }
                     return Ok(Async::Ready((
-                        self.delivered_amount,
+                        StreamDelivery {
+                            sent_amount: self.sent_amount,
+                            delivered_amount: self.delivered_amount,
+                            destination_asset_code: self
+                                .destination_asset
+                                .as_ref()
+                                .map(|(asset_code, _)| asset_code.clone()),
+                            destination_asset_scale: self
+                                .destination_asset
+                                .as_ref()
+                                .map(|(_, asset_scale)| *asset_scale),
+                        },
                         self.next.take().unwrap(),
                     )));
                 }
@@ -328,7 +593,8 @@ where
 #[cfg(test)]
 mod send_money_tests {
     use super::*;
-    use crate::test_helpers::{TestAccount, EXAMPLE_CONNECTOR};
+    use crate::test_helpers::{test_account, EXAMPLE_CONNECTOR};
+    use futures03::future::err;
     use interledger_ildcp::IldcpService;
     use interledger_packet::{ErrorCode as IlpErrorCode, RejectBuilder};
     use interledger_service::incoming_service_fn;
@@ -338,18 +604,13 @@ mod send_money_tests {
 
     #[test]
     fn stops_at_final_errors() {
-        let account = TestAccount {
-            id: 0,
-            asset_code: "XYZ".to_string(),
-            asset_scale: 9,
-            ilp_address: Address::from_str("example.destination").unwrap(),
-        };
+        let account = test_account(&Address::from_str("example.destination").unwrap());
         let requests = Arc::new(Mutex::new(Vec::new()));
         let requests_clone = requests.clone();
         let result = send_money(
             IldcpService::new(incoming_service_fn(move |request| {
                 requests_clone.lock().push(request);
-                Err(RejectBuilder {
+                err(RejectBuilder {
                     code: IlpErrorCode::F00_BAD_REQUEST,
                     message: b"just some final error",
                     triggered_by: Some(&EXAMPLE_CONNECTOR),
@@ -366,4 +627,50 @@ mod send_money_tests {
         assert!(result.is_err());
         assert_eq!(requests.lock().len(), 1);
     }
+
+    #[test]
+    fn rejects_zero_exchange_rate() {
+        let account = test_account(&Address::from_str("example.destination").unwrap());
+        let shared_secret = Bytes::from(&[0; 32][..]);
+        let receiver_secret = shared_secret.clone();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_clone = requests.clone();
+        // Nothing arrives at the receiver
+        let result = send_destination_amount(
+            IldcpService::new(incoming_service_fn(move |request| {
+                requests_clone.lock().push(request);
+                let data = StreamPacketBuilder {
+                    ilp_packet_type: IlpPacketType::Reject,
+                    prepare_amount: 0,
+                    sequence: 1,
+                    frames: &[],
+                }
+                .build()
+                .into_encrypted(&receiver_secret);
+                err(RejectBuilder {
+                    code: IlpErrorCode::F99_APPLICATION_ERROR,
+                    message: &[],
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &data[..],
+                }
+                .build())
+            })),
+            &account,
+            Address::from_str("example.destination").unwrap(),
+            &shared_secret[..],
+            0,
+            100,
+            0.0,
+        )
+        .wait();
+        match result {
+            Err(Error::ExchangeRateError(_)) => {}
+            result => panic!(
+                "Unexpected result: {:?}",
+                result.map(|(delivery, _)| delivery)
+            ),
+        }
+        // Only the test packet was sent
+        assert_eq!(requests.lock().len(), 1);
+    }
 }
@@ -0,0 +1,470 @@
+use super::crypto::*;
+use super::data::{
+    DataStream, Notifier, DEFAULT_MAX_STREAM_ID, DEFAULT_WINDOW, MAX_DATA_PER_PACKET,
+};
+use super::error::Error;
+use super::packet::*;
+use bytes::{Bytes, BytesMut};
+use futures::Future;
+use futures03::{
+    compat::Future01CompatExt,
+    future::{poll_fn, select},
+    task::Poll,
+    TryFutureExt,
+};
+use interledger_packet::{Address, ErrorClass, PacketType as IlpPacketType, PrepareBuilder};
+use interledger_service::*;
+use log::{debug, warn};
+use parking_lot::Mutex;
+use std::{
+    cmp::{max, min},
+    collections::BTreeMap,
+    str,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio_timer::Delay;
+
+/// How long the client waits before sending a packet to pick up data from the receiver when it
+/// has nothing to send.
+///
+/// The receiver can only send data in its responses to the client's packets, so the client only
+/// sends these while a stream is waiting for data from the receiver. The delay doubles each time
+/// the receiver has nothing for us, up to `MAX_DATA_POLL_INTERVAL`, and goes back to this once
+/// data is sent or received.
+const DATA_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_DATA_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct ConnectionState {
+    streams: BTreeMap<u64, DataStream>,
+    next_stream_id: u64,
+    closing: bool,
+    closed: bool,
+    // The receiver sent more data than we allowed, so the connection is closed with a FlowControlError
+    flow_control_error: bool,
+    // The highest stream ID the receiver lets us open
+    remote_max_stream_id: u64,
+}
+
+/// The client side of a STREAM connection, used to exchange data with the receiver.
+///
+/// Clones of the connection refer to the same connection.
+#[derive(Clone, Debug)]
+pub struct StreamClientConnection {
+    state: Arc<Mutex<ConnectionState>>,
+    notifier: Arc<Notifier>,
+}
+
+impl StreamClientConnection {
+    fn new() -> Self {
+        StreamClientConnection {
+            state: Arc::new(Mutex::new(ConnectionState {
+                next_stream_id: 1,
+                remote_max_stream_id: DEFAULT_MAX_STREAM_ID,
+                ..Default::default()
+            })),
+            notifier: Arc::new(Notifier::default()),
+        }
+    }
+
+    /// Open a new stream. Streams opened by the client have odd IDs.
+    pub fn open_stream(&self) -> DataStream {
+        let mut state = self.state.lock();
+        let stream_id = state.next_stream_id;
+        state.next_stream_id += 2;
+        let stream = DataStream::new(stream_id, Some(self.notifier.clone()));
+        state.streams.insert(stream_id, stream.clone());
+        self.notifier.wake();
+        stream
+    }
+
+    /// Get the stream with the given ID, for example to read data the receiver sends on a stream it opened.
+    pub fn data_stream(&self, stream_id: u64) -> DataStream {
+        let mut state = self.state.lock();
+        if let Some(stream) = state.streams.get(&stream_id) {
+            return stream.clone();
+        }
+        let stream = DataStream::new(stream_id, Some(self.notifier.clone()));
+        state.streams.insert(stream_id, stream.clone());
+        self.notifier.wake();
+        stream
+    }
+
+    /// Close the connection once all of the data written to its streams was sent.
+    pub fn close(&self) {
+        self.state.lock().closing = true;
+        self.notifier.wake();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+
+    fn streams(&self) -> Vec<DataStream> {
+        self.state.lock().streams.values().cloned().collect()
+    }
+
+    /// Whether we may send on the given stream. The streams we open must wait until they
+    /// are within the max stream ID the receiver gave us
+    fn is_allowed(&self, stream_id: u64) -> bool {
+        let opened_by_us = stream_id % 2 == 1;
+        !opened_by_us || stream_id <= self.state.lock().remote_max_stream_id
+    }
+
+    fn has_outgoing(&self) -> bool {
+        let streams = self.streams();
+        if streams
+            .iter()
+            .any(|stream| self.is_allowed(stream.stream_id()) && stream.has_outgoing())
+        {
+            return true;
+        }
+        let state = self.state.lock();
+        state.closing && !state.closed && streams.iter().all(|stream| stream.is_flushed())
+    }
+
+    /// Whether any stream is waiting for data from the receiver, which it can only send in
+    /// response to our packets
+    fn expects_reply(&self) -> bool {
+        !self.state.lock().closed && self.streams().iter().any(|stream| stream.is_remote_open())
+    }
+
+    fn set_closed(&self) {
+        self.state.lock().closed = true;
+        for stream in self.streams() {
+            stream.close_connection();
+        }
+    }
+}
+
+/// Open a STREAM connection for sending and receiving data.
+///
+/// This returns the connection, which is used to open streams, and a future that sends
+/// the connection's packets. The future must be run for any data to be sent or received
+/// and it resolves once the connection is closed.
+///
+/// The receiver can only send data in its responses to the connection's packets. While a stream
+/// is open on the receiver's side, the connection sends empty packets to pick up its data, less
+/// often the longer the receiver has nothing to send. Once the receiver closed all of the streams,
+/// no packets are sent until data is written or another stream is opened.
+///
+/// Money can be sent to the same `destination_account` with `send_money` while the connection
+/// is open. The receiver only sends data in its responses to this connection's packets, so none
+/// of it is lost to the payment's packets. Note that `send_money` closes the connection when it
+/// finishes, so all of the data should be exchanged by then.
+pub fn connect<S, A>(
+    service: S,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: &[u8],
+) -> (
+    StreamClientConnection,
+    impl Future<Item = (), Error = Error>,
+)
+where
+    S: IncomingService<A> + Send + 'static,
+    A: Account + 'static,
+{
+    let connection = StreamClientConnection::new();
+    let run = send_packets(
+        service,
+        from_account.clone(),
+        destination_account,
+        Bytes::from(shared_secret),
+        connection.clone(),
+    );
+    (connection, Box::pin(run).compat())
+}
+
+async fn send_packets<S, A>(
+    mut service: S,
+    from_account: A,
+    destination_account: Address,
+    shared_secret: Bytes,
+    connection: StreamClientConnection,
+) -> Result<(), Error>
+where
+    S: IncomingService<A> + Send + 'static,
+    A: Account + 'static,
+{
+    let mut sequence: u64 = 1;
+    // Send the first packet right away to find out if the receiver has anything for us
+    let mut should_send = true;
+    let mut poll_interval = DATA_POLL_INTERVAL;
+    loop {
+        if !should_send && !connection.has_outgoing() {
+            // Wait until something is written, a stream starts waiting for the receiver or,
+            // if one already is, until it is time to check for data from the receiver
+            let expects_reply = connection.expects_reply();
+            let woken = poll_fn(|cx| {
+                connection.notifier.register(cx.waker());
+                if connection.has_outgoing() || (!expects_reply && connection.expects_reply()) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            });
+            if expects_reply {
+                let poll = Delay::new(Instant::now() + poll_interval).compat();
+                select(woken, poll).await;
+            } else {
+                woken.await;
+                poll_interval = DATA_POLL_INTERVAL;
+            }
+        }
+
+        // Load up the STREAM packet
+        let streams = connection.streams();
+        let all_sent = streams.iter().all(|stream| stream.is_flushed());
+        let mut outgoing_data: Vec<(u64, u64, Bytes)> = Vec::new();
+        let mut window_updates: Vec<(u64, u64)> = Vec::new();
+        let mut closing_streams: Vec<u64> = Vec::new();
+        let mut blocked_stream_id: Option<u64> = None;
+        let mut data_left = MAX_DATA_PER_PACKET;
+        for stream in streams.iter() {
+            if !connection.is_allowed(stream.stream_id()) {
+                if stream.has_outgoing() {
+                    blocked_stream_id = Some(stream.stream_id());
+                }
+                continue;
+            }
+            if let Some(max_offset) = stream.window_update(DEFAULT_WINDOW / 2) {
+                window_updates.push((stream.stream_id(), max_offset));
+            }
+            if let Some((offset, data)) = stream.next_outgoing(data_left) {
+                data_left -= data.len();
+                outgoing_data.push((stream.stream_id(), offset, data));
+            }
+            if stream.take_close() {
+                closing_streams.push(stream.stream_id());
+            }
+        }
+        let (closing_connection, close_code) = {
+            let state = connection.state.lock();
+            if state.flow_control_error {
+                (true, ErrorCode::FlowControlError)
+            } else {
+                (state.closing && all_sent, ErrorCode::NoError)
+            }
+        };
+
+        let prepare = {
+            let mut frames: Vec<Frame> = Vec::new();
+            for (stream_id, max_offset) in window_updates {
+                frames.push(Frame::StreamMaxData(StreamMaxDataFrame {
+                    stream_id,
+                    max_offset,
+                }));
+            }
+            for (stream_id, offset, data) in outgoing_data.iter() {
+                frames.push(Frame::StreamData(StreamDataFrame {
+                    stream_id: *stream_id,
+                    offset: *offset,
+                    data: &data[..],
+                }));
+            }
+            for stream_id in closing_streams {
+                frames.push(Frame::StreamClose(StreamCloseFrame {
+                    stream_id,
+                    code: ErrorCode::NoError,
+                    message: "",
+                }));
+            }
+            // Ask the receiver to let us open more streams
+            if let Some(max_stream_id) = blocked_stream_id {
+                frames.push(Frame::ConnectionStreamIdBlocked(
+                    ConnectionStreamIdBlockedFrame { max_stream_id },
+                ));
+            }
+            if closing_connection {
+                frames.push(Frame::ConnectionClose(ConnectionCloseFrame {
+                    code: close_code.clone(),
+                    message: "",
+                }));
+            }
+            let stream_packet = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence,
+                frames: &frames,
+            }
+            .build();
+            debug!(
+                "Sending packet {} with encrypted STREAM packet: {:?}",
+                sequence, stream_packet
+            );
+
+            // Packets that carry no money are fulfilled by the receiver
+            let data = stream_packet.into_encrypted(&shared_secret);
+            let execution_condition = generate_condition(&shared_secret, &data);
+            PrepareBuilder {
+                destination: destination_account.clone(),
+                amount: 0,
+                execution_condition: &execution_condition,
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                data: &data[..],
+            }
+            .build()
+        };
+        let result = service
+            .handle_request(IncomingRequest {
+                from: from_account.clone(),
+                prepare,
+                extensions: Extensions::new(),
+            })
+            .await;
+
+        // Anything that made it to the receiver comes back with a STREAM packet in the response
+        let (response, reject) = match result {
+            Ok(fulfill) => (
+                StreamPacket::from_encrypted(&shared_secret, fulfill.into_data()).ok(),
+                None,
+            ),
+            Err(reject) => (
+                StreamPacket::from_encrypted(&shared_secret, BytesMut::from(reject.data())).ok(),
+                Some(reject),
+            ),
+        };
+        let response = match response {
+            Some(response) => response,
+            None => {
+                for stream in streams.iter() {
+                    stream.nack();
+                }
+                match reject {
+                    Some(ref reject) if reject.code().class() != ErrorClass::Temporary => {
+                        connection.set_closed();
+                        return Err(Error::ConnectionError(format!(
+                            "Packet was rejected with error: {} {}",
+                            reject.code(),
+                            str::from_utf8(reject.message()).unwrap_or_default(),
+                        )));
+                    }
+                    Some(ref reject) => debug!(
+                        "Packet {} was rejected with temporary error: {}",
+                        sequence,
+                        reject.code()
+                    ),
+                    None => warn!("Unable to parse STREAM packet from fulfill data"),
+                }
+                // Give the network some time before trying again
+                should_send = false;
+                let _ = Delay::new(Instant::now() + DATA_POLL_INTERVAL)
+                    .compat()
+                    .await;
+                continue;
+            }
+        };
+        // The receiver treats a packet with the same sequence number as a retry and resends
+        // the data from its last response, so only move on once we got that response
+        sequence += 1;
+        for stream in streams.iter() {
+            stream.ack();
+        }
+        if closing_connection {
+            debug!("Connection closed");
+            connection.set_closed();
+            return match close_code {
+                ErrorCode::NoError => Ok(()),
+                code => Err(Error::ConnectionError(format!(
+                    "Closed the connection: {:?}",
+                    code
+                ))),
+            };
+        }
+
+        // Handle the receiver's frames. If it sent data, it may have more
+        should_send = false;
+        let mut data_received = false;
+        for frame in response.frames() {
+            match frame {
+                Frame::StreamData(frame) => {
+                    if !connection
+                        .data_stream(frame.stream_id)
+                        .receive(frame.offset, frame.data)
+                    {
+                        warn!(
+                            "Receiver sent more data than allowed on stream {}, closing the connection",
+                            frame.stream_id
+                        );
+                        connection.state.lock().flow_control_error = true;
+                        should_send = true;
+                        break;
+                    }
+                    data_received = data_received || !frame.data.is_empty();
+                    should_send = should_send || data_received;
+                }
+                Frame::StreamMaxData(frame) => connection
+                    .data_stream(frame.stream_id)
+                    .set_remote_max_offset(frame.max_offset),
+                Frame::StreamClose(frame) => {
+                    connection.data_stream(frame.stream_id).receive_close()
+                }
+                Frame::ConnectionMaxStreamId(frame) => {
+                    let mut state = connection.state.lock();
+                    state.remote_max_stream_id =
+                        max(state.remote_max_stream_id, frame.max_stream_id);
+                }
+                Frame::ConnectionClose(frame) => {
+                    connection.set_closed();
+                    return match frame.code {
+                        ErrorCode::NoError => Ok(()),
+                        code => Err(Error::ConnectionError(format!(
+                            "Receiver closed the connection: {:?} {}",
+                            code, frame.message
+                        ))),
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        poll_interval =
+            next_poll_interval(poll_interval, data_received || !outgoing_data.is_empty());
+    }
+}
+
+/// How long to wait before picking up data from the receiver again. This backs off while
+/// packets neither send nor receive any data.
+fn next_poll_interval(poll_interval: Duration, exchanged_data: bool) -> Duration {
+    if exchanged_data {
+        DATA_POLL_INTERVAL
+    } else {
+        min(poll_interval * 2, MAX_DATA_POLL_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod polling {
+    use super::*;
+
+    #[test]
+    fn backs_off_while_no_data_is_exchanged() {
+        let mut poll_interval = DATA_POLL_INTERVAL;
+        let mut intervals = Vec::new();
+        for _ in 0..8 {
+            poll_interval = next_poll_interval(poll_interval, false);
+            intervals.push(poll_interval.as_millis());
+        }
+        assert_eq!(intervals, vec![200, 400, 800, 1600, 3200, 5000, 5000, 5000]);
+        assert_eq!(next_poll_interval(poll_interval, true), DATA_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn only_polls_while_a_stream_waits_for_the_receiver() {
+        let connection = StreamClientConnection::new();
+        assert!(!connection.expects_reply());
+
+        let stream = connection.open_stream();
+        assert!(connection.expects_reply());
+        stream.receive_close();
+        assert!(!connection.expects_reply());
+
+        // Reading a stream the receiver opens
+        connection.data_stream(2);
+        assert!(connection.expects_reply());
+        connection.set_closed();
+        assert!(!connection.expects_reply());
+    }
+}
@@ -0,0 +1,451 @@
+use bytes::{Bytes, BytesMut};
+use futures03::io::{AsyncRead, AsyncWrite};
+use futures03::task::{Context, Poll, Waker};
+use log::debug;
+use parking_lot::Mutex;
+use std::{cmp::min, collections::BTreeMap, io, pin::Pin, sync::Arc};
+
+/// How many bytes each side of a stream buffers before applying back pressure.
+///
+/// Both sides assume the other will accept this much data until they hear otherwise.
+pub(crate) const DEFAULT_WINDOW: u64 = 65_536;
+/// Maximum amount of stream data to put in a single packet (ILP packets carry up to 32767 bytes of data,
+/// which includes the STREAM packet's encryption and framing overhead)
+pub(crate) const MAX_DATA_PER_PACKET: usize = 30_000;
+/// The highest stream ID the other side may open until it closes some of its streams.
+/// Senders use odd stream IDs, so this allows 10 of them to be open at once
+pub(crate) const DEFAULT_MAX_STREAM_ID: u64 = 20;
+
+/// Wakes up whatever sends the packets for a connection when there is new data to send.
+#[derive(Debug, Default)]
+pub(crate) struct Notifier {
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Notifier {
+    pub(crate) fn register(&self, waker: &Waker) {
+        *self.waker.lock() = Some(waker.clone());
+    }
+
+    pub(crate) fn wake(&self) {
+        if let Some(waker) = self.waker.lock().take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DataState {
+    // Data received from the other side, keyed by offset
+    incoming: BTreeMap<u64, Bytes>,
+    read_offset: u64,
+    advertised_max_offset: u64,
+    remote_closed: bool,
+    read_waker: Option<Waker>,
+    // Data written locally that the other side hasn't acknowledged yet
+    outgoing: BytesMut,
+    outgoing_offset: u64,
+    in_flight: usize,
+    remote_max_offset: u64,
+    local_closed: bool,
+    close_sent: bool,
+    close_in_flight: bool,
+    write_waker: Option<Waker>,
+    connection_closed: bool,
+}
+
+impl DataState {
+    fn max_offset(&self) -> u64 {
+        self.read_offset + DEFAULT_WINDOW
+    }
+
+    fn sendable(&self) -> usize {
+        let start = self.outgoing_offset + self.in_flight as u64;
+        let end = min(
+            self.remote_max_offset,
+            self.outgoing_offset + self.outgoing.len() as u64,
+        );
+        end.saturating_sub(start) as usize
+    }
+
+    fn should_send_close(&self) -> bool {
+        self.local_closed && !self.close_sent && self.in_flight == self.outgoing.len()
+    }
+
+    fn wake_reader(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_writer(&mut self) {
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// One of the streams of a STREAM connection, used to read and write bytes.
+///
+/// Data written to the stream is buffered until it can be included in a packet, which
+/// happens on the next packet the client sends or, on the server side, in the response to
+/// the client connection's next packet. Writes wait once 64 KiB are buffered and
+/// the other side's flow control limits are respected. Closing the stream sends a
+/// `StreamClose` frame once all of the data was sent, after which the other side reads EOF.
+#[derive(Clone, Debug)]
+pub struct DataStream {
+    stream_id: u64,
+    state: Arc<Mutex<DataState>>,
+    notifier: Option<Arc<Notifier>>,
+}
+
+impl DataStream {
+    pub(crate) fn new(stream_id: u64, notifier: Option<Arc<Notifier>>) -> Self {
+        DataStream {
+            stream_id,
+            state: Arc::new(Mutex::new(DataState {
+                incoming: BTreeMap::new(),
+                read_offset: 0,
+                advertised_max_offset: DEFAULT_WINDOW,
+                remote_closed: false,
+                read_waker: None,
+                outgoing: BytesMut::new(),
+                outgoing_offset: 0,
+                in_flight: 0,
+                remote_max_offset: DEFAULT_WINDOW,
+                local_closed: false,
+                close_sent: false,
+                close_in_flight: false,
+                write_waker: None,
+                connection_closed: false,
+            })),
+            notifier,
+        }
+    }
+
+    pub fn stream_id(&self) -> u64 {
+        self.stream_id
+    }
+
+    /// Buffer data received from the other side.
+    ///
+    /// Returns false, without buffering anything, if the data goes past the maximum offset
+    /// the other side is allowed to send up to. The connection should then be closed with
+    /// a `FlowControlError`.
+    pub(crate) fn receive(&self, offset: u64, data: &[u8]) -> bool {
+        let mut state = self.state.lock();
+        let end = offset.saturating_add(data.len() as u64);
+        if end > state.max_offset() {
+            debug!(
+                "Stream {} received data up to offset {} but only allowed {}",
+                self.stream_id,
+                end,
+                state.max_offset()
+            );
+            return false;
+        }
+        if end <= state.read_offset {
+            // Data was already read
+            return true;
+        }
+        let is_longer = state
+            .incoming
+            .get(&offset)
+            .map(|existing| existing.len() < data.len())
+            .unwrap_or(true);
+        if is_longer {
+            state.incoming.insert(offset, Bytes::from(data));
+            state.wake_reader();
+        }
+        true
+    }
+
+    /// The other side closed the stream, so it won't send any more data
+    pub(crate) fn receive_close(&self) {
+        let mut state = self.state.lock();
+        state.remote_closed = true;
+        state.wake_reader();
+    }
+
+    pub(crate) fn set_remote_max_offset(&self, max_offset: u64) {
+        let mut state = self.state.lock();
+        if max_offset > state.remote_max_offset {
+            state.remote_max_offset = max_offset;
+        }
+    }
+
+    /// The connection was closed, so nothing more will be sent or received
+    pub(crate) fn close_connection(&self) {
+        let mut state = self.state.lock();
+        state.connection_closed = true;
+        state.wake_reader();
+        state.wake_writer();
+    }
+
+    /// Returns the new maximum offset to tell the other side about, if it grew by
+    /// at least `threshold` since it was last sent
+    pub(crate) fn window_update(&self, threshold: u64) -> Option<u64> {
+        let mut state = self.state.lock();
+        let max_offset = state.max_offset();
+        if max_offset >= state.advertised_max_offset + threshold {
+            state.advertised_max_offset = max_offset;
+            Some(max_offset)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the other side may still send data on the stream, because it did not close it
+    /// and the connection is still open
+    pub(crate) fn is_remote_open(&self) -> bool {
+        let state = self.state.lock();
+        !state.remote_closed && !state.connection_closed
+    }
+
+    /// Whether there is data or a close that can be sent right now
+    pub(crate) fn has_outgoing(&self) -> bool {
+        let state = self.state.lock();
+        state.sendable() > 0 || state.should_send_close()
+    }
+
+    /// Whether everything written to the stream, and its close if it was closed, was sent
+    pub(crate) fn is_flushed(&self) -> bool {
+        let state = self.state.lock();
+        state.outgoing.is_empty() && !state.should_send_close()
+    }
+
+    /// Take up to `max_len` bytes of data to send, returning the offset they start at.
+    ///
+    /// The data is considered in flight until `ack` or `nack` is called.
+    pub(crate) fn next_outgoing(&self, max_len: usize) -> Option<(u64, Bytes)> {
+        let mut state = self.state.lock();
+        let len = min(state.sendable(), max_len);
+        if len == 0 {
+            return None;
+        }
+        let start = state.in_flight;
+        let offset = state.outgoing_offset + start as u64;
+        let data = Bytes::from(&state.outgoing[start..start + len]);
+        state.in_flight += len;
+        Some((offset, data))
+    }
+
+    /// Returns true (once) if a `StreamClose` frame should be sent
+    pub(crate) fn take_close(&self) -> bool {
+        let mut state = self.state.lock();
+        if state.should_send_close() {
+            state.close_sent = true;
+            state.close_in_flight = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The other side received everything that was in flight
+    pub(crate) fn ack(&self) {
+        let mut state = self.state.lock();
+        let in_flight = state.in_flight;
+        state.outgoing.split_to(in_flight);
+        state.outgoing_offset += in_flight as u64;
+        state.in_flight = 0;
+        state.close_in_flight = false;
+        state.wake_writer();
+    }
+
+    /// What was in flight didn't make it to the other side and needs to be sent again
+    pub(crate) fn nack(&self) {
+        let mut state = self.state.lock();
+        state.in_flight = 0;
+        if state.close_in_flight {
+            state.close_sent = false;
+            state.close_in_flight = false;
+        }
+    }
+
+    fn notify(&self) {
+        if let Some(ref notifier) = self.notifier {
+            notifier.wake();
+        }
+    }
+}
+
+impl AsyncRead for DataStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock();
+        let mut read = 0;
+        while read < buf.len() {
+            let offset = match state.incoming.keys().next() {
+                Some(offset) if *offset <= state.read_offset => *offset,
+                _ => break,
+            };
+            let chunk = state.incoming.remove(&offset).unwrap();
+            let skip = (state.read_offset - offset) as usize;
+            if skip >= chunk.len() {
+                // Already read
+                continue;
+            }
+            let len = min(chunk.len() - skip, buf.len() - read);
+            buf[read..read + len].copy_from_slice(&chunk[skip..skip + len]);
+            read += len;
+            state.read_offset += len as u64;
+            if skip + len < chunk.len() {
+                let read_offset = state.read_offset;
+                state
+                    .incoming
+                    .insert(read_offset, chunk.slice_from(skip + len));
+            }
+        }
+
+        if read > 0 {
+            let should_notify =
+                state.max_offset() >= state.advertised_max_offset + DEFAULT_WINDOW / 2;
+            drop(state);
+            if should_notify {
+                // Let the other side know it can send more
+                self.notify();
+            }
+            Poll::Ready(Ok(read))
+        } else if state.remote_closed || state.connection_closed {
+            Poll::Ready(Ok(0))
+        } else {
+            state.read_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl AsyncWrite for DataStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock();
+        if state.local_closed || state.connection_closed {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Stream is closed",
+            )));
+        }
+        let space = (DEFAULT_WINDOW as usize).saturating_sub(state.outgoing.len());
+        if space == 0 {
+            state.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let len = min(space, buf.len());
+        state.outgoing.extend_from_slice(&buf[..len]);
+        drop(state);
+        self.notify();
+        Poll::Ready(Ok(len))
+    }
+
+    /// Resolves once the other side has received everything written so far
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock();
+        if state.outgoing.is_empty() {
+            Poll::Ready(Ok(()))
+        } else if state.connection_closed {
+            Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Connection closed before all data was sent",
+            )))
+        } else {
+            state.write_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Resolves once all of the data and the `StreamClose` frame were sent
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock();
+        let newly_closed = !state.local_closed;
+        state.local_closed = true;
+        if state.close_sent && !state.close_in_flight {
+            Poll::Ready(Ok(()))
+        } else if state.connection_closed {
+            if state.outgoing.is_empty() {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "Connection closed before all data was sent",
+                )))
+            }
+        } else {
+            state.write_waker = Some(cx.waker().clone());
+            drop(state);
+            if newly_closed {
+                self.notify();
+            }
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod data_stream {
+    use super::*;
+    use futures03::{
+        executor::block_on,
+        io::{AsyncReadExt, AsyncWriteExt},
+    };
+
+    #[test]
+    fn reads_data_in_order() {
+        let mut stream = DataStream::new(1, None);
+        assert!(stream.receive(5, b" world"));
+        assert!(stream.receive(0, b"hello"));
+        assert!(stream.receive(0, b"hel"));
+        stream.receive_close();
+        let mut data = Vec::new();
+        block_on(stream.read_to_end(&mut data)).unwrap();
+        assert_eq!(data, b"hello world");
+    }
+
+    #[test]
+    fn respects_remote_max_offset() {
+        let mut stream = DataStream::new(1, None);
+        block_on(stream.write_all(&[0; DEFAULT_WINDOW as usize][..])).unwrap();
+        assert!(stream.next_outgoing(usize::MAX).is_some());
+        assert!(!stream.has_outgoing());
+
+        // Nothing more can be sent until the other side raises the limit
+        stream.ack();
+        block_on(stream.write_all(b"more")).unwrap();
+        assert!(stream.next_outgoing(usize::MAX).is_none());
+        stream.set_remote_max_offset(DEFAULT_WINDOW + 2);
+        assert_eq!(
+            stream.next_outgoing(usize::MAX),
+            Some((DEFAULT_WINDOW, Bytes::from(&b"mo"[..])))
+        );
+    }
+
+    #[test]
+    fn rejects_data_past_the_max_offset() {
+        let mut stream = DataStream::new(1, None);
+        assert!(stream.receive(0, b"hello"));
+        assert!(!stream.receive(DEFAULT_WINDOW - 1, b" world"));
+        assert!(!stream.receive(u64::MAX, b" world"));
+        stream.receive_close();
+
+        // Only the allowed data was buffered
+        let mut data = Vec::new();
+        block_on(stream.read_to_end(&mut data)).unwrap();
+        assert_eq!(data, b"hello");
+    }
+
+    #[test]
+    fn resends_data_that_was_not_acknowledged() {
+        let mut stream = DataStream::new(1, None);
+        block_on(stream.write_all(b"hello")).unwrap();
+        assert_eq!(stream.next_outgoing(3), Some((0, Bytes::from(&b"hel"[..]))));
+        stream.nack();
+        assert_eq!(stream.next_outgoing(3), Some((0, Bytes::from(&b"hel"[..]))));
+        stream.ack();
+        assert_eq!(
+            stream.next_outgoing(usize::MAX),
+            Some((3, Bytes::from(&b"lo"[..])))
+        );
+    }
+}
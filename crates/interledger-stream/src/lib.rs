@@ -6,27 +6,39 @@
 
 mod client;
 mod congestion;
+mod connection;
 mod crypto;
+mod data;
 mod error;
 mod packet;
+mod quote;
 mod server;
 
-pub use client::send_money;
+pub use client::{send_destination_amount, send_money, StreamDelivery};
+pub use connection::{connect, StreamClientConnection};
+pub use data::DataStream;
 pub use error::Error;
-pub use server::{ConnectionGenerator, StreamReceiverService};
+pub use quote::{quote, Quote};
+pub use server::{ConnectionEvent, ConnectionGenerator, StreamConnection, StreamReceiverService};
 
 #[cfg(test)]
 pub mod test_helpers {
+    use super::{ConnectionEvent, ConnectionGenerator, StreamReceiverService};
     use bytes::Bytes;
-    use futures::{future::ok, Future};
-    use interledger_ildcp::IldcpAccount;
-    use interledger_packet::Address;
-    use interledger_router::RouterStore;
-    use interledger_service::{Account, AccountStore, Username};
+    use futures::sync::mpsc::UnboundedReceiver;
+    use futures03::future::{err, ok};
+    use interledger_ildcp::{IldcpAccount, IldcpService};
+    use interledger_packet::{Address, ErrorCode, RejectBuilder};
+    use interledger_router::{Router, RouterStore, RoutingTable};
+    use interledger_service::{
+        outgoing_service_fn, Account, AccountStore, IncomingService, StoreError, Username,
+    };
     use lazy_static::lazy_static;
-    use std::collections::HashMap;
+    use std::future::Future;
     use std::iter::FromIterator;
+    use std::pin::Pin;
     use std::str::FromStr;
+    use std::sync::Arc;
 
     lazy_static! {
         pub static ref EXAMPLE_CONNECTOR: Address = Address::from_str("example.connector").unwrap();
@@ -68,6 +80,16 @@ pub mod test_helpers {
         }
     }
 
+    /// An account with the XYZ asset at scale 9 and the given client address
+    pub fn test_account(client_address: &Address) -> TestAccount {
+        TestAccount {
+            id: 0,
+            ilp_address: client_address.clone(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+        }
+    }
+
     #[derive(Clone)]
     pub struct TestStore {
         pub route: (Bytes, TestAccount),
@@ -79,58 +101,45 @@ pub mod test_helpers {
         fn get_accounts(
             &self,
             _account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
-        ) -> Box<dyn Future<Item = Vec<TestAccount>, Error = ()> + Send> {
-            Box::new(ok(vec![self.route.1.clone()]))
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<TestAccount>, StoreError>> + Send>> {
+            Box::pin(ok(vec![self.route.1.clone()]))
         }
 
         // stub implementation (not used in these tests)
         fn get_account_id_from_username(
             &self,
             _username: &Username,
-        ) -> Box<dyn Future<Item = u64, Error = ()> + Send> {
-            Box::new(ok(1))
+        ) -> Pin<Box<dyn Future<Output = Result<u64, StoreError>> + Send>> {
+            Box::pin(ok(1))
         }
     }
 
     impl RouterStore for TestStore {
-        fn routing_table(&self) -> HashMap<Bytes, u64> {
-            HashMap::from_iter(vec![(self.route.0.clone(), self.route.1.id())].into_iter())
+        fn routing_table(&self) -> Arc<RoutingTable<u64>> {
+            Arc::new(RoutingTable::from_iter(vec![(
+                self.route.0.clone(),
+                self.route.1.id(),
+            )]))
         }
     }
-}
-
-#[cfg(test)]
-mod send_money_to_receiver {
-    use super::test_helpers::*;
-    use super::*;
-    use bytes::Bytes;
-    use futures::Future;
-    use interledger_ildcp::IldcpService;
-    use interledger_packet::Address;
-    use interledger_packet::{ErrorCode, RejectBuilder};
-    use interledger_router::Router;
-    use interledger_service::outgoing_service_fn;
-    use std::str::FromStr;
-    use tokio::runtime::Runtime;
 
-    #[test]
-    fn send_money_test() {
+    /// A STREAM receiver at `example.receiver` that accepts up to `receive_max` per connection,
+    /// behind a Router and an ILDCP service. Returns the receiver, the events it emits, and the
+    /// destination account and shared secret of a new connection to it
+    pub fn test_receiver(
+        receive_max: u64,
+    ) -> (
+        impl IncomingService<TestAccount> + Clone,
+        UnboundedReceiver<ConnectionEvent>,
+        Address,
+        [u8; 32],
+    ) {
         let server_secret = Bytes::from(&[0; 32][..]);
-        let destination_address = Address::from_str("example.receiver").unwrap();
-        let account = TestAccount {
-            id: 0,
-            ilp_address: destination_address.clone(),
-            asset_code: "XYZ".to_string(),
-            asset_scale: 9,
-        };
-        let store = TestStore {
-            route: (destination_address.to_bytes(), account),
-        };
         let connection_generator = ConnectionGenerator::new(server_secret.clone());
-        let server = StreamReceiverService::new(
+        let mut server = StreamReceiverService::new(
             server_secret,
             outgoing_service_fn(|_| {
-                Err(RejectBuilder {
+                err(RejectBuilder {
                     code: ErrorCode::F02_UNREACHABLE,
                     message: b"No other outgoing handler",
                     triggered_by: Some(&EXAMPLE_RECEIVER),
@@ -139,31 +148,398 @@ mod send_money_to_receiver {
                 .build())
             }),
         );
-        let server = Router::new(EXAMPLE_RECEIVER.clone(), store, server);
-        let server = IldcpService::new(server);
-
+        server.receive_max(receive_max);
+        let events = server.subscribe();
+        let store = TestStore {
+            route: (EXAMPLE_RECEIVER.to_bytes(), test_account(&EXAMPLE_RECEIVER)),
+        };
+        let server = IldcpService::new(Router::new(EXAMPLE_RECEIVER.clone(), store, server));
         let (destination_account, shared_secret) =
-            connection_generator.generate_address_and_secret(&destination_address);
+            connection_generator.generate_address_and_secret(&EXAMPLE_RECEIVER);
+        (server, events, destination_account, shared_secret)
+    }
+}
+
+#[cfg(test)]
+mod send_money_to_receiver {
+    use super::packet::StreamPacket;
+    use super::test_helpers::*;
+    use super::*;
+    use bytes::BytesMut;
+    use futures::{Future, Stream};
+    use interledger_service::{incoming_service_fn, IncomingRequest, IncomingService};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use tokio::runtime::Runtime;
 
-        let destination_address = Address::from_str("example.receiver").unwrap();
+    #[test]
+    fn send_money_test() {
+        let account = test_account(&EXAMPLE_RECEIVER);
+        let (server, events, destination_account, shared_secret) = test_receiver(u64::MAX);
         let run = send_money(
             server,
-            &test_helpers::TestAccount {
-                id: 0,
-                asset_code: "XYZ".to_string(),
-                asset_scale: 9,
-                ilp_address: destination_address,
-            },
+            &account,
             destination_account,
             &shared_secret[..],
             100,
         )
-        .and_then(|(delivered_amount, _service)| {
-            assert_eq!(delivered_amount, 100);
+        .and_then(|(delivery, _service)| {
+            assert_eq!(delivery.delivered_amount, 100);
+            assert_eq!(delivery.destination_asset_code, Some("XYZ".to_string()));
+            assert_eq!(delivery.destination_asset_scale, Some(9));
             Ok(())
         })
         .map_err(|err| panic!(err));
         let runtime = Runtime::new().unwrap();
         runtime.block_on_all(run).unwrap();
+
+        // The receiver was notified about the money and the connection closing
+        let events = events.collect().wait().unwrap();
+        match events.last().unwrap() {
+            ConnectionEvent::Closed(connection) => {
+                assert_eq!(connection.total_received(), 100);
+                assert_eq!(connection.stream_received(1), 100);
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+        let received: u64 = events
+            .iter()
+            .map(|event| match event {
+                ConnectionEvent::MoneyReceived { amount, .. } => *amount,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(received, 100);
+    }
+
+    #[test]
+    fn stops_when_receiver_does_not_accept_more() {
+        let account = test_account(&EXAMPLE_RECEIVER);
+        let (server, _events, destination_account, shared_secret) = test_receiver(0);
+        let result = send_money(
+            server,
+            &account,
+            destination_account,
+            &shared_secret[..],
+            100,
+        )
+        .wait();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn delivers_exact_destination_amount() {
+        let account = test_account(&EXAMPLE_RECEIVER);
+        let (server, events, destination_account, shared_secret) = test_receiver(u64::MAX);
+        let (delivery, _) = send_destination_amount(
+            server,
+            &account,
+            destination_account,
+            &shared_secret[..],
+            100,
+            1000,
+            0.9,
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(
+            delivery,
+            StreamDelivery {
+                sent_amount: 100,
+                delivered_amount: 100,
+                destination_asset_code: Some("XYZ".to_string()),
+                destination_asset_scale: Some(9),
+            }
+        );
+
+        // The test packets used to find the exchange rate did not deliver anything
+        let events = events.collect().wait().unwrap();
+        match events.last().unwrap() {
+            ConnectionEvent::Closed(connection) => assert_eq!(connection.total_received(), 100),
+            event => panic!("Unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn continues_sequence_after_probing_exchange_rate() {
+        let account = test_account(&EXAMPLE_RECEIVER);
+        let (mut server, _events, destination_account, shared_secret) = test_receiver(u64::MAX);
+        let sequences = Arc::new(Mutex::new(Vec::new()));
+        let sequences_clone = sequences.clone();
+        let receiver = destination_account.clone();
+        let service = incoming_service_fn(move |request: IncomingRequest<TestAccount>| {
+            // Skip the ILDCP request
+            if request.prepare.destination() == receiver {
+                let packet = StreamPacket::from_encrypted(
+                    &shared_secret,
+                    BytesMut::from(request.prepare.data()),
+                )
+                .unwrap();
+                sequences_clone.lock().push(packet.sequence());
+            }
+            server.handle_request(request)
+        });
+        send_destination_amount(
+            service,
+            &account,
+            destination_account,
+            &shared_secret[..],
+            100,
+            1000,
+            0.9,
+        )
+        .wait()
+        .unwrap();
+
+        let sequences = sequences.lock();
+        // The test packet, the money and the connection close
+        assert!(sequences.len() >= 3);
+        assert_eq!(sequences[0], 1);
+        assert!(sequences.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn does_not_send_below_min_exchange_rate() {
+        let account = test_account(&EXAMPLE_RECEIVER);
+        let (server, _events, destination_account, shared_secret) = test_receiver(u64::MAX);
+        let result = send_destination_amount(
+            server.clone(),
+            &account,
+            destination_account.clone(),
+            &shared_secret[..],
+            100,
+            1000,
+            1.5,
+        )
+        .wait();
+        match result {
+            Err(Error::ExchangeRateError(_)) => {}
+            result => panic!(
+                "Unexpected result: {:?}",
+                result.map(|(delivery, _)| delivery)
+            ),
+        }
+
+        // Delivering the amount would cost more than the maximum
+        let result = send_destination_amount(
+            server,
+            &account,
+            destination_account,
+            &shared_secret[..],
+            100,
+            99,
+            0.5,
+        )
+        .wait();
+        match result {
+            Err(Error::ExchangeRateError(_)) => {}
+            result => panic!(
+                "Unexpected result: {:?}",
+                result.map(|(delivery, _)| delivery)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod send_data_to_receiver {
+    use super::test_helpers::*;
+    use super::*;
+    use futures::{Future, Stream};
+    use futures03::{
+        channel::oneshot,
+        compat::{Future01CompatExt, Stream01CompatExt},
+        future::join,
+        io::{AsyncReadExt, AsyncWriteExt},
+        FutureExt, StreamExt, TryFutureExt,
+    };
+    use interledger_service::{incoming_service_fn, IncomingRequest, IncomingService};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn exchanges_data_with_receiver() {
+        let account = test_account(&EXAMPLE_RECEIVER);
+        let (server, events, destination_account, shared_secret) = test_receiver(u64::MAX);
+        let (connection, run) = connect(server, &account, destination_account, &shared_secret[..]);
+        let mut runtime = Runtime::new().unwrap();
+        runtime.spawn(run.map_err(|err| panic!("Connection error: {:?}", err)));
+
+        let client = async move {
+            let mut stream = connection.open_stream();
+            stream.write_all(b"send me an invoice").await.unwrap();
+            stream.close().await.unwrap();
+            let mut reply = Vec::new();
+            stream.read_to_end(&mut reply).await.unwrap();
+            connection.close();
+            reply
+        };
+        let receiver = async move {
+            // Reply on the first stream the sender sends data on
+            let mut events = events.compat();
+            let mut stream = loop {
+                match events.next().await {
+                    Some(Ok(ConnectionEvent::DataReceived {
+                        connection,
+                        stream_id,
+                    })) => break connection.data_stream(stream_id),
+                    Some(_) => {}
+                    None => panic!("Receiver stopped before getting any data"),
+                }
+            };
+            let mut request = Vec::new();
+            stream.read_to_end(&mut request).await.unwrap();
+            stream.write_all(b"invoice #1").await.unwrap();
+            stream.close().await.unwrap();
+            request
+        };
+
+        let (reply, request) = runtime
+            .block_on(Box::pin(join(client, receiver).map(Ok::<_, ()>)).compat())
+            .unwrap();
+        assert_eq!(request, b"send me an invoice");
+        assert_eq!(reply, b"invoice #1");
+    }
+
+    #[test]
+    fn waits_for_receiver_to_allow_more_streams() {
+        let account = test_account(&EXAMPLE_RECEIVER);
+        let (server, events, destination_account, shared_secret) = test_receiver(u64::MAX);
+        let (connection, run) = connect(server, &account, destination_account, &shared_secret[..]);
+
+        // One more stream than the receiver allows at first
+        let client = async move {
+            for _ in 0..11 {
+                let mut stream = connection.open_stream();
+                stream.write_all(b"hello").await.unwrap();
+                stream.close().await.unwrap();
+            }
+            connection.close();
+        };
+        let mut runtime = Runtime::new().unwrap();
+        runtime.spawn(Box::pin(client.map(Ok::<_, ()>)).compat());
+        runtime.block_on(run).unwrap();
+
+        let mut streams: Vec<u64> = events
+            .wait()
+            .filter_map(|event| match event {
+                Ok(ConnectionEvent::DataReceived { stream_id, .. }) => Some(stream_id),
+                _ => None,
+            })
+            .collect();
+        streams.dedup();
+        assert_eq!(streams.len(), 11);
+        assert_eq!(streams.last(), Some(&21));
+    }
+
+    #[test]
+    fn receives_data_written_while_money_is_sent() {
+        let account = test_account(&EXAMPLE_RECEIVER);
+        let (server, events, destination_account, shared_secret) = test_receiver(u64::MAX);
+        let (connection, run) = connect(
+            server.clone(),
+            &account,
+            destination_account.clone(),
+            &shared_secret[..],
+        );
+        let mut runtime = Runtime::new().unwrap();
+        runtime.spawn(run.map_err(|err| panic!("Connection error: {:?}", err)));
+
+        // The payment closes the connection when it is done, so hold its last packet until
+        // the reply was read
+        let (reply_read, reply_was_read) = oneshot::channel::<()>();
+        let reply_was_read = reply_was_read.shared();
+        let receiver = destination_account.clone();
+        let payment_service = incoming_service_fn(move |request: IncomingRequest<TestAccount>| {
+            let mut server = server.clone();
+            let reply_was_read = reply_was_read.clone();
+            let is_close =
+                request.prepare.destination() == receiver && request.prepare.amount() == 0;
+            async move {
+                if is_close {
+                    let _ = reply_was_read.await;
+                }
+                server.handle_request(request).await
+            }
+        });
+
+        let test = async move {
+            let mut stream = connection.open_stream();
+            stream.write_all(b"send me an invoice").await.unwrap();
+            stream.flush().await.unwrap();
+            let mut events = events.compat();
+            let receiver_connection = loop {
+                match events.next().await {
+                    Some(Ok(ConnectionEvent::DataReceived { connection, .. })) => break connection,
+                    Some(_) => {}
+                    None => panic!("Receiver stopped before getting any data"),
+                }
+            };
+
+            // The reply is written before the money arrives, so it is waiting to be sent while
+            // the receiver answers the payment's packets
+            let mut reply_stream = receiver_connection.data_stream(stream.stream_id());
+            reply_stream.write_all(b"invoice #1").await.unwrap();
+            let payment = send_money(
+                payment_service,
+                &account,
+                destination_account,
+                &shared_secret[..],
+                100,
+            )
+            .compat();
+            let read_reply = async move {
+                let mut reply = [0; 10];
+                stream.read_exact(&mut reply).await.unwrap();
+                let _ = reply_read.send(());
+                reply
+            };
+            let (payment, reply) = join(payment, read_reply).await;
+            (payment.unwrap().0, reply)
+        };
+
+        let (delivery, reply) = runtime
+            .block_on(Box::pin(test.map(Ok::<_, ()>)).compat())
+            .unwrap();
+        assert_eq!(delivery.delivered_amount, 100);
+        assert_eq!(&reply, b"invoice #1");
+    }
+
+    #[test]
+    fn picks_up_data_on_streams_the_receiver_opens() {
+        let account = test_account(&EXAMPLE_RECEIVER);
+        let (server, events, destination_account, shared_secret) = test_receiver(u64::MAX);
+        let (connection, run) = connect(server, &account, destination_account, &shared_secret[..]);
+        let mut runtime = Runtime::new().unwrap();
+        runtime.spawn(run.map_err(|err| panic!("Connection error: {:?}", err)));
+
+        // The connection has no streams waiting for the receiver, so the receiver's data is
+        // only picked up once the stream is read
+        let test = async move {
+            let mut events = events.compat();
+            let receiver_connection = match events.next().await {
+                Some(Ok(ConnectionEvent::Opened(connection))) => connection,
+                _ => panic!("Receiver did not open the connection"),
+            };
+            let mut stream = receiver_connection.data_stream(2);
+            let write = async move {
+                stream.write_all(b"hello").await.unwrap();
+                stream.close().await.unwrap();
+            };
+            let read = async move {
+                let mut data = Vec::new();
+                connection
+                    .data_stream(2)
+                    .read_to_end(&mut data)
+                    .await
+                    .unwrap();
+                connection.close();
+                data
+            };
+            join(write, read).await.1
+        };
+        let data = runtime
+            .block_on(Box::pin(test.map(Ok::<_, ()>)).compat())
+            .unwrap();
+        assert_eq!(data, b"hello");
     }
 }
@@ -0,0 +1,341 @@
+use super::crypto::*;
+use super::error::Error;
+use super::packet::*;
+use bytes::{Bytes, BytesMut};
+use futures::Future;
+use futures03::TryFutureExt;
+use interledger_packet::{
+    Address, ErrorClass, ErrorCode as IlpErrorCode, MaxPacketAmountDetails,
+    PacketType as IlpPacketType, PrepareBuilder,
+};
+use interledger_service::*;
+use log::debug;
+use std::{
+    str,
+    time::{Duration, SystemTime},
+};
+
+/// How many test packets to send before giving up on finding the exchange rate
+const MAX_PROBE_ATTEMPTS: usize = 10;
+
+/// The exchange rate and path limits found by sending test packets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quote {
+    /// Amount the receiver gets per unit sent, in the receiver's and sender's asset units
+    pub exchange_rate: f64,
+    /// Largest packet amount a connector along the path accepts, if one reported a limit
+    pub max_packet_amount: Option<u64>,
+}
+
+/// Get a quote for sending `source_amount` to the receiver using the STREAM transport protocol.
+///
+/// This sends unfulfillable test packets, so no money is moved. If a connector along the path
+/// rejects `source_amount` as too large, the returned exchange rate is for a smaller amount
+/// and `max_packet_amount` is set.
+pub fn quote<S, A>(
+    service: S,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: &[u8],
+    source_amount: u64,
+) -> impl Future<Item = Quote, Error = Error>
+where
+    S: IncomingService<A>,
+    A: Account,
+{
+    let shared_secret = Bytes::from(shared_secret);
+    let from_account = from_account.clone();
+    Box::pin(async move {
+        let mut service = service;
+        let (quote, next_sequence) = probe_exchange_rate(
+            &mut service,
+            from_account.clone(),
+            destination_account.clone(),
+            shared_secret.clone(),
+            source_amount,
+        )
+        .await?;
+        // The test packets opened a connection on the receiver, so close it rather than
+        // leaving it open until the receiver cleans it up
+        close_connection(
+            &mut service,
+            from_account,
+            destination_account,
+            &shared_secret,
+            next_sequence,
+        )
+        .await;
+        Ok(quote)
+    })
+    .compat()
+}
+
+/// Find the exchange rate to the receiver by sending it unfulfillable packets.
+///
+/// The receiver rejects each test packet but reports the amount that arrived in the
+/// STREAM packet in its reject, so no money is moved.
+///
+/// The test packets use the first sequence numbers of the connection, so this also returns the
+/// next sequence number for any packets sent on the connection afterwards.
+pub(crate) async fn probe_exchange_rate<S, A>(
+    service: &mut S,
+    from_account: A,
+    destination_account: Address,
+    shared_secret: Bytes,
+    amount: u64,
+) -> Result<(Quote, u64), Error>
+where
+    S: IncomingService<A>,
+    A: Account,
+{
+    let mut amount = amount;
+    let mut max_packet_amount: Option<u64> = None;
+    for sequence in 1..=MAX_PROBE_ATTEMPTS as u64 {
+        if amount == 0 {
+            break;
+        }
+        let stream_packet = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence,
+            frames: &[Frame::StreamMoney(StreamMoneyFrame {
+                stream_id: 1,
+                shares: 1,
+            })],
+        }
+        .build();
+        let data = stream_packet.into_encrypted(&shared_secret);
+        let prepare = PrepareBuilder {
+            destination: destination_account.clone(),
+            amount,
+            execution_condition: &random_condition(),
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            data: &data[..],
+        }
+        .build();
+        debug!(
+            "Sending test packet of {} to find the exchange rate",
+            amount
+        );
+
+        let reject = match service
+            .handle_request(IncomingRequest {
+                from: from_account.clone(),
+                prepare,
+                extensions: Extensions::new(),
+            })
+            .await
+        {
+            Ok(_) => {
+                return Err(Error::ExchangeRateError(
+                    "Receiver fulfilled a test packet".to_string(),
+                ))
+            }
+            Err(reject) => reject,
+        };
+
+        match reject.code() {
+            IlpErrorCode::F99_APPLICATION_ERROR => {
+                let packet =
+                    StreamPacket::from_encrypted(&shared_secret, BytesMut::from(reject.data()))
+                        .map_err(|_| {
+                            Error::ExchangeRateError(
+                                "Unable to parse STREAM packet from test packet reject".to_string(),
+                            )
+                        })?;
+                let exchange_rate = packet.prepare_amount() as f64 / amount as f64;
+                debug!(
+                    "Test packet of {} delivered {} (exchange rate: {})",
+                    amount,
+                    packet.prepare_amount(),
+                    exchange_rate
+                );
+                return Ok((
+                    Quote {
+                        exchange_rate,
+                        max_packet_amount,
+                    },
+                    sequence + 1,
+                ));
+            }
+            IlpErrorCode::F08_AMOUNT_TOO_LARGE => {
+                let details = MaxPacketAmountDetails::from_bytes(reject.data()).map_err(|_| {
+                    Error::ExchangeRateError(
+                        "Unable to parse max packet amount details from reject".to_string(),
+                    )
+                })?;
+                // The limit is in the units of the connector that rejected the packet
+                let reduced = (u128::from(details.max_amount()) * u128::from(amount))
+                    .checked_div(u128::from(details.amount_received()))
+                    .unwrap_or(0) as u64;
+                amount = reduced.min(amount - 1);
+                max_packet_amount = Some(amount);
+            }
+            IlpErrorCode::T04_INSUFFICIENT_LIQUIDITY => amount /= 10,
+            code if code.class() == ErrorClass::Temporary => {}
+            code => {
+                return Err(Error::ExchangeRateError(format!(
+                    "Test packet was rejected with error: {} {}",
+                    code,
+                    str::from_utf8(reject.message()).unwrap_or_default(),
+                )))
+            }
+        }
+    }
+    Err(Error::ExchangeRateError(
+        "Unable to find the exchange rate to the receiver".to_string(),
+    ))
+}
+
+/// Tell the receiver the connection is closed by sending it a `ConnectionClose` frame.
+///
+/// The packet is unfulfillable and carries no money, so its reject is expected and ignored.
+async fn close_connection<S, A>(
+    service: &mut S,
+    from_account: A,
+    destination_account: Address,
+    shared_secret: &[u8],
+    sequence: u64,
+) where
+    S: IncomingService<A>,
+    A: Account,
+{
+    let stream_packet = StreamPacketBuilder {
+        ilp_packet_type: IlpPacketType::Prepare,
+        prepare_amount: 0,
+        sequence,
+        frames: &[Frame::ConnectionClose(ConnectionCloseFrame {
+            code: ErrorCode::NoError,
+            message: "",
+        })],
+    }
+    .build();
+    let data = stream_packet.into_encrypted(shared_secret);
+    let prepare = PrepareBuilder {
+        destination: destination_account,
+        amount: 0,
+        execution_condition: &random_condition(),
+        expires_at: SystemTime::now() + Duration::from_secs(30),
+        data: &data[..],
+    }
+    .build();
+    debug!("Closing connection after sending test packets");
+    let _ = service
+        .handle_request(IncomingRequest {
+            from: from_account,
+            prepare,
+            extensions: Extensions::new(),
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod quote_tests {
+    use super::*;
+    use crate::test_helpers::{test_account, test_receiver, EXAMPLE_CONNECTOR, EXAMPLE_RECEIVER};
+    use crate::ConnectionEvent;
+    use futures::Stream;
+    use futures03::future::err;
+    use interledger_packet::RejectBuilder;
+    use interledger_service::incoming_service_fn;
+    use std::str::FromStr;
+
+    #[test]
+    fn finds_exchange_rate_and_max_packet_amount() {
+        let account = test_account(&Address::from_str("example.sender").unwrap());
+        let shared_secret = Bytes::from(&[0; 32][..]);
+        let receiver_secret = shared_secret.clone();
+        // Doubles the amount but only forwards packets of up to 50
+        let service = incoming_service_fn(move |request| {
+            let amount = request.prepare.amount();
+            if amount > 50 {
+                let details = MaxPacketAmountDetails::new(amount, 50).to_bytes();
+                return err(RejectBuilder {
+                    code: IlpErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: b"Packet too large",
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &details[..],
+                }
+                .build());
+            }
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Reject,
+                prepare_amount: amount * 2,
+                sequence: 1,
+                frames: &[],
+            }
+            .build()
+            .into_encrypted(&receiver_secret);
+            err(RejectBuilder {
+                code: IlpErrorCode::F99_APPLICATION_ERROR,
+                message: &[],
+                triggered_by: Some(&EXAMPLE_RECEIVER),
+                data: &data[..],
+            }
+            .build())
+        });
+
+        let result = quote(
+            service,
+            &account,
+            EXAMPLE_RECEIVER.clone(),
+            &shared_secret[..],
+            1000,
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(
+            result,
+            Quote {
+                exchange_rate: 2.0,
+                max_packet_amount: Some(50),
+            }
+        );
+    }
+
+    #[test]
+    fn stops_at_final_errors() {
+        let account = test_account(&Address::from_str("example.sender").unwrap());
+        let service = incoming_service_fn(|_| {
+            err(RejectBuilder {
+                code: IlpErrorCode::F02_UNREACHABLE,
+                message: b"No route found",
+                triggered_by: Some(&EXAMPLE_CONNECTOR),
+                data: &[],
+            }
+            .build())
+        });
+        let result = quote(
+            service,
+            &account,
+            EXAMPLE_RECEIVER.clone(),
+            &[0; 32][..],
+            1000,
+        )
+        .wait();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn closes_the_receiver_connection() {
+        let (server, events, destination_account, shared_secret) = test_receiver(u64::MAX);
+        let result = quote(
+            server,
+            &test_account(&EXAMPLE_RECEIVER),
+            destination_account,
+            &shared_secret[..],
+            1000,
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(result.exchange_rate, 1.0);
+
+        let events = events.collect().wait().unwrap();
+        match events.last() {
+            Some(ConnectionEvent::Closed(connection)) => {
+                assert_eq!(connection.total_received(), 0)
+            }
+            _ => panic!("Expected the connection to be closed, got: {:?}", events),
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use super::crypto::*;
+use super::data::{DataStream, DEFAULT_MAX_STREAM_ID, MAX_DATA_PER_PACKET};
+use super::packet::ErrorCode as StreamErrorCode;
 use super::packet::*;
 use base64;
 use bytes::Bytes;
-use futures::future::result;
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures03::future::ready;
 use hex;
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::{
@@ -11,10 +14,16 @@ use interledger_packet::{
 };
 use interledger_service::{Account, BoxedIlpFuture, OutgoingRequest, OutgoingService};
 use log::debug;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::marker::PhantomData;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
 const STREAM_SERVER_SECRET_GENERATOR: &[u8] = b"ilp_stream_secret_generator";
+/// How long a connection is kept after the last packet the sender sent on it
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// A STREAM connection generator that creates `destination_account` and `shared_secret` values
 /// based on a single root secret.
@@ -85,15 +94,310 @@ impl ConnectionGenerator {
     }
 }
 
+/// Notifications about the connections handled by a `StreamReceiverService`.
+///
+/// Amounts are denominated in the units of the account the money was received on.
+#[derive(Clone, Debug)]
+pub enum ConnectionEvent {
+    /// The first packet for a connection was received
+    Opened(StreamConnection),
+    /// A packet was fulfilled and `amount` was credited to the given stream
+    MoneyReceived {
+        connection: StreamConnection,
+        stream_id: u64,
+        amount: u64,
+    },
+    /// Data was received on the given stream, which can be read with `StreamConnection::data_stream`
+    DataReceived {
+        connection: StreamConnection,
+        stream_id: u64,
+    },
+    /// The sender closed the connection
+    Closed(StreamConnection),
+}
+
+#[derive(Debug, Default)]
+struct StreamState {
+    total_received: u64,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct ConnectionState {
+    source_account: Option<Address>,
+    receive_max: u64,
+    total_received: u64,
+    streams: HashMap<u64, StreamState>,
+    data_streams: BTreeMap<u64, DataStream>,
+    closed: bool,
+    last_activity: Instant,
+    /// The sequence number of the data packet we last replied to with data the sender has not
+    /// confirmed yet. Data is only sent in replies to the packets of a `StreamClientConnection`
+    unacked_sequence: Option<u64>,
+    /// The highest stream ID the sender may open, which goes up as its streams are closed
+    max_stream_id: u64,
+}
+
+impl ConnectionState {
+    fn data_stream(&mut self, stream_id: u64) -> DataStream {
+        self.data_streams
+            .entry(stream_id)
+            .or_insert_with(|| DataStream::new(stream_id, None))
+            .clone()
+    }
+
+    /// Whether the sender may use the given stream. Streams that are already open are always allowed,
+    /// but new ones must be within the max stream ID
+    fn allows_stream(&self, stream_id: u64) -> bool {
+        stream_id <= self.max_stream_id
+            || self.streams.contains_key(&stream_id)
+            || self.data_streams.contains_key(&stream_id)
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        for stream in self.data_streams.values() {
+            stream.close_connection();
+        }
+    }
+}
+
+/// A handle to the server side of a STREAM connection.
+///
+/// Connections are identified by the `destination_account` the sender is paying, which is
+/// one of the addresses created by the `ConnectionGenerator`. Clones of the handle refer to
+/// the same connection.
+#[derive(Clone, Debug)]
+pub struct StreamConnection {
+    destination_account: Address,
+    state: Arc<Mutex<ConnectionState>>,
+    connections: Weak<RwLock<ConnectionMap>>,
+}
+
+impl StreamConnection {
+    fn new(
+        destination_account: Address,
+        receive_max: u64,
+        connections: Weak<RwLock<ConnectionMap>>,
+    ) -> Self {
+        StreamConnection {
+            destination_account,
+            state: Arc::new(Mutex::new(ConnectionState {
+                source_account: None,
+                receive_max,
+                total_received: 0,
+                streams: HashMap::new(),
+                data_streams: BTreeMap::new(),
+                closed: false,
+                last_activity: Instant::now(),
+                unacked_sequence: None,
+                max_stream_id: DEFAULT_MAX_STREAM_ID,
+            })),
+            connections,
+        }
+    }
+
+    pub fn destination_account(&self) -> &Address {
+        &self.destination_account
+    }
+
+    /// The address the sender told us to use to reach it, if any
+    pub fn source_account(&self) -> Option<Address> {
+        self.state.lock().source_account.clone()
+    }
+
+    /// Total amount received on all of the connection's streams
+    pub fn total_received(&self) -> u64 {
+        self.state.lock().total_received
+    }
+
+    /// Amount received on the given stream
+    pub fn stream_received(&self, stream_id: u64) -> u64 {
+        self.state
+            .lock()
+            .streams
+            .get(&stream_id)
+            .map(|stream| stream.total_received)
+            .unwrap_or(0)
+    }
+
+    /// Maximum total amount the connection will accept
+    pub fn receive_max(&self) -> u64 {
+        self.state.lock().receive_max
+    }
+
+    /// Change the maximum total amount the connection will accept.
+    ///
+    /// Packets that would take the total received above this amount are rejected
+    /// and the sender is told how much more it can send.
+    pub fn set_receive_max(&self, receive_max: u64) {
+        self.state.lock().receive_max = receive_max;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+
+    /// Get the stream with the given ID to read the data the sender sent on it or write data to it.
+    ///
+    /// Data written to the stream is sent back to the sender in the responses to the packets
+    /// of its `StreamClientConnection`.
+    pub fn data_stream(&self, stream_id: u64) -> DataStream {
+        self.state.lock().data_stream(stream_id)
+    }
+
+    /// Stop accepting money and data on this connection.
+    ///
+    /// Further packets are rejected with a `ConnectionClose` frame so the sender stops as well.
+    pub fn close(&self) {
+        self.state.lock().close();
+        self.remove();
+    }
+
+    /// Stop tracking the closed connection in the `StreamReceiverService`
+    fn remove(&self) {
+        if let Some(connections) = self.connections.upgrade() {
+            connections.write().remove(self, Instant::now());
+        }
+    }
+}
+
+/// The open connections of a `StreamReceiverService`, along with when the ones that were
+/// closed were removed, so that late packets for those are rejected instead of opening them again.
+#[derive(Debug, Default)]
+struct ConnectionMap {
+    open: HashMap<Address, StreamConnection>,
+    closed: HashMap<Address, Instant>,
+}
+
+impl ConnectionMap {
+    fn remove(&mut self, connection: &StreamConnection, now: Instant) {
+        let address = &connection.destination_account;
+        // The address may have been reused for a new connection after this one went idle
+        let is_current = self
+            .open
+            .get(address)
+            .map(|open| Arc::ptr_eq(&open.state, &connection.state))
+            .unwrap_or(false);
+        if is_current {
+            self.open.remove(address);
+            self.closed.insert(address.clone(), now);
+        }
+    }
+
+    /// Close and remove the connections the sender has not used within the idle timeout,
+    /// and forget the ones that were closed before then. Returns the connections it closed.
+    fn remove_idle(&mut self, idle_timeout: Duration, now: Instant) -> Vec<StreamConnection> {
+        let mut removed = Vec::new();
+        self.open.retain(|_, connection| {
+            let mut state = connection.state.lock();
+            if now.duration_since(state.last_activity) < idle_timeout {
+                true
+            } else {
+                state.close();
+                removed.push(connection.clone());
+                false
+            }
+        });
+        self.closed
+            .retain(|_, closed_at| now.duration_since(*closed_at) < idle_timeout);
+        removed
+    }
+}
+
+/// The connections of a `StreamReceiverService` and the subscribers to their events.
+#[derive(Clone)]
+struct Connections {
+    connections: Arc<RwLock<ConnectionMap>>,
+    subscribers: Arc<Mutex<Vec<UnboundedSender<ConnectionEvent>>>>,
+    receive_max: u64,
+    idle_timeout: Duration,
+}
+
+impl Connections {
+    fn new(receive_max: u64) -> Self {
+        Connections {
+            connections: Arc::new(RwLock::new(ConnectionMap::default())),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            receive_max,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+        }
+    }
+
+    fn get(&self, destination_account: &Address) -> Option<StreamConnection> {
+        self.connections
+            .read()
+            .open
+            .get(destination_account)
+            .cloned()
+    }
+
+    /// Returns the connection for the given address and whether it was just created.
+    ///
+    /// Idle connections are removed whenever a new one is opened. Packets for connections
+    /// that were closed recently get a closed connection that is not tracked.
+    fn get_or_open(&self, destination_account: &Address) -> (StreamConnection, bool) {
+        if let Some(connection) = self.get(destination_account) {
+            return (connection, false);
+        }
+        let (connection, idle_connections) = {
+            let mut connections = self.connections.write();
+            if let Some(connection) = connections.open.get(destination_account) {
+                return (connection.clone(), false);
+            }
+            let idle_connections = connections.remove_idle(self.idle_timeout, Instant::now());
+            if connections.closed.contains_key(destination_account) {
+                let connection = StreamConnection::new(destination_account.clone(), 0, Weak::new());
+                connection.state.lock().closed = true;
+                return (connection, false);
+            }
+            let connection = StreamConnection::new(
+                destination_account.clone(),
+                self.receive_max,
+                Arc::downgrade(&self.connections),
+            );
+            connections
+                .open
+                .insert(destination_account.clone(), connection.clone());
+            (connection, idle_connections)
+        };
+        for idle_connection in idle_connections {
+            debug!(
+                "Closing idle connection {}",
+                idle_connection.destination_account()
+            );
+            self.notify(ConnectionEvent::Closed(idle_connection));
+        }
+        (connection, true)
+    }
+
+    fn subscribe(&self) -> UnboundedReceiver<ConnectionEvent> {
+        let (sender, receiver) = unbounded();
+        self.subscribers.lock().push(sender);
+        receiver
+    }
+
+    fn notify(&self, event: ConnectionEvent) {
+        // Subscribers that dropped their receiver are removed
+        self.subscribers
+            .lock()
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+}
+
 /// An OutgoingService that fulfills incoming STREAM packets.
 ///
-/// Note this does **not** maintain STREAM state, but instead fulfills
-/// all incoming packets to collect the money.
+/// The server keeps track of each connection it has received packets for, along with the
+/// amount received on each of its streams, and rejects packets that would go over the
+/// connection's `receive_max`. Callers can look up connections by address or `subscribe`
+/// to be notified as connections are opened, receive money or data and get closed.
 ///
-/// This does not currently support handling data sent via STREAM.
+/// Data sent on a connection's streams can be read, and replied to, using the
+/// connection's `data_stream`s.
 #[derive(Clone)]
 pub struct StreamReceiverService<O: OutgoingService<A>, A: Account> {
     connection_generator: ConnectionGenerator,
+    connections: Connections,
     next: O,
     account_type: PhantomData<A>,
 }
@@ -107,10 +411,36 @@ where
         let connection_generator = ConnectionGenerator::new(server_secret);
         StreamReceiverService {
             connection_generator,
+            connections: Connections::new(u64::MAX),
             next,
             account_type: PhantomData,
         }
     }
+
+    /// Set the maximum amount new connections will accept (defaults to `u64::MAX`).
+    ///
+    /// This can be changed for individual connections with `StreamConnection::set_receive_max`.
+    pub fn receive_max(&mut self, receive_max: u64) -> &mut Self {
+        self.connections.receive_max = receive_max;
+        self
+    }
+
+    /// Set how long connections are kept after the sender last sent a packet on them
+    /// (defaults to 60 seconds). Idle connections are closed and removed when a new one is opened.
+    pub fn idle_timeout(&mut self, idle_timeout: Duration) -> &mut Self {
+        self.connections.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Get notified about the connections handled by this service and any of its clones.
+    pub fn subscribe(&self) -> UnboundedReceiver<ConnectionEvent> {
+        self.connections.subscribe()
+    }
+
+    /// Look up the connection for the given `destination_account`, if any packets were received for it.
+    pub fn connection(&self, destination_account: &Address) -> Option<StreamConnection> {
+        self.connections.get(destination_account)
+    }
 }
 
 // TODO should this be an OutgoingService instead so the balance logic is applied before this is called?
@@ -134,20 +464,31 @@ where
         if dest.starts_with(to.as_ref()) {
             if let Ok(shared_secret) = self.connection_generator.rederive_secret(&destination) {
                 {
-                    return Box::new(result(receive_money(&shared_secret, &to, request.prepare)));
+                    return Box::pin(ready(receive_money(
+                        &shared_secret,
+                        &request.to,
+                        &self.connections,
+                        request.prepare,
+                    )));
                 }
             }
         }
-        Box::new(self.next.send_request(request))
+        Box::pin(self.next.send_request(request))
     }
 }
 
-// TODO send asset code and scale back to sender also
-fn receive_money(
+/// Handle a STREAM packet sent to the given account.
+///
+/// The account's asset code and scale are sent back to the sender in the first reply on
+/// each connection and whenever the sender tells us its address, so that it knows which
+/// units the amounts it delivers are in.
+fn receive_money<A: IldcpAccount>(
     shared_secret: &[u8; 32],
-    client_address: &Address,
+    account: &A,
+    connections: &Connections,
     prepare: Prepare,
 ) -> Result<Fulfill, Reject> {
+    let client_address = account.client_address();
     // Generate fulfillment
     let fulfillment = generate_fulfillment(&shared_secret[..], prepare.data());
     let condition = hash_sha256(&fulfillment);
@@ -155,6 +496,7 @@ fn receive_money(
 
     // Parse STREAM packet
     // TODO avoid copying data
+    let destination = prepare.destination();
     let prepare_amount = prepare.amount();
     let stream_packet =
         StreamPacket::from_encrypted(shared_secret, prepare.into_data()).map_err(|_| {
@@ -168,24 +510,292 @@ fn receive_money(
             .build()
         })?;
 
-    let mut response_frames: Vec<Frame> = Vec::new();
+    let (connection, opened) = connections.get_or_open(&destination);
+    let mut events = Vec::new();
+    if opened {
+        events.push(ConnectionEvent::Opened(connection.clone()));
+    }
+
+    let mut state = connection.state.lock();
+    state.last_activity = Instant::now();
+    let was_closed = state.closed;
+
+    let mut remote_closed = false;
+    // Set if the sender broke the rules, in which case we close the connection with this error
+    let mut connection_error: Option<(StreamErrorCode, &str)> = None;
+    let mut send_asset_details = opened;
+    // Tell the sender how many streams it may open in the first reply and whenever that changes
+    let mut send_max_stream_id = opened;
+    let mut money_frames: Vec<(u64, u64)> = Vec::new();
+    let mut data_received: Vec<u64> = Vec::new();
 
     // Handle STREAM frames
-    // TODO reject if they send data?
     for frame in stream_packet.frames() {
-        // Tell the sender the stream can handle lots of money
-        if let Frame::StreamMoney(frame) = frame {
+        if let Some(stream_id) = frame_stream_id(&frame) {
+            if !was_closed && connection_error.is_none() && !state.allows_stream(stream_id) {
+                debug!(
+                    "Sender opened stream {} above the max stream ID of {}, closing connection {}",
+                    stream_id, state.max_stream_id, destination
+                );
+                connection_error = Some((
+                    StreamErrorCode::StreamIdError,
+                    "Stream ID exceeded the allowed max stream ID",
+                ));
+            }
+            if connection_error.is_some() {
+                continue;
+            }
+        }
+        match frame {
+            Frame::StreamMoney(frame) => money_frames.push((frame.stream_id, frame.shares)),
+            Frame::ConnectionNewAddress(frame) => {
+                state.source_account = Some(frame.source_account);
+                send_asset_details = true;
+            }
+            Frame::ConnectionClose(_) => remote_closed = true,
+            Frame::ConnectionStreamIdBlocked(_) => send_max_stream_id = true,
+            Frame::StreamClose(frame) => {
+                let was_open = match state.streams.get(&frame.stream_id) {
+                    Some(stream) => !stream.closed,
+                    None => state.data_streams.contains_key(&frame.stream_id),
+                };
+                state.streams.entry(frame.stream_id).or_default().closed = true;
+                if was_open {
+                    // Let the sender open another stream in place of this one
+                    state.max_stream_id += 2;
+                    send_max_stream_id = true;
+                }
+                if let Some(stream) = state.data_streams.get(&frame.stream_id) {
+                    stream.receive_close();
+                }
+            }
+            Frame::StreamData(frame) if !was_closed => {
+                if !state
+                    .data_stream(frame.stream_id)
+                    .receive(frame.offset, frame.data)
+                {
+                    debug!(
+                        "Sender exceeded the flow control limit on stream {}, closing connection {}",
+                        frame.stream_id, destination
+                    );
+                    connection_error = Some((
+                        StreamErrorCode::FlowControlError,
+                        "Stream data exceeded the allowed max offset",
+                    ));
+                } else if !frame.data.is_empty() && !data_received.contains(&frame.stream_id) {
+                    data_received.push(frame.stream_id);
+                }
+            }
+            Frame::StreamMaxData(frame) => {
+                if let Some(stream) = state.data_streams.get(&frame.stream_id) {
+                    stream.set_remote_max_offset(frame.max_offset);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Data is only sent back to a `StreamClientConnection`, which sends one packet at a time
+    // and reads the data in our responses. Its packets carry no money and can be fulfilled,
+    // unlike those of `send_money`, which may have several in flight and does not read the data,
+    // or the unfulfillable test packets used for quotes
+    let is_data_packet = prepare_amount == 0 && is_fulfillable && money_frames.is_empty();
+
+    // The data client only moves on to the next sequence number once it got our response, so a
+    // later data packet confirms the data we sent in the last response and the same one means
+    // it was lost. An earlier one is a late copy of a packet we already answered
+    if let (true, Some(sequence)) = (is_data_packet, state.unacked_sequence) {
+        if stream_packet.sequence() >= sequence {
+            let delivered = stream_packet.sequence() > sequence;
+            for stream in state.data_streams.values() {
+                if delivered {
+                    stream.ack();
+                } else {
+                    stream.nack();
+                }
+            }
+            state.unacked_sequence = None;
+        }
+    }
+
+    // Split the amount between the streams according to their shares,
+    // giving whatever is left after rounding down to the last one
+    let total_shares: u128 = money_frames
+        .iter()
+        .map(|(_, shares)| u128::from(*shares))
+        .sum();
+    let mut amounts: Vec<(u64, u64)> = Vec::new();
+    let mut left_to_allocate = prepare_amount;
+    for (i, (stream_id, shares)) in money_frames.iter().enumerate() {
+        let amount = if i == money_frames.len() - 1 {
+            left_to_allocate
+        } else {
+            (u128::from(prepare_amount) * u128::from(*shares))
+                .checked_div(total_shares)
+                .unwrap_or(0) as u64
+        };
+        left_to_allocate -= amount;
+        if let Some(entry) = amounts.iter_mut().find(|(id, _)| id == stream_id) {
+            entry.1 += amount;
+        } else {
+            amounts.push((*stream_id, amount));
+        }
+    }
+
+    // Data written to the connection's streams is sent back in the response to a data packet
+    // (whether it is a Fulfill or a Reject) and stays in flight until the sender confirms it got the response
+    let mut outgoing_data: Vec<(u64, u64, Bytes)> = Vec::new();
+    let mut window_updates: Vec<(u64, u64)> = Vec::new();
+    let mut closing_streams: Vec<u64> = Vec::new();
+    if is_data_packet
+        && state.unacked_sequence.is_none()
+        && !was_closed
+        && connection_error.is_none()
+    {
+        let mut data_left = MAX_DATA_PER_PACKET;
+        for (stream_id, stream) in state.data_streams.iter() {
+            if let Some(max_offset) = stream.window_update(1) {
+                window_updates.push((*stream_id, max_offset));
+            }
+            if let Some((offset, data)) = stream.next_outgoing(data_left) {
+                data_left -= data.len();
+                outgoing_data.push((*stream_id, offset, data));
+            }
+            if stream.take_close() {
+                closing_streams.push(*stream_id);
+            }
+        }
+        if !outgoing_data.is_empty() || !closing_streams.is_empty() {
+            state.unacked_sequence = Some(stream_packet.sequence());
+        }
+    }
+
+    let mut response_frames: Vec<Frame> = Vec::new();
+    if send_asset_details {
+        response_frames.push(Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+            source_asset_code: account.asset_code(),
+            source_asset_scale: account.asset_scale(),
+        }));
+    }
+    let closed_streams: Vec<u64> = amounts
+        .iter()
+        .map(|(stream_id, _)| *stream_id)
+        .filter(|stream_id| {
+            state
+                .streams
+                .get(stream_id)
+                .map(|stream| stream.closed)
+                .unwrap_or(false)
+        })
+        .collect();
+    let receivable = state.receive_max.saturating_sub(state.total_received);
+    let exceeds_receive_max = prepare_amount > receivable;
+    let is_unallocated = prepare_amount > 0 && amounts.is_empty();
+
+    if was_closed {
+        response_frames.push(Frame::ConnectionClose(ConnectionCloseFrame {
+            code: StreamErrorCode::NoError,
+            message: "",
+        }));
+    } else if let Some((ref code, message)) = connection_error {
+        response_frames.push(Frame::ConnectionClose(ConnectionCloseFrame {
+            code: code.clone(),
+            message,
+        }));
+    } else if send_max_stream_id {
+        response_frames.push(Frame::ConnectionMaxStreamId(ConnectionMaxStreamIdFrame {
+            max_stream_id: state.max_stream_id,
+        }));
+    }
+    for stream_id in closed_streams.iter() {
+        response_frames.push(Frame::StreamClose(StreamCloseFrame {
+            stream_id: *stream_id,
+            code: StreamErrorCode::NoError,
+            message: "",
+        }));
+    }
+
+    let should_fulfill = is_fulfillable
+        && prepare_amount >= stream_packet.prepare_amount()
+        && !was_closed
+        && connection_error.is_none()
+        && closed_streams.is_empty()
+        && !exceeds_receive_max
+        && !is_unallocated;
+
+    if should_fulfill {
+        for (stream_id, amount) in amounts.iter() {
+            state.streams.entry(*stream_id).or_default().total_received += amount;
+            if *amount > 0 {
+                events.push(ConnectionEvent::MoneyReceived {
+                    connection: connection.clone(),
+                    stream_id: *stream_id,
+                    amount: *amount,
+                });
+            }
+        }
+        state.total_received += prepare_amount;
+    }
+
+    // Tell the sender how much more each stream can receive
+    let receivable = state.receive_max.saturating_sub(state.total_received);
+    for (stream_id, _) in amounts.iter() {
+        if !closed_streams.contains(stream_id) {
+            let total_received = state
+                .streams
+                .get(stream_id)
+                .map(|stream| stream.total_received)
+                .unwrap_or(0);
             response_frames.push(Frame::StreamMaxMoney(StreamMaxMoneyFrame {
-                stream_id: frame.stream_id,
-                // TODO will returning zero here cause problems?
-                total_received: 0,
-                receive_max: u64::max_value(),
+                stream_id: *stream_id,
+                total_received,
+                receive_max: total_received.saturating_add(receivable),
             }));
         }
     }
 
+    for (stream_id, max_offset) in window_updates {
+        response_frames.push(Frame::StreamMaxData(StreamMaxDataFrame {
+            stream_id,
+            max_offset,
+        }));
+    }
+    for (stream_id, offset, data) in outgoing_data.iter() {
+        response_frames.push(Frame::StreamData(StreamDataFrame {
+            stream_id: *stream_id,
+            offset: *offset,
+            data: &data[..],
+        }));
+    }
+    for stream_id in closing_streams {
+        response_frames.push(Frame::StreamClose(StreamCloseFrame {
+            stream_id,
+            code: StreamErrorCode::NoError,
+            message: "",
+        }));
+    }
+
+    for stream_id in data_received {
+        events.push(ConnectionEvent::DataReceived {
+            connection: connection.clone(),
+            stream_id,
+        });
+    }
+    let closing = (remote_closed || connection_error.is_some()) && !was_closed;
+    if closing {
+        state.close();
+        events.push(ConnectionEvent::Closed(connection.clone()));
+    }
+    drop(state);
+    if closing {
+        connection.remove();
+    }
+    for event in events {
+        connections.notify(event);
+    }
+
     // Return Fulfill or Reject Packet
-    if is_fulfillable && prepare_amount >= stream_packet.prepare_amount() {
+    if should_fulfill {
         let response_packet = StreamPacketBuilder {
             sequence: stream_packet.sequence(),
             ilp_packet_type: IlpPacketType::Fulfill,
@@ -213,7 +823,23 @@ fn receive_money(
             frames: &response_frames,
         }
         .build();
-        if !is_fulfillable {
+        if was_closed {
+            debug!("Connection {} was already closed", destination);
+        } else if let Some((ref code, _)) = connection_error {
+            debug!(
+                "Connection {} was closed with error: {:?}",
+                destination, code
+            );
+        } else if !closed_streams.is_empty() {
+            debug!("Streams {:?} were already closed", closed_streams);
+        } else if exceeds_receive_max {
+            debug!(
+                "Received: {} when the connection can only receive: {} more",
+                prepare_amount, receivable
+            );
+        } else if is_unallocated {
+            debug!("Packet contains money but no StreamMoney frames");
+        } else if !is_fulfillable {
             debug!("Packet is unfulfillable");
         } else if prepare_amount < stream_packet.prepare_amount() {
             debug!(
@@ -238,6 +864,20 @@ fn receive_money(
     }
 }
 
+/// The stream a frame refers to, if any
+fn frame_stream_id(frame: &Frame) -> Option<u64> {
+    match frame {
+        Frame::StreamClose(frame) => Some(frame.stream_id),
+        Frame::StreamMoney(frame) => Some(frame.stream_id),
+        Frame::StreamMaxMoney(frame) => Some(frame.stream_id),
+        Frame::StreamMoneyBlocked(frame) => Some(frame.stream_id),
+        Frame::StreamData(frame) => Some(frame.stream_id),
+        Frame::StreamMaxData(frame) => Some(frame.stream_id),
+        Frame::StreamDataBlocked(frame) => Some(frame.stream_id),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod connection_generator {
     use super::*;
@@ -296,10 +936,12 @@ fn test_stream_packet() -> StreamPacket {
 #[cfg(test)]
 mod receiving_money {
     use super::*;
+    use crate::test_helpers::test_account;
     use interledger_packet::PrepareBuilder;
 
     use std::str::FromStr;
     use std::time::UNIX_EPOCH;
+
     #[test]
     fn fulfills_valid_packet() {
         let client_address = Address::from_str("example.destination").unwrap();
@@ -324,7 +966,13 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &client_address, prepare);
+        let connections = Connections::new(u64::MAX);
+        let result = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        );
         assert!(result.is_ok());
     }
 
@@ -352,7 +1000,13 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &client_address, prepare);
+        let connections = Connections::new(u64::MAX);
+        let result = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        );
         assert!(result.is_ok());
     }
 
@@ -381,7 +1035,13 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &client_address, prepare);
+        let connections = Connections::new(u64::MAX);
+        let result = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        );
         assert!(result.is_err());
     }
 
@@ -420,18 +1080,672 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &client_address, prepare);
+        let connections = Connections::new(u64::MAX);
+        let result = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        );
         assert!(result.is_err());
     }
 }
 
+#[cfg(test)]
+mod connection_state {
+    use super::*;
+    use crate::data::DEFAULT_WINDOW;
+    use crate::test_helpers::test_account;
+    use bytes::BytesMut;
+    use futures::{Future, Stream};
+    use futures03::{executor::block_on, io::AsyncWriteExt};
+    use interledger_packet::PrepareBuilder;
+
+    use std::str::FromStr;
+    use std::time::UNIX_EPOCH;
+
+    fn prepare_with_frames(
+        destination_account: &Address,
+        shared_secret: &[u8],
+        amount: u64,
+        frames: &[Frame],
+    ) -> Prepare {
+        prepare_with_sequence(destination_account, shared_secret, 1, amount, frames)
+    }
+
+    fn prepare_with_sequence(
+        destination_account: &Address,
+        shared_secret: &[u8],
+        sequence: u64,
+        amount: u64,
+        frames: &[Frame],
+    ) -> Prepare {
+        let data = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence,
+            frames,
+        }
+        .build()
+        .into_encrypted(shared_secret);
+        let execution_condition = generate_condition(shared_secret, &data);
+        PrepareBuilder {
+            destination: destination_account.clone(),
+            amount,
+            expires_at: UNIX_EPOCH,
+            data: &data[..],
+            execution_condition: &execution_condition,
+        }
+        .build()
+    }
+
+    fn money_frame(stream_id: u64, shares: u64) -> Frame<'static> {
+        Frame::StreamMoney(StreamMoneyFrame { stream_id, shares })
+    }
+
+    fn response_frames(shared_secret: &[u8], data: &[u8]) -> StreamPacket {
+        StreamPacket::from_encrypted(shared_secret, BytesMut::from(data)).unwrap()
+    }
+
+    #[test]
+    fn tracks_money_received_per_stream() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(u64::MAX);
+        let events = connections.subscribe();
+
+        for _ in 0..2 {
+            let prepare = prepare_with_frames(
+                &destination_account,
+                &shared_secret,
+                100,
+                &[money_frame(1, 1), money_frame(3, 3)],
+            );
+            receive_money(
+                &shared_secret,
+                &test_account(&client_address),
+                &connections,
+                prepare,
+            )
+            .unwrap();
+        }
+
+        let connection = connections.get(&destination_account).unwrap();
+        assert_eq!(connection.total_received(), 200);
+        assert_eq!(connection.stream_received(1), 50);
+        assert_eq!(connection.stream_received(3), 150);
+
+        drop(connections);
+        let events = events.collect().wait().unwrap();
+        assert_eq!(events.len(), 5);
+        match &events[0] {
+            ConnectionEvent::Opened(connection) => {
+                assert_eq!(connection.destination_account(), &destination_account)
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+        match &events[2] {
+            ConnectionEvent::MoneyReceived {
+                stream_id, amount, ..
+            } => {
+                assert_eq!(*stream_id, 3);
+                assert_eq!(*amount, 75);
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn sends_asset_details_in_first_reply() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(u64::MAX);
+        let asset_details = Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+            source_asset_code: "XYZ",
+            source_asset_scale: 9,
+        });
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            100,
+            &[money_frame(1, 1)],
+        );
+        let fulfill = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        )
+        .unwrap();
+        let packet = response_frames(&shared_secret, fulfill.data());
+        assert_eq!(packet.frames().next().unwrap(), asset_details);
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            100,
+            &[money_frame(1, 1)],
+        );
+        let fulfill = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        )
+        .unwrap();
+        let packet = response_frames(&shared_secret, fulfill.data());
+        assert!(packet.frames().all(|frame| frame != asset_details));
+    }
+
+    #[test]
+    fn rejects_packets_over_receive_max() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(150);
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            100,
+            &[money_frame(1, 1)],
+        );
+        assert!(receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare
+        )
+        .is_ok());
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            100,
+            &[money_frame(1, 1)],
+        );
+        let reject = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        )
+        .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F99_APPLICATION_ERROR);
+        let packet = response_frames(&shared_secret, reject.data());
+        assert_eq!(
+            packet.frames().next().unwrap(),
+            Frame::StreamMaxMoney(StreamMaxMoneyFrame {
+                stream_id: 1,
+                total_received: 100,
+                receive_max: 150,
+            })
+        );
+        assert_eq!(
+            connections
+                .get(&destination_account)
+                .unwrap()
+                .total_received(),
+            100
+        );
+    }
+
+    #[test]
+    fn rejects_packets_after_sender_closes_connection() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(u64::MAX);
+        let events = connections.subscribe();
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            10,
+            &[
+                money_frame(1, 1),
+                Frame::ConnectionClose(ConnectionCloseFrame {
+                    code: StreamErrorCode::NoError,
+                    message: "",
+                }),
+            ],
+        );
+        assert!(receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare
+        )
+        .is_ok());
+        assert!(connections.get(&destination_account).is_none());
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            10,
+            &[money_frame(1, 1)],
+        );
+        let reject = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        )
+        .unwrap_err();
+        let packet = response_frames(&shared_secret, reject.data());
+        assert_eq!(
+            packet.frames().next().unwrap(),
+            Frame::ConnectionClose(ConnectionCloseFrame {
+                code: StreamErrorCode::NoError,
+                message: "",
+            })
+        );
+
+        drop(connections);
+        let events = events.collect().wait().unwrap();
+        match events.last().unwrap() {
+            ConnectionEvent::Closed(connection) => assert_eq!(connection.total_received(), 10),
+            event => panic!("Unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn rejects_money_for_closed_streams() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(u64::MAX);
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            0,
+            &[Frame::StreamClose(StreamCloseFrame {
+                stream_id: 1,
+                code: StreamErrorCode::NoError,
+                message: "",
+            })],
+        );
+        assert!(receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare
+        )
+        .is_ok());
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            10,
+            &[money_frame(1, 1)],
+        );
+        let reject = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        )
+        .unwrap_err();
+        let packet = response_frames(&shared_secret, reject.data());
+        match packet.frames().next().unwrap() {
+            Frame::StreamClose(frame) => assert_eq!(frame.stream_id, 1),
+            frame => panic!("Unexpected frame: {:?}", frame),
+        }
+
+        // Other streams on the same connection are unaffected
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            10,
+            &[money_frame(3, 1)],
+        );
+        assert!(receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_packets_after_receiver_closes_connection() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(u64::MAX);
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            10,
+            &[money_frame(1, 1)],
+        );
+        assert!(receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare
+        )
+        .is_ok());
+        let connection = connections.get(&destination_account).unwrap();
+        connection.close();
+        assert!(connections.get(&destination_account).is_none());
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            10,
+            &[money_frame(1, 1)],
+        );
+        assert!(receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare
+        )
+        .is_err());
+        assert_eq!(connection.total_received(), 10);
+    }
+
+    #[test]
+    fn closes_connection_when_sender_exceeds_max_data() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(u64::MAX);
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            0,
+            &[Frame::StreamData(StreamDataFrame {
+                stream_id: 1,
+                offset: DEFAULT_WINDOW,
+                data: b"too much",
+            })],
+        );
+        let reject = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        )
+        .unwrap_err();
+        let packet = response_frames(&shared_secret, reject.data());
+        let close_code = packet.frames().find_map(|frame| match frame {
+            Frame::ConnectionClose(frame) => Some(frame.code),
+            _ => None,
+        });
+        assert_eq!(close_code, Some(StreamErrorCode::FlowControlError));
+        assert!(connections.get(&destination_account).is_none());
+    }
+
+    #[test]
+    fn closes_connection_when_sender_exceeds_max_stream_id() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(u64::MAX);
+
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            100,
+            &[money_frame(DEFAULT_MAX_STREAM_ID + 1, 1)],
+        );
+        let reject = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        )
+        .unwrap_err();
+        let packet = response_frames(&shared_secret, reject.data());
+        let close_code = packet.frames().find_map(|frame| match frame {
+            Frame::ConnectionClose(frame) => Some(frame.code),
+            _ => None,
+        });
+        assert_eq!(close_code, Some(StreamErrorCode::StreamIdError));
+        assert!(connections.get(&destination_account).is_none());
+    }
+
+    #[test]
+    fn raises_max_stream_id_when_streams_close() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(u64::MAX);
+        let max_stream_id = |data: &[u8]| {
+            response_frames(&shared_secret, data)
+                .frames()
+                .find_map(|frame| match frame {
+                    Frame::ConnectionMaxStreamId(frame) => Some(frame.max_stream_id),
+                    _ => None,
+                })
+        };
+
+        // The sender learns the limit in the first reply
+        let prepare = prepare_with_frames(
+            &destination_account,
+            &shared_secret,
+            100,
+            &[money_frame(1, 1)],
+        );
+        let fulfill = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        )
+        .unwrap();
+        assert_eq!(max_stream_id(fulfill.data()), Some(DEFAULT_MAX_STREAM_ID));
+
+        // Closing a stream lets it open another one
+        let prepare = prepare_with_sequence(
+            &destination_account,
+            &shared_secret,
+            2,
+            0,
+            &[Frame::StreamClose(StreamCloseFrame {
+                stream_id: 1,
+                code: StreamErrorCode::NoError,
+                message: "",
+            })],
+        );
+        let fulfill = receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        )
+        .unwrap();
+        assert_eq!(
+            max_stream_id(fulfill.data()),
+            Some(DEFAULT_MAX_STREAM_ID + 2)
+        );
+
+        let prepare = prepare_with_sequence(
+            &destination_account,
+            &shared_secret,
+            3,
+            100,
+            &[money_frame(DEFAULT_MAX_STREAM_ID + 1, 1)],
+        );
+        receive_money(
+            &shared_secret,
+            &test_account(&client_address),
+            &connections,
+            prepare,
+        )
+        .unwrap();
+        let connection = connections.get(&destination_account).unwrap();
+        assert_eq!(connection.stream_received(DEFAULT_MAX_STREAM_ID + 1), 100);
+    }
+
+    #[test]
+    fn resends_data_until_the_sender_confirms_it() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(u64::MAX);
+        let sent_data = |sequence: u64| -> Vec<(u64, Vec<u8>)> {
+            let prepare =
+                prepare_with_sequence(&destination_account, &shared_secret, sequence, 0, &[]);
+            let fulfill = receive_money(
+                &shared_secret,
+                &test_account(&client_address),
+                &connections,
+                prepare,
+            )
+            .unwrap();
+            response_frames(&shared_secret, fulfill.data())
+                .frames()
+                .filter_map(|frame| match frame {
+                    Frame::StreamData(frame) => Some((frame.offset, frame.data.to_vec())),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert!(sent_data(1).is_empty());
+        let mut stream = connections
+            .get(&destination_account)
+            .unwrap()
+            .data_stream(2);
+        block_on(stream.write_all(b"hello")).unwrap();
+        assert_eq!(sent_data(2), vec![(0, b"hello".to_vec())]);
+
+        // The sender did not get the response, so it tries the same packet again
+        assert_eq!(sent_data(2), vec![(0, b"hello".to_vec())]);
+
+        // Its next packet confirms it got the data
+        block_on(stream.write_all(b" world")).unwrap();
+        assert_eq!(sent_data(3), vec![(5, b" world".to_vec())]);
+        assert!(sent_data(4).is_empty());
+    }
+
+    #[test]
+    fn only_sends_data_in_replies_to_data_packets() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let connections = Connections::new(u64::MAX);
+        let sent_data = |sequence: u64, amount: u64, frames: &[Frame]| -> Vec<Vec<u8>> {
+            let prepare = prepare_with_sequence(
+                &destination_account,
+                &shared_secret,
+                sequence,
+                amount,
+                frames,
+            );
+            let fulfill = receive_money(
+                &shared_secret,
+                &test_account(&client_address),
+                &connections,
+                prepare,
+            )
+            .unwrap();
+            response_frames(&shared_secret, fulfill.data())
+                .frames()
+                .filter_map(|frame| match frame {
+                    Frame::StreamData(frame) => Some(frame.data.to_vec()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // The money packets of a payment on the same connection have their own sequence numbers
+        assert!(sent_data(1, 10, &[money_frame(1, 1)]).is_empty());
+        let mut stream = connections
+            .get(&destination_account)
+            .unwrap()
+            .data_stream(2);
+        block_on(stream.write_all(b"hello")).unwrap();
+        assert!(sent_data(2, 10, &[money_frame(1, 1)]).is_empty());
+        assert_eq!(sent_data(1, 0, &[]), vec![b"hello".to_vec()]);
+
+        // A money packet with a later sequence number does not confirm the data
+        assert!(sent_data(3, 10, &[money_frame(1, 1)]).is_empty());
+        assert_eq!(sent_data(1, 0, &[]), vec![b"hello".to_vec()]);
+        assert!(sent_data(2, 0, &[]).is_empty());
+    }
+
+    #[test]
+    fn removes_closed_and_idle_connections() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let mut connections = Connections::new(u64::MAX);
+        connections.idle_timeout = Duration::from_millis(50);
+        let events = connections.subscribe();
+        let open_connection = |connections: &Connections| {
+            let (destination_account, shared_secret) =
+                connection_generator.generate_address_and_secret(&client_address);
+            let prepare = prepare_with_frames(
+                &destination_account,
+                &shared_secret,
+                10,
+                &[money_frame(1, 1)],
+            );
+            assert!(receive_money(
+                &shared_secret,
+                &test_account(&client_address),
+                connections,
+                prepare
+            )
+            .is_ok());
+            connections.get(&destination_account).unwrap()
+        };
+
+        let closed = open_connection(&connections);
+        let idle = open_connection(&connections);
+        assert_eq!(connections.connections.read().open.len(), 2);
+        closed.close();
+        assert_eq!(connections.connections.read().open.len(), 1);
+
+        std::thread::sleep(Duration::from_millis(100));
+        open_connection(&connections);
+        {
+            let connections = connections.connections.read();
+            assert_eq!(connections.open.len(), 1);
+            assert!(!connections.open.contains_key(idle.destination_account()));
+            // The closed connection is forgotten once the idle timeout passes as well
+            assert!(connections.closed.is_empty());
+        }
+        assert!(idle.is_closed());
+
+        drop(connections);
+        let events = events.collect().wait().unwrap();
+        assert!(events.iter().any(|event| match event {
+            ConnectionEvent::Closed(connection) =>
+                connection.destination_account() == idle.destination_account(),
+            _ => false,
+        }));
+    }
+}
+
 #[cfg(test)]
 mod stream_receiver_service {
     use super::*;
     use crate::test_helpers::*;
-    use futures::Future;
+    use futures03::{executor::block_on, future::err};
     use interledger_packet::PrepareBuilder;
-    use interledger_service::outgoing_service_fn;
+    use interledger_service::{outgoing_service_fn, Extensions};
 
     use std::str::FromStr;
     use std::time::UNIX_EPOCH;
@@ -463,24 +1777,23 @@ mod stream_receiver_service {
             }),
         );
 
-        let result = service
-            .send_request(OutgoingRequest {
-                from: TestAccount {
-                    id: 0,
-                    ilp_address: Address::from_str("example.sender").unwrap(),
-                    asset_code: "XYZ".to_string(),
-                    asset_scale: 9,
-                },
-                to: TestAccount {
-                    id: 1,
-                    ilp_address: client_address.clone(),
-                    asset_code: "XYZ".to_string(),
-                    asset_scale: 9,
-                },
-                original_amount: prepare.amount(),
-                prepare,
-            })
-            .wait();
+        let result = block_on(service.send_request(OutgoingRequest {
+            from: TestAccount {
+                id: 0,
+                ilp_address: Address::from_str("example.sender").unwrap(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+            },
+            to: TestAccount {
+                id: 1,
+                ilp_address: client_address.clone(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+            },
+            original_amount: prepare.amount(),
+            prepare,
+            extensions: Extensions::new(),
+        }));
         assert!(result.is_ok());
     }
 
@@ -514,24 +1827,23 @@ mod stream_receiver_service {
             }),
         );
 
-        let result = service
-            .send_request(OutgoingRequest {
-                from: TestAccount {
-                    id: 0,
-                    ilp_address: Address::from_str("example.sender").unwrap(),
-                    asset_code: "XYZ".to_string(),
-                    asset_scale: 9,
-                },
-                to: TestAccount {
-                    id: 1,
-                    ilp_address: client_address.clone(),
-                    asset_code: "XYZ".to_string(),
-                    asset_scale: 9,
-                },
-                original_amount: prepare.amount(),
-                prepare,
-            })
-            .wait();
+        let result = block_on(service.send_request(OutgoingRequest {
+            from: TestAccount {
+                id: 0,
+                ilp_address: Address::from_str("example.sender").unwrap(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+            },
+            to: TestAccount {
+                id: 1,
+                ilp_address: client_address.clone(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+            },
+            original_amount: prepare.amount(),
+            prepare,
+            extensions: Extensions::new(),
+        }));
         assert!(result.is_err());
     }
 
@@ -561,7 +1873,7 @@ mod stream_receiver_service {
         let mut service = StreamReceiverService::new(
             server_secret.clone(),
             outgoing_service_fn(|_| {
-                Err(RejectBuilder {
+                err(RejectBuilder {
                     code: ErrorCode::F02_UNREACHABLE,
                     message: &[],
                     data: &[],
@@ -571,24 +1883,23 @@ mod stream_receiver_service {
             }),
         );
 
-        let result = service
-            .send_request(OutgoingRequest {
-                from: TestAccount {
-                    id: 0,
-                    ilp_address: Address::from_str("example.sender").unwrap(),
-                    asset_code: "XYZ".to_string(),
-                    asset_scale: 9,
-                },
-                original_amount: prepare.amount(),
-                to: TestAccount {
-                    id: 1,
-                    ilp_address: client_address.clone(),
-                    asset_code: "XYZ".to_string(),
-                    asset_scale: 9,
-                },
-                prepare,
-            })
-            .wait();
+        let result = block_on(service.send_request(OutgoingRequest {
+            from: TestAccount {
+                id: 0,
+                ilp_address: Address::from_str("example.sender").unwrap(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+            },
+            original_amount: prepare.amount(),
+            to: TestAccount {
+                id: 1,
+                ilp_address: client_address.clone(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+            },
+            prepare,
+            extensions: Extensions::new(),
+        }));
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().triggered_by().unwrap(),
@@ -1,6 +1,7 @@
 use base64;
 use bytes::Bytes;
 use futures::{future::ok, Future};
+use futures03::{future::err, TryFutureExt};
 use hyper::{
     header::{HeaderValue, ACCEPT},
     service::{service_fn, Service},
@@ -62,7 +63,7 @@ pub fn send_spsp_payment_btp(
         vec![account.clone()],
         true,
         outgoing_service_fn(|request: OutgoingRequest<Account>| {
-            Err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: &format!(
                     "No route found for address: {:?}",
@@ -81,7 +82,7 @@ pub fn send_spsp_payment_btp(
     })
     .and_then(move |btp_service| {
         let service = btp_service.handle_incoming(incoming_service_fn(|_| {
-            Err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: b"Not expecting incoming prepare packets",
                 triggered_by: Some(&LOCAL_ILP_ADDRESS),
@@ -138,7 +139,7 @@ pub fn send_spsp_payment_http(
         LOCAL_ILP_ADDRESS.clone(),
         store.clone(),
         outgoing_service_fn(|request: OutgoingRequest<Account>| {
-            Err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: &format!("No outgoing route for: {:?}", request.from.client_address())
                     .as_bytes(),
@@ -191,7 +192,7 @@ pub fn run_spsp_server_btp(
         vec![incoming_account.clone()],
         true,
         outgoing_service_fn(move |request: OutgoingRequest<Account>| {
-            Err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: &format!("No outgoing route for: {:?}", request.from.client_address(),)
                     .as_bytes(),
@@ -216,33 +217,38 @@ pub fn run_spsp_server_btp(
 
         btp_service.handle_incoming(incoming_service.clone());
 
-        get_ildcp_info(&mut incoming_service, incoming_account.clone()).and_then(move |info| {
-            debug!("SPSP server got ILDCP info: {:?}", info);
-            let client_address = info.client_address();
-            // Update the ILP Address with the ildcp info request's address
-            *ilp_address.write() = client_address.to_bytes();
+        let incoming_account = incoming_account.clone();
+        Box::pin(async move { get_ildcp_info(&mut incoming_service, incoming_account).await })
+            .compat()
+            .and_then(move |info| {
+                debug!("SPSP server got ILDCP info: {:?}", info);
+                let client_address = info.client_address();
+                // Update the ILP Address with the ildcp info request's address
+                *ilp_address.write() = client_address.to_bytes();
 
-            let receiver_account =
-                AccountBuilder::new(client_address.clone(), LOCAL_USERNAME.clone())
-                    .asset_code(String::from_utf8(info.asset_code().to_vec()).unwrap_or_default())
-                    .asset_scale(info.asset_scale())
-                    // Send all outgoing packets to this account
-                    .additional_routes(&[&b""[..]])
-                    .build();
-            store.add_account(receiver_account);
+                let receiver_account =
+                    AccountBuilder::new(client_address.clone(), LOCAL_USERNAME.clone())
+                        .asset_code(
+                            String::from_utf8(info.asset_code().to_vec()).unwrap_or_default(),
+                        )
+                        .asset_scale(info.asset_scale())
+                        // Send all outgoing packets to this account
+                        .additional_routes(&[&b""[..]])
+                        .build();
+                store.add_account(receiver_account);
 
-            if !quiet {
-                println!("Listening on: {}", address);
-            }
-            debug!(
-                "SPSP server listening on {} with ILP address {}",
-                &address, client_address,
-            );
-            let spsp_responder = SpspResponder::new(client_address, server_secret);
-            Server::bind(&address)
-                .serve(move || spsp_responder.clone())
-                .map_err(|e| eprintln!("Server error: {:?}", e))
-        })
+                if !quiet {
+                    println!("Listening on: {}", address);
+                }
+                debug!(
+                    "SPSP server listening on {} with ILP address {}",
+                    &address, client_address,
+                );
+                let spsp_responder = SpspResponder::new(client_address, server_secret);
+                Server::bind(&address)
+                    .serve(move || spsp_responder.clone())
+                    .map_err(|e| eprintln!("Server error: {:?}", e))
+            })
     })
 }
 
@@ -268,7 +274,7 @@ pub fn run_spsp_server_http(
     let outgoing_handler = StreamReceiverService::new(
         server_secret,
         outgoing_service_fn(move |request: OutgoingRequest<Account>| {
-            Err(RejectBuilder {
+            err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: &format!(
                     "No handler configured for destination: {}",
@@ -331,7 +337,7 @@ pub fn run_moneyd_local(
     // TODO this needs a reference to the BtpService so it can send outgoing packets
     println!("Listening on: {}", address);
     let rejecter = outgoing_service_fn(move |_| {
-        Err(RejectBuilder {
+        err(RejectBuilder {
             code: ErrorCode::F02_UNREACHABLE,
             message: b"No open connection for account",
             triggered_by: Some(&ilp_address),
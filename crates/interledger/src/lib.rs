@@ -19,6 +19,8 @@ pub mod service {
 pub mod cli;
 #[cfg(feature = "cli")]
 pub mod node;
+#[cfg(feature = "cli")]
+pub mod pipeline;
 
 /// Bilateral Transport Protocol (BTP) client and server
 #[cfg(feature = "btp")]
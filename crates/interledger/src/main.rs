@@ -118,6 +118,10 @@ pub fn main() {
                                 .long("redis_uri")
                                 .help("Redis database to add the account to")
                                 .default_value("redis://127.0.0.1:6379"),
+                            Arg::with_name("database_url")
+                                .long("database_url")
+                                .help("SQL database to add the account to, instead of Redis (for example, sqlite://interledger.db)")
+                                .takes_value(true),
                             Arg::with_name("server_secret")
                                 .long("server_secret")
                                 .help("Cryptographic seed used to derive keys")
@@ -125,9 +129,8 @@ pub fn main() {
                                 .required(true),
                             Arg::with_name("ilp_address")
                                 .long("ilp_address")
-                                .help("ILP Address of this account")
-                                .takes_value(true)
-                                .required(true),
+                                .help("ILP Address of this account (Child accounts get one under the node's address if it is left out)")
+                                .takes_value(true),
                             Arg::with_name("asset_code")
                                 .long("asset_code")
                                 .help("Asset that this account's balance is denominated in")
@@ -293,9 +296,6 @@ pub fn main() {
                         } else {
                             (None, None)
                         };
-                    let redis_uri =
-                        value_t!(matches, "redis_uri", String).expect("redis_uri is required");
-                    let redis_uri = Url::parse(&redis_uri).expect("redis_uri is not a valid URI");
                     let server_secret: [u8; 32] = {
                         let encoded: String = value_t!(matches, "server_secret", String).unwrap();
                         let mut server_secret = [0; 32];
@@ -306,10 +306,9 @@ pub fn main() {
                         server_secret
                     };
                     let account = AccountDetails {
-                        ilp_address: Address::from_str(
-                            &value_t!(matches, "ilp_address", String).unwrap(),
-                        )
-                        .unwrap(),
+                        ilp_address: matches
+                            .value_of("ilp_address")
+                            .map(|address| Address::from_str(address).unwrap()),
                         username: Username::from_str(
                             &value_t!(matches, "username", String).unwrap(),
                         )
@@ -342,11 +341,27 @@ pub fn main() {
                         amount_per_minute_limit: value_t!(matches, "amount_per_minute_limit", u64)
                             .ok(),
                         settlement_engine_url: None,
+                        route_cost: None,
+                        route_export_policy: None,
+                        route_import_policy: None,
+                        route_auth_key: None,
+                        route_weight: None,
                     };
-                    tokio::run(
-                        insert_account_redis(redis_uri, &server_secret, account)
-                            .and_then(move |_| Ok(())),
-                    );
+                    if let Some(database_url) = matches.value_of("database_url") {
+                        tokio::run(
+                            insert_account_sql(database_url, &server_secret, account)
+                                .and_then(move |_| Ok(())),
+                        );
+                    } else {
+                        let redis_uri =
+                            value_t!(matches, "redis_uri", String).expect("redis_uri is required");
+                        let redis_uri =
+                            Url::parse(&redis_uri).expect("redis_uri is not a valid URI");
+                        tokio::run(
+                            insert_account_redis(redis_uri, &server_secret, account)
+                                .and_then(move |_| Ok(())),
+                        );
+                    }
                 }
                 _ => app.print_help().unwrap(),
             },
@@ -0,0 +1,285 @@
+//! Configuration of the services that make up the node's incoming and outgoing chains.
+//!
+//! Each service is a named stage. The node config lists the stages in the order
+//! they should see each request, so built-in services can be removed or reordered.
+//! Custom services are registered as hooks under a name and can then be used
+//! anywhere in the list like the built-in ones.
+
+use interledger_ildcp::IldcpAccount;
+use interledger_service::{
+    layer_fn, Account, BoxedIncomingLayer, BoxedIncomingService, BoxedOutgoingLayer,
+    BoxedOutgoingService,
+};
+use serde::{Deserialize, Deserializer};
+use std::{collections::HashMap, fmt};
+
+/// A service in the incoming chain, between the BTP and HTTP servers and the Router.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IncomingStage {
+    RateLimit,
+    Validator,
+    MaxPacketAmount,
+    Ildcp,
+    SettlementMessage,
+    Echo,
+    /// A service registered with `PipelineHooks::incoming`
+    Custom(String),
+}
+
+impl<'a> From<&'a str> for IncomingStage {
+    fn from(name: &'a str) -> Self {
+        match name {
+            "rate_limit" => IncomingStage::RateLimit,
+            "validator" => IncomingStage::Validator,
+            "max_packet_amount" => IncomingStage::MaxPacketAmount,
+            "ildcp" => IncomingStage::Ildcp,
+            "settlement_message" => IncomingStage::SettlementMessage,
+            "echo" => IncomingStage::Echo,
+            name => IncomingStage::Custom(name.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for IncomingStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            IncomingStage::RateLimit => "rate_limit",
+            IncomingStage::Validator => "validator",
+            IncomingStage::MaxPacketAmount => "max_packet_amount",
+            IncomingStage::Ildcp => "ildcp",
+            IncomingStage::SettlementMessage => "settlement_message",
+            IncomingStage::Echo => "echo",
+            IncomingStage::Custom(name) => name,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for IncomingStage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(IncomingStage::from(
+            String::deserialize(deserializer)?.as_str(),
+        ))
+    }
+}
+
+/// A service in the outgoing chain, between the Router and the BTP connections.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OutgoingStage {
+    ExchangeRate,
+    Balance,
+    StreamReceiver,
+    ExpiryShortener,
+    HttpClient,
+    Validator,
+    /// A service registered with `PipelineHooks::outgoing`
+    Custom(String),
+}
+
+impl<'a> From<&'a str> for OutgoingStage {
+    fn from(name: &'a str) -> Self {
+        match name {
+            "exchange_rate" => OutgoingStage::ExchangeRate,
+            "balance" => OutgoingStage::Balance,
+            "stream_receiver" => OutgoingStage::StreamReceiver,
+            "expiry_shortener" => OutgoingStage::ExpiryShortener,
+            "http_client" => OutgoingStage::HttpClient,
+            "validator" => OutgoingStage::Validator,
+            name => OutgoingStage::Custom(name.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for OutgoingStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            OutgoingStage::ExchangeRate => "exchange_rate",
+            OutgoingStage::Balance => "balance",
+            OutgoingStage::StreamReceiver => "stream_receiver",
+            OutgoingStage::ExpiryShortener => "expiry_shortener",
+            OutgoingStage::HttpClient => "http_client",
+            OutgoingStage::Validator => "validator",
+            OutgoingStage::Custom(name) => name,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for OutgoingStage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(OutgoingStage::from(
+            String::deserialize(deserializer)?.as_str(),
+        ))
+    }
+}
+
+fn default_incoming_stages() -> Vec<IncomingStage> {
+    vec![
+        IncomingStage::RateLimit,
+        IncomingStage::Validator,
+        IncomingStage::MaxPacketAmount,
+        IncomingStage::Ildcp,
+        IncomingStage::SettlementMessage,
+        IncomingStage::Echo,
+    ]
+}
+
+fn default_outgoing_stages() -> Vec<OutgoingStage> {
+    vec![
+        OutgoingStage::ExchangeRate,
+        OutgoingStage::Balance,
+        OutgoingStage::StreamReceiver,
+        // Note: the expiry shortener must come before the Validator so that the expiry duration
+        // is shortened before we check whether there is enough time left
+        OutgoingStage::ExpiryShortener,
+        OutgoingStage::HttpClient,
+        OutgoingStage::Validator,
+    ]
+}
+
+/// The stages of the incoming and outgoing chains, listed in the order in which
+/// they handle each request. Stages that are left out are not used.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct PipelineConfig {
+    #[serde(default = "default_incoming_stages")]
+    pub incoming: Vec<IncomingStage>,
+    #[serde(default = "default_outgoing_stages")]
+    pub outgoing: Vec<OutgoingStage>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            incoming: default_incoming_stages(),
+            outgoing: default_outgoing_stages(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PipelineError {
+    /// A stage was configured that is neither built in nor registered as a hook
+    UnknownStage(String),
+}
+
+impl PipelineConfig {
+    /// The layers for all of the incoming stages, in order, to be added to a `ServiceBuilder`.
+    ///
+    /// Custom stages are looked up in the hooks and built-in ones are created by `builtin`.
+    /// Stages that neither of them provide are an error.
+    pub fn incoming_layers<A, F>(
+        &self,
+        hooks: &PipelineHooks<A>,
+        mut builtin: F,
+    ) -> Result<Vec<BoxedIncomingLayer<A>>, PipelineError>
+    where
+        A: Account,
+        F: FnMut(&IncomingStage) -> Option<BoxedIncomingLayer<A>>,
+    {
+        self.incoming
+            .iter()
+            .map(|stage| {
+                match stage {
+                    IncomingStage::Custom(name) => hooks.incoming.get(name).cloned(),
+                    stage => builtin(stage),
+                }
+                .ok_or_else(|| PipelineError::UnknownStage(stage.to_string()))
+            })
+            .collect()
+    }
+
+    /// The layers for all of the outgoing stages, in order, to be added to a `ServiceBuilder`.
+    ///
+    /// Custom stages are looked up in the hooks and built-in ones are created by `builtin`.
+    /// Stages that neither of them provide are an error.
+    pub fn outgoing_layers<A, F>(
+        &self,
+        hooks: &PipelineHooks<A>,
+        mut builtin: F,
+    ) -> Result<Vec<BoxedOutgoingLayer<A>>, PipelineError>
+    where
+        A: Account,
+        F: FnMut(&OutgoingStage) -> Option<BoxedOutgoingLayer<A>>,
+    {
+        self.outgoing
+            .iter()
+            .map(|stage| {
+                match stage {
+                    OutgoingStage::Custom(name) => hooks.outgoing.get(name).cloned(),
+                    stage => builtin(stage),
+                }
+                .ok_or_else(|| PipelineError::UnknownStage(stage.to_string()))
+            })
+            .collect()
+    }
+}
+
+/// Custom services that can be added to the pipeline by name.
+///
+/// Each hook is given the next service in the chain and returns the service that wraps it.
+pub struct PipelineHooks<A: Account> {
+    incoming: HashMap<String, BoxedIncomingLayer<A>>,
+    outgoing: HashMap<String, BoxedOutgoingLayer<A>>,
+}
+
+impl<A: Account + 'static> PipelineHooks<A> {
+    pub fn new() -> Self {
+        PipelineHooks {
+            incoming: HashMap::new(),
+            outgoing: HashMap::new(),
+        }
+    }
+
+    /// Register a custom incoming stage
+    pub fn incoming<F>(&mut self, name: &str, hook: F) -> &mut Self
+    where
+        F: Fn(BoxedIncomingService<A>) -> BoxedIncomingService<A> + Send + Sync + 'static,
+    {
+        self.incoming
+            .insert(name.to_string(), BoxedIncomingLayer::new(layer_fn(hook)));
+        self
+    }
+
+    /// Register a custom outgoing stage
+    pub fn outgoing<F>(&mut self, name: &str, hook: F) -> &mut Self
+    where
+        F: Fn(BoxedOutgoingService<A>) -> BoxedOutgoingService<A> + Send + Sync + 'static,
+    {
+        self.outgoing
+            .insert(name.to_string(), BoxedOutgoingLayer::new(layer_fn(hook)));
+        self
+    }
+}
+
+impl<A: Account + 'static> Default for PipelineHooks<A> {
+    fn default() -> Self {
+        PipelineHooks::new()
+    }
+}
+
+impl<A: Account> Clone for PipelineHooks<A> {
+    fn clone(&self) -> Self {
+        PipelineHooks {
+            incoming: self.incoming.clone(),
+            outgoing: self.outgoing.clone(),
+        }
+    }
+}
+
+/// A custom incoming service that can be added to the chain of any store's accounts.
+pub trait IncomingHook: Send + Sync + 'static {
+    fn wrap<A>(&self, next: BoxedIncomingService<A>) -> BoxedIncomingService<A>
+    where
+        A: Account + IldcpAccount + Sync + 'static;
+}
+
+/// A custom outgoing service that can be added to the chain of any store's accounts.
+pub trait OutgoingHook: Send + Sync + 'static {
+    fn wrap<A>(&self, next: BoxedOutgoingService<A>) -> BoxedOutgoingService<A>
+    where
+        A: Account + IldcpAccount + Sync + 'static;
+}
@@ -5,7 +5,7 @@ use futures::{
 };
 use interledger::{
     cli,
-    node::{AccountDetails, InterledgerNode},
+    node::{AccountDetails, InterledgerNode, StoreType},
 };
 use interledger_packet::Address;
 use interledger_service::Username;
@@ -26,21 +26,28 @@ fn btp_end_to_end() {
     let http_port = get_open_port(Some(7770));
     let settlement_port = get_open_port(Some(7771));
     let node = InterledgerNode {
-        ilp_address: Address::from_str("example.node").unwrap(),
+        ilp_address: Some(Address::from_str("example.node").unwrap()),
         default_spsp_account: None,
         admin_auth_token: "admin".to_string(),
+        store: StoreType::Redis,
         redis_connection: context.get_client_connection_info(),
+        database_url: String::new(),
+        memory_store: Default::default(),
         btp_address: ([127, 0, 0, 1], btp_port).into(),
         http_address: ([127, 0, 0, 1], http_port).into(),
         settlement_address: ([127, 0, 0, 1], settlement_port).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        node_id: None,
+        routing_policy: Default::default(),
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     let run = ok(()).and_then(move |_| {
         let spawn_connector = ok(tokio::spawn(node.serve())).and_then(move |_| {
             join_all(vec![
                 node.insert_account(AccountDetails {
-                    ilp_address: Address::from_str("example.node.one").unwrap(),
+                    ilp_address: Some(Address::from_str("example.node.one").unwrap()),
                     username: Username::from_str("alice").unwrap(),
                     asset_code: "XYZ".to_string(),
                     asset_scale: 9,
@@ -60,9 +67,14 @@ fn btp_end_to_end() {
                     packets_per_minute_limit: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: None,
+                    route_cost: None,
+                    route_export_policy: None,
+                    route_import_policy: None,
+                    route_auth_key: None,
+                    route_weight: None,
                 }),
                 node.insert_account(AccountDetails {
-                    ilp_address: Address::from_str("example.node.two").unwrap(),
+                    ilp_address: Some(Address::from_str("example.node.two").unwrap()),
                     username: Username::from_str("bob").unwrap(),
                     asset_code: "XYZ".to_string(),
                     asset_scale: 9,
@@ -82,6 +94,11 @@ fn btp_end_to_end() {
                     packets_per_minute_limit: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: None,
+                    route_cost: None,
+                    route_export_policy: None,
+                    route_import_policy: None,
+                    route_auth_key: None,
+                    route_weight: None,
                 }),
             ])
         });
@@ -0,0 +1,232 @@
+use env_logger;
+use futures::{
+    future::{join_all, ok},
+    Future,
+};
+use interledger::{
+    cli,
+    node::{AccountDetails, InterledgerNode, StoreType},
+    pipeline::{IncomingHook, IncomingStage, OutgoingStage},
+};
+use interledger_api::NodeStore;
+use interledger_ildcp::IldcpAccount;
+use interledger_packet::Address;
+use interledger_service::{Account, BoxedIncomingService, Username};
+use interledger_store_redis::IntoConnectionInfo;
+use log::debug;
+use serde_json::json;
+use std::{
+    env,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::runtime::Runtime;
+
+mod redis_helpers;
+use redis_helpers::get_open_port;
+
+mod test_helpers;
+use test_helpers::{get_balance, send_money_to_username};
+
+fn account_details(username: &str, token: &str) -> AccountDetails {
+    AccountDetails {
+        ilp_address: Some(Address::from_str(&format!("example.node.{}", username)).unwrap()),
+        username: Username::from_str(username).unwrap(),
+        asset_code: "XYZ".to_string(),
+        asset_scale: 9,
+        btp_incoming_token: Some(token.to_string()),
+        btp_uri: None,
+        http_endpoint: None,
+        http_incoming_token: Some(token.to_string()),
+        http_outgoing_token: None,
+        max_packet_amount: u64::max_value(),
+        min_balance: None,
+        settle_threshold: None,
+        settle_to: None,
+        send_routes: false,
+        receive_routes: false,
+        routing_relation: None,
+        round_trip_time: None,
+        packets_per_minute_limit: None,
+        amount_per_minute_limit: None,
+        settlement_engine_url: None,
+        route_cost: None,
+        route_export_policy: None,
+        route_import_policy: None,
+        route_auth_key: None,
+        route_weight: None,
+    }
+}
+
+fn test_node(store: StoreType, database_url: String) -> InterledgerNode {
+    let btp_port = get_open_port(Some(7768));
+    let http_port = get_open_port(Some(7770));
+    let settlement_port = get_open_port(Some(7771));
+    InterledgerNode {
+        ilp_address: Some(Address::from_str("example.node").unwrap()),
+        default_spsp_account: None,
+        admin_auth_token: "admin".to_string(),
+        store,
+        redis_connection: "redis://127.0.0.1:6379".into_connection_info().unwrap(),
+        database_url,
+        memory_store: Default::default(),
+        btp_address: ([127, 0, 0, 1], btp_port).into(),
+        http_address: ([127, 0, 0, 1], http_port).into(),
+        settlement_address: ([127, 0, 0, 1], settlement_port).into(),
+        secret_seed: cli::random_secret(),
+        route_broadcast_interval: Some(200),
+        node_id: None,
+        routing_policy: Default::default(),
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
+    }
+}
+
+/// Add two accounts and then start the node
+fn insert_accounts_and_serve(node: InterledgerNode) -> impl Future<Item = (), Error = ()> {
+    ok(()).and_then(move |_| {
+        join_all(vec![
+            node.insert_account(account_details("one", "token-one")),
+            node.insert_account(account_details("two", "token-two")),
+        ])
+        .and_then(move |ids| {
+            assert_eq!(ids.len(), 2);
+            assert_ne!(ids[0], ids[1]);
+            debug!("Starting node with accounts: {:?}", ids);
+            // Starts the node with no external services
+            node.serve()
+        })
+    })
+}
+
+/// Create an account through the node's API
+fn create_account(http_port: u16, username: &str) -> impl Future<Item = (), Error = ()> {
+    let client = reqwest::r#async::Client::new();
+    client
+        .post(&format!("http://localhost:{}/accounts", http_port))
+        .header("Authorization", "Bearer admin")
+        .json(&json!({
+            "username": username,
+            "ilp_address": format!("example.node.{}", username),
+            "asset_code": "XYZ",
+            "asset_scale": 9,
+        }))
+        .send()
+        .and_then(|res| res.error_for_status())
+        .map(|_| ())
+        .map_err(|err| {
+            eprintln!("Error creating account: {:?}", err);
+        })
+}
+
+#[test]
+fn runs_with_embedded_sqlite() {
+    let _ = env_logger::try_init();
+    let database_path = env::temp_dir().join(format!("ilp-node-{}.db", cli::random_token()));
+    let node = test_node(
+        StoreType::Sql,
+        format!("sqlite://{}", database_path.display()),
+    );
+    let http_port = node.http_address.port();
+    let mut runtime = Runtime::new().unwrap();
+    let result = runtime.block_on(
+        insert_accounts_and_serve(node)
+            .and_then(move |_| create_account(http_port, "three"))
+            .and_then(move |_| get_balance("three", http_port, "admin")),
+    );
+    let _ = std::fs::remove_file(database_path);
+    assert_eq!(result.unwrap(), 0);
+}
+
+#[test]
+fn runs_with_in_memory_store() {
+    let _ = env_logger::try_init();
+    let node = test_node(StoreType::Memory, String::new());
+    let store = node.memory_store.clone();
+    let mut runtime = Runtime::new().unwrap();
+    runtime.block_on(insert_accounts_and_serve(node)).unwrap();
+    // The node was cloned to serve it but the accounts are kept in the same store
+    let accounts = futures03::executor::block_on(store.get_all_accounts()).unwrap();
+    assert_eq!(accounts.len(), 2);
+}
+
+/// Counts how many pipelines it was added to
+struct CountingHook(Arc<AtomicUsize>);
+
+impl IncomingHook for CountingHook {
+    fn wrap<A>(&self, next: BoxedIncomingService<A>) -> BoxedIncomingService<A>
+    where
+        A: Account + IldcpAccount + Sync + 'static,
+    {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        next
+    }
+}
+
+#[test]
+fn uses_pipeline_hooks_with_any_store() {
+    let _ = env_logger::try_init();
+    let count = Arc::new(AtomicUsize::new(0));
+    let database_path = env::temp_dir().join(format!("ilp-node-{}.db", cli::random_token()));
+    let stores = vec![
+        (
+            StoreType::Sql,
+            format!("sqlite://{}", database_path.display()),
+        ),
+        (StoreType::Memory, String::new()),
+    ];
+    let mut runtime = Runtime::new().unwrap();
+    for (store, database_url) in stores {
+        let mut node = test_node(store, database_url);
+        node.pipeline
+            .incoming
+            .push(IncomingStage::Custom("counter".to_string()));
+        node.pipeline_hooks
+            .incoming("counter", CountingHook(count.clone()));
+        runtime.block_on(insert_accounts_and_serve(node)).unwrap();
+    }
+    let _ = std::fs::remove_file(database_path);
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn sends_payments_through_reordered_stages() {
+    let _ = env_logger::try_init();
+    let mut node = test_node(StoreType::Memory, String::new());
+    let http_port = node.http_address.port();
+    node.pipeline.incoming = vec![
+        IncomingStage::Echo,
+        IncomingStage::Ildcp,
+        IncomingStage::MaxPacketAmount,
+        IncomingStage::Validator,
+        IncomingStage::RateLimit,
+        IncomingStage::SettlementMessage,
+    ];
+    node.pipeline.outgoing = vec![
+        OutgoingStage::ExpiryShortener,
+        OutgoingStage::Validator,
+        OutgoingStage::ExchangeRate,
+        OutgoingStage::Balance,
+        OutgoingStage::HttpClient,
+        OutgoingStage::StreamReceiver,
+    ];
+    let mut runtime = Runtime::new().unwrap();
+    let delivered = runtime
+        .block_on(insert_accounts_and_serve(node).and_then(move |_| {
+            send_money_to_username(http_port, http_port, 1000, "two", "one", "token-one")
+        }))
+        .unwrap();
+    assert_eq!(delivered, 1000);
+
+    // The Balance stage still saw the packets that the STREAM receiver after it fulfilled
+    let balances = runtime
+        .block_on(join_all(vec![
+            get_balance("one", http_port, "admin"),
+            get_balance("two", http_port, "admin"),
+        ]))
+        .unwrap();
+    assert_eq!(balances, vec![-1000, 1000]);
+}
@@ -0,0 +1,132 @@
+use env_logger;
+use futures::{future::join_all, Future};
+use interledger::{
+    cli,
+    node::{AccountDetails, InterledgerNode, StoreType},
+};
+use interledger_api::NodeStore;
+use interledger_ildcp::IldcpAccount;
+use interledger_packet::Address;
+use interledger_service::Username;
+use interledger_store_redis::IntoConnectionInfo;
+use std::str::FromStr;
+use tokio::runtime::Runtime;
+
+mod redis_helpers;
+use redis_helpers::get_open_port;
+
+fn account_details(username: &str, routing_relation: &str) -> AccountDetails {
+    AccountDetails {
+        ilp_address: None,
+        username: Username::from_str(username).unwrap(),
+        asset_code: "XYZ".to_string(),
+        asset_scale: 9,
+        btp_incoming_token: None,
+        btp_uri: None,
+        http_endpoint: None,
+        http_incoming_token: None,
+        http_outgoing_token: None,
+        max_packet_amount: u64::max_value(),
+        min_balance: None,
+        settle_threshold: None,
+        settle_to: None,
+        send_routes: false,
+        receive_routes: false,
+        routing_relation: Some(routing_relation.to_string()),
+        round_trip_time: None,
+        packets_per_minute_limit: None,
+        amount_per_minute_limit: None,
+        settlement_engine_url: None,
+        route_cost: None,
+        route_export_policy: None,
+        route_import_policy: None,
+        route_auth_key: None,
+        route_weight: None,
+    }
+}
+
+fn test_node(ilp_address: Option<&str>, btp_port: u16, http_port: u16) -> InterledgerNode {
+    InterledgerNode {
+        ilp_address: ilp_address.map(|address| Address::from_str(address).unwrap()),
+        default_spsp_account: None,
+        admin_auth_token: "admin".to_string(),
+        store: StoreType::Memory,
+        redis_connection: "redis://127.0.0.1:6379".into_connection_info().unwrap(),
+        database_url: String::new(),
+        memory_store: Default::default(),
+        btp_address: ([127, 0, 0, 1], btp_port).into(),
+        http_address: ([127, 0, 0, 1], http_port).into(),
+        settlement_address: ([127, 0, 0, 1], get_open_port(None)).into(),
+        secret_seed: cli::random_secret(),
+        route_broadcast_interval: Some(200),
+        node_id: None,
+        routing_policy: Default::default(),
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
+    }
+}
+
+/// Starts a parent node and a child node without an ILP address, which reaches
+/// its parent with the given account, and returns the address the child gets
+fn child_address_from_parent(
+    parent_btp: u16,
+    parent_http: u16,
+    mut parent_account: AccountDetails,
+) -> Address {
+    let parent = test_node(Some("example.parent"), parent_btp, parent_http);
+    let child = test_node(None, get_open_port(None), get_open_port(None));
+    let child_store = child.memory_store.clone();
+
+    let mut child_account = account_details("child", "Child");
+    child_account.btp_incoming_token = Some("child-token".to_string());
+    child_account.http_incoming_token = Some("child-token".to_string());
+    parent_account.ilp_address = Some(Address::from_str("example.parent").unwrap());
+
+    let mut runtime = Runtime::new().unwrap();
+    let setup = join_all(vec![
+        parent.insert_account(child_account),
+        child.insert_account(parent_account),
+    ])
+    .and_then(move |_| parent.serve())
+    .and_then(move |_| child.serve());
+    runtime.block_on(setup).unwrap();
+
+    // Children of the child node are named under the address it got
+    let grandchild = futures03::executor::block_on(
+        child_store.insert_account(account_details("grandchild", "Child")),
+    )
+    .unwrap();
+    grandchild.client_address().clone()
+}
+
+#[test]
+fn gets_address_from_parent_over_http() {
+    let _ = env_logger::try_init();
+    let parent_http = get_open_port(None);
+    let mut parent_account = account_details("parent", "Parent");
+    parent_account.http_endpoint = Some(format!("http://127.0.0.1:{}/ilp", parent_http));
+    parent_account.http_outgoing_token = Some("child:child-token".to_string());
+
+    let address = child_address_from_parent(get_open_port(None), parent_http, parent_account);
+    assert_eq!(
+        address,
+        Address::from_str("example.parent.child.grandchild").unwrap()
+    );
+}
+
+#[test]
+fn gets_address_from_parent_over_btp() {
+    let _ = env_logger::try_init();
+    let parent_btp = get_open_port(None);
+    let mut parent_account = account_details("parent", "Parent");
+    parent_account.btp_uri = Some(format!(
+        "btp+ws://:child:child-token@127.0.0.1:{}",
+        parent_btp
+    ));
+
+    let address = child_address_from_parent(parent_btp, get_open_port(None), parent_account);
+    assert_eq!(
+        address,
+        Address::from_str("example.parent.child.grandchild").unwrap()
+    );
+}
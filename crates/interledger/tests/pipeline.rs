@@ -0,0 +1,290 @@
+use futures03::{executor::block_on, future::ok};
+use interledger::{
+    packet::{Address, FulfillBuilder, PrepareBuilder},
+    pipeline::*,
+    service::*,
+};
+use lazy_static::lazy_static;
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+lazy_static! {
+    static ref ALICE: Username = Username::from_str("alice").unwrap();
+}
+
+#[derive(Clone, Debug)]
+struct TestAccount;
+
+impl Account for TestAccount {
+    type AccountId = u64;
+
+    fn id(&self) -> u64 {
+        0
+    }
+
+    fn username(&self) -> &Username {
+        &ALICE
+    }
+}
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+/// Records its name and then passes the request on to the next service
+#[derive(Clone)]
+struct LogService<S> {
+    name: String,
+    log: Log,
+    next: S,
+}
+
+impl<S> IncomingService<TestAccount> for LogService<S>
+where
+    S: IncomingService<TestAccount>,
+{
+    type Future = S::Future;
+
+    fn handle_request(&mut self, request: IncomingRequest<TestAccount>) -> Self::Future {
+        self.log.lock().unwrap().push(self.name.clone());
+        self.next.handle_request(request)
+    }
+}
+
+impl<S> OutgoingService<TestAccount> for LogService<S>
+where
+    S: OutgoingService<TestAccount>,
+{
+    type Future = S::Future;
+
+    fn send_request(&mut self, request: OutgoingRequest<TestAccount>) -> Self::Future {
+        self.log.lock().unwrap().push(self.name.clone());
+        self.next.send_request(request)
+    }
+}
+
+fn incoming_request() -> IncomingRequest<TestAccount> {
+    IncomingRequest {
+        from: TestAccount,
+        prepare: PrepareBuilder {
+            destination: Address::from_str("example.destination").unwrap(),
+            amount: 100,
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            execution_condition: &[0; 32],
+            data: &[],
+        }
+        .build(),
+        extensions: Extensions::new(),
+    }
+}
+
+fn incoming_pipeline(
+    config: &PipelineConfig,
+    hooks: &PipelineHooks<TestAccount>,
+    log: &Log,
+) -> Result<BoxedIncomingService<TestAccount>, PipelineError> {
+    let log_clone = log.clone();
+    let router = incoming_service_fn(move |_| {
+        log_clone.lock().unwrap().push("router".to_string());
+        ok(FulfillBuilder {
+            fulfillment: &[0; 32],
+            data: &[],
+        }
+        .build())
+    });
+    let layers = config.incoming_layers(hooks, |stage| {
+        let name = stage.to_string();
+        let log = log.clone();
+        Some(BoxedIncomingLayer::new(layer_fn(move |next| LogService {
+            name: name.clone(),
+            log: log.clone(),
+            next,
+        })))
+    })?;
+    Ok(ServiceBuilder::new()
+        .layer(layers)
+        .incoming_service(BoxedIncomingService::new(router)))
+}
+
+fn outgoing_pipeline(
+    config: &PipelineConfig,
+    hooks: &PipelineHooks<TestAccount>,
+    log: &Log,
+) -> Result<BoxedOutgoingService<TestAccount>, PipelineError> {
+    let log_clone = log.clone();
+    let btp = outgoing_service_fn(move |_| {
+        log_clone.lock().unwrap().push("btp".to_string());
+        ok(FulfillBuilder {
+            fulfillment: &[0; 32],
+            data: &[],
+        }
+        .build())
+    });
+    let layers = config.outgoing_layers(hooks, |stage| {
+        let name = stage.to_string();
+        let log = log.clone();
+        Some(BoxedOutgoingLayer::new(layer_fn(move |next| LogService {
+            name: name.clone(),
+            log: log.clone(),
+            next,
+        })))
+    })?;
+    Ok(ServiceBuilder::new()
+        .layer(layers)
+        .outgoing_service(BoxedOutgoingService::new(btp)))
+}
+
+fn log_hooks(log: &Log, names: &[&'static str]) -> PipelineHooks<TestAccount> {
+    let mut hooks = PipelineHooks::new();
+    for &name in names {
+        let log_incoming = log.clone();
+        let log_outgoing = log.clone();
+        hooks
+            .incoming(name, move |next| {
+                BoxedIncomingService::new(LogService {
+                    name: name.to_string(),
+                    log: log_incoming.clone(),
+                    next,
+                })
+            })
+            .outgoing(name, move |next| {
+                BoxedOutgoingService::new(LogService {
+                    name: name.to_string(),
+                    log: log_outgoing.clone(),
+                    next,
+                })
+            });
+    }
+    hooks
+}
+
+#[test]
+fn default_pipeline_contains_all_builtin_stages() {
+    let config: PipelineConfig = serde_json::from_str("{}").unwrap();
+    assert_eq!(config, PipelineConfig::default());
+    assert_eq!(
+        config
+            .incoming
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        vec![
+            "rate_limit",
+            "validator",
+            "max_packet_amount",
+            "ildcp",
+            "settlement_message",
+            "echo"
+        ]
+    );
+    assert_eq!(
+        config
+            .outgoing
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        vec![
+            "exchange_rate",
+            "balance",
+            "stream_receiver",
+            "expiry_shortener",
+            "http_client",
+            "validator"
+        ]
+    );
+}
+
+#[test]
+fn parses_builtin_and_custom_stages() {
+    let config: PipelineConfig =
+        serde_json::from_str(r#"{"incoming": ["validator", "fee"], "outgoing": ["fee"]}"#).unwrap();
+    assert_eq!(
+        config.incoming,
+        vec![
+            IncomingStage::Validator,
+            IncomingStage::Custom("fee".to_string())
+        ]
+    );
+    assert_eq!(
+        config.outgoing,
+        vec![OutgoingStage::Custom("fee".to_string())]
+    );
+}
+
+#[test]
+fn incoming_stages_run_in_configured_order() {
+    let log = Log::default();
+    let config: PipelineConfig = serde_json::from_str(
+        r#"{"incoming": ["fee", "validator", "rate_limit", "audit", "ildcp"]}"#,
+    )
+    .unwrap();
+    let hooks = log_hooks(&log, &["fee", "audit"]);
+    let mut service = incoming_pipeline(&config, &hooks, &log).unwrap();
+
+    assert!(block_on(service.handle_request(incoming_request())).is_ok());
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec!["fee", "validator", "rate_limit", "audit", "ildcp", "router"]
+    );
+}
+
+#[test]
+fn outgoing_stages_run_in_configured_order() {
+    let log = Log::default();
+    let config: PipelineConfig =
+        serde_json::from_str(r#"{"outgoing": ["validator", "fee", "balance"]}"#).unwrap();
+    let hooks = log_hooks(&log, &["fee"]);
+    let mut service = outgoing_pipeline(&config, &hooks, &log).unwrap();
+
+    let request = incoming_request().into_outgoing(TestAccount);
+    assert!(block_on(service.send_request(request)).is_ok());
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec!["validator", "fee", "balance", "btp"]
+    );
+}
+
+#[test]
+fn stages_left_out_are_not_run() {
+    let log = Log::default();
+    let mut config = PipelineConfig::default();
+    config
+        .incoming
+        .retain(|stage| *stage != IncomingStage::Echo);
+    let mut service = incoming_pipeline(&config, &PipelineHooks::new(), &log).unwrap();
+
+    assert!(block_on(service.handle_request(incoming_request())).is_ok());
+    let log = log.lock().unwrap();
+    assert!(!log.contains(&"echo".to_string()));
+    assert_eq!(log.len(), config.incoming.len() + 1);
+}
+
+#[test]
+fn rejects_unregistered_custom_stage() {
+    let log = Log::default();
+    let config: PipelineConfig =
+        serde_json::from_str(r#"{"incoming": ["validator", "fee"]}"#).unwrap();
+    let result = incoming_pipeline(&config, &PipelineHooks::new(), &log);
+    assert_eq!(
+        result.err(),
+        Some(PipelineError::UnknownStage("fee".to_string()))
+    );
+}
+
+#[test]
+fn rejects_builtin_stage_that_is_not_provided() {
+    let config: PipelineConfig =
+        serde_json::from_str(r#"{"outgoing": ["validator", "balance"]}"#).unwrap();
+    let result = config.outgoing_layers(&PipelineHooks::<TestAccount>::new(), |stage| {
+        if *stage == OutgoingStage::Validator {
+            Some(BoxedOutgoingLayer::new(Identity))
+        } else {
+            None
+        }
+    });
+    assert_eq!(
+        result.err(),
+        Some(PipelineError::UnknownStage("balance".to_string()))
+    );
+}
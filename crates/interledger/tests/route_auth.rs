@@ -0,0 +1,159 @@
+use env_logger;
+use futures::{future::join_all, Future};
+use interledger::{
+    cli,
+    node::{AccountDetails, InterledgerNode, StoreType},
+};
+use interledger_packet::Address;
+use interledger_router::RouterStore;
+use interledger_service::Username;
+use interledger_store_redis::IntoConnectionInfo;
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use tokio::{runtime::Runtime, timer::Delay};
+
+mod redis_helpers;
+use redis_helpers::get_open_port;
+
+fn peer_details(
+    ilp_address: &str,
+    username: &str,
+    http_port: u16,
+    incoming_token: &str,
+    outgoing_token: &str,
+    route_auth_key: &str,
+) -> AccountDetails {
+    AccountDetails {
+        ilp_address: Some(Address::from_str(ilp_address).unwrap()),
+        username: Username::from_str(username).unwrap(),
+        asset_code: "XYZ".to_string(),
+        asset_scale: 9,
+        btp_incoming_token: None,
+        btp_uri: None,
+        http_endpoint: Some(format!("http://127.0.0.1:{}/ilp", http_port)),
+        http_incoming_token: Some(incoming_token.to_string()),
+        http_outgoing_token: Some(outgoing_token.to_string()),
+        max_packet_amount: u64::max_value(),
+        min_balance: Some(-1_000_000_000),
+        settle_threshold: None,
+        settle_to: None,
+        send_routes: true,
+        receive_routes: true,
+        routing_relation: Some("Peer".to_string()),
+        round_trip_time: None,
+        packets_per_minute_limit: None,
+        amount_per_minute_limit: None,
+        settlement_engine_url: None,
+        route_cost: None,
+        route_export_policy: None,
+        route_import_policy: None,
+        route_auth_key: Some(route_auth_key.to_string()),
+        route_weight: None,
+    }
+}
+
+fn child_details(ilp_address: &str, username: &str) -> AccountDetails {
+    AccountDetails {
+        ilp_address: Some(Address::from_str(ilp_address).unwrap()),
+        username: Username::from_str(username).unwrap(),
+        asset_code: "XYZ".to_string(),
+        asset_scale: 9,
+        btp_incoming_token: Some(username.to_string()),
+        btp_uri: None,
+        http_endpoint: None,
+        http_incoming_token: None,
+        http_outgoing_token: None,
+        max_packet_amount: u64::max_value(),
+        min_balance: None,
+        settle_threshold: None,
+        settle_to: None,
+        send_routes: false,
+        receive_routes: false,
+        routing_relation: Some("Child".to_string()),
+        round_trip_time: None,
+        packets_per_minute_limit: None,
+        amount_per_minute_limit: None,
+        settlement_engine_url: None,
+        route_cost: None,
+        route_export_policy: None,
+        route_import_policy: None,
+        route_auth_key: None,
+        route_weight: None,
+    }
+}
+
+fn test_node(ilp_address: &str, http_port: u16) -> InterledgerNode {
+    InterledgerNode {
+        ilp_address: Some(Address::from_str(ilp_address).unwrap()),
+        default_spsp_account: None,
+        admin_auth_token: "admin".to_string(),
+        store: StoreType::Memory,
+        redis_connection: "redis://127.0.0.1:6379".into_connection_info().unwrap(),
+        database_url: String::new(),
+        memory_store: Default::default(),
+        btp_address: ([127, 0, 0, 1], get_open_port(None)).into(),
+        http_address: ([127, 0, 0, 1], http_port).into(),
+        settlement_address: ([127, 0, 0, 1], get_open_port(None)).into(),
+        secret_seed: cli::random_secret(),
+        route_broadcast_interval: Some(200),
+        node_id: None,
+        routing_policy: Default::default(),
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
+    }
+}
+
+/// Peers node one and node two with the given route auth keys, gives node two
+/// a child account and returns whether node one learned the route to that child
+fn learns_peer_routes(node_one_key: &str, node_two_key: &str) -> bool {
+    let node_one_http = get_open_port(None);
+    let node_two_http = get_open_port(None);
+    let node_one = test_node("example.one", node_one_http);
+    let node_two = test_node("example.two", node_two_http);
+    let node_one_store = node_one.memory_store.clone();
+
+    let mut runtime = Runtime::new().unwrap();
+    let setup = join_all(vec![
+        node_one.insert_account(peer_details(
+            "example.two",
+            "two",
+            node_two_http,
+            "token-two",
+            "one:token-one",
+            node_one_key,
+        )),
+        node_two.insert_account(peer_details(
+            "example.one",
+            "one",
+            node_one_http,
+            "token-one",
+            "two:token-two",
+            node_two_key,
+        )),
+        node_two.insert_account(child_details("example.two.child", "child")),
+    ])
+    .and_then(move |_| node_one.serve().join(node_two.serve()))
+    .and_then(|_| {
+        // Give the nodes a few broadcast intervals to exchange their routes
+        Delay::new(Instant::now() + Duration::from_millis(3000)).map_err(|_| ())
+    });
+    runtime.block_on(setup).unwrap();
+
+    node_one_store
+        .routing_table()
+        .contains_key(b"example.two.child")
+}
+
+#[test]
+fn accepts_routes_signed_with_the_shared_key() {
+    let _ = env_logger::try_init();
+    assert!(learns_peer_routes("shared secret", "shared secret"));
+}
+
+#[test]
+fn rejects_routes_signed_with_a_different_key() {
+    let _ = env_logger::try_init();
+    assert!(!learns_peer_routes("shared secret", "other secret"));
+}
@@ -4,7 +4,7 @@ use env_logger;
 use futures::Future;
 use interledger::{
     cli,
-    node::{AccountDetails, InterledgerNode},
+    node::{AccountDetails, InterledgerNode, StoreType},
 };
 use interledger_packet::Address;
 use interledger_service::Username;
@@ -46,22 +46,29 @@ fn three_nodes() {
         .unwrap();
 
     let node1 = InterledgerNode {
-        ilp_address: Address::from_str("example.one").unwrap(),
+        ilp_address: Some(Address::from_str("example.one").unwrap()),
         default_spsp_account: Some(Username::from_str("one").unwrap()),
         admin_auth_token: "admin".to_string(),
+        store: StoreType::Redis,
         redis_connection: connection_info1,
+        database_url: String::new(),
+        memory_store: Default::default(),
         btp_address: ([127, 0, 0, 1], get_open_port(None)).into(),
         http_address: ([127, 0, 0, 1], node1_http).into(),
         settlement_address: ([127, 0, 0, 1], node1_settlement).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        node_id: None,
+        routing_policy: Default::default(),
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     let node1_clone = node1.clone();
     runtime.spawn(
         // TODO insert the accounts via HTTP request
         node1_clone
             .insert_account(AccountDetails {
-                ilp_address: Address::from_str("example.one").unwrap(),
+                ilp_address: Some(Address::from_str("example.one").unwrap()),
                 username: Username::from_str("alice").unwrap(),
                 asset_code: "XYZ".to_string(),
                 asset_scale: 9,
@@ -81,12 +88,17 @@ fn three_nodes() {
                 packets_per_minute_limit: None,
                 amount_per_minute_limit: None,
                 settlement_engine_url: None,
+                route_cost: None,
+                route_export_policy: None,
+                route_import_policy: None,
+                route_auth_key: None,
+                route_weight: None,
             })
             .and_then(move |_|
         // TODO insert the accounts via HTTP request
         node1_clone
             .insert_account(AccountDetails {
-                ilp_address: Address::from_str("example.two").unwrap(),
+                ilp_address: Some(Address::from_str("example.two").unwrap()),
                 username: Username::from_str("bob").unwrap(),
                 asset_code: "XYZ".to_string(),
                 asset_scale: 9,
@@ -106,26 +118,38 @@ fn three_nodes() {
                 packets_per_minute_limit: None,
                 amount_per_minute_limit: None,
                 settlement_engine_url: None,
+                route_cost: None,
+                route_export_policy: None,
+                route_import_policy: None,
+                route_auth_key: None,
+                route_weight: None,
             }))
             .and_then(move |_| node1.serve()),
     );
 
     let node2 = InterledgerNode {
-        ilp_address: Address::from_str("example.two").unwrap(),
+        ilp_address: Some(Address::from_str("example.two").unwrap()),
         default_spsp_account: Some(Username::from_str("two").unwrap()),
         admin_auth_token: "admin".to_string(),
+        store: StoreType::Redis,
         redis_connection: connection_info2,
+        database_url: String::new(),
+        memory_store: Default::default(),
         btp_address: ([127, 0, 0, 1], node2_btp).into(),
         http_address: ([127, 0, 0, 1], node2_http).into(),
         settlement_address: ([127, 0, 0, 1], node2_settlement).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        node_id: None,
+        routing_policy: Default::default(),
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     let node2_clone = node2.clone();
     runtime.spawn(
         node2_clone
             .insert_account(AccountDetails {
-                ilp_address: Address::from_str("example.one").unwrap(),
+                ilp_address: Some(Address::from_str("example.one").unwrap()),
                 username: Username::from_str("alice").unwrap(),
                 asset_code: "XYZ".to_string(),
                 asset_scale: 9,
@@ -145,10 +169,15 @@ fn three_nodes() {
                 packets_per_minute_limit: None,
                 amount_per_minute_limit: None,
                 settlement_engine_url: None,
+                route_cost: None,
+                route_export_policy: None,
+                route_import_policy: None,
+                route_auth_key: None,
+                route_weight: None,
             })
             .and_then(move |_| {
                 node2_clone.insert_account(AccountDetails {
-                    ilp_address: Address::from_str("example.two.three").unwrap(),
+                    ilp_address: Some(Address::from_str("example.two.three").unwrap()),
                     username: Username::from_str("charlie").unwrap(),
                     asset_code: "ABC".to_string(),
                     asset_scale: 6,
@@ -168,6 +197,11 @@ fn three_nodes() {
                     packets_per_minute_limit: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: None,
+                    route_cost: None,
+                    route_export_policy: None,
+                    route_import_policy: None,
+                    route_auth_key: None,
+                    route_weight: None,
                 })
             })
             .and_then(move |_| node2.serve())
@@ -188,15 +222,22 @@ fn three_nodes() {
     );
 
     let node3 = InterledgerNode {
-        ilp_address: Address::from_str("example.two.three").unwrap(),
+        ilp_address: Some(Address::from_str("example.two.three").unwrap()),
         default_spsp_account: Some(Username::from_str("three").unwrap()),
         admin_auth_token: "admin".to_string(),
+        store: StoreType::Redis,
         redis_connection: connection_info3,
+        database_url: String::new(),
+        memory_store: Default::default(),
         btp_address: ([127, 0, 0, 1], get_open_port(None)).into(),
         http_address: ([127, 0, 0, 1], node3_http).into(),
         settlement_address: ([127, 0, 0, 1], node3_settlement).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        node_id: None,
+        routing_policy: Default::default(),
+        pipeline: Default::default(),
+        pipeline_hooks: Default::default(),
     };
     let node3_clone = node3.clone();
     runtime.spawn(
@@ -204,7 +245,7 @@ fn three_nodes() {
         delay(50).map_err(|err| panic!(err)).and_then(move |_| {
             node3_clone
                 .insert_account(AccountDetails {
-                    ilp_address: Address::from_str("example.two.three").unwrap(),
+                    ilp_address: Some(Address::from_str("example.two.three").unwrap()),
                     username: Username::from_str("charlie").unwrap(),
                     asset_code: "ABC".to_string(),
                     asset_scale: 6,
@@ -224,10 +265,15 @@ fn three_nodes() {
                     packets_per_minute_limit: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: None,
+                    route_cost: None,
+                    route_export_policy: None,
+                    route_import_policy: None,
+                    route_auth_key: None,
+                    route_weight: None,
                 })
                 .and_then(move |_| {
                     node3_clone.insert_account(AccountDetails {
-                        ilp_address: Address::from_str("example.two").unwrap(),
+                        ilp_address: Some(Address::from_str("example.two").unwrap()),
                         username: Username::from_str("bob").unwrap(),
                         asset_code: "ABC".to_string(),
                         asset_scale: 6,
@@ -247,6 +293,11 @@ fn three_nodes() {
                         packets_per_minute_limit: None,
                         amount_per_minute_limit: None,
                         settlement_engine_url: None,
+                        route_cost: None,
+                        route_export_policy: None,
+                        route_import_policy: None,
+                        route_auth_key: None,
+                        route_weight: None,
                     })
                 })
                 .and_then(move |_| node3.serve())